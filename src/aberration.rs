@@ -0,0 +1,39 @@
+use crate::color::Color;
+
+/// 横向色差：镜头对不同颜色的放大率不一样，离画面中心越远，红和蓝分得越开，
+/// 高反差的边上会有红蓝的边。在clamp之前的整张图上做：绿色不动，
+/// 红色按1 + strength、蓝色按1 - strength的放大率从原图上重新取
+pub fn apply(strength: f32, width: u32, height: u32, pixels: &mut [Color]) {
+    if strength == 0.0 || width == 0 || height == 0 {
+        return;
+    }
+    let source = pixels.to_vec();
+    let (w, h) = (width as usize, height as usize);
+    let get = |x: isize, y: isize| {
+        let x = x.clamp(0, w as isize - 1) as usize;
+        let y = y.clamp(0, h as isize - 1) as usize;
+        source[y * w + x]
+    };
+    // (x, y)按像素算，像素中心在整数加0.5的地方
+    let sample = |x: f32, y: f32| {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = get(x0, y0) * (1.0 - fx) + get(x0 + 1, y0) * fx;
+        let bottom = get(x0, y0 + 1) * (1.0 - fx) + get(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    };
+    let center = (w as f32 / 2.0, h as f32 / 2.0);
+    let scaled = |x: f32, y: f32, scale: f32| {
+        sample(
+            center.0 + (x - center.0) / scale,
+            center.1 + (y - center.1) / scale,
+        )
+    };
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = ((i % w) as f32 + 0.5, (i / w) as f32 + 0.5);
+        pixel.r = scaled(x, y, 1.0 + strength).r;
+        pixel.b = scaled(x, y, 1.0 - strength).b;
+    }
+}
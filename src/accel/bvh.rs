@@ -0,0 +1,296 @@
+use crate::accel::{
+    fallback_material, fallback_normal, fallback_texture_coords, nearest_hit, split_unbounded,
+    Accelerator, Item,
+};
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::overlay::BoundsBox;
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    light::EmissiveSurface,
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+use crate::stats;
+
+/// 叶子里最多放几个物体
+const MAX_LEAF_ITEMS: usize = 4;
+/// SAH分桶的个数
+const NUM_BUCKETS: usize = 12;
+
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        count: usize,
+    },
+    /// 左孩子紧跟在后面，右孩子在second
+    Interior {
+        bounds: Aabb,
+        second: usize,
+        axis: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// 层次包围盒，按SAH分桶建树，节点压平存在数组里
+pub struct Bvh {
+    items: Vec<Item>,
+    nodes: Vec<Node>,
+    /// 没有包围盒的物体，不在树里
+    unbounded: Vec<Item>,
+}
+
+struct BuildItem {
+    index: usize,
+    bounds: Aabb,
+    centroid: Point,
+}
+
+impl Bvh {
+    fn all_items(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter().chain(&self.unbounded)
+    }
+
+    fn build_node(&mut self, build: &mut [BuildItem], start: usize, order: &mut Vec<usize>) {
+        let bounds = build
+            .iter()
+            .map(|b| b.bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let make_leaf = |nodes: &mut Vec<Node>, order: &mut Vec<usize>, build: &[BuildItem]| {
+            nodes.push(Node::Leaf {
+                bounds,
+                start,
+                count: build.len(),
+            });
+            order.extend(build.iter().map(|b| b.index));
+        };
+        if build.len() <= MAX_LEAF_ITEMS {
+            make_leaf(&mut self.nodes, order, build);
+            return;
+        }
+
+        let centroids = Aabb::from_points(&build.iter().map(|b| b.centroid).collect::<Vec<_>>());
+        let axis = centroids.longest_axis();
+        let (lo, hi) = (centroids.min.axis(axis), centroids.max.axis(axis));
+        if hi - lo <= 0.0 {
+            make_leaf(&mut self.nodes, order, build);
+            return;
+        }
+
+        // 按重心把物体分到桶里，在桶的边界上找SAH代价最小的切分
+        let bucket_of = |b: &BuildItem| {
+            let t = (b.centroid.axis(axis) - lo) / (hi - lo);
+            ((t * NUM_BUCKETS as Float) as usize).min(NUM_BUCKETS - 1)
+        };
+        let mut buckets: Vec<(usize, Option<Aabb>)> = vec![(0, None); NUM_BUCKETS];
+        for b in build.iter() {
+            let bucket = &mut buckets[bucket_of(b)];
+            bucket.0 += 1;
+            bucket.1 = Some(bucket.1.map_or(b.bounds, |a| a.union(&b.bounds)));
+        }
+        let side_cost = |side: &[(usize, Option<Aabb>)]| {
+            let count: usize = side.iter().map(|b| b.0).sum();
+            let area = side
+                .iter()
+                .filter_map(|b| b.1)
+                .reduce(|a, b| a.union(&b))
+                .map_or(0.0, |a| a.surface_area());
+            count as Float * area
+        };
+        let (split, cost) = (1..NUM_BUCKETS)
+            .map(|i| (i, side_cost(&buckets[..i]) + side_cost(&buckets[i..])))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let leaf_cost = build.len() as Float * bounds.surface_area();
+        if cost >= leaf_cost && build.len() <= MAX_LEAF_ITEMS * 4 {
+            make_leaf(&mut self.nodes, order, build);
+            return;
+        }
+
+        let mut mid = partition(build, |b| bucket_of(b) < split);
+        if mid == 0 || mid == build.len() {
+            // 都落在一边时按重心的中位数对半分
+            build.sort_by(|a, b| a.centroid.axis(axis).total_cmp(&b.centroid.axis(axis)));
+            mid = build.len() / 2;
+        }
+
+        let node = self.nodes.len();
+        self.nodes.push(Node::Interior {
+            bounds,
+            second: 0,
+            axis,
+        });
+        let (left, right) = build.split_at_mut(mid);
+        self.build_node(left, start, order);
+        let second_index = self.nodes.len();
+        self.build_node(right, start + mid, order);
+        if let Node::Interior { second, .. } = &mut self.nodes[node] {
+            *second = second_index;
+        }
+    }
+}
+
+/// 把满足条件的挪到前面，返回分界的位置
+fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+impl Accelerator for Bvh {
+    fn build(items: Vec<Item>) -> Self {
+        let (bounded, unbounded) = split_unbounded(items);
+        let (items, mut build): (Vec<Item>, Vec<BuildItem>) = bounded
+            .into_iter()
+            .enumerate()
+            .map(|(index, (item, bounds))| {
+                let build = BuildItem {
+                    index,
+                    bounds,
+                    centroid: bounds.centroid(),
+                };
+                (item, build)
+            })
+            .unzip();
+        let mut bvh = Bvh {
+            items: Vec::new(),
+            nodes: Vec::new(),
+            unbounded,
+        };
+        if build.is_empty() {
+            return bvh;
+        }
+        let mut order = Vec::with_capacity(items.len());
+        bvh.build_node(&mut build, 0, &mut order);
+
+        // 按叶子的顺序重新排物体，叶子里就只用存区间
+        let mut slots: Vec<Option<Item>> = items.into_iter().map(Some).collect();
+        bvh.items = order
+            .into_iter()
+            .map(|index| slots[index].take().unwrap())
+            .collect();
+        bvh
+    }
+
+    fn len(&self) -> usize {
+        self.items.len() + self.unbounded.len()
+    }
+}
+
+impl Intersectable for Bvh {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.intersect_hit(ray).map(|hit| hit.distance)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let mut nearest = nearest_hit(&self.unbounded, ray);
+        if self.nodes.is_empty() {
+            return nearest;
+        }
+        let mut stack = vec![0];
+        let mut visits = 0;
+        while let Some(index) = stack.pop() {
+            visits += 1;
+            let node = &self.nodes[index];
+            match node.bounds().hit(&ray.origin, &ray.direction) {
+                Some((t0, _)) if nearest.as_ref().is_none_or(|n| t0 <= n.distance) => {}
+                _ => continue,
+            }
+            match *node {
+                Node::Leaf { start, count, .. } => {
+                    for item in &self.items[start..start + count] {
+                        if let Some(hit) = item.intersect_hit(ray) {
+                            if nearest.as_ref().is_none_or(|n| hit.distance < n.distance) {
+                                nearest = Some(hit);
+                            }
+                        }
+                    }
+                }
+                Node::Interior { second, axis, .. } => {
+                    // 先走离光线起点近的那边，后压栈的先出来
+                    if ray.direction.axis(axis) < 0.0 {
+                        stack.push(index + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(index + 1);
+                    }
+                }
+            }
+        }
+        stats::count(|c| c.node_visits += visits);
+        nearest
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|node| *node.bounds())
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        fallback_normal()
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        fallback_texture_coords()
+    }
+
+    fn get_material(&self) -> &Material {
+        fallback_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        for item in self.all_items() {
+            item.validate(report);
+        }
+    }
+
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        for item in self.all_items() {
+            item.caustic_bounds(out);
+        }
+    }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        for item in self.all_items() {
+            item.emissive_surfaces(out);
+        }
+    }
+
+    fn collect_bounds(&self, out: &mut Vec<BoundsBox>) {
+        let mut stack = if self.nodes.is_empty() {
+            Vec::new()
+        } else {
+            vec![(0, 0)]
+        };
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            out.push(BoundsBox {
+                bounds: *node.bounds(),
+                depth: Some(depth),
+            });
+            if let Node::Interior { second, .. } = *node {
+                stack.push((index + 1, depth + 1));
+                stack.push((second, depth + 1));
+            }
+        }
+        for item in self.all_items() {
+            item.collect_bounds(out);
+        }
+    }
+}
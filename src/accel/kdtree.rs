@@ -0,0 +1,328 @@
+use crate::accel::{
+    fallback_material, fallback_normal, fallback_texture_coords, nearest_hit, split_unbounded,
+    Accelerator, Item,
+};
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::overlay::BoundsBox;
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    light::EmissiveSurface,
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+use crate::stats;
+
+/// 物体少于这个数就不再往下分
+const MAX_LEAF_ITEMS: usize = 2;
+/// SAH里遍历一个节点和求交一个物体的相对代价
+const TRAVERSAL_COST: Float = 1.0;
+const INTERSECT_COST: Float = 80.0;
+/// 切出一边是空的时给点优惠，这样空的区域会被尽快切掉
+const EMPTY_BONUS: Float = 0.5;
+
+enum KdNode {
+    /// indices里从start开始的count个
+    Leaf { start: usize, count: usize },
+    /// 在axis轴上的split处切开，下面的孩子紧跟在后面，上面的孩子在above
+    Interior {
+        axis: usize,
+        split: Float,
+        above: usize,
+    },
+}
+
+/// kd树：按SAH在物体包围盒的边上切分空间，跨过切面的物体两边都放。
+/// 细长的几何体很多时（比如建筑场景）通常比BVH切得更紧
+pub struct KdTree {
+    items: Vec<Item>,
+    /// 树里物体的包围盒
+    bounds: Option<Aabb>,
+    nodes: Vec<KdNode>,
+    /// 叶子里存的是物体在items里的下标
+    indices: Vec<usize>,
+    /// 没有包围盒的物体，不在树里
+    unbounded: Vec<Item>,
+}
+
+impl KdTree {
+    fn all_items(&self) -> impl Iterator<Item = &Item> {
+        self.items.iter().chain(&self.unbounded)
+    }
+
+    fn build_node(
+        &mut self,
+        item_bounds: &[Aabb],
+        node_bounds: Aabb,
+        items: Vec<usize>,
+        depth: usize,
+    ) {
+        let node = self.nodes.len();
+        let make_leaf = |tree: &mut KdTree, items: &[usize]| {
+            tree.nodes.push(KdNode::Leaf {
+                start: tree.indices.len(),
+                count: items.len(),
+            });
+            tree.indices.extend_from_slice(items);
+        };
+        if items.len() <= MAX_LEAF_ITEMS || depth == 0 {
+            make_leaf(self, &items);
+            return;
+        }
+
+        let split = match best_split(item_bounds, &node_bounds, &items) {
+            Some((axis, split, cost)) if cost < INTERSECT_COST * items.len() as Float => {
+                (axis, split)
+            }
+            _ => {
+                make_leaf(self, &items);
+                return;
+            }
+        };
+        let (axis, split) = split;
+        // 正好贴在切面上的扁平物体放到下面
+        let below: Vec<usize> = items
+            .iter()
+            .copied()
+            .filter(|&i| {
+                item_bounds[i].min.axis(axis) < split || item_bounds[i].max.axis(axis) <= split
+            })
+            .collect();
+        let above: Vec<usize> = items
+            .iter()
+            .copied()
+            .filter(|&i| item_bounds[i].max.axis(axis) > split)
+            .collect();
+
+        let (mut below_bounds, mut above_bounds) = (node_bounds, node_bounds);
+        set_axis(&mut below_bounds.max, axis, split);
+        set_axis(&mut above_bounds.min, axis, split);
+
+        self.nodes.push(KdNode::Interior {
+            axis,
+            split,
+            above: 0,
+        });
+        self.build_node(item_bounds, below_bounds, below, depth - 1);
+        let above_index = self.nodes.len();
+        self.build_node(item_bounds, above_bounds, above, depth - 1);
+        if let KdNode::Interior { above, .. } = &mut self.nodes[node] {
+            *above = above_index;
+        }
+    }
+}
+
+fn set_axis(p: &mut Point, axis: usize, value: Float) {
+    match axis {
+        0 => p.x = value,
+        1 => p.y = value,
+        _ => p.z = value,
+    }
+}
+
+/// 在三个轴上把所有包围盒的边都试一遍，返回SAH代价最小的(轴, 位置, 代价)
+fn best_split(
+    item_bounds: &[Aabb],
+    node_bounds: &Aabb,
+    items: &[usize],
+) -> Option<(usize, Float, Float)> {
+    let total_area = node_bounds.surface_area();
+    let extent = node_bounds.max - node_bounds.min;
+    let mut best: Option<(usize, Float, Float)> = None;
+    for axis in 0..3 {
+        // (位置, 是不是起点)，同一位置起点排在终点前面
+        let mut edges: Vec<(Float, bool)> = items
+            .iter()
+            .flat_map(|&i| {
+                [
+                    (item_bounds[i].min.axis(axis), true),
+                    (item_bounds[i].max.axis(axis), false),
+                ]
+            })
+            .collect();
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let (lo, hi) = (node_bounds.min.axis(axis), node_bounds.max.axis(axis));
+        let (other0, other1) = ((axis + 1) % 3, (axis + 2) % 3);
+        let (d0, d1) = (extent.axis(other0), extent.axis(other1));
+        let (mut n_below, mut n_above) = (0, items.len());
+        for &(t, is_start) in &edges {
+            if !is_start {
+                n_above -= 1;
+            }
+            if t > lo && t < hi {
+                let below_area = 2.0 * (d0 * d1 + (t - lo) * (d0 + d1));
+                let above_area = 2.0 * (d0 * d1 + (hi - t) * (d0 + d1));
+                let bonus = if n_below == 0 || n_above == 0 {
+                    EMPTY_BONUS
+                } else {
+                    0.0
+                };
+                let cost = TRAVERSAL_COST
+                    + INTERSECT_COST
+                        * (1.0 - bonus)
+                        * (below_area / total_area * n_below as Float
+                            + above_area / total_area * n_above as Float);
+                if best.is_none_or(|(_, _, c)| cost < c) {
+                    best = Some((axis, t, cost));
+                }
+            }
+            if is_start {
+                n_below += 1;
+            }
+        }
+    }
+    best
+}
+
+impl Accelerator for KdTree {
+    fn build(items: Vec<Item>) -> Self {
+        let (bounded, unbounded) = split_unbounded(items);
+        let (items, item_bounds): (Vec<Item>, Vec<Aabb>) = bounded.into_iter().unzip();
+        let bounds = item_bounds.iter().copied().reduce(|a, b| a.union(&b));
+        let mut tree = KdTree {
+            items,
+            bounds,
+            nodes: Vec::new(),
+            indices: Vec::new(),
+            unbounded,
+        };
+        if let Some(bounds) = bounds {
+            let n = item_bounds.len() as Float;
+            let max_depth = (8.0 + 1.3 * n.log2()).round() as usize;
+            tree.build_node(
+                &item_bounds,
+                bounds,
+                (0..item_bounds.len()).collect(),
+                max_depth,
+            );
+        }
+        tree
+    }
+
+    fn len(&self) -> usize {
+        self.items.len() + self.unbounded.len()
+    }
+}
+
+impl Intersectable for KdTree {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.intersect_hit(ray).map(|hit| hit.distance)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let mut nearest = nearest_hit(&self.unbounded, ray);
+        let Some((t_min, t_max)) = self
+            .bounds
+            .and_then(|bounds| bounds.hit(&ray.origin, &ray.direction))
+        else {
+            return nearest;
+        };
+        // 从近到远走，每个节点带着光线在它里面的那一段[t_min, t_max]
+        let mut stack = vec![(0, t_min, t_max)];
+        let mut visits = 0;
+        while let Some((mut index, t_min, mut t_max)) = stack.pop() {
+            if nearest.as_ref().is_some_and(|n| n.distance < t_min) {
+                break;
+            }
+            loop {
+                visits += 1;
+                match self.nodes[index] {
+                    KdNode::Interior { axis, split, above } => {
+                        let origin = ray.origin.axis(axis);
+                        let direction = ray.direction.axis(axis);
+                        let t_plane = (split - origin) / direction;
+                        let below_first = origin < split || (origin == split && direction <= 0.0);
+                        let (first, second) = if below_first {
+                            (index + 1, above)
+                        } else {
+                            (above, index + 1)
+                        };
+                        if t_plane > t_max || t_plane <= 0.0 {
+                            index = first;
+                        } else if t_plane < t_min {
+                            index = second;
+                        } else {
+                            stack.push((second, t_plane, t_max));
+                            index = first;
+                            t_max = t_plane;
+                        }
+                    }
+                    KdNode::Leaf { start, count } => {
+                        for &i in &self.indices[start..start + count] {
+                            if let Some(hit) = self.items[i].intersect_hit(ray) {
+                                if nearest.as_ref().is_none_or(|n| hit.distance < n.distance) {
+                                    nearest = Some(hit);
+                                }
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+            // 这个叶子范围里已经有交点了，后面的节点都更远
+            if nearest.as_ref().is_some_and(|n| n.distance <= t_max) {
+                break;
+            }
+        }
+        stats::count(|c| c.node_visits += visits);
+        nearest
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.bounds
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        fallback_normal()
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        fallback_texture_coords()
+    }
+
+    fn get_material(&self) -> &Material {
+        fallback_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        for item in self.all_items() {
+            item.validate(report);
+        }
+    }
+
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        for item in self.all_items() {
+            item.caustic_bounds(out);
+        }
+    }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        for item in self.all_items() {
+            item.emissive_surfaces(out);
+        }
+    }
+
+    /// 节点本身不存包围盒，从根的盒子开始按切面一路切出来
+    fn collect_bounds(&self, out: &mut Vec<BoundsBox>) {
+        let mut stack: Vec<_> = self.bounds.iter().map(|b| (0, *b, 0)).collect();
+        while let Some((index, bounds, depth)) = stack.pop() {
+            out.push(BoundsBox {
+                bounds,
+                depth: Some(depth),
+            });
+            if let KdNode::Interior { axis, split, above } = self.nodes[index] {
+                let (mut below_bounds, mut above_bounds) = (bounds, bounds);
+                set_axis(&mut below_bounds.max, axis, split);
+                set_axis(&mut above_bounds.min, axis, split);
+                stack.push((index + 1, below_bounds, depth + 1));
+                stack.push((above, above_bounds, depth + 1));
+            }
+        }
+        for item in self.all_items() {
+            item.collect_bounds(out);
+        }
+    }
+}
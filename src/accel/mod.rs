@@ -0,0 +1,83 @@
+mod bvh;
+mod kdtree;
+
+pub use bvh::Bvh;
+pub use kdtree::KdTree;
+
+use crate::color::Color;
+use crate::math::{Aabb, Vector3};
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::material::{Material, TextureCoords};
+use std::sync::OnceLock;
+
+pub type Item = Box<dyn Intersectable + Send + Sync>;
+
+/// 把有包围盒的物体和它的包围盒挑出来建树；平面这种包围盒是None的放不进树里，
+/// 放在第二个列表里，求交时和树并排逐个试
+fn split_unbounded(items: Vec<Item>) -> (Vec<(Item, Aabb)>, Vec<Item>) {
+    let mut bounded = Vec::with_capacity(items.len());
+    let mut unbounded = Vec::new();
+    for item in items {
+        match item.bounds() {
+            Some(bounds) => bounded.push((item, bounds)),
+            None => unbounded.push(item),
+        }
+    }
+    (bounded, unbounded)
+}
+
+/// 逐个求交，返回最近的交点
+fn nearest_hit<'a>(items: &'a [Item], ray: &Ray) -> Option<Intersection<'a>> {
+    items
+        .iter()
+        .filter_map(|item| item.intersect_hit(ray))
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+// 加速结构求交返回的总是里面真正打中的物体，着色时不会拿它本身；
+// 万一直接拿它着色，就当成一个法线朝上、uv是0的灰色漫反射面，不让渲染崩掉。
+// SphereGroup这种自己返回里面物体的组合也用这几个
+pub(crate) fn fallback_normal() -> Vector3 {
+    Vector3::new(0.0, 1.0, 0.0)
+}
+
+pub(crate) fn fallback_texture_coords() -> TextureCoords {
+    TextureCoords { u: 0.0, v: 0.0 }
+}
+
+pub(crate) fn fallback_material() -> &'static Material {
+    static MATERIAL: OnceLock<Material> = OnceLock::new();
+    MATERIAL.get_or_init(|| Material::diffuse(Color::new(0.5, 0.5, 0.5)))
+}
+
+/// 空间加速结构：把一堆物体组织起来，自己也当成一个物体放进场景，
+/// 求交时返回里面真正打中的那个物体。有包围盒的物体建成树，没有的逐个求交；
+/// 有没包围盒的物体时，加速结构自己的bounds也是None
+pub trait Accelerator: Intersectable + Send + Sync {
+    fn build(items: Vec<Item>) -> Self
+    where
+        Self: Sized;
+
+    /// 里面物体的个数
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// 每个场景自己选用哪种加速结构
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AcceleratorKind {
+    Bvh,
+    KdTree,
+}
+
+impl AcceleratorKind {
+    pub fn build(self, items: Vec<Item>) -> Item {
+        match self {
+            AcceleratorKind::Bvh => Box::new(Bvh::build(items)),
+            AcceleratorKind::KdTree => Box::new(KdTree::build(items)),
+        }
+    }
+}
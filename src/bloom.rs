@@ -0,0 +1,134 @@
+use crate::color::Color;
+
+/// 泛光：从画面里取出特别亮的部分，模糊成光晕再加回去，很亮的灯和自发光的球看上去会发光，
+/// 而不是一块边缘生硬的白色。在clamp之前的线性颜色上做
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    /// 亮度超过它的部分才泛光，1就是显示出来已经全白的地方
+    pub threshold: f32,
+    /// 光晕加回去时乘的系数
+    pub intensity: f32,
+    /// 高斯金字塔的层数，每层长宽减半，层数越多光晕铺得越开
+    pub levels: usize,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.3,
+            levels: 6,
+        }
+    }
+}
+
+/// 金字塔的一层，镜头光晕也用它缩小、插值
+pub(crate) struct Level {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Level {
+    /// 超出边界的取最近的边上的像素
+    fn get(&self, x: isize, y: isize) -> Color {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// 5×5的二项式核（1 4 6 4 1）模糊，再隔一个取一个，长宽减半
+    pub fn downsample(&self) -> Level {
+        const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        // 先横着模糊、减半，再竖着
+        let mut rows = Vec::with_capacity(width * self.height);
+        for y in 0..self.height as isize {
+            for x in 0..width as isize {
+                rows.push((0..5).fold(Color::black(), |sum, k| {
+                    sum + self.get(x * 2 + k as isize - 2, y) * KERNEL[k]
+                }));
+            }
+        }
+        let rows = Level {
+            width,
+            height: self.height,
+            pixels: rows,
+        };
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                pixels.push((0..5).fold(Color::black(), |sum, k| {
+                    sum + rows.get(x, y * 2 + k as isize - 2) * KERNEL[k]
+                }));
+            }
+        }
+        Level {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// 双线性插值，(x, y)按这一层的像素算，像素中心在整数加0.5的地方
+    pub fn sample(&self, x: f32, y: f32) -> Color {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// 只留超过阈值的那部分亮度，颜色不变
+pub(crate) fn bright(pixels: &[Color], threshold: f32) -> Vec<Color> {
+    pixels
+        .iter()
+        .map(|&c| {
+            let luminance = c.luminance();
+            if luminance > threshold {
+                c * ((luminance - threshold) / luminance)
+            } else {
+                Color::black()
+            }
+        })
+        .collect()
+}
+
+/// 在width × height、按行排的线性颜色上加泛光
+pub fn apply(settings: &BloomSettings, width: u32, height: u32, pixels: &mut [Color]) {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 || settings.levels == 0 {
+        return;
+    }
+    let mut level = Level {
+        width,
+        height,
+        pixels: bright(pixels, settings.threshold),
+    };
+    // 每一层放大回原来的大小加在一起，小的层给出大范围的光晕
+    let mut glow = vec![Color::black(); width * height];
+    let mut count = 0;
+    while count < settings.levels && (level.width > 1 || level.height > 1) {
+        level = level.downsample();
+        count += 1;
+        let scale = (
+            level.width as f32 / width as f32,
+            level.height as f32 / height as f32,
+        );
+        for (i, g) in glow.iter_mut().enumerate() {
+            let (x, y) = ((i % width) as f32 + 0.5, (i / width) as f32 + 0.5);
+            *g += level.sample(x * scale.0, y * scale.1);
+        }
+    }
+    if count == 0 {
+        return;
+    }
+    let weight = settings.intensity / count as f32;
+    for (pixel, g) in pixels.iter_mut().zip(glow) {
+        *pixel += g * weight;
+    }
+}
@@ -0,0 +1,180 @@
+use crate::bsdf::{orthonormal_basis, Bsdf, BsdfSample};
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Vector3};
+
+/// GGX微表面反射：GGX法线分布，Smith遮挡，Fresnel用Schlick近似。
+/// 采样用的是可见法线分布(VNDF)。切线和副切线方向可以有不同的粗糙度
+pub struct Ggx {
+    pub normal: Vector3,
+    pub tangent: Vector3,
+    pub bitangent: Vector3,
+    /// 切线方向roughness的平方
+    pub alpha_x: Float,
+    /// 副切线方向roughness的平方
+    pub alpha_y: Float,
+    /// 垂直入射时的反射率
+    pub f0: Color,
+}
+
+pub fn fresnel_schlick(f0: Color, cos_theta: Float) -> Color {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0) as f32;
+    let m5 = m * m * m * m * m;
+    f0 + (Color::white() - f0) * m5
+}
+
+fn roughness_to_alpha(roughness: f32) -> Float {
+    let r = (roughness as Float).clamp(0.0, 1.0);
+    (r * r).max(1e-4)
+}
+
+impl Ggx {
+    pub fn new(normal: Vector3, roughness: f32, f0: Color) -> Self {
+        let (tangent, bitangent) = orthonormal_basis(&normal);
+        let alpha = roughness_to_alpha(roughness);
+        Self {
+            normal,
+            tangent,
+            bitangent,
+            alpha_x: alpha,
+            alpha_y: alpha,
+            f0,
+        }
+    }
+
+    /// 各向异性的版本，rotation是切线绕法线转的角度（弧度）
+    pub fn anisotropic(
+        normal: Vector3,
+        tangent: Vector3,
+        roughness: (f32, f32),
+        rotation: f32,
+        f0: Color,
+    ) -> Self {
+        // 先把切线正交化到法线上，退化时随便取一个
+        let projected = tangent - normal * tangent.dot(&normal);
+        let tangent = if projected.norm() > 1e-12 {
+            projected.normalize()
+        } else {
+            orthonormal_basis(&normal).0
+        };
+        let bitangent = normal.cross(&tangent);
+        let (sin, cos) = (rotation as Float).sin_cos();
+        let rotated = tangent * cos + bitangent * sin;
+        Self {
+            normal,
+            tangent: rotated,
+            bitangent: normal.cross(&rotated),
+            alpha_x: roughness_to_alpha(roughness.0),
+            alpha_y: roughness_to_alpha(roughness.1),
+            f0,
+        }
+    }
+
+    fn to_local(&self, v: &Vector3) -> Vector3 {
+        Vector3::new(
+            v.dot(&self.tangent),
+            v.dot(&self.bitangent),
+            v.dot(&self.normal),
+        )
+    }
+
+    fn to_world(&self, v: &Vector3) -> Vector3 {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+
+    /// 法线分布 D(m)
+    fn d(&self, m: &Vector3) -> Float {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let x = m.x / self.alpha_x;
+        let y = m.y / self.alpha_y;
+        let denom = x * x + y * y + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * denom * denom)
+    }
+
+    /// Smith的Λ(ω)
+    fn lambda(&self, w: &Vector3) -> Float {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return Float::INFINITY;
+        }
+        let ax = self.alpha_x * w.x;
+        let ay = self.alpha_y * w.y;
+        ((1.0 + (ax * ax + ay * ay) / cos2).sqrt() - 1.0) * 0.5
+    }
+
+    fn g1(&self, w: &Vector3) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    fn g2(&self, wo: &Vector3, wi: &Vector3) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Heitz 2018，在可见法线分布里采样一个微表面法线
+    fn sample_visible_normal(&self, wo: &Vector3, u: (Float, Float)) -> Vector3 {
+        let vh = Vector3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vector3::new(-vh.y, vh.x, 0.0) * len2.sqrt().recip()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+        let r = u.0.sqrt();
+        let phi = 2.0 * PI * u.1;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        Vector3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+impl Bsdf for Ggx {
+    fn eval(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let wo = self.to_local(wo);
+        let wi = self.to_local(wi);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::black();
+        }
+        let m = (wo + wi).normalize();
+        let f = fresnel_schlick(self.f0, wi.dot(&m));
+        // f * cosθi = D G F / (4 cosθo)
+        f * (self.d(&m) * self.g2(&wo, &wi) / (4.0 * wo.z)) as f32
+    }
+
+    fn diffuse_part(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::black()
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> Float {
+        let wo = self.to_local(wo);
+        let wi = self.to_local(wi);
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let m = (wo + wi).normalize();
+        self.g1(&wo) * self.d(&m) / (4.0 * wo.z)
+    }
+
+    fn sample(&self, wo: &Vector3, u: (Float, Float)) -> Option<BsdfSample> {
+        let wo_local = self.to_local(wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        let m = self.sample_visible_normal(&wo_local, u);
+        let wi_local = m * (2.0 * wo_local.dot(&m)) - wo_local;
+        if wi_local.z <= 0.0 {
+            return None;
+        }
+        let f = fresnel_schlick(self.f0, wi_local.dot(&m));
+        Some(BsdfSample {
+            direction: self.to_world(&wi_local).normalize(),
+            pdf: self.g1(&wo_local) * self.d(&m) / (4.0 * wo_local.z),
+            // f * cos / pdf 化简之后只剩 F * G2 / G1
+            weight: f * (self.g2(&wo_local, &wi_local) / self.g1(&wo_local)) as f32,
+        })
+    }
+}
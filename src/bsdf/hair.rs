@@ -0,0 +1,234 @@
+use crate::bsdf::{orthonormal_basis, Bsdf, BsdfSample};
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Vector3};
+
+/// 头发表皮的折射率
+const ETA: Float = 1.55;
+
+/// 截在[a, b]上的logistic分布，s是它的尺度
+struct TrimmedLogistic {
+    s: Float,
+    a: Float,
+    b: Float,
+}
+
+impl TrimmedLogistic {
+    fn cdf(&self, x: Float) -> Float {
+        1.0 / (1.0 + (-x / self.s).exp())
+    }
+
+    fn pdf(&self, x: Float) -> Float {
+        if x < self.a || x > self.b {
+            return 0.0;
+        }
+        let e = (-x.abs() / self.s).exp();
+        e / (self.s * (1.0 + e) * (1.0 + e)) / (self.cdf(self.b) - self.cdf(self.a))
+    }
+
+    fn sample(&self, u: Float) -> Float {
+        let (low, high) = (self.cdf(self.a), self.cdf(self.b));
+        let c = (low + u * (high - low)).clamp(Float::EPSILON, 1.0 - Float::EPSILON);
+        (-self.s * (1.0 / c - 1.0).ln()).clamp(self.a, self.b)
+    }
+}
+
+/// 角度差换到[-π, π]
+fn wrap(phi: Float) -> Float {
+    (phi + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// 散射的三条路径：表面直接反射R，穿过纤维TT，进去在里面反射一次再出来TRT
+#[derive(Clone, Copy, PartialEq)]
+enum Lobe {
+    R,
+    Tt,
+    Trt,
+}
+
+const LOBES: [Lobe; 3] = [Lobe::R, Lobe::Tt, Lobe::Trt];
+
+/// 简化的Marschner头发模型。方向用相对纤维的角度表示：θ是和纤维横截面的夹角，
+/// φ是绕纤维转的角度。每条路径的散射是沿θ的分布（高光锥，鳞片的倾角让它偏开一点）
+/// 乘上沿φ的分布，再乘上这条路径剩下的能量；eval已经含了cosθ
+pub struct HairBsdf {
+    tangent: Vector3,
+    normal: Vector3,
+    bitangent: Vector3,
+    /// 光穿过纤维一次剩下的比例
+    color: Color,
+    /// 沿θ的宽度（logistic的尺度），TT窄一半，TRT宽一倍
+    longitudinal: Float,
+    /// TT绕纤维散开的宽度
+    azimuthal: Float,
+    /// 鳞片倾角，弧度
+    tilt: Float,
+}
+
+impl HairBsdf {
+    /// tangent沿着纤维，normal只用来定φ从哪里算起
+    pub fn new(
+        normal: Vector3,
+        tangent: Vector3,
+        color: Color,
+        roughness: f32,
+        azimuthal_roughness: f32,
+        tilt: f32,
+    ) -> Self {
+        let tangent = tangent.normalize();
+        let n = normal - tangent * tangent.dot(&normal);
+        let normal = if n.length() > 1e-6 {
+            n.normalize()
+        } else {
+            orthonormal_basis(&tangent).0
+        };
+        // 标准差换成logistic的尺度：σ = sπ/√3
+        let scale = |width: Float| width.max(1e-3) * (3.0 as Float).sqrt() / PI;
+        Self {
+            bitangent: tangent.cross(&normal),
+            tangent,
+            normal,
+            color,
+            longitudinal: scale(roughness as Float * PI / 2.0),
+            azimuthal: scale(azimuthal_roughness as Float * PI),
+            tilt: tilt as Float,
+        }
+    }
+
+    fn angles(&self, w: &Vector3) -> (Float, Float) {
+        let theta = w.dot(&self.tangent).clamp(-1.0, 1.0).asin();
+        let phi = w.dot(&self.bitangent).atan2(w.dot(&self.normal));
+        (theta, phi)
+    }
+
+    fn direction(&self, theta: Float, phi: Float) -> Vector3 {
+        self.tangent * theta.sin()
+            + (self.normal * phi.cos() + self.bitangent * phi.sin()) * theta.cos()
+    }
+
+    /// 每条路径剩下的能量，按wo算菲涅尔
+    fn attenuation(&self, lobe: Lobe, theta_o: Float) -> Color {
+        let f0 = ((ETA - 1.0) / (ETA + 1.0)).powi(2);
+        let f = (f0 + (1.0 - f0) * (1.0 - theta_o.cos()).powi(5)) as f32;
+        match lobe {
+            Lobe::R => Color::white() * f,
+            Lobe::Tt => self.color * ((1.0 - f) * (1.0 - f)),
+            Lobe::Trt => self.color * self.color * ((1.0 - f) * (1.0 - f) * f),
+        }
+    }
+
+    /// 沿θ的分布：以镜面方向-θo为中心，鳞片让各条路径偏开不同的角度
+    fn longitudinal(&self, lobe: Lobe, theta_o: Float) -> (TrimmedLogistic, Float) {
+        let (shift, width) = match lobe {
+            Lobe::R => (-2.0 * self.tilt, self.longitudinal),
+            Lobe::Tt => (self.tilt, self.longitudinal * 0.5),
+            Lobe::Trt => (4.0 * self.tilt, self.longitudinal * 2.0),
+        };
+        let center = -theta_o + shift;
+        let distribution = TrimmedLogistic {
+            s: width,
+            a: -PI / 2.0 - center,
+            b: PI / 2.0 - center,
+        };
+        (distribution, center)
+    }
+
+    /// 沿φ的分布（φ是wi和wo绕纤维的角度差）的密度
+    fn azimuthal_pdf(&self, lobe: Lobe, phi: Float) -> Float {
+        let phi = wrap(phi);
+        match lobe {
+            Lobe::R => (phi * 0.5).cos() * 0.25,
+            Lobe::Tt => self.tt_azimuth().pdf(wrap(phi - PI)),
+            Lobe::Trt => 1.0 / (2.0 * PI),
+        }
+    }
+
+    fn tt_azimuth(&self) -> TrimmedLogistic {
+        TrimmedLogistic {
+            s: self.azimuthal,
+            a: -PI,
+            b: PI,
+        }
+    }
+
+    /// 采样时选各条路径的概率，按能量的亮度分
+    fn lobe_weights(&self, theta_o: Float) -> [Float; 3] {
+        let mut weights = LOBES.map(|lobe| self.attenuation(lobe, theta_o).luminance() as Float);
+        let total: Float = weights.iter().sum();
+        for w in weights.iter_mut() {
+            *w = if total > 0.0 { *w / total } else { 1.0 / 3.0 };
+        }
+        weights
+    }
+
+    /// 沿θ和沿φ的密度的乘积，不含cosθ
+    fn lobe_density(&self, lobe: Lobe, wo: (Float, Float), wi: (Float, Float)) -> Float {
+        let (distribution, center) = self.longitudinal(lobe, wo.0);
+        distribution.pdf(wi.0 - center) * self.azimuthal_pdf(lobe, wi.1 - wo.1)
+    }
+}
+
+impl Bsdf for HairBsdf {
+    fn eval(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let (o, i) = (self.angles(wo), self.angles(wi));
+        LOBES
+            .iter()
+            .map(|&lobe| self.attenuation(lobe, o.0) * self.lobe_density(lobe, o, i) as f32)
+            .sum()
+    }
+
+    /// 头发没有漫反射，多次散射出来的柔和的颜色靠路径追踪自己算
+    fn diffuse_part(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::black()
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> Float {
+        let (o, i) = (self.angles(wo), self.angles(wi));
+        let cos = i.0.cos();
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        let weights = self.lobe_weights(o.0);
+        // 立体角上dω = cosθ dθ dφ
+        LOBES
+            .iter()
+            .zip(weights)
+            .map(|(&lobe, w)| w * self.lobe_density(lobe, o, i))
+            .sum::<Float>()
+            / cos
+    }
+
+    fn sample(&self, wo: &Vector3, u: (Float, Float)) -> Option<BsdfSample> {
+        let o = self.angles(wo);
+        let weights = self.lobe_weights(o.0);
+        // 用u.0选路径，再把它拉伸回[0, 1)用来采θ
+        let mut u0 = u.0;
+        let mut lobe = Lobe::Trt;
+        for (&candidate, w) in LOBES.iter().zip(weights) {
+            if u0 < w {
+                lobe = candidate;
+                u0 /= w;
+                break;
+            }
+            u0 -= w;
+        }
+        let u0 = u0.clamp(0.0, 1.0);
+        let (distribution, center) = self.longitudinal(lobe, o.0);
+        let theta = center + distribution.sample(u0);
+        let dphi = match lobe {
+            Lobe::R => 2.0 * (2.0 * u.1 - 1.0).clamp(-1.0, 1.0).asin(),
+            Lobe::Tt => PI + self.tt_azimuth().sample(u.1),
+            Lobe::Trt => (2.0 * u.1 - 1.0) * PI,
+        };
+        let direction = self.direction(theta, o.1 + dphi);
+        let pdf = self.pdf(wo, &direction);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            pdf,
+            weight: self.eval(wo, &direction) / pdf as f32,
+        })
+    }
+}
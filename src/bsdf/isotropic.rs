@@ -0,0 +1,29 @@
+use crate::bsdf::{uniform_sample_sphere, Bsdf, BsdfSample};
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Vector3};
+
+/// 介质里各向同性的相位函数，当成一个没有cos项的BSDF来用
+pub struct IsotropicPhase;
+
+impl Bsdf for IsotropicPhase {
+    fn eval(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::white() * (1.0 / (4.0 * PI)) as f32
+    }
+
+    fn diffuse_part(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::white()
+    }
+
+    fn pdf(&self, _wo: &Vector3, _wi: &Vector3) -> Float {
+        1.0 / (4.0 * PI)
+    }
+
+    fn sample(&self, _wo: &Vector3, u: (Float, Float)) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            direction: uniform_sample_sphere(u),
+            pdf: 1.0 / (4.0 * PI),
+            weight: Color::white(),
+        })
+    }
+}
@@ -0,0 +1,44 @@
+use crate::bsdf::{cosine_sample_hemisphere, orthonormal_basis, Bsdf, BsdfSample};
+use crate::color::Color;
+use crate::math::{consts::PI, Float, Vector3};
+
+pub struct Lambertian {
+    pub normal: Vector3,
+    /// 反照率，已经乘上了表面颜色
+    pub albedo: Color,
+}
+
+impl Bsdf for Lambertian {
+    fn eval(&self, _wo: &Vector3, wi: &Vector3) -> Color {
+        let cos = self.normal.dot(wi);
+        if cos <= 0.0 {
+            Color::black()
+        } else {
+            self.albedo * (cos as f32 / std::f32::consts::PI)
+        }
+    }
+
+    fn diffuse_part(&self, _wo: &Vector3, _wi: &Vector3) -> Color {
+        Color::white()
+    }
+
+    fn pdf(&self, _wo: &Vector3, wi: &Vector3) -> Float {
+        self.normal.dot(wi).max(0.0) / PI
+    }
+
+    fn sample(&self, _wo: &Vector3, u: (Float, Float)) -> Option<BsdfSample> {
+        let local = cosine_sample_hemisphere(u);
+        if local.z <= 0.0 {
+            return None;
+        }
+        let (tangent, bitangent) = orthonormal_basis(&self.normal);
+        let direction =
+            (tangent * local.x + bitangent * local.y + self.normal * local.z).normalize();
+        Some(BsdfSample {
+            direction,
+            pdf: local.z / PI,
+            // f * cos / pdf 对于漫反射正好就是反照率
+            weight: self.albedo,
+        })
+    }
+}
@@ -0,0 +1,70 @@
+mod ggx;
+mod hair;
+mod isotropic;
+mod lambertian;
+mod principled;
+
+pub use ggx::{fresnel_schlick, Ggx};
+pub use hair::HairBsdf;
+pub use isotropic::IsotropicPhase;
+pub use lambertian::Lambertian;
+pub use principled::PrincipledBsdf;
+
+use crate::color::Color;
+use crate::math::{consts::PI, Float, Vector3};
+
+pub struct BsdfSample {
+    pub direction: Vector3,
+    /// 立体角上的pdf
+    pub pdf: Float,
+    /// f * cosθ / pdf，也就是这条采样光线对颜色的权重
+    pub weight: Color,
+}
+
+/// 约定wo和wi都是从着色点指出去的单位向量
+pub trait Bsdf {
+    /// 返回 f(wo, wi) * cosθi
+    fn eval(&self, wo: &Vector3, wi: &Vector3) -> Color;
+    /// f(wo, wi)里漫反射那部分占的比例（每个通道分开），分间接漫反射和间接高光用
+    fn diffuse_part(&self, wo: &Vector3, wi: &Vector3) -> Color;
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> Float;
+    fn sample(&self, wo: &Vector3, u: (Float, Float)) -> Option<BsdfSample>;
+}
+
+/// 以normal为z轴建一个正交基，返回(切线, 副切线)
+pub fn orthonormal_basis(normal: &Vector3) -> (Vector3, Vector3) {
+    let helper = if normal.x.abs() > 0.9 {
+        Vector3::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3::new(1.0, 0.0, 0.0)
+    };
+    let tangent = helper.cross(normal).normalize();
+    let bitangent = normal.cross(&tangent);
+    (tangent, bitangent)
+}
+
+/// 余弦加权的半球采样，返回局部坐标系（z朝上）里的方向
+pub fn cosine_sample_hemisphere(u: (Float, Float)) -> Vector3 {
+    let r = u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vector3::new(r * phi.cos(), r * phi.sin(), (1.0 - u.0).max(0.0).sqrt())
+}
+
+/// 在整个球面上均匀采样一个方向
+pub fn uniform_sample_sphere(u: (Float, Float)) -> Vector3 {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u.1;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// MIS的power heuristic（β = 2）
+pub fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
@@ -0,0 +1,89 @@
+use crate::bsdf::{Bsdf, BsdfSample, Ggx, Lambertian};
+use crate::color::Color;
+use crate::math::{Float, Vector3};
+
+/// 不透明部分的principled BSDF：漫反射和GGX高光按比例叠起来
+pub struct PrincipledBsdf {
+    pub diffuse: Lambertian,
+    pub specular: Ggx,
+    /// 采样时选高光lobe的概率
+    pub specular_probability: Float,
+}
+
+impl PrincipledBsdf {
+    pub fn new(
+        normal: Vector3,
+        base_color: Color,
+        metallic: f32,
+        roughness: f32,
+        specular: f32,
+    ) -> Self {
+        let metallic = metallic.clamp(0.0, 1.0);
+        // 非金属的F0是0.08 * specular，specular = 0.5时就是常见的0.04
+        let dielectric_f0 = Color::white() * (0.08 * specular.max(0.0));
+        let f0 = dielectric_f0 * (1.0 - metallic) + base_color * metallic;
+        let diffuse_albedo = base_color * (1.0 - metallic);
+        let specular_weight = f0.luminance() as Float;
+        let diffuse_weight = diffuse_albedo.luminance() as Float;
+        let specular_probability = if specular_weight + diffuse_weight > 0.0 {
+            (specular_weight / (specular_weight + diffuse_weight)).clamp(0.1, 1.0)
+        } else {
+            1.0
+        };
+        Self {
+            diffuse: Lambertian {
+                normal,
+                albedo: diffuse_albedo,
+            },
+            specular: Ggx::new(normal, roughness, f0),
+            specular_probability,
+        }
+    }
+}
+
+impl Bsdf for PrincipledBsdf {
+    fn eval(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        self.diffuse.eval(wo, wi) + self.specular.eval(wo, wi)
+    }
+
+    fn diffuse_part(&self, wo: &Vector3, wi: &Vector3) -> Color {
+        let diffuse = self.diffuse.eval(wo, wi);
+        let total = diffuse + self.specular.eval(wo, wi);
+        let part = |d: f32, t: f32| if t > 0.0 { d / t } else { 0.0 };
+        Color {
+            r: part(diffuse.r, total.r),
+            g: part(diffuse.g, total.g),
+            b: part(diffuse.b, total.b),
+        }
+    }
+
+    fn pdf(&self, wo: &Vector3, wi: &Vector3) -> Float {
+        self.specular_probability * self.specular.pdf(wo, wi)
+            + (1.0 - self.specular_probability) * self.diffuse.pdf(wo, wi)
+    }
+
+    fn sample(&self, wo: &Vector3, u: (Float, Float)) -> Option<BsdfSample> {
+        // 用u.0选lobe，再把它拉伸回[0, 1)给lobe自己用
+        let sample = if u.0 < self.specular_probability {
+            self.specular
+                .sample(wo, (u.0 / self.specular_probability, u.1))
+        } else {
+            self.diffuse.sample(
+                wo,
+                (
+                    (u.0 - self.specular_probability) / (1.0 - self.specular_probability),
+                    u.1,
+                ),
+            )
+        }?;
+        let pdf = self.pdf(wo, &sample.direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: sample.direction,
+            pdf,
+            weight: self.eval(wo, &sample.direction) / pdf as f32,
+        })
+    }
+}
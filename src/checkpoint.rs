@@ -0,0 +1,307 @@
+use crate::color::Color;
+use crate::filter::FilterSampler;
+use crate::hdr::HdrImage;
+use crate::integrator::Splats;
+use crate::rendering::{average, finish_hdr, grader, post_process, sample_pixel, Crop};
+use crate::scene::{light::LightSampler, Scene};
+#[cfg(feature = "fs")]
+use crate::{Error, Result};
+use image::{DynamicImage, ImageBuffer, Rgba};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(feature = "fs")]
+use std::{fs, path::Path};
+
+#[cfg(feature = "fs")]
+const MAGIC: &[u8; 8] = b"NRTCKPT4";
+#[cfg(feature = "fs")]
+const PIXEL_BYTES: usize = 36;
+
+/// 一个像素所有样本按滤波器权重累加起来的颜色、颜色的平方和alpha，以及权重的和。
+/// Box滤波器的权重都是1，weight就是样本数
+#[derive(Debug, Clone, Copy, Default)]
+struct Sum {
+    color: Color,
+    square: Color,
+    alpha: f32,
+    weight: f32,
+}
+
+/// 累积缓冲：每个像素所有样本的和以及样本数，可以存到文件里以后接着渲染
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Sum>,
+    samples: Vec<u32>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        let n = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            sums: vec![Sum::default(); n],
+            samples: vec![0; n],
+        }
+    }
+
+    /// 所有像素里最少的样本数
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    /// 每个像素已经累积的样本数，按行排
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.samples
+    }
+
+    /// 样本数的灰度图，最多的像素是白色，没有样本的是黑色，看样本花在了哪里
+    pub fn sample_count_image(&self) -> DynamicImage {
+        let max = self.samples.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let image = ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let count = self.samples[(x + y * self.width) as usize] as f32;
+            let v = (count / max * 255.0).round() as u8;
+            Rgba([v, v, v, 255])
+        });
+        DynamicImage::ImageRgba8(image)
+    }
+
+    /// 每个像素再加samples个样本。lights是LightSampler::for_scene(scene)建的，
+    /// 分好几批加样本时共用一个，焦散光子图和辐照度缓存不用每批重建
+    pub fn add_samples(&mut self, scene: &Scene, lights: &LightSampler, samples: u32) {
+        let filter = FilterSampler::new(scene.settings.filter);
+        let splats = Splats::new(scene, &Crop::full(scene), samples);
+        let width = self.width;
+        #[cfg(feature = "parallel")]
+        let pixels = self.sums.par_iter_mut().zip(self.samples.par_iter_mut());
+        #[cfg(not(feature = "parallel"))]
+        let pixels = self.sums.iter_mut().zip(self.samples.iter_mut());
+        // 这一批每个像素的权重和，光线追踪的贡献按它加进去
+        let weights: Vec<f32> = pixels
+            .enumerate()
+            .map(|(i, (sum, count))| {
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                let mut weight = 0.0;
+                // 接着已有的样本号往下编，续渲染的结果和一次渲染完一样
+                for sample in *count..*count + samples {
+                    let s = sample_pixel(scene, lights, &filter, &splats, x, y, sample);
+                    sum.color += s.color * s.weight;
+                    sum.square += s.color * s.color * s.weight;
+                    sum.alpha += s.alpha * s.weight;
+                    weight += s.weight;
+                }
+                sum.weight += weight;
+                *count += samples;
+                weight
+            })
+            .collect();
+        for (i, (sum, weight)) in self.sums.iter_mut().zip(weights).enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            sum.color += splats.get(x, y) * weight;
+        }
+    }
+
+    /// 每个像素当前还没clamp的平均颜色和alpha，还没有样本的像素是不透明的黑色
+    fn averages(&self) -> (Vec<Color>, Vec<f32>) {
+        self.sums
+            .iter()
+            .zip(&self.samples)
+            .map(|(sum, &count)| match count {
+                0 => (Color::black(), 1.0),
+                _ => average(sum.color, sum.alpha, sum.weight),
+            })
+            .unzip()
+    }
+
+    /// 当前的平均值，和render一样加后期、调色、clamp到[0, 1]，按scene的输出色彩空间编码
+    pub fn image(&self, scene: &Scene) -> DynamicImage {
+        let (mut colors, alphas) = self.averages();
+        post_process(scene, self.width, self.height, &mut colors);
+        let grade = grader(scene);
+        let output_space = scene.settings.output_space;
+        let image = ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let i = (x + y * self.width) as usize;
+            Rgba::from(grade(colors[i]).to_rgba8_with_alpha(alphas[i], output_space))
+        });
+        DynamicImage::ImageRgba8(image)
+    }
+
+    /// 当前的平均值，不clamp，大于1的都留着，存成.hdr或.pfm用。
+    /// 镜头光晕、泛光、色差和白平衡都是线性的，照样加上；lift、gamma、gain是给显示用的，不做
+    pub fn hdr_image(&self, scene: &Scene) -> HdrImage {
+        let (mut colors, _) = self.averages();
+        finish_hdr(scene, self.width, self.height, &mut colors);
+        HdrImage::new(self.width, self.height, colors)
+    }
+
+    /// 每个像素平均值的标准误差，每个通道分开估计：样本的方差除以样本数再开方。
+    /// 光线追踪贴上去的splat不算在里面；样本少于两个的像素是0
+    pub fn standard_error(&self) -> HdrImage {
+        let pixels = self
+            .sums
+            .iter()
+            .zip(&self.samples)
+            .map(|(sum, &count)| {
+                if count < 2 || sum.weight <= 0.0 {
+                    return Color::black();
+                }
+                let mean = sum.color / sum.weight;
+                let square = sum.square / sum.weight;
+                let error = |m: f32, s: f32| ((s - m * m).max(0.0) / (count - 1) as f32).sqrt();
+                Color {
+                    r: error(mean.r, square.r),
+                    g: error(mean.g, square.g),
+                    b: error(mean.b, square.b),
+                }
+            })
+            .collect();
+        HdrImage::new(self.width, self.height, pixels)
+    }
+
+    /// 和image一样，但每个通道16位，存成PNG时天空和软阴影的渐变不会有色带
+    pub fn image16(&self, scene: &Scene) -> DynamicImage {
+        let (mut colors, alphas) = self.averages();
+        post_process(scene, self.width, self.height, &mut colors);
+        let grade = grader(scene);
+        let output_space = scene.settings.output_space;
+        let image = ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let i = (x + y * self.width) as usize;
+            Rgba::from(grade(colors[i]).to_rgba16_with_alpha(alphas[i], output_space))
+        });
+        DynamicImage::ImageRgba16(image)
+    }
+
+    /// 文件格式：魔数、宽、高，然后每个像素r, g, b, r², g², b², alpha, 权重的和（f32）和样本数（u32），
+    /// 都是小端。
+    /// 先写到临时文件再改名，写到一半崩溃也不会把上一个检查点弄坏
+    #[cfg(feature = "fs")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut bytes = Vec::with_capacity(16 + self.sums.len() * PIXEL_BYTES);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        for (sum, count) in self.sums.iter().zip(&self.samples) {
+            let Sum {
+                color,
+                square,
+                alpha,
+                weight,
+            } = sum;
+            let values = [
+                color.r, color.g, color.b, square.r, square.g, square.b, *alpha, *weight,
+            ];
+            for v in values {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        // 在原来的文件名后面加上.tmp，检查点自己叫*.tmp时也不会和它重名
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, bytes)?;
+        fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        if !bytes.starts_with(MAGIC) || bytes.len() < 16 {
+            return Err(Error::parse("not a render checkpoint"));
+        }
+        let word = |i: usize| [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
+        let width = u32::from_le_bytes(word(8));
+        let height = u32::from_le_bytes(word(12));
+        // 宽高是文件里读出来的，先和文件大小对上再分配，坏文件不会让我们分配一大块内存
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|n| n.checked_mul(PIXEL_BYTES))
+            .and_then(|n| n.checked_add(16));
+        if expected != Some(bytes.len()) {
+            return Err(Error::parse("render checkpoint has the wrong size"));
+        }
+        let mut accumulator = Self::new(width, height);
+        for (i, chunk) in bytes[16..].chunks_exact(PIXEL_BYTES).enumerate() {
+            let float =
+                |j: usize| f32::from_le_bytes([chunk[j], chunk[j + 1], chunk[j + 2], chunk[j + 3]]);
+            accumulator.sums[i] = Sum {
+                color: Color {
+                    r: float(0),
+                    g: float(4),
+                    b: float(8),
+                },
+                square: Color {
+                    r: float(12),
+                    g: float(16),
+                    b: float(20),
+                },
+                alpha: float(24),
+                weight: float(28),
+            };
+            accumulator.samples[i] =
+                u32::from_le_bytes([chunk[32], chunk[33], chunk[34], chunk[35]]);
+        }
+        Ok(accumulator)
+    }
+}
+
+/// 不定样本数，在budget这么长的时间里一批一批地渲染，时间到了就返回已经累积的结果。
+/// 每批的样本数像render_with_preview一样翻倍，但按上一批每个样本花的时间估计，
+/// 剩下的时间不够就缩小这一批，连一个样本都不够就停下；至少渲染一批，所以会稍微超时。
+/// wasm32上没有时钟，Instant::now会panic，那里没有这个函数
+#[cfg(not(target_arch = "wasm32"))]
+pub fn render_for(scene: &Scene, budget: Duration) -> Accumulator {
+    let start = Instant::now();
+    let lights = LightSampler::for_scene(scene);
+    let mut accumulator = Accumulator::new(scene.width, scene.height);
+    let mut per_sample = Duration::default();
+    loop {
+        let done = accumulator.min_samples();
+        let remaining = budget.saturating_sub(start.elapsed());
+        let mut pass = done.max(1);
+        if done > 0 {
+            let affordable = remaining.as_secs_f64() / per_sample.as_secs_f64().max(1e-9);
+            if affordable < 1.0 {
+                break;
+            }
+            pass = pass.min(affordable as u32);
+        }
+        let pass_start = Instant::now();
+        accumulator.add_samples(scene, &lights, pass);
+        per_sample = pass_start.elapsed() / pass;
+    }
+    accumulator
+}
+
+/// 分批渲染到每个像素total_samples个样本，每批samples_per_pass个，每批之后存一次检查点。
+/// checkpoint已经存在并且大小和场景一样时从它接着渲染，所以崩溃以后重新调用就能继续
+#[cfg(feature = "fs")]
+pub fn render_with_checkpoints<P: AsRef<Path>>(
+    scene: &Scene,
+    total_samples: u32,
+    samples_per_pass: u32,
+    checkpoint: P,
+) -> Result<DynamicImage> {
+    let checkpoint = checkpoint.as_ref();
+    let mut accumulator = match Accumulator::load(checkpoint) {
+        Ok(a) if a.width == scene.width && a.height == scene.height => a,
+        Ok(_) => return Err(Error::parse("checkpoint size does not match the scene")),
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            Accumulator::new(scene.width, scene.height)
+        }
+        Err(e) => return Err(e),
+    };
+    let lights = LightSampler::for_scene(scene);
+    while accumulator.min_samples() < total_samples {
+        let pass = samples_per_pass
+            .max(1)
+            .min(total_samples - accumulator.min_samples());
+        accumulator.add_samples(scene, &lights, pass);
+        accumulator.save(checkpoint)?;
+    }
+    Ok(accumulator.image(scene))
+}
@@ -1,4 +1,4 @@
-use std::ops::{Add, AddAssign, Div, Mul};
+use std::ops::{Add, AddAssign, Div, Mul, Sub};
 pub const GAMMA: f32 = 2.2;
 
 fn gamma_encode(linear: f32) -> f32 {
@@ -9,6 +9,156 @@ fn gamma_decode(encoded: f32) -> f32 {
     encoded.powf(GAMMA)
 }
 
+/// 输出图片的色彩空间：原色和传递函数，存PNG、显示预览时按它编码。
+/// 渲染本身都在线性的sRGB原色下做
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputSpace {
+    /// sRGB原色，简单的2.2 gamma，原来一直是这样
+    #[default]
+    Gamma22,
+    /// sRGB原色，标准的分段传递函数
+    Srgb,
+    /// 和sRGB原色相同，BT.709的传递函数，视频用
+    Rec709,
+    /// 不编码，线性的sRGB原色，给后面还要合成的流程用
+    Linear,
+    /// Display P3：P3原色、D65白点，传递函数和sRGB一样，广色域的显示器用
+    DisplayP3,
+}
+
+impl OutputSpace {
+    /// 线性sRGB原色的颜色（[0, 1]之内）换成这个色彩空间编码后的值
+    pub fn encode(self, color: Color) -> Color {
+        let color = match self {
+            Self::DisplayP3 => Color {
+                r: 0.822_5 * color.r + 0.177_5 * color.g,
+                g: 0.033_2 * color.r + 0.966_8 * color.g,
+                b: 0.017_1 * color.r + 0.072_4 * color.g + 0.910_5 * color.b,
+            }
+            .clamp(),
+            _ => color,
+        };
+        let transfer = |v: f32| match self {
+            Self::Gamma22 => gamma_encode(v),
+            Self::Srgb | Self::DisplayP3 if v <= 0.003_130_8 => 12.92 * v,
+            Self::Srgb | Self::DisplayP3 => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+            Self::Rec709 if v < 0.018 => 4.5 * v,
+            Self::Rec709 => 1.099 * v.powf(0.45) - 0.099,
+            Self::Linear => v,
+        };
+        Color {
+            r: transfer(color.r),
+            g: transfer(color.g),
+            b: transfer(color.b),
+        }
+    }
+}
+
+pub const MIN_WAVELENGTH: f32 = 380.0;
+pub const MAX_WAVELENGTH: f32 = 780.0;
+
+/// CIE 1931色匹配函数的多瓣高斯拟合（Wyman 2013），返回XYZ
+fn wavelength_to_xyz(wavelength: f32) -> (f32, f32, f32) {
+    let g = |mu: f32, sigma1: f32, sigma2: f32| {
+        let t = (wavelength - mu) / if wavelength < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+/// 热度图的颜色，t从0到1依次是黑、蓝、绿、黄、红，超出范围的截到两头
+pub fn heatmap(t: f32) -> Color {
+    const STOPS: [(f32, f32, f32); 5] = [
+        (0.0, 0.0, 0.0),
+        (0.0, 0.0, 1.0),
+        (0.0, 1.0, 0.0),
+        (1.0, 1.0, 0.0),
+        (1.0, 0.0, 0.0),
+    ];
+    let t = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (t as usize).min(STOPS.len() - 2);
+    let f = t - i as f32;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    Color::new(
+        a.0 + (b.0 - a.0) * f,
+        a.1 + (b.1 - a.1) * f,
+        a.2 + (b.2 - a.2) * f,
+    )
+}
+
+/// 物体ID通道里编号id的颜色：打散过的，相邻的编号颜色也差得很远，每个通道不低于0.2
+pub fn id_color(id: u32) -> Color {
+    let mut z = (id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z ^= z >> 31;
+    let channel = |shift: u32| 0.2 + 0.8 * ((z >> shift) & 0xff) as f32 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+/// 单一波长的光在线性sRGB下的颜色，负的分量截掉
+pub fn wavelength_to_rgb(wavelength: f32) -> Color {
+    let (x, y, z) = wavelength_to_xyz(wavelength);
+    Color {
+        r: (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        g: (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        b: (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    }
+}
+
+/// 在[MIN_WAVELENGTH, MAX_WAVELENGTH]里均匀采样波长时每个样本的权重，
+/// 归一化成所有波长平均下来是白色
+pub fn spectral_weight(wavelength: f32) -> Color {
+    static MEAN: std::sync::OnceLock<Color> = std::sync::OnceLock::new();
+    let mean = MEAN.get_or_init(|| {
+        let steps = 400;
+        (0..steps)
+            .map(|i| {
+                let t = (i as f32 + 0.5) / steps as f32;
+                wavelength_to_rgb(MIN_WAVELENGTH + t * (MAX_WAVELENGTH - MIN_WAVELENGTH))
+            })
+            .sum::<Color>()
+            / steps as f32
+    });
+    let rgb = wavelength_to_rgb(wavelength);
+    Color {
+        r: rgb.r / mean.r,
+        g: rgb.g / mean.g,
+        b: rgb.b / mean.b,
+    }
+}
+
+/// 黑体辐射的颜色（线性空间，最大分量归一到1），用Tanner Helland的拟合
+pub fn blackbody(kelvin: f32) -> Color {
+    let t = (kelvin / 100.0).clamp(10.0, 400.0);
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_846)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+    Color::from_rgba8([
+        r.clamp(0.0, 255.0) as u8,
+        g.clamp(0.0, 255.0) as u8,
+        b.clamp(0.0, 255.0) as u8,
+        255,
+    ])
+}
+
 #[derive(Debug, PartialEq, Default, Clone, Copy)]
 pub struct Color {
     pub r: f32,
@@ -17,15 +167,50 @@ pub struct Color {
 }
 
 impl Color {
+    pub fn new(r: f32, g: f32, b: f32) -> Self {
+        Color { r, g, b }
+    }
+
     pub fn to_rgba8(self) -> [u8; 4] {
+        self.to_rgba8_in(OutputSpace::default())
+    }
+
+    /// 按space编码成8位
+    pub fn to_rgba8_in(self, space: OutputSpace) -> [u8; 4] {
+        let encoded = space.encode(self);
         [
-            (gamma_encode(self.r) * 255f32) as u8,
-            (gamma_encode(self.g) * 255f32) as u8,
-            (gamma_encode(self.b) * 255f32) as u8,
+            (encoded.r * 255f32) as u8,
+            (encoded.g * 255f32) as u8,
+            (encoded.b * 255f32) as u8,
             255u8,
         ]
     }
 
+    /// self是乘过alpha的颜色（没打中的样本算黑的一起平均出来的），
+    /// 除回去再按space编码，PNG存的是没乘过的
+    pub fn to_rgba8_with_alpha(self, alpha: f32, space: OutputSpace) -> [u8; 4] {
+        if alpha <= 0.0 {
+            return [0, 0, 0, 0];
+        }
+        let [r, g, b, _] = (self / alpha).clamp().to_rgba8_in(space);
+        [r, g, b, (alpha.min(1.0) * 255.0).round() as u8]
+    }
+
+    /// 和to_rgba8_with_alpha一样，每个通道16位，平滑的渐变不会出现色带
+    pub fn to_rgba16_with_alpha(self, alpha: f32, space: OutputSpace) -> [u16; 4] {
+        if alpha <= 0.0 {
+            return [0, 0, 0, 0];
+        }
+        let color = space.encode((self / alpha).clamp());
+        let channel = |v: f32| (v * 65535.0).round() as u16;
+        [
+            channel(color.r),
+            channel(color.g),
+            channel(color.b),
+            channel(alpha.min(1.0)),
+        ]
+    }
+
     pub fn from_rgba8(rgba8: [u8; 4]) -> Self {
         Color {
             r: gamma_decode(rgba8[0] as f32 / 255f32),
@@ -34,14 +219,27 @@ impl Color {
         }
     }
 
+    /// 不做gamma解码，数据贴图用
+    pub fn from_linear_rgba8(rgba8: [u8; 4]) -> Self {
+        Color {
+            r: rgba8[0] as f32 / 255f32,
+            g: rgba8[1] as f32 / 255f32,
+            b: rgba8[2] as f32 / 255f32,
+        }
+    }
+
     pub fn clamp(&self) -> Color {
         Color {
-            r: self.r.min(1.0).max(0.0),
-            b: self.b.min(1.0).max(0.0),
-            g: self.g.min(1.0).max(0.0),
+            r: self.r.clamp(0.0, 1.0),
+            b: self.b.clamp(0.0, 1.0),
+            g: self.g.clamp(0.0, 1.0),
         }
     }
 
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
     pub fn black() -> Self {
         Color {
             r: 0.0,
@@ -49,6 +247,14 @@ impl Color {
             b: 0.0,
         }
     }
+
+    pub fn white() -> Self {
+        Color {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        }
+    }
 }
 
 impl Add for Color {
@@ -62,6 +268,17 @@ impl Add for Color {
     }
 }
 
+impl Sub for Color {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Self {
+            r: self.r - other.r,
+            g: self.g - other.g,
+            b: self.b - other.b,
+        }
+    }
+}
+
 impl AddAssign for Color {
     fn add_assign(&mut self, other: Self) {
         *self = Self {
@@ -0,0 +1,268 @@
+use crate::color::Color;
+use crate::rendering::{grader, par_render_crop_linear, post_process, Crop};
+use crate::scene::{light::LightSampler, Scene};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// 协议（都是小端）：worker连上以后先发MAGIC和场景的指纹，
+// 然后coordinator每次发一个字节的消息类型，TILE后面跟x, y, width, height四个u32，
+// worker回这一块每个像素还没clamp的r, g, b和alpha（f32）；没有活了发DONE，场景对不上发MISMATCH。
+// 泛光、光晕和色差要看整张图，所以等块都收齐了由coordinator做，调色也在那里做
+const MAGIC: &[u8; 8] = b"NRTNET02";
+const DONE: u8 = 0;
+const TILE: u8 = 1;
+const MISMATCH: u8 = 2;
+const PIXEL_BYTES: usize = 16;
+/// 没有新连接时隔多久再看一次
+const ACCEPT_POLL: Duration = Duration::from_millis(20);
+/// 连上来的客户端这么久不发握手就断开，端口扫描之类的连接不会让coordinator一直等
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// coordinate的tile_timeout没有特别要求时用这个
+pub const DEFAULT_TILE_TIMEOUT: Duration = Duration::from_secs(600);
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// 场景本身没法序列化，两边各自用同样的代码搭同一个场景；
+/// 握手时比一下这些，防止拿不同的场景拼出一张图
+fn fingerprint(scene: &Scene) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    for v in [
+        scene.width,
+        scene.height,
+        scene.items.len() as u32,
+        scene.lights.len() as u32,
+        scene.spectral as u32,
+        scene.transparent as u32,
+        scene.settings.samples,
+        scene.settings.max_depth as u32,
+    ] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    bytes.extend_from_slice(&scene.seed.to_le_bytes());
+    bytes
+}
+
+struct Queue {
+    pending: VecDeque<Crop>,
+    done: usize,
+    total: usize,
+}
+
+struct Shared<'a> {
+    scene: &'a Scene,
+    queue: Mutex<Queue>,
+    changed: Condvar,
+    /// 整张图还没clamp的颜色和alpha，按行排
+    pixels: Mutex<Vec<(Color, f32)>>,
+    tile_timeout: Duration,
+    progress: &'a (dyn Fn(usize, usize) + Sync),
+}
+
+impl Shared<'_> {
+    fn finished(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        queue.done == queue.total
+    }
+
+    /// 拿下一块；队列空了但别的worker手上还有没交的，就等着，它们断线的话那几块会放回来
+    fn next_tile(&self) -> Option<Crop> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(tile) = queue.pending.pop_front() {
+                return Some(tile);
+            }
+            if queue.done == queue.total {
+                return None;
+            }
+            queue = self.changed.wait(queue).unwrap();
+        }
+    }
+
+    fn give_back(&self, tile: Crop) {
+        self.queue.lock().unwrap().pending.push_front(tile);
+        self.changed.notify_all();
+    }
+
+    fn complete(&self, tile: &Crop, bytes: &[u8]) {
+        let width = self.scene.width;
+        let mut pixels = self.pixels.lock().unwrap();
+        for (i, chunk) in bytes.chunks_exact(PIXEL_BYTES).enumerate() {
+            let float =
+                |j: usize| f32::from_le_bytes([chunk[j], chunk[j + 1], chunk[j + 2], chunk[j + 3]]);
+            let (x, y) = (
+                tile.x + i as u32 % tile.width,
+                tile.y + i as u32 / tile.width,
+            );
+            let color = Color {
+                r: float(0),
+                g: float(4),
+                b: float(8),
+            };
+            pixels[(x + y * width) as usize] = (color, float(12));
+        }
+        drop(pixels);
+        let (done, total) = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.done += 1;
+            (queue.done, queue.total)
+        };
+        self.changed.notify_all();
+        (self.progress)(done, total);
+    }
+
+    fn serve(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let expected = fingerprint(self.scene);
+        let mut hello = vec![0; expected.len()];
+        stream.read_exact(&mut hello)?;
+        if hello != expected {
+            stream.write_all(&[MISMATCH])?;
+            return Err(invalid_data("worker is rendering a different scene"));
+        }
+        stream.set_read_timeout(Some(self.tile_timeout))?;
+        while let Some(tile) = self.next_tile() {
+            match render_remotely(&mut stream, &tile) {
+                Ok(pixels) => self.complete(&tile, &pixels),
+                Err(e) => {
+                    self.give_back(tile);
+                    return Err(e);
+                }
+            }
+        }
+        stream.write_all(&[DONE])
+    }
+}
+
+fn render_remotely(stream: &mut TcpStream, tile: &Crop) -> io::Result<Vec<u8>> {
+    let mut message = vec![TILE];
+    for v in [tile.x, tile.y, tile.width, tile.height] {
+        message.extend_from_slice(&v.to_le_bytes());
+    }
+    stream.write_all(&message)?;
+    let mut pixels = vec![0; tile.width as usize * tile.height as usize * PIXEL_BYTES];
+    stream.read_exact(&mut pixels)?;
+    Ok(pixels)
+}
+
+/// 分布式渲染的协调端：把画面切成tile_size大小的块，发给连上listener的worker，
+/// 收回来拼成整张图，再和render一样加后期、调色。worker可以随时加入；
+/// 中途断线或者一块超过tile_timeout还没交，它手上那块会交给别的worker重算，
+/// 所以tile_timeout要比最慢的机器渲染最重的一块花的时间长。
+/// 每收回一块调用一次progress(已完成块数, 总块数)
+pub fn coordinate(
+    scene: &Scene,
+    listener: &TcpListener,
+    tile_size: u32,
+    tile_timeout: Duration,
+    progress: impl Fn(usize, usize) + Sync,
+) -> io::Result<DynamicImage> {
+    let tiles = Crop::tiles(scene, tile_size);
+    let shared = Shared {
+        scene,
+        queue: Mutex::new(Queue {
+            total: tiles.len(),
+            pending: tiles.into(),
+            done: 0,
+        }),
+        changed: Condvar::new(),
+        pixels: Mutex::new(vec![
+            (Color::black(), 0.0);
+            scene.width as usize * scene.height as usize
+        ]),
+        tile_timeout,
+        progress: &progress,
+    };
+    listener.set_nonblocking(true)?;
+    let accepted = thread::scope(|s| {
+        while !shared.finished() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let shared = &shared;
+                    // 一个worker出错不影响别的，它的块已经放回队列了
+                    s.spawn(move || shared.serve(stream).ok());
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                Err(e) => {
+                    // 让等着的线程也退出来
+                    let mut queue = shared.queue.lock().unwrap();
+                    queue.pending.clear();
+                    queue.done = queue.total;
+                    shared.changed.notify_all();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    });
+    listener.set_nonblocking(false)?;
+    accepted?;
+    let (mut colors, alphas): (Vec<_>, Vec<_>) =
+        shared.pixels.into_inner().unwrap().into_iter().unzip();
+    post_process(scene, scene.width, scene.height, &mut colors);
+    let grade = grader(scene);
+    let output_space = scene.settings.output_space;
+    let image = RgbaImage::from_fn(scene.width, scene.height, |x, y| {
+        let i = (x + y * scene.width) as usize;
+        Rgba::from(grade(colors[i]).to_rgba8_with_alpha(alphas[i], output_space))
+    });
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// 分布式渲染的工作端：连上coordinator，一直渲染它发来的块直到它说做完了。
+/// scene必须和coordinator那边的一样（包括种子），返回这台机器渲染了几块
+pub fn work<A: ToSocketAddrs>(scene: &Scene, coordinator: A) -> io::Result<usize> {
+    let mut stream = TcpStream::connect(coordinator)?;
+    stream.set_nodelay(true)?;
+    stream.write_all(&fingerprint(scene))?;
+    let lights = LightSampler::for_scene(scene);
+    let mut rendered = 0;
+    loop {
+        let mut kind = [0u8];
+        stream.read_exact(&mut kind)?;
+        match kind[0] {
+            DONE => return Ok(rendered),
+            TILE => {
+                let mut header = [0u8; 16];
+                stream.read_exact(&mut header)?;
+                let word = |i: usize| {
+                    u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]])
+                };
+                let tile = Crop {
+                    x: word(0),
+                    y: word(4),
+                    width: word(8),
+                    height: word(12),
+                };
+                let inside = |start: u32, size: u32, limit: u32| {
+                    start.checked_add(size).is_some_and(|end| end <= limit)
+                };
+                if !inside(tile.x, tile.width, scene.width)
+                    || !inside(tile.y, tile.height, scene.height)
+                {
+                    return Err(invalid_data("tile is outside the image"));
+                }
+                let mut bytes =
+                    Vec::with_capacity(tile.width as usize * tile.height as usize * PIXEL_BYTES);
+                for (color, alpha) in par_render_crop_linear(scene, &lights, &tile) {
+                    for v in [color.r, color.g, color.b, alpha] {
+                        bytes.extend_from_slice(&v.to_le_bytes());
+                    }
+                }
+                stream.write_all(&bytes)?;
+                rendered += 1;
+            }
+            MISMATCH => return Err(invalid_data("coordinator is rendering a different scene")),
+            _ => return Err(invalid_data("unexpected message from coordinator")),
+        }
+    }
+}
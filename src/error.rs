@@ -0,0 +1,67 @@
+use image::ImageError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 读场景、模型、贴图，以及渲染、存图时可能出的错
+#[derive(Debug)]
+pub enum Error {
+    /// 读写文件出错
+    Io(io::Error),
+    /// 图片读不出来或者存不下来
+    Image(ImageError),
+    /// 文件内容不对：场景文件、模型、体积数据、检查点的格式有问题
+    Parse(String),
+    /// 场景本身有问题，比如引用了没注册的材质
+    Scene(String),
+    /// 出在某个文件里的错，显示的时候带上文件名
+    File(PathBuf, Box<Error>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    #[cfg(feature = "fs")]
+    pub(crate) fn parse(message: impl Into<String>) -> Self {
+        Self::Parse(message.into())
+    }
+
+    /// 标上出错的文件
+    pub fn in_file<P: AsRef<Path>>(self, path: P) -> Self {
+        Self::File(path.as_ref().to_path_buf(), Box::new(self))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => e.fmt(f),
+            Self::Image(e) => e.fmt(f),
+            Self::Parse(message) | Self::Scene(message) => f.write_str(message),
+            Self::File(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Image(e) => Some(e),
+            Self::File(_, e) => Some(e.as_ref()),
+            Self::Parse(_) | Self::Scene(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(e: ImageError) -> Self {
+        Self::Image(e)
+    }
+}
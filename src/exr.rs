@@ -0,0 +1,375 @@
+use crate::color::Color;
+use crate::hdr::HdrImage;
+use crate::rendering::{Aov, Lighting};
+use std::convert::{TryFrom, TryInto};
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::{fs::File, io::BufWriter, path::Path};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// 单层、扫描线、不压缩
+const VERSION: u32 = 2;
+const PIXEL_TYPE_UINT: i32 = 0;
+const PIXEL_TYPE_HALF: i32 = 1;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+/// 写出来的OpenEXR：扫描线、不压缩，每个通道都是32位浮点。
+/// 通道名里的点分出层，比如"normal.X"是normal层的X通道，不带点的R、G、B是主图
+pub struct Exr {
+    pub width: u32,
+    pub height: u32,
+    channels: Vec<(String, Vec<f32>)>,
+}
+
+impl Exr {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            channels: Vec::new(),
+        }
+    }
+
+    /// values按行排，长度必须是width * height；同名的通道会被替换
+    pub fn add_channel(&mut self, name: &str, values: Vec<f32>) {
+        assert_eq!(values.len(), self.width as usize * self.height as usize);
+        self.channels.retain(|(n, _)| n != name);
+        self.channels.push((name.to_string(), values));
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        // 文件里的通道必须按名字排序
+        let mut channels: Vec<_> = self.channels.iter().collect();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        let long_names = channels.iter().any(|(name, _)| name.len() > 31);
+        let flags = if long_names { 0x400 } else { 0 };
+        header.extend_from_slice(&(VERSION | flags).to_le_bytes());
+
+        let mut list = Vec::new();
+        for (name, _) in &channels {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+            list.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+            // pLinear和三个保留字节
+            list.extend_from_slice(&[0; 4]);
+            list.extend_from_slice(&1i32.to_le_bytes());
+            list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        list.push(0);
+        attribute(&mut header, "channels", "chlist", &list);
+        attribute(&mut header, "compression", "compression", &[0]);
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        // 每条扫描线一块：y、数据长度，然后每个通道的一整行
+        let width = self.width as usize;
+        let line_size = channels.len() * width * 4;
+        let first_line = header.len() + self.height as usize * 8;
+        for y in 0..self.height as usize {
+            let offset = (first_line + y * (8 + line_size)) as u64;
+            header.extend_from_slice(&offset.to_le_bytes());
+        }
+        w.write_all(&header)?;
+
+        let mut line = Vec::with_capacity(8 + line_size);
+        for y in 0..self.height as usize {
+            line.clear();
+            line.extend_from_slice(&(y as i32).to_le_bytes());
+            line.extend_from_slice(&(line_size as i32).to_le_bytes());
+            for (_, values) in &channels {
+                for v in &values[y * width..(y + 1) * width] {
+                    line.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            w.write_all(&line)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        let path = path.as_ref();
+        let result = File::create(path).and_then(|file| {
+            let mut w = BufWriter::new(file);
+            self.write(&mut w)?;
+            w.flush()
+        });
+        result.map_err(|e| crate::Error::from(e).in_file(path))
+    }
+}
+
+/// 头里的一个属性：名字、类型、长度、值
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// 主图和aov_pass的各个通道放进一个EXR：R、G、B，normal.X/Y/Z，depth.Z，
+/// albedo.R/G/B，objectid.id。没打中东西的像素法线是0，深度是无穷大，ID是0
+pub fn aov_layers(beauty: &HdrImage, aovs: &[Option<Aov>]) -> Exr {
+    let mut exr = Exr::new(beauty.width, beauty.height);
+    let channel = |f: &dyn Fn(&Color) -> f32| beauty.pixels.iter().map(f).collect();
+    exr.add_channel("R", channel(&|c| c.r));
+    exr.add_channel("G", channel(&|c| c.g));
+    exr.add_channel("B", channel(&|c| c.b));
+    let aov = |f: &dyn Fn(&Aov) -> f32, miss: f32| {
+        aovs.iter()
+            .map(|a| a.as_ref().map_or(miss, f))
+            .collect::<Vec<f32>>()
+    };
+    exr.add_channel("normal.X", aov(&|a| a.normal.x as f32, 0.0));
+    exr.add_channel("normal.Y", aov(&|a| a.normal.y as f32, 0.0));
+    exr.add_channel("normal.Z", aov(&|a| a.normal.z as f32, 0.0));
+    exr.add_channel("depth.Z", aov(&|a| a.depth as f32, f32::INFINITY));
+    exr.add_channel("albedo.R", aov(&|a| a.albedo.r, 0.0));
+    exr.add_channel("albedo.G", aov(&|a| a.albedo.g, 0.0));
+    exr.add_channel("albedo.B", aov(&|a| a.albedo.b, 0.0));
+    exr.add_channel("objectid.id", aov(&|a| a.object_id as f32, 0.0));
+    exr
+}
+
+/// aov_layers再加上lighting_pass的三个通道：direct.R/G/B、indirect_diffuse.R/G/B和
+/// indirect_specular.R/G/B，主图是三个通道的和
+pub fn lighting_layers(
+    width: u32,
+    height: u32,
+    lighting: &[Lighting],
+    aovs: &[Option<Aov>],
+) -> Exr {
+    let beauty = HdrImage::new(
+        width,
+        height,
+        lighting.iter().map(Lighting::total).collect(),
+    );
+    let mut exr = aov_layers(&beauty, aovs);
+    let mut layer = |name: &str, colors: Vec<Color>| {
+        exr.add_channel(&format!("{}.R", name), colors.iter().map(|c| c.r).collect());
+        exr.add_channel(&format!("{}.G", name), colors.iter().map(|c| c.g).collect());
+        exr.add_channel(&format!("{}.B", name), colors.iter().map(|c| c.b).collect());
+    };
+    layer("direct", lighting.iter().map(|l| l.direct).collect());
+    layer(
+        "indirect_diffuse",
+        lighting.iter().map(|l| l.indirect_diffuse).collect(),
+    );
+    layer(
+        "indirect_specular",
+        lighting.iter().map(|l| l.indirect_specular).collect(),
+    );
+    exr
+}
+
+/// aov_layers再加上light_group_pass的每一组：<组名>.R/G/B，
+/// 主图是所有组的和。layers是组名和那一组的图
+pub fn light_group_layers(
+    width: u32,
+    height: u32,
+    layers: &[(&str, Vec<Color>)],
+    aovs: &[Option<Aov>],
+) -> Exr {
+    let mut beauty = vec![Color::black(); width as usize * height as usize];
+    for (_, colors) in layers {
+        for (b, &c) in beauty.iter_mut().zip(colors) {
+            *b += c;
+        }
+    }
+    let mut exr = aov_layers(&HdrImage::new(width, height, beauty), aovs);
+    for (name, colors) in layers {
+        exr.add_channel(&format!("{}.R", name), colors.iter().map(|c| c.r).collect());
+        exr.add_channel(&format!("{}.G", name), colors.iter().map(|c| c.g).collect());
+        exr.add_channel(&format!("{}.B", name), colors.iter().map(|c| c.b).collect());
+    }
+    exr
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("EXR: {}", message))
+}
+
+/// 按顺序从头里读东西
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .at
+            .checked_add(n)
+            .filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| invalid("unexpected end of file"))?;
+        let slice = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(slice)
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// 以0结尾的字符串
+    fn name(&mut self) -> io::Result<&'a str> {
+        let rest = &self.bytes[self.at..];
+        let length = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("unterminated name"))?;
+        let name = std::str::from_utf8(&rest[..length]).map_err(|_| invalid("bad name"))?;
+        self.at += length + 1;
+        Ok(name)
+    }
+}
+
+/// 16位浮点换成f32
+fn half_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// 读OpenEXR里的R、G、B（没有的话用Y当灰度），只支持单层、扫描线、不压缩的文件，
+/// 也就是Exr写出来的那种；通道可以是half、float或者uint
+pub fn read_rgb(bytes: &[u8]) -> io::Result<HdrImage> {
+    let mut r = Reader { bytes, at: 0 };
+    if r.take(4)? != MAGIC {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    let version = r.i32()?;
+    // 0x200是tiled，0x1000是多层
+    if version & 0x1200 != 0 {
+        return Err(invalid("only single-part scanline files are supported"));
+    }
+    let mut channels = Vec::new();
+    let mut window = None;
+    let mut compression = 0;
+    loop {
+        let name = r.name()?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = r.name()?;
+        let size = r.i32()?;
+        let value = r.take(usize::try_from(size).map_err(|_| invalid("bad attribute"))?)?;
+        let mut v = Reader {
+            bytes: value,
+            at: 0,
+        };
+        match (name, kind) {
+            ("channels", "chlist") => loop {
+                let channel = v.name()?;
+                if channel.is_empty() {
+                    break;
+                }
+                let pixel_type = v.i32()?;
+                // pLinear、保留字节、xSampling、ySampling
+                v.take(4)?;
+                if v.i32()? != 1 || v.i32()? != 1 {
+                    return Err(invalid("subsampled channels are not supported"));
+                }
+                channels.push((channel, pixel_type));
+            },
+            ("compression", "compression") => compression = v.take(1)?[0],
+            ("dataWindow", "box2i") => {
+                window = Some([v.i32()?, v.i32()?, v.i32()?, v.i32()?]);
+            }
+            _ => {}
+        }
+    }
+    if compression != 0 {
+        return Err(invalid("only uncompressed files are supported"));
+    }
+    let [x0, y0, x1, y1] = window.ok_or_else(|| invalid("missing dataWindow"))?;
+    if x1 < x0 || y1 < y0 {
+        return Err(invalid("empty dataWindow"));
+    }
+    let (width, height) = ((x1 - x0 + 1) as u32, (y1 - y0 + 1) as u32);
+    // 文件里的通道是按名字排好的
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+    let sample_size = |pixel_type: i32| match pixel_type {
+        PIXEL_TYPE_HALF => Ok(2),
+        PIXEL_TYPE_UINT | PIXEL_TYPE_FLOAT => Ok(4),
+        _ => Err(invalid("unknown pixel type")),
+    };
+    let mut offsets = Vec::with_capacity(height as usize);
+    for _ in 0..height {
+        offsets.push(r.u64()?);
+    }
+
+    let find = |name: &str| channels.iter().position(|(n, _)| *n == name);
+    let gray = find("Y");
+    let rgb = [find("R").or(gray), find("G").or(gray), find("B").or(gray)];
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = vec![Color::black(); width * height];
+    for offset in offsets {
+        let mut line = Reader {
+            bytes,
+            at: usize::try_from(offset).map_err(|_| invalid("bad line offset"))?,
+        };
+        let y = (line.i32()? - y0) as usize;
+        line.i32()?;
+        if y >= height {
+            return Err(invalid("scanline outside dataWindow"));
+        }
+        let mut values = Vec::with_capacity(channels.len());
+        for &(_, pixel_type) in &channels {
+            let row = line.take(width * sample_size(pixel_type)?)?;
+            let row: Vec<f32> = match pixel_type {
+                PIXEL_TYPE_HALF => row
+                    .chunks_exact(2)
+                    .map(|b| half_to_f32(u16::from_le_bytes([b[0], b[1]])))
+                    .collect(),
+                PIXEL_TYPE_FLOAT => row
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect(),
+                _ => row
+                    .chunks_exact(4)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32)
+                    .collect(),
+            };
+            values.push(row);
+        }
+        let sample = |channel: Option<usize>, x: usize| channel.map_or(0.0, |c| values[c][x]);
+        for (x, pixel) in pixels[y * width..(y + 1) * width].iter_mut().enumerate() {
+            *pixel = Color::new(sample(rgb[0], x), sample(rgb[1], x), sample(rgb[2], x));
+        }
+    }
+    Ok(HdrImage::new(width as u32, height as u32, pixels))
+}
@@ -0,0 +1,153 @@
+use crate::math::Float;
+
+/// 把样本合成像素用的重建滤波器，都是x、y两个方向可分离的。
+/// 样本按滤波器的形状分布在像素周围（可以落到相邻像素里），每个样本带一个权重，
+/// 像素是样本按权重的平均
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PixelFilter {
+    /// 像素内均匀分布，边缘锯齿最明显
+    #[default]
+    Box,
+    /// 三角形，radius是半宽（像素）
+    Tent { radius: f32 },
+    /// 截断在radius处的高斯，减掉边上的值让它在radius处正好是0
+    Gaussian { radius: f32, sigma: f32 },
+    /// Mitchell–Netravali三次滤波器，b = c = 1/3时模糊和振铃比较平衡。
+    /// 有负的部分，对应的样本权重是负的
+    Mitchell { radius: f32, b: f32, c: f32 },
+}
+
+impl PixelFilter {
+    pub fn tent() -> Self {
+        Self::Tent { radius: 1.0 }
+    }
+
+    pub fn gaussian() -> Self {
+        Self::Gaussian {
+            radius: 1.5,
+            sigma: 0.5,
+        }
+    }
+
+    pub fn mitchell() -> Self {
+        Self::Mitchell {
+            radius: 2.0,
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        match *self {
+            Self::Box => 0.5,
+            Self::Tent { radius }
+            | Self::Gaussian { radius, .. }
+            | Self::Mitchell { radius, .. } => radius,
+        }
+    }
+
+    /// 一个方向上离像素中心x处的值
+    pub fn evaluate(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match *self {
+            Self::Box => {
+                if x <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Self::Tent { radius } => (radius - x).max(0.0),
+            Self::Gaussian { radius, sigma } => {
+                let g = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                (g(x) - g(radius)).max(0.0)
+            }
+            Self::Mitchell { radius, b, c } => {
+                let x = 2.0 * x / radius;
+                let x2 = x * x;
+                let x3 = x2 * x;
+                let v = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x2
+                        + (6.0 - 2.0 * b)
+                } else if x < 2.0 {
+                    (-b - 6.0 * c) * x3
+                        + (6.0 * b + 30.0 * c) * x2
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                } else {
+                    0.0
+                };
+                v / 6.0
+            }
+        }
+    }
+}
+
+/// 表格里的格数，一个方向
+const TABLE_SIZE: usize = 64;
+
+/// 按|f|把滤波器离散成一张分段常数的表，用来按滤波器的形状采样像素里的位置
+/// （filter importance sampling）。每次渲染开始时建一次
+pub struct FilterSampler {
+    filter: PixelFilter,
+    radius: f32,
+    values: Vec<f32>,
+    cdf: Vec<f32>,
+    /// ∫|f|，一个方向
+    integral: f32,
+}
+
+impl FilterSampler {
+    pub fn new(filter: PixelFilter) -> Self {
+        let radius = filter.radius();
+        let width = 2.0 * radius / TABLE_SIZE as f32;
+        let values: Vec<f32> = (0..TABLE_SIZE)
+            .map(|i| filter.evaluate(-radius + (i as f32 + 0.5) * width))
+            .collect();
+        let mut acc = 0.0;
+        let cdf: Vec<f32> = values
+            .iter()
+            .map(|v| {
+                acc += v.abs() * width;
+                acc
+            })
+            .collect();
+        Self {
+            filter,
+            radius,
+            values,
+            cdf,
+            integral: acc,
+        }
+    }
+
+    /// 用[0, 1)的u采样一个方向上的偏移，返回(偏移, 权重)，权重是f / pdf
+    fn sample_1d(&self, u: Float) -> (Float, f32) {
+        let u = u as f32 * self.integral;
+        let index = self.cdf.partition_point(|&c| c <= u).min(TABLE_SIZE - 1);
+        let start = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        let width = 2.0 * self.radius / TABLE_SIZE as f32;
+        let bin = self.values[index].abs() * width;
+        let t = if bin > 0.0 { (u - start) / bin } else { 0.5 };
+        let x = -self.radius + (index as f32 + t.clamp(0.0, 1.0)) * width;
+        let pdf = self.values[index].abs() / self.integral;
+        let weight = if pdf > 0.0 {
+            self.filter.evaluate(x) / pdf
+        } else {
+            0.0
+        };
+        (x as Float, weight)
+    }
+
+    /// 用两个[0, 1)的随机数采样像素里的位置，返回(0, 0)在像素左上角的偏移和样本的权重。
+    /// Box滤波器直接把随机数当偏移，和原来的均匀采样一样
+    pub fn sample(&self, u: (Float, Float)) -> ((Float, Float), f32) {
+        if self.filter == PixelFilter::Box || self.integral <= 0.0 {
+            return (u, 1.0);
+        }
+        let (x, wx) = self.sample_1d(u.0);
+        let (y, wy) = self.sample_1d(u.1);
+        ((0.5 + x, 0.5 + y), wx * wy)
+    }
+}
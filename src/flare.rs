@@ -0,0 +1,134 @@
+use crate::bloom::{bright, Level};
+use crate::color::Color;
+use crate::math::consts::PI;
+
+/// 最多从几个最亮的地方画星芒
+const MAX_SOURCES: usize = 8;
+
+/// 镜头光晕：很亮的东西在镜头里来回反射，沿着穿过画面中心的直线留下一串鬼影，
+/// 光圈的叶片还会让它射出几条星芒。在clamp之前的整张图上，只看超过阈值的那部分亮度
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlareSettings {
+    /// 亮度超过它的部分才产生光晕
+    pub threshold: f32,
+    /// 鬼影的个数
+    pub ghosts: usize,
+    /// 相邻两个鬼影隔多远，按亮处到画面中心的距离算
+    pub dispersal: f32,
+    /// 星芒的条数，0是不要
+    pub streaks: usize,
+    /// 加回去时乘的系数
+    pub intensity: f32,
+}
+
+impl Default for FlareSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            ghosts: 4,
+            dispersal: 0.35,
+            streaks: 6,
+            intensity: 0.05,
+        }
+    }
+}
+
+/// 在width × height、按行排的线性颜色上加光晕
+pub fn apply(settings: &FlareSettings, width: u32, height: u32, pixels: &mut [Color]) {
+    let (width, height) = (width as usize, height as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+    // 缩小到四分之一再用，单个的亮点噪声不会变成清楚的鬼影
+    let mut level = Level {
+        width,
+        height,
+        pixels: bright(pixels, settings.threshold),
+    };
+    for _ in 0..2 {
+        level = level.downsample();
+    }
+    let mut flare = vec![Color::black(); width * height];
+    add_ghosts(settings, &level, width, &mut flare);
+    add_streaks(settings, &level, width, height, &mut flare);
+    for (pixel, f) in pixels.iter_mut().zip(flare) {
+        *pixel += f * settings.intensity;
+    }
+}
+
+/// 每个像素往画面中心的方向上按dispersal一步步取亮处，取到的就是落在这里的鬼影。
+/// 离画面中心越远的鬼影越暗
+fn add_ghosts(settings: &FlareSettings, level: &Level, width: usize, flare: &mut [Color]) {
+    let height = flare.len() / width;
+    for (i, f) in flare.iter_mut().enumerate() {
+        // 按画面的比例归一化到[0, 1]
+        let u = ((i % width) as f32 + 0.5) / width as f32;
+        let v = ((i / width) as f32 + 0.5) / height as f32;
+        let step = (
+            (0.5 - u) * settings.dispersal,
+            (0.5 - v) * settings.dispersal,
+        );
+        // 第0步就是亮处自己，不算
+        for k in 1..=settings.ghosts {
+            let (x, y) = (u + step.0 * k as f32, v + step.1 * k as f32);
+            if !(0.0..1.0).contains(&x) || !(0.0..1.0).contains(&y) {
+                continue;
+            }
+            let distance = ((x - 0.5).powi(2) + (y - 0.5).powi(2)).sqrt() / 0.5f32.sqrt();
+            let weight = (1.0 - distance).max(0.0).powi(4);
+            *f += level.sample(x * level.width as f32, y * level.height as f32) * weight;
+        }
+    }
+}
+
+/// 从缩小后最亮的几个局部最大值往外画均匀分布的几条直线，越远越暗
+fn add_streaks(
+    settings: &FlareSettings,
+    level: &Level,
+    width: usize,
+    height: usize,
+    flare: &mut [Color],
+) {
+    if settings.streaks == 0 {
+        return;
+    }
+    let at = |x: usize, y: usize| level.pixels[y * level.width + x];
+    let mut sources: Vec<(f32, usize, usize)> = (0..level.height)
+        .flat_map(|y| (0..level.width).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let luminance = at(x, y).luminance();
+            let neighbors = (y.saturating_sub(1)..(y + 2).min(level.height)).flat_map(|ny| {
+                (x.saturating_sub(1)..(x + 2).min(level.width)).map(move |nx| (nx, ny))
+            });
+            let peak = neighbors
+                .filter(|&n| n != (x, y))
+                .all(|(nx, ny)| at(nx, ny).luminance() < luminance);
+            (luminance > 0.0 && peak).then_some((luminance, x, y))
+        })
+        .collect();
+    sources.sort_by(|a, b| b.0.total_cmp(&a.0));
+    sources.truncate(MAX_SOURCES);
+    let scale = (
+        width as f32 / level.width as f32,
+        height as f32 / level.height as f32,
+    );
+    let length = (width.min(height) as f32 * 0.25).max(1.0);
+    for (_, x, y) in sources {
+        let color = at(x, y);
+        let center = ((x as f32 + 0.5) * scale.0, (y as f32 + 0.5) * scale.1);
+        for k in 0..settings.streaks {
+            // 转一点，偶数条时不会正好横平竖直
+            let angle = 2.0 * PI as f32 * k as f32 / settings.streaks as f32 + 0.3;
+            let direction = (angle.cos(), angle.sin());
+            for t in 1..length as usize {
+                let px = center.0 + direction.0 * t as f32;
+                let py = center.1 + direction.1 * t as f32;
+                if px < 0.0 || py < 0.0 || px >= width as f32 || py >= height as f32 {
+                    break;
+                }
+                let falloff = (1.0 - t as f32 / length).powi(2);
+                flare[py as usize * width + px as usize] += color * falloff;
+            }
+        }
+    }
+}
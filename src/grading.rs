@@ -0,0 +1,58 @@
+use crate::color::{blackbody, Color};
+
+/// 白平衡和调色，和在修图软件里最后调一下一样，在光晕、泛光这些之后做。
+/// 白平衡在clamp之前的线性颜色上，lift、gamma、gain在clamp到[0, 1]之后
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorGrading {
+    /// 当成白色的光的色温（开尔文），6500是不调；写低了（比如钨丝灯的3200）
+    /// 画面整体偏蓝，用来抵消暖色的灯，写高了偏暖
+    pub temperature: f32,
+    /// 色调，正的偏品红，负的偏绿，-1到1
+    pub tint: f32,
+    /// 抬高暗部，0是不动，黑色变成lift
+    pub lift: Color,
+    /// 中间调，大于1变亮，1是不动
+    pub gamma: Color,
+    /// 整体乘的系数，白色变成gain
+    pub gain: Color,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            temperature: 6500.0,
+            tint: 0.0,
+            lift: Color::black(),
+            gamma: Color::white(),
+            gain: Color::white(),
+        }
+    }
+}
+
+impl ColorGrading {
+    /// 白平衡每个通道要乘的系数，亮度不变
+    pub fn white_balance(&self) -> Color {
+        let light = blackbody(self.temperature);
+        let reference = blackbody(6500.0);
+        let scale = Color {
+            r: reference.r / light.r.max(1e-4),
+            g: reference.g / light.g.max(1e-4) * (1.0 - 0.5 * self.tint),
+            b: reference.b / light.b.max(1e-4),
+        };
+        scale / scale.luminance().max(1e-4)
+    }
+
+    /// 乘过white_balance的颜色clamp以后做lift、gamma、gain，结果在[0, 1]里
+    pub fn grade(&self, color: Color) -> Color {
+        let channel = |x: f32, lift: f32, gamma: f32, gain: f32| {
+            (gain * (x + lift * (1.0 - x))).max(0.0).powf(1.0 / gamma)
+        };
+        let c = color.clamp();
+        Color {
+            r: channel(c.r, self.lift.r, self.gamma.r, self.gain.r),
+            g: channel(c.g, self.lift.g, self.gamma.g, self.gain.g),
+            b: channel(c.b, self.lift.b, self.gamma.b, self.gain.b),
+        }
+        .clamp()
+    }
+}
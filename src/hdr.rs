@@ -0,0 +1,126 @@
+use crate::color::Color;
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+/// 没有clamp、没有gamma的线性颜色，按行从上到下排。
+/// 存成.hdr或者.pfm，大于1的亮度都留着，可以在HDR查看器里调曝光
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    pub fn new(width: u32, height: u32, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width as usize * height as usize);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn rows(&self) -> impl DoubleEndedIterator<Item = &[Color]> {
+        self.pixels.chunks_exact(self.width.max(1) as usize)
+    }
+
+    /// Radiance RGBE格式，扫描线不压缩
+    pub fn write_hdr<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(
+            w,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        for row in self.rows() {
+            let bytes: Vec<u8> = row.iter().flat_map(|c| rgbe(*c)).collect();
+            w.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// PFM格式：每个通道一个f32，小端，行从下往上
+    pub fn write_pfm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.rows().rev() {
+            let bytes: Vec<u8> = row
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b])
+                .flat_map(f32::to_le_bytes)
+                .collect();
+            w.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// 按扩展名读.hdr或者.exr（只支持Exr写出来的那种不压缩的）
+    #[cfg(feature = "fs")]
+    pub fn load<P: AsRef<Path>>(path: P) -> crate::Result<Self> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let result = match extension.to_ascii_lowercase().as_str() {
+            "hdr" => File::open(path)
+                .map_err(crate::Error::from)
+                .and_then(|file| {
+                    let decoder = image::hdr::HdrDecoder::new(BufReader::new(file))?;
+                    let metadata = decoder.metadata();
+                    let pixels = decoder
+                        .read_image_hdr()?
+                        .into_iter()
+                        .map(|p| Color::new(p.0[0], p.0[1], p.0[2]))
+                        .collect();
+                    Ok(Self::new(metadata.width, metadata.height, pixels))
+                }),
+            "exr" => std::fs::read(path)
+                .and_then(|bytes| crate::exr::read_rgb(&bytes))
+                .map_err(crate::Error::from),
+            _ => Err(crate::Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected .hdr or .exr",
+            ))),
+        };
+        result.map_err(|e| e.in_file(path))
+    }
+
+    /// 按扩展名存成.hdr或者.pfm
+    #[cfg(feature = "fs")]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let write = match extension.to_ascii_lowercase().as_str() {
+            "hdr" => Self::write_hdr,
+            "pfm" => Self::write_pfm,
+            _ => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, "expected .hdr or .pfm");
+                return Err(crate::Error::from(e).in_file(path));
+            }
+        };
+        let result = File::create(path).and_then(|file| {
+            let mut w = BufWriter::new(file);
+            write(self, &mut w)?;
+            w.flush()
+        });
+        result.map_err(|e| crate::Error::from(e).in_file(path))
+    }
+}
+
+/// 三个通道共用一个指数：尾数是最亮的通道缩放到[128, 256)，指数加128存
+fn rgbe(color: Color) -> [u8; 4] {
+    let v = color.r.max(color.g).max(color.b);
+    if !v.is_finite() || v <= 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    let exponent = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    let mantissa = |c: f32| (c.max(0.0) * scale).min(255.0) as u8;
+    [
+        mantissa(color.r),
+        mantissa(color.g),
+        mantissa(color.b),
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
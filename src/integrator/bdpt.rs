@@ -0,0 +1,819 @@
+use super::{Integrator, PathTracer, Splats};
+use crate::bsdf::{Bsdf, Ggx, HairBsdf, Lambertian, PrincipledBsdf};
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Point, Transform, Vector3};
+use crate::rendering::{
+    fresnel, offset_origin, set_camera_hit, trace, trace_lights, transmittance, Intersection, Ray,
+};
+use crate::sampling::{random, random_2d};
+use crate::scene::camera::{perspective_extent, Projection};
+use crate::scene::light::LightSampler;
+use crate::scene::material::SurfaceType;
+use crate::scene::{Distance, Scene};
+
+/// 双向路径追踪：从相机和从光源各走一条子路径，再把两条子路径上的点两两连起来。
+/// 每种连法都是对同一条完整路径的一种采样，按power heuristic做MIS。
+/// 光源那头的点直接连到相机（光线追踪）时贡献落在别的像素上，加到Splats里，只有透视相机能这么连。
+/// 次表面散射当成漫反射，透明涂层和色散不算，焦散光子图也不用；
+/// 场景里有雾或者体积时整个退回路径追踪
+pub struct Bidirectional;
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, scene: &Scene, lights: &LightSampler, ray: &Ray, splats: &Splats) -> Color {
+        if scene.medium.is_some() || scene.items.iter().any(|i| i.volume().is_some()) {
+            return PathTracer.radiance(scene, lights, ray, splats);
+        }
+        let context = Context {
+            scene,
+            lights,
+            bounds: *lights.bounds(),
+            camera: Pinhole::new(scene, ray.time),
+            time: ray.time,
+        };
+        let max_depth = scene.settings.max_depth;
+        let mut camera = vec![Vertex {
+            kind: Kind::Camera,
+            point: ray.origin,
+            normal: None,
+            bsdf: None,
+            wo: -ray.direction,
+            beta: Color::white(),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }];
+        let pdf = context
+            .camera
+            .as_ref()
+            .map_or(1.0, |c| c.pdf(&ray.direction));
+        let first = ray.spawn(ray.origin, ray.direction);
+        let mut color =
+            context.random_walk(first, Color::white(), pdf, max_depth + 2, true, &mut camera);
+        let light = context.light_subpath(max_depth + 1);
+
+        for t in 1..=camera.len() {
+            for s in 0..=light.len() {
+                // 一共s + t个点，s + t - 2次弹射
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > max_depth {
+                    continue;
+                }
+                if t == 1 {
+                    context.splat(&light, s, splats);
+                } else {
+                    color += context.connect(&light, &camera, s, t);
+                }
+            }
+        }
+        color
+    }
+
+    fn light_tracing(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Camera,
+    /// delta是点光源、平行光这种打不中的，infinite是平行光和天光
+    Light {
+        index: usize,
+        delta: bool,
+        infinite: bool,
+    },
+    Surface,
+}
+
+/// 子路径上的一个点
+struct Vertex {
+    kind: Kind,
+    point: Point,
+    /// 几何法线，相机、点光源和无穷远的光源没有
+    normal: Option<Vector3>,
+    /// 表面点的BSDF，镜面和玻璃这种delta的没有，连不过去
+    bsdf: Option<Box<dyn Bsdf>>,
+    /// 指向子路径上前一个点；无穷远的光源是从场景指向光源的方向
+    wo: Vector3,
+    /// 从子路径的起点到这里的throughput
+    beta: Color,
+    /// 这个点的散射是delta的，只能沿着子路径走下去
+    delta: bool,
+    /// 从自己这条子路径的前一个点采样到这里的面积pdf
+    pdf_fwd: Float,
+    /// 从另一个方向（下一个点）采样到这里的面积pdf
+    pdf_rev: Float,
+}
+
+impl Vertex {
+    fn is_infinite(&self) -> bool {
+        matches!(self.kind, Kind::Light { infinite: true, .. })
+    }
+}
+
+/// from指向to的单位向量，无穷远的光源只看方向
+fn direction(from: &Vertex, to: &Vertex) -> Vector3 {
+    if from.is_infinite() {
+        -from.wo
+    } else if to.is_infinite() {
+        to.wo
+    } else {
+        (to.point - from.point).normalize()
+    }
+}
+
+/// from处的立体角pdf换成to处的面积pdf，到无穷远的光源的还是立体角的
+fn area_pdf(pdf: Float, from: &Vertex, to: &Vertex) -> Float {
+    if to.is_infinite() {
+        return pdf;
+    }
+    let w = direction(from, to);
+    let cos = to.normal.map_or(1.0, |n| n.dot(&w).abs());
+    if from.is_infinite() {
+        pdf * cos
+    } else {
+        pdf * cos / (to.point - from.point).norm()
+    }
+}
+
+/// 表面怎么散射
+enum Scatter {
+    Bsdf(Box<dyn Bsdf>),
+    /// 理想镜面反射
+    Mirror,
+    /// 光滑的电介质，按菲涅尔随机反射或者折射
+    Glass {
+        index: f32,
+        tint: Color,
+    },
+}
+
+/// 交点处的散射和表面自己发的光。反射和透射混在一起的材质随机选一种，
+/// 选中的概率和那一部分的比例正好抵消
+fn scatter_at(intersection: &Intersection, point: &Point, wo: &Vector3) -> (Scatter, Color) {
+    let material = intersection.material();
+    let base_color = intersection.base_color(point);
+    let normal = intersection.surface_normal(point);
+    // BSDF只在法线那一侧，翻到wo这边
+    let normal = if normal.dot(wo) < 0.0 {
+        -normal
+    } else {
+        normal
+    };
+    let diffuse = |albedo| Scatter::Bsdf(Box::new(Lambertian { normal, albedo }));
+    match material.surface {
+        SurfaceType::Diffuse => (diffuse(base_color * material.albedo), Color::black()),
+        SurfaceType::Reflective { reflectivity } => {
+            if random() < reflectivity as Float {
+                (Scatter::Mirror, Color::black())
+            } else {
+                (diffuse(base_color * material.albedo), Color::black())
+            }
+        }
+        SurfaceType::Microfacet {
+            roughness_u,
+            roughness_v,
+            rotation,
+        } => {
+            let bsdf = Ggx::anisotropic(
+                normal,
+                intersection.tangent(point),
+                (roughness_u, roughness_v),
+                rotation,
+                base_color,
+            );
+            (Scatter::Bsdf(Box::new(bsdf)), Color::black())
+        }
+        SurfaceType::Refractive {
+            index,
+            transparency,
+            ..
+        } => {
+            let tint = base_color * transparency;
+            (Scatter::Glass { index, tint }, Color::black())
+        }
+        SurfaceType::Principled(ref principled) => {
+            let metallic = intersection.metallic(principled, point);
+            let transmission = (principled.transmission * (1.0 - metallic)) as Float;
+            let scatter = if random() < transmission {
+                Scatter::Glass {
+                    index: principled.ior,
+                    tint: base_color,
+                }
+            } else {
+                Scatter::Bsdf(Box::new(PrincipledBsdf::new(
+                    normal,
+                    base_color,
+                    metallic,
+                    intersection.roughness(principled, point),
+                    principled.specular,
+                )))
+            };
+            (scatter, intersection.emission(principled, point))
+        }
+        SurfaceType::Subsurface(ref subsurface) => {
+            (diffuse(subsurface.scatter_color), Color::black())
+        }
+        SurfaceType::Hair(ref hair) => {
+            let bsdf = HairBsdf::new(
+                normal,
+                intersection.tangent(point),
+                base_color,
+                hair.roughness,
+                hair.azimuthal_roughness,
+                hair.tilt,
+            );
+            (Scatter::Bsdf(Box::new(bsdf)), Color::black())
+        }
+    }
+}
+
+/// 透视相机，光线追踪时把点投到画面上
+struct Pinhole {
+    transform: Transform,
+    origin: Point,
+    /// 画面在z = -1处，x方向是[-half_width, half_width]，y方向是[-half_height, half_height]
+    half_width: Float,
+    half_height: Float,
+    /// z = -1处整个画面的面积
+    area: Float,
+    width: u32,
+    height: u32,
+}
+
+impl Pinhole {
+    fn new(scene: &Scene, time: Float) -> Option<Self> {
+        // 有畸变时画面上的位置和方向对不上，有景深时光线不是从一个点出发的，也不连
+        if !matches!(scene.camera.projection, Projection::Perspective)
+            || scene.camera.distortion != 0.0
+            || scene.camera.lens.is_some()
+        {
+            return None;
+        }
+        let transform = scene.camera.transform_at(time);
+        let (half_width, half_height) = perspective_extent((scene.width, scene.height), scene.fov);
+        Some(Self {
+            origin: transform.point(&Point::zero()),
+            transform,
+            half_width,
+            half_height,
+            area: 4.0 * half_width * half_height,
+            width: scene.width,
+            height: scene.height,
+        })
+    }
+
+    /// 相机光线在画面上均匀分布时，朝direction的立体角pdf，画面外面是0
+    fn pdf(&self, direction: &Vector3) -> Float {
+        let local = self.transform.inverse_vector(direction).normalize();
+        let cos = -local.z;
+        if cos <= 0.0
+            || (local.x / cos).abs() > self.half_width
+            || (local.y / cos).abs() > self.half_height
+        {
+            return 0.0;
+        }
+        1.0 / (self.area * cos * cos * cos)
+    }
+
+    /// point落在画面上的像素
+    fn pixel(&self, point: &Point) -> Option<(u32, u32)> {
+        let local = self.transform.inverse_point(point);
+        if local.z >= 0.0 {
+            return None;
+        }
+        let fx = (local.x / -local.z / self.half_width + 1.0) / 2.0;
+        let fy = (1.0 - local.y / -local.z / self.half_height) / 2.0;
+        if !(0.0..1.0).contains(&fx) || !(0.0..1.0).contains(&fy) {
+            return None;
+        }
+        let x = ((fx * self.width as Float) as u32).min(self.width - 1);
+        let y = ((fy * self.height as Float) as u32).min(self.height - 1);
+        Some((x, y))
+    }
+}
+
+/// 一个相机样本要用的东西
+struct Context<'a> {
+    scene: &'a Scene,
+    lights: &'a LightSampler,
+    bounds: (Point, Distance),
+    /// 不是透视相机、镜头有畸变或者有景深时没有，也就不做光线追踪
+    camera: Option<Pinhole>,
+    time: Float,
+}
+
+impl Context<'_> {
+    fn ray(&self, origin: Point, direction: Vector3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            wavelength: None,
+            time: self.time,
+            camera: false,
+        }
+    }
+
+    /// 沿ray往下走，打中的点接在path后面，直到path有max个点。
+    /// pdf是采样出ray方向的立体角pdf，beta是ray带的throughput。
+    /// radiance是从相机出发的子路径：打中光源就停，返回路上表面自己发的光
+    fn random_walk(
+        &self,
+        mut ray: Ray,
+        mut beta: Color,
+        mut pdf: Float,
+        max: usize,
+        radiance: bool,
+        path: &mut Vec<Vertex>,
+    ) -> Color {
+        let scene = self.scene;
+        let mut emitted = Color::black();
+        while path.len() < max {
+            let intersection = trace(scene, &ray);
+            let light_hit = if radiance {
+                trace_lights(scene, &ray).filter(|(_, distance)| {
+                    intersection.as_ref().is_none_or(|i| *distance < i.distance)
+                })
+            } else {
+                None
+            };
+            if radiance && path.len() == 1 {
+                // 天光在无穷远处，看到它的像素在透明背景下还是透明的
+                let light_visible = light_hit.is_some_and(|(_, distance)| distance.is_finite());
+                set_camera_hit(intersection.is_some() || light_visible);
+            }
+            let prev = path.len() - 1;
+
+            if let Some((index, distance)) = light_hit {
+                let infinite = distance.is_infinite();
+                let point = if infinite {
+                    ray.origin + ray.direction * (2.0 * self.bounds.1)
+                } else {
+                    ray.origin + ray.direction * distance
+                };
+                let light = scene.lights[index].as_ref();
+                let normal = light
+                    .emission(&point, &-ray.direction, &self.bounds)
+                    .and_then(|e| e.normal);
+                let mut vertex = Vertex {
+                    kind: Kind::Light {
+                        index,
+                        delta: false,
+                        infinite,
+                    },
+                    point,
+                    normal,
+                    bsdf: None,
+                    wo: if infinite {
+                        ray.direction
+                    } else {
+                        -ray.direction
+                    },
+                    beta,
+                    delta: false,
+                    pdf_fwd: 0.0,
+                    pdf_rev: 0.0,
+                };
+                vertex.pdf_fwd = area_pdf(pdf, &path[prev], &vertex);
+                path.push(vertex);
+                break;
+            }
+            let Some(intersection) = intersection else {
+                break;
+            };
+
+            let point = ray.origin + ray.direction * intersection.distance;
+            let normal = intersection.surface_normal(&point);
+            let wo = -ray.direction;
+            let (scatter, emission) = scatter_at(&intersection, &point, &wo);
+            if radiance {
+                emitted += beta * emission;
+            }
+            let mut vertex = Vertex {
+                kind: Kind::Surface,
+                point,
+                normal: Some(normal),
+                bsdf: None,
+                wo,
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            vertex.pdf_fwd = area_pdf(pdf, &path[prev], &vertex);
+            if path.len() + 1 >= max {
+                if let Scatter::Bsdf(bsdf) = scatter {
+                    vertex.bsdf = Some(bsdf);
+                }
+                path.push(vertex);
+                break;
+            }
+
+            let bias = scene.settings.shadow_bias;
+            let (next, mut weight, pdf_rev) = match scatter {
+                Scatter::Bsdf(bsdf) => {
+                    let sample = bsdf.sample(&wo, random_2d());
+                    let pdf_rev = sample.as_ref().map_or(0.0, |s| bsdf.pdf(&s.direction, &wo));
+                    vertex.bsdf = Some(bsdf);
+                    let Some(sample) = sample else {
+                        path.push(vertex);
+                        break;
+                    };
+                    pdf = sample.pdf;
+                    let origin = offset_origin(scene, point, normal, &sample.direction);
+                    (ray.spawn(origin, sample.direction), sample.weight, pdf_rev)
+                }
+                Scatter::Mirror => {
+                    vertex.delta = true;
+                    pdf = 0.0;
+                    let next = Ray::create_reflection(normal, &ray, point, bias);
+                    (next, Color::white(), 0.0)
+                }
+                Scatter::Glass { index, tint } => {
+                    vertex.delta = true;
+                    pdf = 0.0;
+                    let reflection = Ray::create_reflection(normal, &ray, point, bias);
+                    let next = if random() < fresnel(ray.direction, normal, index) {
+                        reflection
+                    } else {
+                        Ray::create_transmission(normal, &ray, point, bias, index)
+                            .unwrap_or(reflection)
+                    };
+                    (next, tint, 0.0)
+                }
+            };
+            if path.len() > scene.settings.russian_roulette_depth {
+                let survival = weight.r.max(weight.g).max(weight.b).min(0.95);
+                if random() as f32 >= survival {
+                    path.push(vertex);
+                    break;
+                }
+                weight = weight / survival;
+            }
+            path[prev].pdf_rev = area_pdf(pdf_rev, &vertex, &path[prev]);
+            path.push(vertex);
+            beta = beta * weight;
+            if beta == Color::black() {
+                break;
+            }
+            ray = next;
+        }
+        emitted
+    }
+
+    /// 按功率挑一个光源，从它发出最多max个点的子路径
+    fn light_subpath(&self, max: usize) -> Vec<Vertex> {
+        let mut path = Vec::new();
+        let Some((index, selection)) = self.lights.sample(random()) else {
+            return path;
+        };
+        let light = self.scene.lights[index].as_ref();
+        let Some(emission) = light.emit(&self.bounds) else {
+            return path;
+        };
+        let selection = selection as Float;
+        let pdf_position = emission.pdf_position * selection;
+        if pdf_position <= 0.0 || emission.pdf_direction <= 0.0 {
+            return path;
+        }
+        let cos = emission
+            .normal
+            .map_or(1.0, |n| n.dot(&emission.direction).abs());
+        path.push(Vertex {
+            kind: Kind::Light {
+                index,
+                delta: emission.delta,
+                infinite: emission.infinite,
+            },
+            point: emission.origin,
+            normal: emission.normal,
+            bsdf: None,
+            wo: -emission.direction,
+            beta: emission.radiance / pdf_position as f32,
+            delta: false,
+            pdf_fwd: pdf_position,
+            pdf_rev: 0.0,
+        });
+        let beta = emission.radiance * (cos / (pdf_position * emission.pdf_direction)) as f32;
+        let ray = self.ray(emission.origin, emission.direction);
+        self.random_walk(ray, beta, emission.pdf_direction, max, false, &mut path);
+        // 无穷远的光源：位置的pdf在第一个打中的点上，方向的pdf算在光源上，和从着色点采样它一样
+        if emission.infinite && path.len() > 1 {
+            let first = &path[1];
+            let cos = first
+                .normal
+                .map_or(1.0, |n| n.dot(&emission.direction).abs());
+            path[1].pdf_fwd = emission.pdf_position * cos;
+            path[0].pdf_fwd = light.pdf(&path[1].point, &-emission.direction) * selection;
+        }
+        path
+    }
+
+    /// vertex从prev那边过来、采样到next的面积pdf
+    fn pdf(&self, vertex: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> Float {
+        let pdf = match vertex.kind {
+            Kind::Light { .. } => return self.pdf_light(vertex, next),
+            Kind::Camera => self
+                .camera
+                .as_ref()
+                .map_or(0.0, |c| c.pdf(&direction(vertex, next))),
+            Kind::Surface => match (&vertex.bsdf, prev) {
+                (Some(bsdf), Some(prev)) => {
+                    bsdf.pdf(&direction(vertex, prev), &direction(vertex, next))
+                }
+                _ => 0.0,
+            },
+        };
+        area_pdf(pdf, vertex, next)
+    }
+
+    /// 光源light发出的光线打到next的面积pdf
+    fn pdf_light(&self, light: &Vertex, next: &Vertex) -> Float {
+        let Kind::Light {
+            index, infinite, ..
+        } = light.kind
+        else {
+            return 0.0;
+        };
+        let w = direction(light, next);
+        let cos = next.normal.map_or(1.0, |n| n.dot(&w).abs());
+        let radius = self.bounds.1;
+        if infinite {
+            return cos / (PI * radius * radius);
+        }
+        self.scene.lights[index]
+            .emission(&light.point, &w, &self.bounds)
+            .map_or(0.0, |e| {
+                e.pdf_direction * cos / (next.point - light.point).norm()
+            })
+    }
+
+    /// 光源那头的子路径从light这个点开始的pdf（算上挑中这个光源的概率）；
+    /// 无穷远的光源是从next采样它的方向的立体角pdf
+    fn pdf_light_origin(&self, light: &Vertex, next: &Vertex) -> Float {
+        let Kind::Light {
+            index, infinite, ..
+        } = light.kind
+        else {
+            return 0.0;
+        };
+        let selection = self.lights.pdf(index) as Float;
+        let source = self.scene.lights[index].as_ref();
+        let w = direction(light, next);
+        if infinite {
+            return source.pdf(&next.point, &-w) * selection;
+        }
+        source
+            .emission(&light.point, &w, &self.bounds)
+            .map_or(0.0, |e| e.pdf_position * selection)
+    }
+
+    /// from和to之间有没有挡住，透过去多少
+    fn visibility(&self, from: &Vertex, to: &Vertex) -> Color {
+        let w = direction(from, to);
+        let origin = match from.normal {
+            Some(normal) => offset_origin(self.scene, from.point, normal, &w),
+            None => from.point,
+        };
+        let target = match to.normal {
+            Some(normal) => offset_origin(self.scene, to.point, normal, &-w),
+            None => to.point,
+        };
+        let segment = target - origin;
+        let ray = self.ray(origin, segment.normalize());
+        transmittance(self.scene, self.lights, &ray, segment.length())
+    }
+
+    /// 光源子路径的前s个点和相机子路径的前t个点（t >= 2）连成的路径的贡献，乘过MIS权重
+    fn connect(&self, light: &[Vertex], camera: &[Vertex], s: usize, t: usize) -> Color {
+        let pt = &camera[t - 1];
+        match s {
+            // 相机子路径自己打中了光源
+            0 => {
+                let Kind::Light { index, .. } = pt.kind else {
+                    return Color::black();
+                };
+                let color = pt.beta * self.scene.lights[index].emitted();
+                if color == Color::black() {
+                    return color;
+                }
+                color * self.mis_weight(light, camera, s, t, None) as f32
+            }
+            // 从相机子路径的最后一个点对光源采样
+            1 => {
+                let Some(bsdf) = &pt.bsdf else {
+                    return Color::black();
+                };
+                let Some((index, selection)) = self.lights.sample(random()) else {
+                    return Color::black();
+                };
+                let source = self.scene.lights[index].as_ref();
+                // 会发光的物体打中时已经在scatter_at里算过了，这里再连就重复了
+                if source.object_id().is_some() {
+                    return Color::black();
+                }
+                let sample = source.sample(&pt.point);
+                let f = bsdf.eval(&pt.wo, &sample.direction);
+                if f == Color::black() || sample.intensity == Color::black() {
+                    return Color::black();
+                }
+                let normal = pt.normal.unwrap_or(sample.direction);
+                let shadow = self.ray(
+                    offset_origin(self.scene, pt.point, normal, &sample.direction),
+                    sample.direction,
+                );
+                let transmittance =
+                    transmittance(self.scene, self.lights, &shadow, sample.distance);
+                if transmittance == Color::black() {
+                    return transmittance;
+                }
+                let infinite = sample.distance.is_infinite();
+                let point = if infinite {
+                    pt.point + sample.direction * (2.0 * self.bounds.1)
+                } else {
+                    pt.point + sample.direction * sample.distance
+                };
+                let mut sampled = Vertex {
+                    kind: Kind::Light {
+                        index,
+                        delta: sample.pdf.is_none(),
+                        infinite,
+                    },
+                    point,
+                    normal: source
+                        .emission(&point, &-sample.direction, &self.bounds)
+                        .and_then(|e| e.normal),
+                    bsdf: None,
+                    wo: if infinite {
+                        sample.direction
+                    } else {
+                        -sample.direction
+                    },
+                    beta: Color::black(),
+                    delta: false,
+                    pdf_fwd: 0.0,
+                    pdf_rev: 0.0,
+                };
+                sampled.pdf_fwd = self.pdf_light_origin(&sampled, pt);
+                let weight = self.mis_weight(light, camera, s, t, Some(&sampled));
+                pt.beta
+                    * f
+                    * sample.intensity
+                    * transmittance
+                    * (weight / selection as Float) as f32
+            }
+            _ => {
+                let qs = &light[s - 1];
+                let (Some(pt_bsdf), Some(qs_bsdf)) = (&pt.bsdf, &qs.bsdf) else {
+                    return Color::black();
+                };
+                let w = direction(pt, qs);
+                let color = qs.beta
+                    * qs_bsdf.eval(&qs.wo, &-w)
+                    * pt_bsdf.eval(&pt.wo, &w)
+                    * pt.beta
+                    * (1.0 / (qs.point - pt.point).norm()) as f32;
+                if color == Color::black() {
+                    return color;
+                }
+                let visibility = self.visibility(pt, qs);
+                if visibility == Color::black() {
+                    return visibility;
+                }
+                color * visibility * self.mis_weight(light, camera, s, t, None) as f32
+            }
+        }
+    }
+
+    /// 光源子路径的第s个点直接连到相机，贡献加到它在画面上的像素
+    fn splat(&self, light: &[Vertex], s: usize, splats: &Splats) {
+        let Some(camera) = &self.camera else {
+            return;
+        };
+        let qs = &light[s - 1];
+        let Some(bsdf) = &qs.bsdf else {
+            return;
+        };
+        let Some((x, y)) = camera.pixel(&qs.point) else {
+            return;
+        };
+        let to_camera = camera.origin - qs.point;
+        let w = to_camera.normalize();
+        // 相机的重要性：画面上均匀的相机光线换到立体角上，再换到qs处的面积上
+        let importance = camera.pdf(&-w) / to_camera.norm();
+        let color = qs.beta * bsdf.eval(&qs.wo, &w) * importance as f32;
+        if color == Color::black() {
+            return;
+        }
+        let sampled = Vertex {
+            kind: Kind::Camera,
+            point: camera.origin,
+            normal: None,
+            bsdf: None,
+            wo: -w,
+            beta: Color::white(),
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        let visibility = self.visibility(qs, &sampled);
+        if visibility == Color::black() {
+            return;
+        }
+        let weight = self.mis_weight(light, &[], s, 1, Some(&sampled));
+        splats.add(x, y, color * visibility * weight as f32);
+    }
+
+    /// (s, t)这种连法的MIS权重。把连接点换到路径上别的位置就是别的连法，
+    /// 它们的pdf之比可以从每个点两个方向的pdf一路乘过去；
+    /// sampled是s = 1时对光源采样出的点，或者t = 1时的相机
+    fn mis_weight(
+        &self,
+        light: &[Vertex],
+        camera: &[Vertex],
+        s: usize,
+        t: usize,
+        sampled: Option<&Vertex>,
+    ) -> Float {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let qs = match s {
+            0 => None,
+            1 => sampled,
+            _ => Some(&light[s - 1]),
+        };
+        let pt = if t == 1 { sampled } else { camera.get(t - 1) };
+        let Some(pt) = pt else {
+            return 0.0;
+        };
+        let qs_minus = (s > 1).then(|| &light[s - 2]);
+        let pt_minus = (t > 1).then(|| &camera[t - 2]);
+
+        // 连起来以后，连接点和它们前一个点从另一头采样到的pdf变了
+        let pt_rev = match (qs, pt_minus) {
+            (Some(qs), _) => self.pdf(qs, qs_minus, pt),
+            (None, Some(pt_minus)) => self.pdf_light_origin(pt, pt_minus),
+            (None, None) => 0.0,
+        };
+        let pt_minus_rev = pt_minus.map_or(0.0, |pt_minus| match qs {
+            Some(qs) => self.pdf(pt, Some(qs), pt_minus),
+            None => self.pdf_light(pt, pt_minus),
+        });
+        let qs_rev = qs.map_or(0.0, |qs| self.pdf(pt, pt_minus, qs));
+        let qs_minus_rev = match (qs, qs_minus) {
+            (Some(qs), Some(qs_minus)) => self.pdf(qs, Some(pt), qs_minus),
+            _ => 0.0,
+        };
+
+        // 每个点的(pdf_fwd, pdf_rev, delta)，连接点总是能连的
+        let camera_vertex = |i: usize| {
+            if i + 1 == t {
+                (pt.pdf_fwd, pt_rev, false)
+            } else if i + 2 == t {
+                (camera[i].pdf_fwd, pt_minus_rev, camera[i].delta)
+            } else {
+                (camera[i].pdf_fwd, camera[i].pdf_rev, camera[i].delta)
+            }
+        };
+        let light_vertex = |i: usize| match qs {
+            Some(qs) if i + 1 == s => (qs.pdf_fwd, qs_rev, false),
+            _ if i + 2 == s => (light[i].pdf_fwd, qs_minus_rev, light[i].delta),
+            _ => (light[i].pdf_fwd, light[i].pdf_rev, light[i].delta),
+        };
+        let remap = |pdf: Float| if pdf != 0.0 { pdf } else { 1.0 };
+
+        let mut sum = 0.0;
+        // 相机那头少一个点
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            let (fwd, rev, delta) = camera_vertex(i);
+            ratio *= remap(rev) / remap(fwd);
+            // i = 1是连到相机上，只有透视相机能连
+            if !delta && !camera_vertex(i - 1).2 && (i > 1 || self.camera.is_some()) {
+                sum += ratio * ratio;
+            }
+        }
+        // 光源那头少一个点
+        let light_delta = match (s, qs, light.first()) {
+            (1, Some(v), _) | (_, _, Some(v)) => {
+                matches!(v.kind, Kind::Light { delta: true, .. })
+            }
+            _ => false,
+        };
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            let (fwd, rev, delta) = light_vertex(i);
+            ratio *= remap(rev) / remap(fwd);
+            let delta_before = if i > 0 {
+                light_vertex(i - 1).2
+            } else {
+                light_delta
+            };
+            if !delta && !delta_before {
+                sum += ratio * ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
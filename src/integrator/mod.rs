@@ -0,0 +1,123 @@
+mod bdpt;
+
+pub use bdpt::Bidirectional;
+
+use crate::color::Color;
+use crate::math::Float;
+use crate::rendering::{cast_ray, Crop, Ray};
+use crate::scene::{camera::Camera, light::LightSampler, Scene};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 算一条相机光线带回来的radiance。随机数只能从random()取，图才能复现
+pub trait Integrator: Sync {
+    /// 落到别的像素上的贡献加到splats里
+    fn radiance(&self, scene: &Scene, lights: &LightSampler, ray: &Ray, splats: &Splats) -> Color;
+
+    /// 会不会往Splats里加东西，不会的话渲染时不给Splats分配内存
+    fn light_tracing(&self) -> bool {
+        false
+    }
+}
+
+/// 用哪个积分器
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IntegratorKind {
+    /// 从相机出发的路径追踪，每个着色点对光源采样，和BSDF采样做MIS
+    #[default]
+    Path,
+    /// 双向路径追踪，见Bidirectional。室内只靠一点点开口照亮、焦散很多的场景收敛快得多，
+    /// 每个样本也慢得多
+    Bidirectional,
+}
+
+impl IntegratorKind {
+    pub fn integrator(self) -> &'static dyn Integrator {
+        match self {
+            Self::Path => &PathTracer,
+            Self::Bidirectional => &Bidirectional,
+        }
+    }
+}
+
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(&self, scene: &Scene, lights: &LightSampler, ray: &Ray, _splats: &Splats) -> Color {
+        cast_ray(scene, lights, ray, 0)
+    }
+}
+
+/// 定点数的小数位数，累加的结果和加的顺序无关，多线程渲染的图每次也都一样
+const SPLAT_SCALE: f64 = (1u64 << 32) as f64;
+
+/// 从光源出发的路径直接连到相机时，贡献落在画面上哪个像素是随机的，先加到这里，
+/// 这一次渲染的样本都算完以后再加到像素上。只收crop里的像素
+pub struct Splats {
+    crop: Crop,
+    /// 每个贡献乘上它再加，相机的曝光也算在里面
+    scale: f64,
+    pixels: Vec<[AtomicU64; 3]>,
+    /// 取出来时按相机加暗角
+    camera: Camera,
+    /// 整个画面的大小
+    size: (u32, u32),
+}
+
+impl Splats {
+    /// 渲染crop里的像素、每个像素samples个样本时用的。
+    /// 每个相机样本带一条光源那头的路径，一共只有crop里的像素数乘samples条，
+    /// 落到一个像素上的贡献要按整个画面的像素数折算
+    pub fn new(scene: &Scene, crop: &Crop, samples: u32) -> Self {
+        let size = crop.width as usize * crop.height as usize;
+        let enabled = scene.settings.integrator.integrator().light_tracing() && size > 0;
+        let pixels = if enabled {
+            (0..size).map(|_| Default::default()).collect()
+        } else {
+            Vec::new()
+        };
+        let full = scene.width as f64 * scene.height as f64;
+        Self {
+            crop: *crop,
+            scale: full / (size.max(1) as f64 * samples.max(1) as f64)
+                * scene.camera.exposure.scale() as f64,
+            pixels,
+            camera: scene.camera.clone(),
+            size: (scene.width, scene.height),
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        let crop = &self.crop;
+        let inside =
+            x >= crop.x && x < crop.x + crop.width && y >= crop.y && y < crop.y + crop.height;
+        (inside && !self.pixels.is_empty())
+            .then(|| ((y - crop.y) * crop.width + (x - crop.x)) as usize)
+    }
+
+    pub fn add(&self, x: u32, y: u32, color: Color) {
+        if let Some(index) = self.index(x, y) {
+            for (channel, value) in self.pixels[index].iter().zip([color.r, color.g, color.b]) {
+                // 负数和NaN变成0，太大的饱和
+                let fixed = (value as f64 * self.scale * SPLAT_SCALE) as u64;
+                channel.fetch_add(fixed, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 像素(x, y)上光线追踪的贡献，已经是每个样本的平均了，暗角也加过了
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        match self.index(x, y) {
+            Some(index) => {
+                let [r, g, b] = self.pixels[index]
+                    .each_ref()
+                    .map(|c| (c.load(Ordering::Relaxed) as f64 / SPLAT_SCALE) as f32);
+                let film = (
+                    (x as Float + 0.5) / self.size.0 as Float,
+                    (y as Float + 0.5) / self.size.1 as Float,
+                );
+                Color { r, g, b } * self.camera.vignette(film, self.size)
+            }
+            None => Color::black(),
+        }
+    }
+}
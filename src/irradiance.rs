@@ -0,0 +1,205 @@
+use crate::color::Color;
+use crate::integrator::IntegratorKind;
+use crate::math::{Float, Point, Vector3};
+use crate::rendering::{gather_irradiance, trace, trace_lights, Ray};
+use crate::sampling::{random, start_sample, SampleState, SamplerKind};
+use crate::scene::light::LightSampler;
+use crate::scene::material::SurfaceType;
+use crate::scene::{Distance, Scene};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// 辐照度缓存的设置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IrradianceSettings {
+    /// 每个缓存点往半球上发多少条光线
+    pub samples: usize,
+    /// 允许的插值误差（Ward的a），越小缓存点越密、越准。插值是有偏的，
+    /// 光照变化快的地方会偏暗一点
+    pub accuracy: Float,
+}
+
+/// 一个缓存点：半球上收集到的间接光，radius是周围物体距离的调和平均
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    position: Point,
+    normal: Vector3,
+    radius: Distance,
+    /// 除过π的辐照度，乘上反照率就是出射的radiance
+    irradiance: Color,
+}
+
+impl Record {
+    /// Ward的误差估计，小于accuracy时这个点能用
+    fn error(&self, point: &Point, normal: &Vector3) -> Float {
+        let distance = (*point - self.position).length();
+        distance / self.radius + (1.0 - normal.dot(&self.normal)).max(0.0).sqrt()
+    }
+}
+
+/// 只在相机看得到的漫反射面上放缓存点，着色时附近的点插值出间接光，
+/// 不用每个样本都往下追踪一整条路径。缓存点按最大的有效半径分格子放进哈希表，
+/// 一个点覆盖的格子都记下它，查的时候只看所在的那一格
+pub struct IrradianceCache {
+    accuracy: Float,
+    cell: Distance,
+    records: Vec<Record>,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl IrradianceCache {
+    fn new(records: Vec<Record>, accuracy: Float) -> Self {
+        let cell = records
+            .iter()
+            .map(|r| r.radius * accuracy)
+            .fold(0.0, Float::max)
+            .max(Float::MIN_POSITIVE);
+        let mut cells: HashMap<_, Vec<usize>> = HashMap::new();
+        for (index, record) in records.iter().enumerate() {
+            let reach = record.radius * accuracy;
+            let low = cell_of(&(record.position - Vector3::new(reach, reach, reach)), cell);
+            let high = cell_of(&(record.position + Vector3::new(reach, reach, reach)), cell);
+            for x in low.0..=high.0 {
+                for y in low.1..=high.1 {
+                    for z in low.2..=high.2 {
+                        cells.entry((x, y, z)).or_default().push(index);
+                    }
+                }
+            }
+        }
+        Self {
+            accuracy,
+            cell,
+            records,
+            cells,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// point处插值出来的间接光（除过π的辐照度），附近没有能用的缓存点时是None。
+    /// 权重是1/误差 - 1/accuracy，到有效范围的边上正好降到0，不会有接缝；
+    /// 缓存点在point前面的不用，不然墙角会漏光
+    pub fn lookup(&self, point: &Point, normal: &Vector3) -> Option<Color> {
+        let indices = self.cells.get(&cell_of(point, self.cell))?;
+        let mut sum = Color::black();
+        let mut total = 0.0;
+        for record in indices.iter().map(|&i| &self.records[i]) {
+            let error = record.error(point, normal);
+            if error >= self.accuracy {
+                continue;
+            }
+            let ahead = (*point - record.position).dot(&(*normal + record.normal)) * 0.5;
+            if ahead < -0.05 * record.radius {
+                continue;
+            }
+            let weight = 1.0 / error.max(Float::MIN_POSITIVE) - 1.0 / self.accuracy;
+            sum += record.irradiance * weight as f32;
+            total += weight;
+        }
+        (total > 0.0).then(|| sum / total as f32)
+    }
+}
+
+fn cell_of(point: &Point, size: Distance) -> (i64, i64, i64) {
+    let cell = |v: Float| (v / size).floor() as i64;
+    (cell(point.x), cell(point.y), cell(point.z))
+}
+
+/// 缓存点的随机数和像素的错开
+const IRRADIANCE_SEED: u64 = 0x6972_7261_6463;
+/// 第一轮每隔这么多个像素看一次
+const COARSEST_SPACING: u32 = 16;
+/// 缓存点的有效范围夹在这么多个像素之间：太小的话墙角的点太多，太大的话插值太糊
+const MIN_PIXELS: Float = 1.5;
+const MAX_PIXELS: Float = 20.0;
+
+/// 按scene.settings.irradiance_cache建辐照度缓存，没开或者不是路径追踪的时候是None。
+/// 从每隔16个像素开始，每轮间隔减半，直到每个像素都看过：相机光线打到的漫反射面
+/// 还没被已有的缓存点盖住的，就在那里放一个新的。同一轮里的点并行算、算完一起加进去，
+/// 所以结果和线程数无关
+pub fn irradiance_cache(scene: &Scene, lights: &LightSampler) -> Option<IrradianceCache> {
+    let settings = scene.settings.irradiance_cache?;
+    if scene.settings.integrator != IntegratorKind::Path {
+        return None;
+    }
+    let mut records = Vec::new();
+    let mut cache = IrradianceCache::new(Vec::new(), settings.accuracy);
+    let mut spacing = COARSEST_SPACING;
+    let mut round = 0;
+    loop {
+        let columns = scene.width.div_ceil(spacing);
+        let count = columns as u64 * scene.height.div_ceil(spacing) as u64;
+        #[cfg(feature = "parallel")]
+        let indices = (0..count).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let indices = 0..count;
+        let found: Vec<Record> = indices
+            .filter_map(|i| {
+                let x = (i % columns as u64) as u32 * spacing;
+                let y = (i / columns as u64) as u32 * spacing;
+                let state = SampleState::new(scene.seed ^ IRRADIANCE_SEED, x, y, 0, round, 1);
+                start_sample(SamplerKind::Random, state);
+                new_record(scene, lights, &cache, &settings, x, y)
+            })
+            .collect();
+        records.extend(found);
+        cache = IrradianceCache::new(records.clone(), settings.accuracy);
+        if spacing == 1 {
+            return Some(cache);
+        }
+        spacing /= 2;
+        round += 1;
+    }
+}
+
+/// 像素(x, y)中心的相机光线打到的点需要新的缓存点时算出它
+fn new_record(
+    scene: &Scene,
+    lights: &LightSampler,
+    cache: &IrradianceCache,
+    settings: &IrradianceSettings,
+    x: u32,
+    y: u32,
+) -> Option<Record> {
+    let time = scene.camera.sample_time(random());
+    let ray = Ray::new_prime(x, y, (0.5, 0.5), time, scene)?;
+    let hit = trace(scene, &ray)?;
+    if trace_lights(scene, &ray).is_some_and(|(_, distance)| distance < hit.distance)
+        || hit.item.volume().is_some()
+        || !matches!(
+            hit.material().surface,
+            SurfaceType::Diffuse | SurfaceType::Reflective { .. }
+        )
+    {
+        return None;
+    }
+    let position = ray.origin + ray.direction * hit.distance;
+    let normal = hit.surface_normal(&position);
+    // 背面漫反射是黑的，着色时也不查缓存
+    if normal.dot(&ray.direction) >= 0.0 || cache.lookup(&position, &normal).is_some() {
+        return None;
+    }
+    // 旁边一个像素的光线和这条的夹角，乘上距离就是一个像素在这里有多大
+    let next = Ray::new_prime(x + 1, y, (0.5, 0.5), time, scene)?;
+    let pixel = next.direction.dot(&ray.direction).min(1.0).acos() * hit.distance;
+    if pixel <= 0.0 {
+        return None;
+    }
+    let (irradiance, harmonic) =
+        gather_irradiance(scene, lights, &ray, position, normal, settings.samples);
+    let reach = |pixels: Float| pixels * pixel / settings.accuracy;
+    Some(Record {
+        position,
+        normal,
+        radius: harmonic.clamp(reach(MIN_PIXELS), reach(MAX_PIXELS)),
+        irradiance,
+    })
+}
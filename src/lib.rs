@@ -0,0 +1,32 @@
+// 颜色一直是f32，Float也是f32时那些`as f32`就成了多余的转换
+#![cfg_attr(feature = "f32", allow(clippy::unnecessary_cast))]
+
+pub mod aberration;
+pub mod accel;
+pub mod bloom;
+pub mod bsdf;
+pub mod checkpoint;
+pub mod color;
+#[cfg(feature = "net")]
+pub mod distributed;
+mod error;
+pub mod exr;
+pub mod filter;
+pub mod flare;
+pub mod grading;
+pub mod hdr;
+pub mod integrator;
+pub mod irradiance;
+pub mod math;
+pub mod overlay;
+pub mod photon;
+pub mod preview;
+pub mod rendering;
+pub mod sampling;
+pub mod scene;
+#[cfg(all(feature = "net", feature = "fs"))]
+pub mod service;
+pub mod stats;
+pub mod web;
+
+pub use error::{Error, Result};
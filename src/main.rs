@@ -1,155 +1,296 @@
 extern crate image;
 
-mod color;
-mod math;
-mod rendering;
-mod scene;
+use std::net::TcpListener;
+use std::process;
+use std::time::{Duration, Instant};
 
-use color::Color;
-use math::{Point, Vector3};
-use rendering::render;
-use scene::{
-    item::{Plane, Sphere},
-    light::{DirectionalLight, SphericalLight},
-    material::{Coloration, Material, SurfaceType, Texture},
-    Scene,
+use raytracer::accel::AcceleratorKind;
+use raytracer::checkpoint::{render_for, Accumulator};
+use raytracer::color::Color;
+use raytracer::distributed::{coordinate, work, DEFAULT_TILE_TIMEOUT};
+use raytracer::exr::{aov_layers, light_group_layers, lighting_layers};
+use raytracer::math::{Point, Vector3};
+#[cfg(feature = "preview-window")]
+use raytracer::preview::WindowPreview;
+use raytracer::preview::{watch_scene, FilePreview, TerminalPreview};
+use raytracer::rendering::{
+    aov_pass, light_group_pass, lighting_pass, render_with_stats, CancelToken, Crop,
 };
+use raytracer::scene::{
+    light::LightSampler,
+    material::{Material, Texture},
+    presets::{cornell_box, material_grid, random_spheres},
+    stress::StressScene,
+    Scene, SceneBuilder,
+};
+use raytracer::service::serve;
+use raytracer::Result;
+
+const WATCH_SAMPLES: u32 = 256;
+const WATCH_POLL: Duration = Duration::from_millis(200);
 
+/// 不带参数时在本机渲染；`coordinator <地址> [--tile-timeout <秒数>]`在这个地址上等worker来分块渲染，
+/// 一块超过这么久（默认600秒）没交就交给别的worker；
+/// `worker <地址>`连上coordinator帮它渲染。几台机器跑的是同一个程序，场景也就一样；
+/// `serve <地址>`开HTTP渲染服务，场景文件从请求里来；
+/// `--watch <场景文件> [预览图]`在场景文件改了以后自动重新渲染，没给预览图就显示在终端里，
+/// 打开了preview-window功能时`--watch <场景文件> --window`显示在窗口里；
+/// `--16bit`和不带参数一样，但存成每个通道16位的PNG；
+/// `--max-seconds <秒数>`不管场景的样本数，一直渲染到时间用完，存到test.png；
+/// `--hdr <输出>`存成不clamp的.hdr或.pfm；`--exr <输出>`把主图和法线、深度、albedo、物体ID、样本数和标准误差放进一个EXR；
+/// `--lighting-exr <输出>`另外再放直接光、间接漫反射和间接高光三个通道，要花三倍的时间；
+/// `--light-groups-exr <输出>`另外再放场景里每个光源分组的光，每组多花一倍的时间；
+/// `--stress <个数> [bvh|kdtree]`生成那么多个实例的压力场景，报告加速结构的构建时间和渲染统计；
+/// `--spheres [种子]`渲染《Ray Tracing in One Weekend》封面的随机小球场景，存到spheres.png；
+/// `--cornell`渲染Cornell盒子，存到cornell.png；`--materials`渲染材质展示板，存到materials.png
 fn main() {
-    test_can_render_scene();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
 }
 
-fn test_can_render_scene() {
-    let tex = image::open("tex.png").unwrap();
-    let scene = Scene {
-        width: 1920,
-        height: 1080,
-        fov: 90.0,
-        items: vec![
-            Box::new(Sphere {
-                center: Point {
-                    x: 0.0,
-                    y: 0.5,
-                    z: -3.0,
-                },
-                radius: 1.2,
-                material: Material {
-                    color: Coloration::Color(Color {
-                        r: 1.0,
-                        g: 1.0,
-                        b: 1.0,
-                    }),
-                    albedo: 0.18,
-                    surface: SurfaceType::Refractive {
-                        index: 1.5,
-                        transparency: 0.9,
-                    },
-                },
-            }),
-            Box::new(Sphere {
-                center: Point {
-                    x: 4.0,
-                    y: 2.0,
-                    z: -7.5,
-                },
-                radius: 3.5,
-                material: Material {
-                    color: Coloration::Texture(Texture {
-                        image: tex.to_rgba(),
-                        offset_x: 0.0,
-                        offset_y: 0.0,
-                        scale: 0.1,
-                    }),
-                    /*
-                    color: Coloration::Color(Color{
-                        r: 1.0,
-                        g: 0.0,
-                        b: 0.0,
-                    }),
-                    */
-                    albedo: 0.5,
-                    surface: SurfaceType::Reflective { reflectivity: 0.4 },
-                },
-            }),
-            Box::new(Sphere {
-                center: Point {
-                    x: -7.5,
-                    y: 2.0,
-                    z: -7.5,
-                },
-                radius: 5.0,
-                material: Material {
-                    color: Coloration::Color(Color {
-                        r: 0.0,
-                        g: 0.0,
-                        b: 1.0,
-                    }),
-                    albedo: 2.0,
-                    surface: SurfaceType::Diffuse,
-                },
-            }),
-            Box::new(Plane {
-                pos: Point {
-                    x: 0.0,
-                    y: -7.0,
-                    z: -5.0,
-                },
-                normal: Vector3::new(0.0, -1.0, 0.0).normalize(),
-                material: Material {
-                    color: Coloration::Texture(Texture {
-                        image: tex.to_rgba(),
-                        offset_x: 0.0,
-                        offset_y: 0.0,
-                        scale: 5.0,
-                    }),
-                    albedo: 0.5,
-                    surface: SurfaceType::Reflective { reflectivity: 0.4 },
-                },
-            }),
-            Box::new(Plane {
-                pos: Point {
-                    x: 0.0,
-                    y: 0.0,
-                    z: -15.0,
-                },
-                normal: Vector3::new(0.0, 0.0, -1.0).normalize(),
-                material: Material {
-                    color: Coloration::Texture(Texture {
-                        image: tex.to_rgba(),
-                        offset_x: 0.0,
-                        offset_y: 0.0,
-                        scale: 5.0,
-                    }),
-                    albedo: 0.5,
-                    surface: SurfaceType::Reflective { reflectivity: 0.4 },
-                },
-            }),
-        ],
-        lights: vec![
-            Box::new(DirectionalLight {
-                direction: Vector3::new(-0.5, -1.0, -1.0).normalize(),
-                color: Color {
-                    r: 1.0,
-                    g: 1.0,
-                    b: 1.0,
-                },
-                intensity: 2.0,
-            }),
-            Box::new(SphericalLight {
-                position: Point::new(3.0, 2.0, -3.0),
-                color: Color {
-                    r: 1.0,
-                    g: 1.0,
-                    b: 1.0,
-                },
-                intensity: 255.0,
-            }),
-        ],
-    };
+fn run(args: &[&str]) -> Result<()> {
+    match args {
+        [] => test_can_render_scene()?,
+        ["--16bit"] => {
+            let scene = build_scene()?;
+            let accumulator = accumulate(&scene);
+            accumulator.image16(&scene).save("./test.png")?;
+        }
+        ["--max-seconds", seconds] => {
+            let budget = parse_seconds(seconds)?;
+            let scene = build_scene()?;
+            let start = Instant::now();
+            let accumulator = render_for(&scene, budget);
+            eprintln!(
+                "{} samples per pixel in {:.1?}",
+                accumulator.min_samples(),
+                start.elapsed()
+            );
+            accumulator.image(&scene).save("./test.png")?;
+        }
+        ["--hdr", output] => {
+            let scene = build_scene()?;
+            let accumulator = accumulate(&scene);
+            accumulator.hdr_image(&scene).save(output)?;
+        }
+        ["--exr", output] => {
+            let scene = build_scene()?;
+            let accumulator = accumulate(&scene);
+            let mut exr = aov_layers(&accumulator.hdr_image(&scene), &aov_pass(&scene));
+            let counts = accumulator.sample_counts().iter().map(|&n| n as f32);
+            exr.add_channel("samples.count", counts.collect());
+            let error = accumulator.standard_error();
+            let channel = |f: fn(&Color) -> f32| error.pixels.iter().map(f).collect();
+            exr.add_channel("stderr.R", channel(|c| c.r));
+            exr.add_channel("stderr.G", channel(|c| c.g));
+            exr.add_channel("stderr.B", channel(|c| c.b));
+            exr.save(output)?;
+        }
+        ["--lighting-exr", output] => {
+            let scene = build_scene()?;
+            let lighting = lighting_pass(&scene);
+            lighting_layers(scene.width, scene.height, &lighting, &aov_pass(&scene))
+                .save(output)?;
+        }
+        ["--light-groups-exr", output] => {
+            let scene = build_scene()?;
+            let groups = light_group_pass(&scene);
+            // 最后一张是不在任何组里的光
+            let names = scene
+                .light_groups
+                .iter()
+                .map(|g| g.name.as_str())
+                .chain(["ungrouped"]);
+            let layers: Vec<_> = names.zip(groups).collect();
+            light_group_layers(scene.width, scene.height, &layers, &aov_pass(&scene))
+                .save(output)?;
+        }
+        ["coordinator", address] => coordinator(address, DEFAULT_TILE_TIMEOUT)?,
+        ["coordinator", address, "--tile-timeout", seconds] => {
+            coordinator(address, parse_seconds(seconds)?)?
+        }
+        ["worker", address] => {
+            let tiles = work(&build_scene()?, address)?;
+            eprintln!("rendered {} tiles", tiles);
+        }
+        ["serve", address] => {
+            let listener = TcpListener::bind(address)?;
+            serve(&listener, std::env::current_dir()?)?;
+        }
+        ["--watch", scene] => {
+            let mut preview = TerminalPreview::new(120);
+            watch_scene(scene, WATCH_SAMPLES, &mut preview, WATCH_POLL)?;
+        }
+        #[cfg(feature = "preview-window")]
+        ["--watch", scene, "--window"] => {
+            let mut preview = WindowPreview::new(*scene);
+            watch_scene(scene, WATCH_SAMPLES, &mut preview, WATCH_POLL)?;
+        }
+        ["--watch", scene, output] => {
+            let mut preview = FilePreview {
+                path: output.into(),
+            };
+            watch_scene(scene, WATCH_SAMPLES, &mut preview, WATCH_POLL)?;
+        }
+        ["--spheres"] => spheres("0")?,
+        ["--spheres", seed] => spheres(seed)?,
+        ["--cornell"] => preset(cornell_box().samples(64), "./cornell.png")?,
+        ["--materials"] => preset(material_grid().samples(64), "./materials.png")?,
+        ["--stress", count] => stress(count, AcceleratorKind::Bvh)?,
+        ["--stress", count, "bvh"] => stress(count, AcceleratorKind::Bvh)?,
+        ["--stress", count, "kdtree"] => stress(count, AcceleratorKind::KdTree)?,
+        _ => eprintln!(
+            "usage: raytracer [coordinator <address> | worker <address> | serve <address> \
+             | --watch <scene> [preview.png | --window] | --16bit | --max-seconds <seconds> | --hdr <out.hdr|out.pfm> | --exr <out.exr> \
+             | --lighting-exr <out.exr> | --light-groups-exr <out.exr> | --stress <count> [bvh|kdtree] | --spheres [seed] | --cornell | --materials]"
+        ),
+    }
+    Ok(())
+}
+
+/// 命令行上的秒数，负数、NaN或者大到Duration放不下的都是错误
+fn parse_seconds(seconds: &str) -> Result<Duration> {
+    seconds
+        .parse()
+        .ok()
+        .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        .ok_or_else(|| raytracer::Error::Parse(format!("bad number of seconds {:?}", seconds)))
+}
+
+fn coordinator(address: &str, tile_timeout: Duration) -> Result<()> {
+    let scene = build_scene()?;
+    let listener = TcpListener::bind(address)?;
+    let img = coordinate(&scene, &listener, 64, tile_timeout, |done, total| {
+        eprint!("\r{}/{} tiles", done, total)
+    })?
+    .to_rgb();
+    eprintln!();
+    img.save("./test.png")?;
+    Ok(())
+}
+
+/// 每个像素渲染场景设置的样本数，留着累积结果
+fn accumulate(scene: &Scene) -> Accumulator {
+    let mut accumulator = Accumulator::new(scene.width, scene.height);
+    let lights = LightSampler::for_scene(scene);
+    accumulator.add_samples(scene, &lights, scene.settings.samples);
+    accumulator
+}
 
-    let img = render(&scene).to_rgb();
+fn test_can_render_scene() -> Result<()> {
+    let scene = build_scene()?;
+    let (img, stats) = render_with_stats(
+        &scene,
+        &Crop::full(&scene),
+        |progress| {
+            eprint!(
+                "\r{:5.1}%  eta {:>4}s",
+                progress.fraction() * 100.0,
+                progress.eta.map_or(0, |eta| eta.as_secs())
+            );
+        },
+        &CancelToken::new(),
+    );
+    let img = img.to_rgb();
+    eprintln!();
+    eprintln!("{}", stats);
     assert_eq!(scene.width, img.width());
     assert_eq!(scene.height, img.height());
 
-    img.save("./test.png").unwrap();
+    img.save("./test.png")?;
+    Ok(())
+}
+
+fn stress(count: &str, accelerator: AcceleratorKind) -> Result<()> {
+    let count = count
+        .parse()
+        .map_err(|_| raytracer::Error::Parse(format!("bad instance count {:?}", count)))?;
+    let settings = StressScene {
+        count,
+        ..StressScene::default()
+    };
+    let mut scene = settings.builder().size(640, 360).samples(4).build()?;
+    let start = Instant::now();
+    scene.accelerate(accelerator);
+    eprintln!("{} instances, built in {:?}", count, start.elapsed());
+    let (img, stats) = render_with_stats(
+        &scene,
+        &Crop::full(&scene),
+        |progress| eprint!("\r{:5.1}%", progress.fraction() * 100.0),
+        &CancelToken::new(),
+    );
+    eprintln!();
+    eprintln!("{}", stats);
+    img.to_rgb().save("./stress.png")?;
+    Ok(())
+}
+
+fn spheres(seed: &str) -> Result<()> {
+    let seed = seed
+        .parse()
+        .map_err(|_| raytracer::Error::Parse(format!("bad seed {:?}", seed)))?;
+    preset(random_spheres(seed).samples(16), "./spheres.png")
+}
+
+/// 渲染内置的场景，打印统计
+fn preset(builder: SceneBuilder, path: &str) -> Result<()> {
+    let mut scene = builder.build()?;
+    scene.accelerate(AcceleratorKind::Bvh);
+    let (img, stats) = render_with_stats(
+        &scene,
+        &Crop::full(&scene),
+        |progress| eprint!("\r{:5.1}%", progress.fraction() * 100.0),
+        &CancelToken::new(),
+    );
+    eprintln!();
+    eprintln!("{}", stats);
+    img.to_rgb().save(path)?;
+    Ok(())
+}
+
+fn build_scene() -> Result<Scene> {
+    let tex = Texture::open("tex.png")?;
+    let scene = SceneBuilder::new()
+        .size(1920, 1080)
+        .fov(90.0)
+        .material(
+            "tiles",
+            Material::reflective(tex.clone().with_scale(5.0), 0.4),
+        )
+        .add_sphere(
+            Point::new(0.0, 0.5, -3.0),
+            1.2,
+            Material::refractive(1.5, 0.9).with_albedo(0.18),
+        )
+        .add_sphere(
+            Point::new(4.0, 2.0, -7.5),
+            3.5,
+            Material::reflective(tex.with_scale(0.1), 0.4),
+        )
+        .add_sphere(
+            Point::new(-7.5, 2.0, -7.5),
+            5.0,
+            Material::diffuse(Color::new(0.0, 0.0, 1.0)).with_albedo(2.0),
+        )
+        .add_plane(
+            Point::new(0.0, -7.0, -5.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            "tiles",
+        )
+        .add_plane(
+            Point::new(0.0, 0.0, -15.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            "tiles",
+        )
+        .add_directional_light(Vector3::new(-0.5, -1.0, -1.0), Color::white(), 2.0)
+        .add_spherical_light(Point::new(3.0, 2.0, -3.0), 0.0, Color::white(), 255.0)
+        .build()?;
+    for warning in scene.validate().check()? {
+        eprintln!("warning: {}", warning);
+    }
+    Ok(scene)
 }
@@ -0,0 +1,102 @@
+use crate::math::{Affine, Float, Point, Vector3};
+
+/// 轴对齐包围盒
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Self {
+        Self { min, max }
+    }
+
+    /// 包住所有点的最小的盒子
+    pub fn from_points(points: &[Point]) -> Self {
+        let mut bounds = Aabb::new(points[0], points[0]);
+        for p in &points[1..] {
+            bounds = bounds.union(&Aabb::new(*p, *p));
+        }
+        bounds
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Point::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    pub fn centroid(&self) -> Point {
+        self.min + (self.max - self.min) * 0.5
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// 最长的那个轴
+    pub fn longest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d.x >= d.y && d.x >= d.z {
+            0
+        } else if d.y >= d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// 八个角变换之后再包起来
+    pub fn transformed(&self, transform: &Affine) -> Aabb {
+        let mut corners = Vec::with_capacity(8);
+        for &x in &[self.min.x, self.max.x] {
+            for &y in &[self.min.y, self.max.y] {
+                for &z in &[self.min.z, self.max.z] {
+                    corners.push(transform.point(&Point::new(x, y, z)));
+                }
+            }
+        }
+        Aabb::from_points(&corners)
+    }
+
+    /// slab法求射线和盒子相交的区间[t0, t1]，t0会被截到0
+    pub fn hit(&self, origin: &Point, direction: &Vector3) -> Option<(Float, Float)> {
+        let mut t0: Float = 0.0;
+        let mut t1 = Float::INFINITY;
+        let axes = [
+            (origin.x, direction.x, self.min.x, self.max.x),
+            (origin.y, direction.y, self.min.y, self.max.y),
+            (origin.z, direction.z, self.min.z, self.max.z),
+        ];
+        for (o, d, min, max) in axes.iter() {
+            let inv = 1.0 / d;
+            let (near, far) = {
+                let a = (min - o) * inv;
+                let b = (max - o) * inv;
+                if a < b {
+                    (a, b)
+                } else {
+                    (b, a)
+                }
+            };
+            // 平行于某个面且在外面时near/far是NaN，max/min会忽略NaN
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
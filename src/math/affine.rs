@@ -0,0 +1,103 @@
+use crate::math::{Float, Point, Vector3};
+
+/// 仿射变换 p' = linear * p + translation，linear按行存
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Affine {
+    pub linear: [[Float; 3]; 3],
+    pub translation: Vector3,
+}
+
+impl Affine {
+    pub fn identity() -> Self {
+        Self {
+            linear: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            translation: Vector3::zero(),
+        }
+    }
+
+    /// 三个列向量就是局部坐标系的x、y、z轴变换之后的样子
+    pub fn from_columns(x: Vector3, y: Vector3, z: Vector3, translation: Vector3) -> Self {
+        Self {
+            linear: [[x.x, y.x, z.x], [x.y, y.y, z.y], [x.z, y.z, z.z]],
+            translation,
+        }
+    }
+
+    pub fn point(&self, p: &Point) -> Point {
+        let v = self.vector(&Vector3::new(p.x, p.y, p.z)) + self.translation;
+        Point::new(v.x, v.y, v.z)
+    }
+
+    pub fn vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.linear;
+        Vector3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// 法线要用逆矩阵的转置，不等比缩放时才不会歪
+    pub fn normal(&self, n: &Vector3) -> Vector3 {
+        let m = &self.inverse().linear;
+        Vector3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+        .normalize()
+    }
+
+    /// 先做self再做outer
+    pub fn then(&self, outer: &Affine) -> Affine {
+        let column = |i: usize| {
+            outer.vector(&Vector3::new(
+                self.linear[0][i],
+                self.linear[1][i],
+                self.linear[2][i],
+            ))
+        };
+        Affine::from_columns(
+            column(0),
+            column(1),
+            column(2),
+            outer.vector(&self.translation) + outer.translation,
+        )
+    }
+
+    pub fn inverse(&self) -> Affine {
+        let m = &self.linear;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let c00 = cofactor(1, 2, 1, 2);
+        let c01 = -cofactor(1, 2, 0, 2);
+        let c02 = cofactor(1, 2, 0, 1);
+        let det = m[0][0] * c00 + m[0][1] * c01 + m[0][2] * c02;
+        let inv_det = 1.0 / det;
+        // 伴随矩阵就是余子式矩阵的转置
+        let linear = [
+            [
+                c00 * inv_det,
+                -cofactor(0, 2, 1, 2) * inv_det,
+                cofactor(0, 1, 1, 2) * inv_det,
+            ],
+            [
+                c01 * inv_det,
+                cofactor(0, 2, 0, 2) * inv_det,
+                -cofactor(0, 1, 0, 2) * inv_det,
+            ],
+            [
+                c02 * inv_det,
+                -cofactor(0, 2, 0, 1) * inv_det,
+                cofactor(0, 1, 0, 1) * inv_det,
+            ],
+        ];
+        let mut inverse = Affine {
+            linear,
+            translation: Vector3::zero(),
+        };
+        inverse.translation = -inverse.vector(&self.translation);
+        inverse
+    }
+}
@@ -1,5 +1,25 @@
+mod aabb;
+mod affine;
 mod point;
+mod simd;
+mod transform;
 mod vector3;
 
+pub use aabb::Aabb;
+pub use affine::Affine;
 pub use point::Point;
+pub use simd::{Float4, Vector3x4};
+pub use transform::Transform;
 pub use vector3::Vector3;
+
+/// 几何和渲染计算用的浮点类型，打开`f32` feature时换成单精度：
+/// SIMD一次能算的个数翻倍，加速结构也只占一半内存；大场景还是用默认的f64
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
@@ -1,21 +1,30 @@
-use crate::math::Vector3;
+use crate::math::{Float, Vector3};
 use std::ops::{Add, Sub};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Point {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl Point {
     pub fn zero() -> Self {
-        Self::new(0f64, 0f64, 0f64)
+        Self::new(0.0, 0.0, 0.0)
     }
 
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
         Self { x, y, z }
     }
+
+    /// 第axis个轴（0、1、2对应x、y、z）上的分量
+    pub fn axis(&self, axis: usize) -> Float {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
 }
 
 impl Sub<Point> for Point {
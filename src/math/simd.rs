@@ -0,0 +1,166 @@
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::math::{Float, Point, Vector3};
+
+/// 四个Float打包在一起（默认是f64）。运算都按lane循环写，对齐到32字节，
+/// LLVM会把它们编成SIMD指令（开了AVX时一条指令就算完四个）
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C, align(32))]
+pub struct Float4(pub [Float; 4]);
+
+impl Float4 {
+    #[inline]
+    pub fn splat(v: Float) -> Self {
+        Float4([v; 4])
+    }
+
+    #[inline]
+    fn map(self, f: impl Fn(Float) -> Float) -> Self {
+        Float4([f(self.0[0]), f(self.0[1]), f(self.0[2]), f(self.0[3])])
+    }
+
+    #[inline]
+    fn zip(self, other: Self, f: impl Fn(Float, Float) -> Float) -> Self {
+        Float4([
+            f(self.0[0], other.0[0]),
+            f(self.0[1], other.0[1]),
+            f(self.0[2], other.0[2]),
+            f(self.0[3], other.0[3]),
+        ])
+    }
+
+    #[inline]
+    pub fn sqrt(self) -> Self {
+        self.map(Float::sqrt)
+    }
+
+    #[inline]
+    pub fn recip(self) -> Self {
+        self.map(Float::recip)
+    }
+
+    #[inline]
+    pub fn min(self, other: Self) -> Self {
+        self.zip(other, Float::min)
+    }
+
+    #[inline]
+    pub fn max(self, other: Self) -> Self {
+        self.zip(other, Float::max)
+    }
+}
+
+impl Add for Float4 {
+    type Output = Float4;
+
+    #[inline]
+    fn add(self, other: Float4) -> Float4 {
+        self.zip(other, |a, b| a + b)
+    }
+}
+
+impl Sub for Float4 {
+    type Output = Float4;
+
+    #[inline]
+    fn sub(self, other: Float4) -> Float4 {
+        self.zip(other, |a, b| a - b)
+    }
+}
+
+impl Mul for Float4 {
+    type Output = Float4;
+
+    #[inline]
+    fn mul(self, other: Float4) -> Float4 {
+        self.zip(other, |a, b| a * b)
+    }
+}
+
+impl Div for Float4 {
+    type Output = Float4;
+
+    #[inline]
+    fn div(self, other: Float4) -> Float4 {
+        self.zip(other, |a, b| a / b)
+    }
+}
+
+impl Neg for Float4 {
+    type Output = Float4;
+
+    #[inline]
+    fn neg(self) -> Float4 {
+        self.map(|a| -a)
+    }
+}
+
+/// 四个向量按分量分开存（SoA），一次算四个点积、叉积
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Vector3x4 {
+    pub x: Float4,
+    pub y: Float4,
+    pub z: Float4,
+}
+
+impl Vector3x4 {
+    #[inline]
+    pub fn splat(v: &Vector3) -> Self {
+        Self {
+            x: Float4::splat(v.x),
+            y: Float4::splat(v.y),
+            z: Float4::splat(v.z),
+        }
+    }
+
+    pub fn from_points(points: [Point; 4]) -> Self {
+        Self {
+            x: Float4([points[0].x, points[1].x, points[2].x, points[3].x]),
+            y: Float4([points[0].y, points[1].y, points[2].y, points[3].y]),
+            z: Float4([points[0].z, points[1].z, points[2].z, points[3].z]),
+        }
+    }
+
+    /// 取出第lane个向量
+    #[inline]
+    pub fn lane(&self, lane: usize) -> Vector3 {
+        Vector3::new(self.x.0[lane], self.y.0[lane], self.z.0[lane])
+    }
+
+    #[inline]
+    pub fn dot(&self, other: &Vector3x4) -> Float4 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    #[inline]
+    pub fn cross(&self, other: &Vector3x4) -> Vector3x4 {
+        Vector3x4 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    #[inline]
+    pub fn normalize(&self) -> Vector3x4 {
+        let inv_len = self.dot(self).sqrt().recip();
+        Vector3x4 {
+            x: self.x * inv_len,
+            y: self.y * inv_len,
+            z: self.z * inv_len,
+        }
+    }
+}
+
+impl Sub for Vector3x4 {
+    type Output = Vector3x4;
+
+    #[inline]
+    fn sub(self, other: Vector3x4) -> Vector3x4 {
+        Vector3x4 {
+            x: self.x - other.x,
+            y: self.y - other.y,
+            z: self.z - other.z,
+        }
+    }
+}
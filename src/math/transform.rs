@@ -0,0 +1,106 @@
+use crate::math::{Affine, Float, Point, Vector3};
+
+/// 先等比缩放，再按x、y、z的顺序绕轴旋转（弧度），最后平移
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: Vector3,
+    pub scale: Float,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            rotation: Vector3::zero(),
+            scale: 1.0,
+        }
+    }
+
+    pub fn translate(translation: Vector3) -> Self {
+        Self {
+            translation,
+            ..Self::identity()
+        }
+    }
+
+    /// 逐个分量线性插值，t = 0是self，t = 1是other
+    pub fn lerp(&self, other: &Transform, t: Float) -> Transform {
+        Transform {
+            translation: self.translation * (1.0 - t) + other.translation * t,
+            rotation: self.rotation * (1.0 - t) + other.rotation * t,
+            scale: self.scale * (1.0 - t) + other.scale * t,
+        }
+    }
+
+    pub fn affine(&self) -> Affine {
+        Affine::from_columns(
+            self.vector(&Vector3::new(1.0, 0.0, 0.0)),
+            self.vector(&Vector3::new(0.0, 1.0, 0.0)),
+            self.vector(&Vector3::new(0.0, 0.0, 1.0)),
+            self.translation,
+        )
+    }
+
+    pub fn point(&self, p: &Point) -> Point {
+        let v = Vector3::new(p.x, p.y, p.z);
+        let v = self.vector(&v) + self.translation;
+        Point::new(v.x, v.y, v.z)
+    }
+
+    pub fn vector(&self, v: &Vector3) -> Vector3 {
+        self.rotate(&(*v * self.scale))
+    }
+
+    /// 只有等比缩放，法线跟着旋转就行
+    pub fn normal(&self, n: &Vector3) -> Vector3 {
+        self.rotate(n).normalize()
+    }
+
+    pub fn inverse_point(&self, p: &Point) -> Point {
+        let v = Vector3::new(p.x, p.y, p.z) - self.translation;
+        let v = self.inverse_vector(&v);
+        Point::new(v.x, v.y, v.z)
+    }
+
+    pub fn inverse_vector(&self, v: &Vector3) -> Vector3 {
+        self.inverse_rotate(v) * (1.0 / self.scale)
+    }
+
+    pub fn inverse_normal(&self, n: &Vector3) -> Vector3 {
+        self.inverse_rotate(n).normalize()
+    }
+
+    fn rotate(&self, v: &Vector3) -> Vector3 {
+        let v = rotate_x(v, self.rotation.x);
+        let v = rotate_y(&v, self.rotation.y);
+        rotate_z(&v, self.rotation.z)
+    }
+
+    fn inverse_rotate(&self, v: &Vector3) -> Vector3 {
+        let v = rotate_z(v, -self.rotation.z);
+        let v = rotate_y(&v, -self.rotation.y);
+        rotate_x(&v, -self.rotation.x)
+    }
+}
+
+fn rotate_x(v: &Vector3, angle: Float) -> Vector3 {
+    let (s, c) = angle.sin_cos();
+    Vector3::new(v.x, c * v.y - s * v.z, s * v.y + c * v.z)
+}
+
+fn rotate_y(v: &Vector3, angle: Float) -> Vector3 {
+    let (s, c) = angle.sin_cos();
+    Vector3::new(c * v.x + s * v.z, v.y, -s * v.x + c * v.z)
+}
+
+fn rotate_z(v: &Vector3, angle: Float) -> Vector3 {
+    let (s, c) = angle.sin_cos();
+    Vector3::new(c * v.x - s * v.y, s * v.x + c * v.y, v.z)
+}
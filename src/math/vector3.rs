@@ -1,29 +1,38 @@
 use std::ops::{Add, Mul, Neg, Sub};
 
-use crate::math::Point;
+use crate::math::{Float, Point};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Vector3 {
-    pub x: f64,
-    pub y: f64,
-    pub z: f64,
+    pub x: Float,
+    pub y: Float,
+    pub z: Float,
 }
 
 impl Vector3 {
     pub fn zero() -> Self {
-        Self::new(0f64, 0f64, 0f64)
+        Self::new(0.0, 0.0, 0.0)
     }
 
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
         Self { x, y, z }
     }
 
-    pub fn length(&self) -> f64 {
+    /// 第axis个轴（0、1、2对应x、y、z）上的分量
+    pub fn axis(&self, axis: usize) -> Float {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
+
+    pub fn length(&self) -> Float {
         self.norm().sqrt()
     }
 
-    pub fn norm(&self) -> f64 {
-        (self.x * self.x + self.y * self.y + self.z * self.z)
+    pub fn norm(&self) -> Float {
+        self.x * self.x + self.y * self.y + self.z * self.z
     }
 
     pub fn normalize(&self) -> Vector3 {
@@ -35,7 +44,7 @@ impl Vector3 {
         }
     }
 
-    pub fn dot(&self, other: &Vector3) -> f64 {
+    pub fn dot(&self, other: &Vector3) -> Float {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
@@ -104,10 +113,10 @@ impl Sub<Point> for Vector3 {
     }
 }
 
-impl Mul<f64> for Vector3 {
+impl Mul<Float> for Vector3 {
     type Output = Vector3;
 
-    fn mul(self, other: f64) -> Vector3 {
+    fn mul(self, other: Float) -> Vector3 {
         Vector3 {
             x: self.x * other,
             y: self.y * other,
@@ -0,0 +1,108 @@
+use crate::color::{heatmap, Color};
+use crate::math::{Aabb, Float, Point};
+use crate::rendering::Ray;
+use crate::scene::Scene;
+use image::{imageops, DynamicImage, ImageBuffer, Rgba};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// 线宽，单位是像素
+const LINE_WIDTH: Float = 1.5;
+
+/// 叠加层上的一个框
+#[derive(Debug, Clone, Copy)]
+pub struct BoundsBox {
+    pub bounds: Aabb,
+    /// None是物体自己的包围盒，Some是加速结构的节点在树里的深度，根是0
+    pub depth: Option<usize>,
+}
+
+/// 场景里所有要画的框：每个物体的包围盒，加速结构再加上它的每个节点
+pub fn scene_bounds(scene: &Scene) -> Vec<BoundsBox> {
+    let mut boxes = Vec::new();
+    for item in &scene.items {
+        item.collect_bounds(&mut boxes);
+    }
+    boxes
+}
+
+/// 物体的框是白的，节点按深度从蓝到红
+fn box_color(b: &BoundsBox, max_depth: usize) -> Color {
+    match b.depth {
+        None => Color::white(),
+        Some(depth) => heatmap(0.25 + 0.75 * depth as f32 / max_depth.max(1) as f32),
+    }
+}
+
+/// p离盒子的某条棱不到width：至少有两个轴上贴着面
+fn near_edge(b: &Aabb, p: &Point, width: Float) -> bool {
+    (0..3)
+        .filter(|&axis| {
+            let v = p.axis(axis);
+            (v - b.min.axis(axis)).abs() < width || (v - b.max.axis(axis)).abs() < width
+        })
+        .count()
+        >= 2
+}
+
+/// 像素(x, y)中心的光线穿过的框里，离相机最近的一条棱的颜色
+fn pixel(scene: &Scene, boxes: &[BoundsBox], max_depth: usize, x: u32, y: u32) -> Rgba<u8> {
+    let time = scene.camera.shutter.0;
+    let (ray, next) = match (
+        Ray::new_prime(x, y, (0.5, 0.5), time, scene),
+        Ray::new_prime(x, y, (1.5, 0.5), time, scene),
+    ) {
+        (Some(ray), Some(next)) => (ray, next),
+        _ => return Rgba([0, 0, 0, 0]),
+    };
+    // 相邻像素的光线夹角，乘上距离就是一个像素在那里有多宽
+    let pixel_angle = ray.direction.dot(&next.direction).clamp(-1.0, 1.0).acos();
+    let mut nearest: Option<(Float, &BoundsBox)> = None;
+    for b in boxes {
+        let (t0, t1) = match b.bounds.hit(&ray.origin, &ray.direction) {
+            Some(hit) => hit,
+            None => continue,
+        };
+        for t in [t0, t1] {
+            let p = ray.origin + ray.direction * t;
+            if nearest.is_none_or(|(d, _)| t < d)
+                && near_edge(&b.bounds, &p, pixel_angle * t * LINE_WIDTH)
+            {
+                nearest = Some((t, b));
+            }
+        }
+    }
+    match nearest {
+        Some((_, b)) => Rgba::from(box_color(b, max_depth).to_rgba8()),
+        None => Rgba([0, 0, 0, 0]),
+    }
+}
+
+/// 把场景里物体的包围盒和加速结构的节点画成线框，没有线的地方是透明的。
+/// 线不会被物体挡住，盒子背面的棱也画出来
+pub fn bounds_overlay(scene: &Scene) -> DynamicImage {
+    let boxes = scene_bounds(scene);
+    let max_depth = boxes.iter().filter_map(|b| b.depth).max().unwrap_or(0);
+    let width = scene.width;
+    #[cfg(feature = "parallel")]
+    let rows = (0..scene.height).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let rows = 0..scene.height;
+    let rows: Vec<Vec<Rgba<u8>>> = rows
+        .map(|y| {
+            (0..width)
+                .map(|x| pixel(scene, &boxes, max_depth, x, y))
+                .collect()
+        })
+        .collect();
+    DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, scene.height, |x, y| {
+        rows[y as usize][x as usize]
+    }))
+}
+
+/// 把bounds_overlay的线框叠在渲染好的图上
+pub fn draw_bounds(scene: &Scene, image: &DynamicImage) -> DynamicImage {
+    let mut base = image.to_rgba();
+    imageops::overlay(&mut base, &bounds_overlay(scene).to_rgba(), 0, 0);
+    DynamicImage::ImageRgba8(base)
+}
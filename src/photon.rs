@@ -0,0 +1,320 @@
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Point, Vector3};
+use crate::rendering::{fresnel, trace, Ray};
+use crate::sampling::{random, random_2d, start_sample, SampleState, SamplerKind};
+use crate::scene::light::LightSampler;
+use crate::scene::material::SurfaceType;
+use crate::scene::{Distance, Scene};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// 焦散光子图的设置
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CausticSettings {
+    /// 每次渲染发多少个光子
+    pub photons: usize,
+    /// 收集光子的半径，越大越平滑、越模糊
+    pub radius: Distance,
+}
+
+/// 光源怎么发光子
+#[derive(Debug, Clone, Copy)]
+pub enum Emitter {
+    /// 从一个点往四面八方均匀地发，intensity是每单位立体角的功率
+    Point { position: Point, intensity: Color },
+    /// 从无穷远沿direction平行地来，irradiance是垂直于光线的每单位面积的功率
+    Parallel {
+        direction: Vector3,
+        irradiance: Color,
+    },
+}
+
+/// 停在漫反射面上的光子：位置、飞过来的方向和带的功率
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub position: Point,
+    pub direction: Vector3,
+    pub power: Color,
+}
+
+/// 光子按半径大小的格子放进哈希表，查一个点只用看周围3×3×3个格子
+pub struct PhotonMap {
+    radius: Distance,
+    photons: Vec<Photon>,
+    cells: HashMap<(i64, i64, i64), (usize, usize)>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>, radius: Distance) -> Self {
+        let cell = |p: &Photon| cell_of(&p.position, radius);
+        photons.sort_by_key(cell);
+        let mut cells = HashMap::new();
+        let mut start = 0;
+        for chunk in photons.chunk_by(|a, b| cell(a) == cell(b)) {
+            cells.insert(cell(&chunk[0]), (start, chunk.len()));
+            start += chunk.len();
+        }
+        Self {
+            radius,
+            photons,
+            cells,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// point处的辐照度估计：半径里和incoming从同一侧打到面上的光子，
+    /// 按Epanechnikov核加权（中间重、边上是0），再除以面积
+    pub fn irradiance(&self, point: &Point, normal: &Vector3, incoming: &Vector3) -> Color {
+        let r2 = self.radius * self.radius;
+        let side = normal.dot(incoming);
+        let (cx, cy, cz) = cell_of(point, self.radius);
+        let mut sum = Color::black();
+        for x in cx - 1..=cx + 1 {
+            for y in cy - 1..=cy + 1 {
+                for z in cz - 1..=cz + 1 {
+                    let Some(&(start, count)) = self.cells.get(&(x, y, z)) else {
+                        continue;
+                    };
+                    for photon in &self.photons[start..start + count] {
+                        let d2 = (photon.position - *point).norm();
+                        if d2 < r2 && photon.direction.dot(normal) * side > 0.0 {
+                            sum += photon.power * (1.0 - d2 / r2) as f32;
+                        }
+                    }
+                }
+            }
+        }
+        sum * (2.0 / (PI * r2)) as f32
+    }
+}
+
+fn cell_of(point: &Point, size: Distance) -> (i64, i64, i64) {
+    let cell = |v: Float| (v / size).floor() as i64;
+    (cell(point.x), cell(point.y), cell(point.z))
+}
+
+/// 光子的随机数和像素的错开
+const PHOTON_SEED: u64 = 0x7068_6f74_6f6e;
+
+/// 按scene.settings.caustics建焦散光子图，没开的时候是None。
+/// 光子只朝会产生焦散的物体（玻璃、镜子）发，经过至少一次镜面反射或折射、停在漫反射面上的才留下，
+/// 这正是路径追踪几乎找不到的那些路径。面光源按它中心处的点光源发，天光不发光子
+pub fn caustic_map(scene: &Scene) -> Option<PhotonMap> {
+    let settings = scene.settings.caustics?;
+    let mut targets = Vec::new();
+    for item in &scene.items {
+        item.caustic_bounds(&mut targets);
+    }
+    let targets: Vec<(Point, Distance)> = targets
+        .iter()
+        .map(|b| {
+            (
+                b.min + (b.max - b.min) * 0.5,
+                (b.max - b.min).length() * 0.5,
+            )
+        })
+        .collect();
+    let emitters: Vec<_> = scene.lights.iter().filter_map(|l| l.emitter()).collect();
+    if targets.is_empty() || emitters.is_empty() {
+        return Some(PhotonMap::new(Vec::new(), settings.radius));
+    }
+    // 按功率在光源之间分光子
+    let powers: Vec<f32> = emitters
+        .iter()
+        .map(|e| match e {
+            Emitter::Point { intensity, .. } => intensity.luminance() * 4.0 * PI as f32,
+            Emitter::Parallel { irradiance, .. } => irradiance.luminance(),
+        })
+        .collect();
+    let selection = LightSampler::from_powers(&powers);
+
+    let count = settings.photons;
+    #[cfg(feature = "parallel")]
+    let indices = (0..count).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let indices = 0..count;
+    let photons: Vec<Vec<Photon>> = indices
+        .map(|i| {
+            let state = SampleState::new(scene.seed ^ PHOTON_SEED, 0, 0, 0, i as u64, 1);
+            start_sample(SamplerKind::Random, state);
+            let (index, pdf) = selection.sample(random())?;
+            let (ray, power) = emit(scene, &emitters[index], &targets)?;
+            Some(trace_photon(scene, ray, power / (pdf * count as f32)))
+        })
+        .map(Option::unwrap_or_default)
+        .collect();
+    Some(PhotonMap::new(photons.concat(), settings.radius))
+}
+
+/// 从emitter朝targets发一个光子，返回光线和功率（已经除过采样的pdf）。
+/// 先按目标的立体角（平行光按投影面积）选一个目标，再在它里面均匀采样；
+/// 几个目标重叠的地方能从好几个目标采到，pdf要按覆盖它的目标个数算
+fn emit(scene: &Scene, emitter: &Emitter, targets: &[(Point, Distance)]) -> Option<(Ray, Color)> {
+    let time = scene.camera.sample_time(random());
+    let ray = |origin, direction| Ray {
+        origin,
+        direction,
+        wavelength: None,
+        time,
+        camera: false,
+    };
+    let pick = |sizes: &[Float]| {
+        let total: Float = sizes.iter().sum();
+        let mut u = random() * total;
+        let index = sizes.iter().position(|&s| {
+            u -= s;
+            u < 0.0
+        });
+        (index.unwrap_or(sizes.len() - 1), total)
+    };
+    match *emitter {
+        Emitter::Point {
+            position,
+            intensity,
+        } => {
+            let cos_max = |&(center, radius): &(Point, Distance)| {
+                let d2 = (center - position).norm();
+                if d2 <= radius * radius {
+                    -1.0
+                } else {
+                    (1.0 - radius * radius / d2).sqrt()
+                }
+            };
+            let solid_angles: Vec<Float> = targets
+                .iter()
+                .map(|t| 2.0 * PI * (1.0 - cos_max(t)))
+                .collect();
+            let (index, total) = pick(&solid_angles);
+            let target = &targets[index];
+            let to_target = target.0 - position;
+            // 光源在包围球里面时整个球面都要采，轴随便取
+            let axis = if to_target.norm() > 0.0 {
+                to_target.normalize()
+            } else {
+                Vector3::new(0.0, 0.0, 1.0)
+            };
+            let (u, v) = random_2d();
+            let cos_theta = 1.0 - u * (1.0 - cos_max(target));
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * v;
+            let (tangent, bitangent) = crate::bsdf::orthonormal_basis(&axis);
+            let direction = (tangent * (sin_theta * phi.cos())
+                + bitangent * (sin_theta * phi.sin())
+                + axis * cos_theta)
+                .normalize();
+            let covering = targets
+                .iter()
+                .filter(|t| {
+                    let cos = cos_max(t);
+                    cos <= -1.0 || (t.0 - position).normalize().dot(&direction) >= cos
+                })
+                .count()
+                .max(1);
+            let power = intensity * (total / covering as Float) as f32;
+            Some((ray(position, direction), power))
+        }
+        Emitter::Parallel {
+            direction,
+            irradiance,
+        } => {
+            let areas: Vec<Float> = targets.iter().map(|t| PI * t.1 * t.1).collect();
+            let (index, total) = pick(&areas);
+            let (center, radius) = targets[index];
+            let (u, v) = random_2d();
+            let r = radius * u.sqrt();
+            let phi = 2.0 * PI * v;
+            let (tangent, bitangent) = crate::bsdf::orthonormal_basis(&direction);
+            let start = center + tangent * (r * phi.cos()) + bitangent * (r * phi.sin());
+            // 从包围球外面出发，之前还要看从无穷远到这里有没有被挡住
+            let origin = start - direction * radius;
+            let back = ray(origin, -direction);
+            if trace(scene, &back).is_some() {
+                return None;
+            }
+            let covering = targets
+                .iter()
+                .filter(|t| {
+                    let offset = t.0 - start;
+                    let along = offset.dot(&direction);
+                    offset.norm() - along * along <= t.1 * t.1
+                })
+                .count()
+                .max(1);
+            let power = irradiance * (total / covering as Float) as f32;
+            Some((ray(origin, direction), power))
+        }
+    }
+}
+
+/// 光子在场景里弹射：玻璃按菲涅尔随机选反射或折射，镜面材质按reflectivity随机反射，
+/// 经过镜面以后打到漫反射面（包括镜面材质的漫反射部分）就存下来。别的材质和体积不处理
+fn trace_photon(scene: &Scene, mut ray: Ray, mut power: Color) -> Vec<Photon> {
+    let bias = scene.settings.shadow_bias;
+    let mut stored = Vec::new();
+    let mut specular = false;
+    for _ in 0..scene.settings.max_depth {
+        let Some(hit) = trace(scene, &ray) else {
+            break;
+        };
+        if hit.item.volume().is_some() {
+            break;
+        }
+        let point = ray.origin + ray.direction * hit.distance;
+        let normal = hit.surface_normal(&point);
+        let photon = Photon {
+            position: point,
+            direction: ray.direction,
+            power,
+        };
+        match hit.material().surface {
+            SurfaceType::Diffuse => {
+                if specular {
+                    stored.push(photon);
+                }
+                break;
+            }
+            SurfaceType::Reflective { reflectivity } => {
+                if specular {
+                    stored.push(photon);
+                }
+                if random() as f32 >= reflectivity {
+                    break;
+                }
+                ray = Ray::create_reflection(normal, &ray, point, bias);
+            }
+            SurfaceType::Refractive {
+                index,
+                transparency,
+                ..
+            } => {
+                power = power * hit.base_color(&point) * transparency;
+                let reflection = Ray::create_reflection(normal, &ray, point, bias);
+                ray = if random() < fresnel(ray.direction, normal, index) {
+                    reflection
+                } else {
+                    Ray::create_transmission(normal, &ray, point, bias, index).unwrap_or(reflection)
+                };
+            }
+            _ => break,
+        }
+        specular = true;
+    }
+    stored
+}
+
+/// 物体的包围盒是不是值得朝它发光子
+pub(crate) fn is_caustic_caster(surface: &SurfaceType) -> bool {
+    matches!(
+        surface,
+        SurfaceType::Refractive { .. } | SurfaceType::Reflective { .. }
+    )
+}
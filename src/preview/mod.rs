@@ -0,0 +1,117 @@
+mod navigation;
+#[cfg(feature = "fs")]
+mod watch;
+#[cfg(feature = "preview-window")]
+mod window;
+
+#[cfg(feature = "fs")]
+pub use navigation::TerminalInput;
+pub use navigation::{explore, NavInput, OrbitController};
+#[cfg(feature = "fs")]
+pub use watch::watch_scene;
+#[cfg(feature = "preview-window")]
+pub use window::WindowPreview;
+
+use crate::checkpoint::Accumulator;
+use crate::rendering::CancelToken;
+use crate::scene::{light::LightSampler, Scene};
+use image::{DynamicImage, GenericImageView};
+use std::io::{self, Write};
+#[cfg(feature = "fs")]
+use std::{fs, path::PathBuf};
+
+/// 渲染时实时显示的地方，每多一批样本就拿到一次当前的平均结果
+pub trait Preview {
+    fn show(&mut self, image: &DynamicImage, samples: u32) -> io::Result<()>;
+
+    /// 渲染完了等下一次刷新时隔一会儿调用一次，窗口在这里处理事件
+    fn idle(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 在终端里用24位色的半格字符显示，上半格和下半格各是一个像素，每次刷新都画在同一个位置
+pub struct TerminalPreview {
+    /// 最多占多少列
+    pub columns: u32,
+    drawn_lines: u32,
+}
+
+impl TerminalPreview {
+    pub fn new(columns: u32) -> Self {
+        Self {
+            columns,
+            drawn_lines: 0,
+        }
+    }
+}
+
+impl Preview for TerminalPreview {
+    fn show(&mut self, image: &DynamicImage, samples: u32) -> io::Result<()> {
+        let width = self.columns.clamp(1, image.width().max(1));
+        // 终端的字符大约是2:1的，一个字符竖着放两个像素正好是方的
+        let height = ((image.height() as u64 * width as u64 / image.width().max(1) as u64) as u32)
+            .max(2)
+            & !1;
+        let small = image
+            .resize_exact(width, height, image::imageops::FilterType::Triangle)
+            .to_rgb();
+        let mut out = String::new();
+        if self.drawn_lines > 0 {
+            out += &format!("\x1b[{}A", self.drawn_lines);
+        }
+        for y in (0..height).step_by(2) {
+            for x in 0..width {
+                let top = small.get_pixel(x, y).0;
+                let bottom = small.get_pixel(x, y + 1).0;
+                out += &format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                );
+            }
+            out += "\x1b[0m\r\n";
+        }
+        out += &format!("{} spp\x1b[K\r\n", samples);
+        self.drawn_lines = height / 2 + 1;
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// 每次刷新都重写一张图，会自动重新加载的看图软件打开它就是实时预览
+#[cfg(feature = "fs")]
+pub struct FilePreview {
+    pub path: PathBuf,
+}
+
+#[cfg(feature = "fs")]
+impl Preview for FilePreview {
+    fn show(&mut self, image: &DynamicImage, _samples: u32) -> io::Result<()> {
+        // 先写临时文件再改名，看图软件不会读到写了一半的图
+        let temp = self.path.with_extension("preview.png");
+        image
+            .save(&temp)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        fs::rename(temp, &self.path)
+    }
+}
+
+/// 渐进式渲染：每批样本数翻倍（1, 1, 2, 4, ...），每批算完就刷新一次预览，
+/// 所以开头几秒就能看到大致的光照。cancel之后在当前这批算完时停下，返回已经累积的结果
+pub fn render_with_preview(
+    scene: &Scene,
+    total_samples: u32,
+    preview: &mut dyn Preview,
+    cancel: &CancelToken,
+) -> io::Result<DynamicImage> {
+    let lights = LightSampler::for_scene(scene);
+    let mut accumulator = Accumulator::new(scene.width, scene.height);
+    while accumulator.min_samples() < total_samples && !cancel.is_cancelled() {
+        let done = accumulator.min_samples();
+        let pass = done.max(1).min(total_samples - done);
+        accumulator.add_samples(scene, &lights, pass);
+        preview.show(&accumulator.image(scene), accumulator.min_samples())?;
+    }
+    Ok(accumulator.image(scene))
+}
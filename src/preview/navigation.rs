@@ -0,0 +1,319 @@
+use super::Preview;
+use crate::checkpoint::Accumulator;
+use crate::math::{consts::FRAC_PI_2, Float, Point, Transform, Vector3};
+use crate::scene::{light::LightSampler, Scene};
+use image::imageops::FilterType;
+use std::io;
+use std::sync::mpsc::{Receiver, TryRecvError};
+#[cfg(feature = "fs")]
+use std::{
+    io::{Read, Write},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+/// 预览里的一次操作
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NavInput {
+    /// 沿视线前后、左右、上下移动，orbit的中心跟着一起动
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+    /// 绕中心转，单位是像素（鼠标拖动的距离）
+    Orbit {
+        dx: Float,
+        dy: Float,
+    },
+    /// 小于1拉近，大于1推远
+    Zoom(Float),
+    Quit,
+}
+
+/// 围绕一个中心点转的相机：yaw绕y轴，pitch绕x轴，相机在中心点后面distance远的地方
+#[derive(Debug, Clone, Copy)]
+pub struct OrbitController {
+    pub target: Point,
+    pub yaw: Float,
+    pub pitch: Float,
+    pub distance: Float,
+    /// 每按一次移动多远
+    pub move_step: Float,
+    /// 每拖动一个像素转多少弧度
+    pub orbit_speed: Float,
+}
+
+impl OrbitController {
+    /// 从相机现在的位置和朝向开始，中心点放在视线前方distance处
+    pub fn from_transform(transform: &Transform, distance: Float) -> Self {
+        let mut controller = Self {
+            target: Point::zero(),
+            yaw: transform.rotation.y,
+            pitch: transform.rotation.x,
+            distance,
+            move_step: distance * 0.1,
+            orbit_speed: 0.01,
+        };
+        let eye = Point::zero() + transform.translation;
+        controller.target = eye + controller.forward() * distance;
+        controller
+    }
+
+    fn rotation(&self) -> Transform {
+        Transform {
+            rotation: Vector3::new(self.pitch, self.yaw, 0.0),
+            ..Transform::identity()
+        }
+    }
+
+    /// 相机看的方向，相机空间里是-z
+    fn forward(&self) -> Vector3 {
+        self.rotation().vector(&Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    fn right(&self) -> Vector3 {
+        self.rotation().vector(&Vector3::new(1.0, 0.0, 0.0))
+    }
+
+    /// 应用一次操作，返回相机有没有动
+    pub fn apply(&mut self, input: NavInput) -> bool {
+        let step = self.move_step;
+        match input {
+            NavInput::Forward => self.target = self.target + self.forward() * step,
+            NavInput::Backward => self.target = self.target - self.forward() * step,
+            NavInput::Left => self.target = self.target - self.right() * step,
+            NavInput::Right => self.target = self.target + self.right() * step,
+            NavInput::Up => self.target = self.target + Vector3::new(0.0, step, 0.0),
+            NavInput::Down => self.target = self.target - Vector3::new(0.0, step, 0.0),
+            NavInput::Orbit { dx, dy } => {
+                self.yaw -= dx * self.orbit_speed;
+                // 不让它翻过头顶
+                let limit = FRAC_PI_2 - 1e-3;
+                self.pitch = (self.pitch - dy * self.orbit_speed).clamp(-limit, limit);
+            }
+            NavInput::Zoom(factor) => self.distance = (self.distance * factor).max(1e-3),
+            NavInput::Quit => return false,
+        }
+        true
+    }
+
+    pub fn transform(&self) -> Transform {
+        let eye = self.target - self.forward() * self.distance;
+        Transform {
+            translation: eye - Point::zero(),
+            ..self.rotation()
+        }
+    }
+}
+
+/// 终端里的键盘和鼠标输入：WASD移动，R/F上下，方向键或者鼠标左键拖动绕中心转，
+/// +/-缩放，q退出。把终端切到raw模式并打开鼠标上报，drop时恢复
+#[cfg(feature = "fs")]
+pub struct TerminalInput {
+    events: Receiver<NavInput>,
+    saved_mode: Option<String>,
+}
+
+#[cfg(feature = "fs")]
+impl TerminalInput {
+    pub fn new() -> io::Result<Self> {
+        let saved = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()?;
+        let saved_mode = Some(String::from_utf8_lossy(&saved.stdout).trim().to_string());
+        Command::new("stty")
+            .args(["raw", "-echo"])
+            .stdin(Stdio::inherit())
+            .status()?;
+        // 按住按键拖动时上报位置，用SGR格式
+        print!("\x1b[?1002h\x1b[?1006h");
+
+        let (sender, events) = mpsc::channel();
+        thread::spawn(move || {
+            let mut parser = InputParser::default();
+            let mut stdin = io::stdin();
+            let mut buffer = [0u8; 64];
+            loop {
+                let n = match stdin.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                for &byte in &buffer[..n] {
+                    if let Some(input) = parser.feed(byte) {
+                        if sender.send(input).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        io::stdout().flush()?;
+        Ok(Self { events, saved_mode })
+    }
+
+    pub fn events(&self) -> &Receiver<NavInput> {
+        &self.events
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        print!("\x1b[?1006l\x1b[?1002l");
+        let _ = io::stdout().flush();
+        if let Some(mode) = self.saved_mode.take() {
+            let _ = Command::new("stty")
+                .arg(mode)
+                .stdin(Stdio::inherit())
+                .status();
+        }
+    }
+}
+
+/// 把终端的字节流解析成操作，转义序列可能被拆成好几次读到
+#[cfg(feature = "fs")]
+#[derive(Default)]
+struct InputParser {
+    pending: Vec<u8>,
+    last_mouse: Option<(Float, Float)>,
+}
+
+#[cfg(feature = "fs")]
+impl InputParser {
+    fn feed(&mut self, byte: u8) -> Option<NavInput> {
+        if self.pending.is_empty() && byte != 0x1b {
+            return match byte {
+                b'w' | b'W' => Some(NavInput::Forward),
+                b's' | b'S' => Some(NavInput::Backward),
+                b'a' | b'A' => Some(NavInput::Left),
+                b'd' | b'D' => Some(NavInput::Right),
+                b'r' | b'R' => Some(NavInput::Up),
+                b'f' | b'F' => Some(NavInput::Down),
+                b'+' | b'=' => Some(NavInput::Zoom(0.9)),
+                b'-' => Some(NavInput::Zoom(1.0 / 0.9)),
+                // Ctrl-C在raw模式下就是一个字节
+                b'q' | b'Q' | 3 => Some(NavInput::Quit),
+                _ => None,
+            };
+        }
+        self.pending.push(byte);
+        let sequence = self.pending.clone();
+        let input = match sequence.as_slice() {
+            [0x1b] => return None,
+            // CSI序列一直读到结束字节（0x40到0x7e）为止
+            [0x1b, b'[', rest @ ..] => match rest {
+                [.., last] if (0x40..=0x7e).contains(last) => match rest {
+                    [b'A'] => Some(NavInput::Orbit { dx: 0.0, dy: -20.0 }),
+                    [b'B'] => Some(NavInput::Orbit { dx: 0.0, dy: 20.0 }),
+                    [b'C'] => Some(NavInput::Orbit { dx: 20.0, dy: 0.0 }),
+                    [b'D'] => Some(NavInput::Orbit { dx: -20.0, dy: 0.0 }),
+                    // SGR鼠标：ESC [ < 按键 ; x ; y M（按下/拖动）或者m（松开）
+                    [b'<', fields @ .., end] => self.mouse(fields, *end == b'm'),
+                    _ => None,
+                },
+                _ if rest.len() < 32 => return None,
+                _ => None,
+            },
+            _ => None,
+        };
+        self.pending.clear();
+        input
+    }
+
+    fn mouse(&mut self, fields: &[u8], released: bool) -> Option<NavInput> {
+        let text = String::from_utf8_lossy(fields);
+        let numbers: Vec<Float> = text.split(';').filter_map(|s| s.parse().ok()).collect();
+        let (button, x, y) = match numbers.as_slice() {
+            [button, x, y] => (*button as u32, *x, *y),
+            _ => return None,
+        };
+        // 32是按着左键在移动
+        let dragging = button == 32 && !released;
+        let previous = self.last_mouse;
+        self.last_mouse = if released { None } else { Some((x, y)) };
+        match previous {
+            // 终端的格子是竖长的，y方向放大一倍
+            Some((px, py)) if dragging => Some(NavInput::Orbit {
+                dx: (x - px) * 4.0,
+                dy: (y - py) * 8.0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// 交互式浏览：相机动的时候按1/reduction的分辨率每帧1个样本地渲染，停下来以后
+/// 按完整分辨率一直累积到max_samples，期间有新的操作就清空重新开始。
+/// 操作从events里来，可以是TerminalInput，也可以是别的窗口后端
+pub fn explore(
+    scene: &mut Scene,
+    controller: &mut OrbitController,
+    events: &Receiver<NavInput>,
+    preview: &mut dyn Preview,
+    reduction: u32,
+    max_samples: u32,
+) -> io::Result<()> {
+    let (width, height) = (scene.width, scene.height);
+    // 相机不动时的累积结果和它的光源采样器（辐照度缓存跟着相机走，挪了要重建）
+    let mut accumulator: Option<(Accumulator, LightSampler)> = None;
+    let mut moving = true;
+    loop {
+        let mut moved = false;
+        loop {
+            let event = if !moving
+                && accumulator
+                    .as_ref()
+                    .is_some_and(|(a, _)| a.min_samples() >= max_samples)
+            {
+                // 已经收敛了，睡着等下一个操作
+                events.recv().ok()
+            } else {
+                match events.try_recv() {
+                    Ok(event) => Some(event),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => None,
+                }
+            };
+            match event {
+                None | Some(NavInput::Quit) => return Ok(()),
+                Some(event) => moved |= controller.apply(event),
+            }
+        }
+        if moved {
+            let transform = controller.transform();
+            scene.camera.start = transform;
+            scene.camera.end = transform;
+            accumulator = None;
+            moving = true;
+        }
+
+        if moving {
+            scene.width = (width / reduction.max(1)).max(1);
+            scene.height = (height / reduction.max(1)).max(1);
+            let mut small = Accumulator::new(scene.width, scene.height);
+            small.add_samples(scene, &LightSampler::for_scene(scene), 1);
+            scene.width = width;
+            scene.height = height;
+            let image = small
+                .image(scene)
+                .resize_exact(width, height, FilterType::Nearest);
+            preview.show(&image, 1)?;
+            moving = false;
+        } else {
+            let (full, lights) = accumulator.get_or_insert_with(|| {
+                (
+                    Accumulator::new(width, height),
+                    LightSampler::for_scene(scene),
+                )
+            });
+            if full.min_samples() < max_samples {
+                full.add_samples(scene, lights, 1);
+                preview.show(&full.image(scene), full.min_samples())?;
+            }
+        }
+    }
+}
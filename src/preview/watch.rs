@@ -0,0 +1,62 @@
+use super::Preview;
+use crate::checkpoint::Accumulator;
+use crate::scene::{light::LightSampler, load_scene_with_files};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// 每批最多几个样本，批和批之间才检查文件有没有改，批太大改了以后要等很久才重新开始
+const MAX_PASS: u32 = 4;
+
+/// 每个文件的修改时间，读不到（比如正在被编辑器替换）时是None
+fn stamps(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// 盯着场景文件和它用到的贴图、OBJ，有一个改了就重新读场景、从头渐进式渲染，
+/// 结果一直刷新到同一个preview里。场景读不出来时把错误打到stderr，等下一次修改。
+/// 每隔poll检查一次文件；只有preview出错时才返回
+pub fn watch_scene<P: AsRef<Path>>(
+    path: P,
+    total_samples: u32,
+    preview: &mut dyn Preview,
+    poll: Duration,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    'reload: loop {
+        let (scene, files) = load_scene_with_files(path);
+        let scene = scene.and_then(|scene| {
+            for warning in scene.validate().check()? {
+                eprintln!("warning: {}", warning);
+            }
+            Ok(scene)
+        });
+        let stamp = stamps(&files);
+        let changed = || stamps(&files) != stamp;
+        match scene {
+            Ok(scene) => {
+                let lights = LightSampler::for_scene(&scene);
+                let mut accumulator = Accumulator::new(scene.width, scene.height);
+                while accumulator.min_samples() < total_samples {
+                    if changed() {
+                        continue 'reload;
+                    }
+                    let done = accumulator.min_samples();
+                    let pass = done.clamp(1, MAX_PASS).min(total_samples - done);
+                    accumulator.add_samples(&scene, &lights, pass);
+                    preview.show(&accumulator.image(&scene), accumulator.min_samples())?;
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+        while !changed() {
+            preview.idle()?;
+            thread::sleep(poll);
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use super::Preview;
+use image::{DynamicImage, GenericImageView};
+use minifb::{Window, WindowOptions};
+use std::io;
+
+/// 在一个窗口里显示，窗口在第一次show时按图的大小打开，标题上带着样本数。
+/// 渲染一批样本的时候窗口不处理事件，拖动、关闭要等这批算完才有反应；
+/// 窗口被关掉以后show和idle返回ErrorKind::Interrupted，render_with_preview和watch_scene就停下
+pub struct WindowPreview {
+    pub title: String,
+    window: Option<Window>,
+}
+
+impl WindowPreview {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            window: None,
+        }
+    }
+}
+
+fn window_error(e: minifb::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+fn check_open(window: &Window) -> io::Result<()> {
+    if window.is_open() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "preview window was closed",
+        ))
+    }
+}
+
+impl Preview for WindowPreview {
+    fn show(&mut self, image: &DynamicImage, samples: u32) -> io::Result<()> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let window = match &mut self.window {
+            Some(window) => window,
+            None => {
+                let mut window = Window::new(&self.title, width, height, WindowOptions::default())
+                    .map_err(window_error)?;
+                // 只在有新的一批样本时刷新，不用等帧率
+                window.limit_update_rate(None);
+                self.window.insert(window)
+            }
+        };
+        check_open(window)?;
+        window.set_title(&format!("{} - {} spp", self.title, samples));
+        // minifb要的是每个像素一个0RGB的u32
+        let buffer: Vec<u32> = image
+            .to_rgb()
+            .pixels()
+            .map(|p| u32::from_be_bytes([0, p.0[0], p.0[1], p.0[2]]))
+            .collect();
+        window
+            .update_with_buffer(&buffer, width, height)
+            .map_err(window_error)
+    }
+
+    fn idle(&mut self) -> io::Result<()> {
+        match &mut self.window {
+            Some(window) => {
+                window.update();
+                check_open(window)
+            }
+            None => Ok(()),
+        }
+    }
+}
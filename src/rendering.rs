@@ -1,74 +1,205 @@
-use crate::color::Color;
-use crate::math::{Point, Vector3};
+use crate::aberration;
+use crate::bloom::{self, BloomSettings};
+use crate::bsdf::{
+    cosine_sample_hemisphere, fresnel_schlick, orthonormal_basis, power_heuristic,
+    uniform_sample_sphere, Bsdf, Ggx, HairBsdf, IsotropicPhase, Lambertian, PrincipledBsdf,
+};
+use crate::color::{
+    heatmap, id_color, spectral_weight, Color, OutputSpace, MAX_WAVELENGTH, MIN_WAVELENGTH,
+};
+use crate::filter::{FilterSampler, PixelFilter};
+use crate::flare::{self, FlareSettings};
+use crate::grading::ColorGrading;
+use crate::hdr::HdrImage;
+use crate::integrator::{IntegratorKind, Splats};
+use crate::irradiance::IrradianceSettings;
+use crate::math::{Aabb, Affine, Float, Point, Vector3};
+use crate::overlay::BoundsBox;
+use crate::photon::{is_caustic_caster, CausticSettings, Emitter};
+use crate::sampling::{random, random_2d, start_sample, SampleState, SamplerKind};
 use crate::scene::{
-    material::{Material, SurfaceType, TextureCoords},
-    Distance, Scene,
+    item::Volume,
+    light::{EmissiveSurface, LightSampler},
+    material::{
+        dispersed_ior, Coloration, Material, Principled, Subsurface, SurfaceType, TextureCoords,
+    },
+    medium::MediumSample,
+    Distance, Scene, Validation,
 };
+use crate::stats::{self, RenderStats, SharedCounters};
 
+#[cfg(feature = "parallel")]
 use rayon::prelude::*;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub const SHADOW_BIAS: Distance = 1e-12;
-pub const MAX_RECURSION: usize = 25;
+/// 渲染质量相关的设置，跟着Scene走，同一个进程里不同的场景可以用不同的质量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    /// 每个像素的样本数
+    pub samples: u32,
+    /// 一条路径最多追踪几段
+    pub max_depth: usize,
+    /// 从第几次弹射开始做俄罗斯轮盘赌
+    pub russian_roulette_depth: usize,
+    /// 次级光线的起点沿法线挪开这么远，免得打中自己
+    pub shadow_bias: Distance,
+    /// 光源数超过这个值时，每个着色点只按功率抽这么多个光源
+    pub max_light_samples: usize,
+    /// 次表面散射随机游走的最多步数
+    pub max_subsurface_steps: usize,
+    /// 每个样本的亮度上限，None是不限制
+    pub sample_clamp: Option<SampleClamp>,
+    /// 样本合成像素用的滤波器
+    pub filter: PixelFilter,
+    /// 样本里的随机数用哪个序列
+    pub sampler: SamplerKind,
+    /// 焦散光子图，None是不用，焦散只靠路径追踪
+    pub caustics: Option<CausticSettings>,
+    /// 怎么算相机光线带回来的光
+    pub integrator: IntegratorKind,
+    /// 辐照度缓存，None是不用，漫反射面的间接光每个样本都追踪
+    pub irradiance_cache: Option<IrradianceSettings>,
+    /// 泛光，None是不加；只在渲染整张图时做
+    pub bloom: Option<BloomSettings>,
+    /// 镜头光晕，None是不加；也只在渲染整张图时做
+    pub flare: Option<FlareSettings>,
+    /// 白平衡和调色，None是不调
+    pub grading: Option<ColorGrading>,
+    /// 输出图片的色彩空间，存文件、显示预览时编码用
+    pub output_space: OutputSpace,
+}
 
-use std::f64;
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            max_depth: 25,
+            russian_roulette_depth: 3,
+            // 单精度下交点本身的误差就有1e-5量级，偏移要跟着大
+            shadow_bias: if cfg!(feature = "f32") { 1e-4 } else { 1e-12 },
+            max_light_samples: 8,
+            max_subsurface_steps: 256,
+            sample_clamp: None,
+            filter: PixelFilter::Box,
+            sampler: SamplerKind::Random,
+            caustics: None,
+            integrator: IntegratorKind::Path,
+            irradiance_cache: None,
+            bloom: None,
+            flare: None,
+            grading: None,
+            output_space: OutputSpace::default(),
+        }
+    }
+}
 
 use image::{DynamicImage, ImageBuffer, Rgba};
 
 pub struct Ray {
     pub origin: Point,
     pub direction: Vector3,
+    /// 光谱模式下这条光线携带的波长（纳米），RGB模式是None
+    pub wavelength: Option<f32>,
+    /// 在一帧里的时刻，运动的物体和相机按这个时刻求位置
+    pub time: Float,
+    /// 从相机直接出发的光线，只有它会被Oriented::cull_backfaces剔除背面
+    pub camera: bool,
+}
+
+/// 像素(x, y)里offset处在画面上的位置，(0, 0)是左上角，(1, 1)是右下角
+fn film_position(x: u32, y: u32, offset: (Float, Float), scene: &Scene) -> (Float, Float) {
+    (
+        (x as Float + offset.0) / scene.width as Float,
+        (y as Float + offset.1) / scene.height as Float,
+    )
 }
 
 impl Ray {
-    /// 坐标系是z向外，x向右，y向上。是个右手系。
-    /// 相机放在z=0处，朝负z方向看；胶片在-1.0处摆放，东西都放到负z那边去
-    /// 所以这里的射线的x和y就是从原点出发到胶片的某个像素的中心，z都是-1.0
-    /// y这里反一下是因为image的y是朝下的，我们是y朝上
-    pub fn new_prime(x: u32, y: u32, scene: &Scene) -> Self {
-        assert!(scene.width > scene.height);
-        let aspect_ratio = (scene.width as f64) / (scene.height as f64);
-        let fov_adjustment = (scene.fov.to_radians() / 2.0).tan();
-        let sensor_x =
-            (((x as f64 + 0.5) / scene.width as f64) * 2.0 - 1.0) * aspect_ratio * fov_adjustment;
-        let sensor_y = -(((y as f64 + 0.5) / scene.height as f64) * 2.0 - 1.0) * fov_adjustment;
+    /// offset是像素内的采样位置，(0.5, 0.5)就是像素中心。
+    /// 先按相机的投影方式在相机自己的坐标系里求出光线，再按time时刻相机的变换摆到世界里；
+    /// 像鱼眼圆外面这种没有对应光线的地方返回None
+    pub fn new_prime(
+        x: u32,
+        y: u32,
+        offset: (Float, Float),
+        time: Float,
+        scene: &Scene,
+    ) -> Option<Self> {
+        Self::new_lens_prime(x, y, offset, None, time, scene)
+    }
 
-        Self {
-            origin: Point::zero(),
-            direction: Vector3 {
-                x: sensor_x,
-                y: sensor_y,
-                z: -1.0,
-            }
-            .normalize(),
+    /// 和new_prime一样，相机有景深时光线从光圈上lens_sample对应的点出发，
+    /// None是从光圈的中心出发，和小孔相机一样
+    pub fn new_lens_prime(
+        x: u32,
+        y: u32,
+        offset: (Float, Float),
+        lens_sample: Option<(Float, Float)>,
+        time: Float,
+        scene: &Scene,
+    ) -> Option<Self> {
+        let size = (scene.width, scene.height);
+        let film = scene
+            .camera
+            .distort(film_position(x, y, offset, scene), size);
+        let (mut origin, mut direction) = scene.camera.projection.ray(film, size, scene.fov)?;
+        if let (Some(lens), Some(u)) = (&scene.camera.lens, lens_sample) {
+            (origin, direction) = lens.refocus(origin, direction, u);
+        }
+
+        let transform = scene.camera.transform_at(time);
+        Some(Self {
+            origin: transform.point(&origin),
+            direction: transform.vector(&direction).normalize(),
+            wavelength: None,
+            time,
+            camera: true,
+        })
+    }
+
+    /// 从这条光线派生出一条新光线，波长这些路径上的属性跟着传下去
+    pub fn spawn(&self, origin: Point, direction: Vector3) -> Self {
+        Ray {
+            origin,
+            direction,
+            wavelength: self.wavelength,
+            time: self.time,
+            camera: false,
         }
     }
 
     pub fn create_reflection(
         normal: Vector3,
-        incident: Vector3,
+        incident: &Ray,
         intersection: Point,
         bias: Distance,
     ) -> Self {
-        Ray {
-            origin: intersection + (normal * bias),
-            direction: incident - normal * (2.0 * incident.dot(&normal)),
-        }
+        let i = incident.direction;
+        incident.spawn(
+            intersection + (normal * bias),
+            i - normal * (2.0 * i.dot(&normal)),
+        )
     }
 
     pub fn create_transmission(
         normal: Vector3,
-        incident: Vector3,
+        incident: &Ray,
         intersection: Point,
         bias: Distance,
         index: f32,
     ) -> Option<Self> {
+        let ray = incident;
+        let incident = ray.direction;
         let mut i_n = incident.dot(&normal);
         let is_into = i_n > 0.0;
         let (eta, n) = if is_into {
-            (index as f64, -normal)
+            (index as Float, -normal)
         } else {
             i_n = -i_n; // side effect
-            (1.0 / (index as f64), normal)
+            (1.0 / (index as Float), normal)
         };
         let i_n_2 = i_n * i_n;
         let i = incident;
@@ -77,183 +208,2206 @@ impl Ray {
             None
         } else {
             let t = (i + n * i_n) * eta - n * k.sqrt();
-            Some(Ray {
-                origin: intersection + ((-n) * bias),
-                direction: t.normalize(),
-            })
+            Some(ray.spawn(intersection + ((-n) * bias), t.normalize()))
         }
     }
 }
 
-pub trait Intersectable {
+/// 让默认方法里能把self当成trait object用
+pub trait AsIntersectable {
+    fn as_intersectable(&self) -> &dyn Intersectable;
+}
+
+impl<T: Intersectable> AsIntersectable for T {
+    fn as_intersectable(&self) -> &dyn Intersectable {
+        self
+    }
+}
+
+pub trait Intersectable: AsIntersectable {
     fn intersect(&self, ray: &Ray) -> Option<Distance>;
     fn surface_normal(&self, hit_point: &Point) -> Vector3;
     fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
     fn get_material(&self) -> &Material;
+
+    /// 求交并返回真正被打中的物体；由很多物体组成的东西（一组球、加速结构）
+    /// 和带变换的物体重写它，这样着色时拿到的是里面那个物体和它的坐标变换
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.intersect(ray)
+            .map(|d| Intersection::new(d, self.as_intersectable()))
+    }
+
+    /// 包围盒，用来建加速结构；平面这种无限大的返回None
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// 体积类的物体返回自己，渲染时在里面做delta tracking而不是当成表面
+    fn volume(&self) -> Option<&Volume> {
+        None
+    }
+
+    /// 从背面打中时怎么办，见Backface
+    fn backface(&self) -> Backface {
+        Backface::Keep
+    }
+
+    /// 沿纹理u方向的切线，各向异性材质用它确定方向
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        orthonormal_basis(&self.surface_normal(hit_point)).0
+    }
+    /// 顶点色，有的话代替材质本身的颜色；只有带顶点色的网格才有
+    fn vertex_color(&self, _hit_point: &Point) -> Option<Color> {
+        None
+    }
+
+    /// 检查自己的参数和材质，有问题记到report里；Scene::validate会调用它
+    fn validate(&self, _report: &mut Validation) {}
+
+    /// 焦散光子朝哪里发：默认是玻璃、镜子这种物体自己的包围盒，
+    /// 加速结构和一组物体要转给里面的每个物体
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        if self.volume().is_none() && is_caustic_caster(&self.get_material().surface) {
+            out.extend(self.bounds());
+        }
+    }
+
+    /// 自发光均匀的面，场景建好时收集起来当成光源采样，见EmissiveLight。
+    /// 默认没有，球、四边形和三角形网格自己加；加速结构、一组物体和Instance要转给里面的每个物体。
+    /// 胶囊、椭球、圆角盒、SDF、高度场、曲线和运动的物体不收集，还是只靠路径打中它们时发光
+    fn emissive_surfaces(&self, _out: &mut Vec<EmissiveSurface>) {}
+
+    /// 调试叠加层要画的框：默认是自己的包围盒，加速结构再加上自己的每个节点
+    fn collect_bounds(&self, out: &mut Vec<BoundsBox>) {
+        if let Some(bounds) = self.bounds() {
+            out.push(BoundsBox {
+                bounds,
+                depth: None,
+            });
+        }
+    }
+}
+
+pub struct LightSample {
+    /// 从着色点指向光源
+    pub direction: Vector3,
+    pub distance: Distance,
+    /// 沿direction到达着色点的光，面光源已经除过pdf了
+    pub intensity: Color,
+    /// 立体角上的pdf，点光源和平行光这种delta光源是None
+    pub pdf: Option<Float>,
+}
+
+/// 从光源发出的一条光线，双向路径追踪用
+pub struct Emission {
+    pub origin: Point,
+    /// 光传播的方向
+    pub direction: Vector3,
+    /// 面光源在origin处的法线，点光源、平行光和天光是None
+    pub normal: Option<Vector3>,
+    /// 点光源是每单位立体角的功率，平行光是垂直于光线的辐照度，别的是radiance
+    pub radiance: Color,
+    /// origin的面积pdf；平行光和天光是在场景包围球的截面圆盘上取点
+    pub pdf_position: Float,
+    /// direction的立体角pdf，平行光是1
+    pub pdf_direction: Float,
+    /// 点光源和平行光：光线打不中，也不能从着色点连过去再采样
+    pub delta: bool,
+    /// 平行光和天光在无穷远处
+    pub infinite: bool,
 }
 
 pub trait Light {
-    fn intensity(&self, hit_point: &Point) -> f32;
-    fn distance(&self, hit_point: &Point) -> Distance;
+    fn sample(&self, hit_point: &Point) -> LightSample;
     fn color(&self) -> Color;
-    fn direction_from(&self, hit_point: &Point) -> Vector3;
+    /// 用来在光源之间做重要性采样的近似功率
+    fn power(&self) -> f32;
+
+    /// 从hit_point沿direction打中光源时，光源采样这个方向的pdf；delta光源打不中，是0
+    fn pdf(&self, _hit_point: &Point, _direction: &Vector3) -> Float {
+        0.0
+    }
+
+    /// 只有有面积的光源才能被光线打中
+    fn intersect(&self, _ray: &Ray) -> Option<Distance> {
+        None
+    }
+
+    /// 被光线打中时光源表面的radiance
+    fn emitted(&self) -> Color {
+        Color::black()
+    }
+
+    /// 焦散光子图怎么从这个光源发光子，不发的返回None
+    fn emitter(&self) -> Option<Emitter> {
+        None
+    }
+
+    /// 双向路径追踪从这个光源发一条光线，bounds是场景的包围球。不发的返回None
+    fn emit(&self, _bounds: &(Point, Distance)) -> Option<Emission> {
+        None
+    }
+
+    /// emit正好在point朝direction发出光线时的Emission，主要是要它的pdf和法线
+    fn emission(
+        &self,
+        _point: &Point,
+        _direction: &Vector3,
+        _bounds: &(Point, Distance),
+    ) -> Option<Emission> {
+        None
+    }
+
+    fn validate(&self, _report: &mut Validation) {}
+
+    /// 场景里会发光的物体当成的光源（EmissiveLight）返回物体的编号：
+    /// 光线打中的是物体本身，它的自发光要和光源采样分MIS的权重
+    fn object_id(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// 光线从法线背后那一侧打到表面时的处理
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backface {
+    /// 背面打不中，光线直接穿过去；物体自己在intersect里不返回背面的交点
+    Cull,
+    /// 打得中，法线不动：封闭的物体用这个，折射靠法线的朝向分里外
+    Keep,
+    /// 打得中，法线翻到光线来的那一侧：没有里外之分的薄片，两面看上去一样
+    Flip,
 }
 
 pub struct Intersection<'a> {
-    pub distance: f64,
+    pub distance: Float,
+    /// 真正被打中的那个物体
     pub item: &'a dyn Intersectable,
+    /// item的局部坐标到世界坐标的变换，经过Moving这种带变换的物体时才有
+    pub to_world: Option<Affine>,
+    /// 物体的编号，经过Tagged时才有
+    pub object_id: Option<u32>,
+    /// 从背面打中了Backface::Flip的物体，surface_normal已经翻过来了；由trace设
+    pub flipped: bool,
+    /// 法线反过来，经过flip_normals的Oriented时才有
+    pub reversed: bool,
+    /// 相机光线打到背面时当成没打中，经过cull_backfaces的Oriented时才有
+    pub cull_backface: bool,
+    /// 打中它的光线的方向；由trace设
+    pub incoming: Vector3,
 }
 
 impl<'a> Intersection<'a> {
-    pub fn new(distance: f64, item: &dyn Intersectable) -> Intersection {
-        Intersection { distance, item }
+    pub fn new(distance: Float, item: &dyn Intersectable) -> Intersection<'_> {
+        Intersection {
+            distance,
+            item,
+            to_world: None,
+            object_id: None,
+            flipped: false,
+            reversed: false,
+            cull_backface: false,
+            incoming: Vector3::zero(),
+        }
+    }
+
+    /// 外面再套一层变换，distance也换算到外层的光线上
+    pub fn transformed(mut self, outer: &Affine, distance_scale: Float) -> Self {
+        self.distance *= distance_scale;
+        self.to_world = Some(match self.to_world {
+            Some(inner) => inner.then(outer),
+            None => *outer,
+        });
+        self
+    }
+
+    fn local_point(&self, hit_point: &Point) -> Point {
+        match &self.to_world {
+            Some(to_world) => to_world.inverse().point(hit_point),
+            None => *hit_point,
+        }
+    }
+
+    fn world_vector(&self, v: Vector3) -> Vector3 {
+        match &self.to_world {
+            Some(to_world) => to_world.vector(&v).normalize(),
+            None => v,
+        }
+    }
+
+    pub fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let n = self.item.surface_normal(&self.local_point(hit_point));
+        let n = match &self.to_world {
+            Some(to_world) => to_world.normal(&n),
+            None => n,
+        };
+        if self.flipped != self.reversed {
+            -n
+        } else {
+            n
+        }
+    }
+
+    pub fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.item.texture_coords(&self.local_point(hit_point))
+    }
+
+    pub fn tangent(&self, hit_point: &Point) -> Vector3 {
+        self.world_vector(self.item.tangent(&self.local_point(hit_point)))
+    }
+
+    pub fn material(&self) -> &'a Material {
+        self.item.get_material()
+    }
+
+    /// 着色用的基础颜色：有顶点色用顶点色，否则按纹理坐标取材质的颜色
+    pub fn base_color(&self, hit_point: &Point) -> Color {
+        let local = self.local_point(hit_point);
+        self.item
+            .vertex_color(&local)
+            .unwrap_or_else(|| self.coloration(&self.material().color, hit_point))
+    }
+
+    /// 按纹理坐标取coloration在这一点的颜色
+    pub fn coloration(&self, coloration: &Coloration, hit_point: &Point) -> Color {
+        let local = self.local_point(hit_point);
+        let facing = if coloration.uses_facing() {
+            self.surface_normal(hit_point).dot(&self.incoming).abs() as f32
+        } else {
+            0.0
+        };
+        coloration.color(&self.item.texture_coords(&local), &local, facing)
+    }
+
+    /// 有贴图时取贴图在这一点的灰度，没有就是value
+    fn scalar(&self, map: &Option<Coloration>, value: f32, hit_point: &Point) -> f32 {
+        match map {
+            Some(map) => {
+                let c = self.coloration(map, hit_point);
+                ((c.r + c.g + c.b) / 3.0).clamp(0.0, 1.0)
+            }
+            None => value,
+        }
+    }
+
+    /// Principled在这一点的粗糙度
+    pub fn roughness(&self, principled: &Principled, hit_point: &Point) -> f32 {
+        self.scalar(&principled.roughness_map, principled.roughness, hit_point)
+    }
+
+    /// Principled在这一点的金属度
+    pub fn metallic(&self, principled: &Principled, hit_point: &Point) -> f32 {
+        self.scalar(&principled.metallic_map, principled.metallic, hit_point)
+    }
+
+    /// 材质镂空的地方，光线应该穿过去
+    pub fn cut_out(&self, hit_point: &Point) -> bool {
+        let opacity = match &self.material().opacity {
+            Some(opacity) => opacity,
+            None => return false,
+        };
+        let value = if opacity.alpha {
+            let local = self.local_point(hit_point);
+            opacity.map.alpha(&self.item.texture_coords(&local))
+        } else {
+            let c = self.coloration(&opacity.map, hit_point);
+            (c.r + c.g + c.b) / 3.0
+        };
+        match opacity.threshold {
+            Some(threshold) => value < threshold,
+            None => random() >= value as Float,
+        }
+    }
+
+    /// Principled表面自己发的光
+    pub fn emission(&self, principled: &Principled, hit_point: &Point) -> Color {
+        match &principled.emission_map {
+            Some(map) => self.coloration(map, hit_point) * principled.emission_strength,
+            None => principled.emission,
+        }
+    }
+
+    /// 光线和同一个物体再求交，次表面散射在物体里游走时用
+    pub fn intersect_again(&self, ray: &Ray) -> Option<Distance> {
+        match &self.to_world {
+            Some(to_world) => {
+                let (local, scale) = local_ray(ray, &to_world.inverse());
+                self.item.intersect(&local).map(|d| d * scale)
+            }
+            None => self.item.intersect(ray),
+        }
     }
 }
 
+/// 把光线变到to_local的坐标系里，方向重新归一化；
+/// 返回的系数把局部光线上的距离换回原来光线上的距离
+pub fn local_ray(ray: &Ray, to_local: &Affine) -> (Ray, Float) {
+    let direction = to_local.vector(&ray.direction);
+    let length = direction.length();
+    (
+        ray.spawn(to_local.point(&ray.origin), direction * (1.0 / length)),
+        1.0 / length,
+    )
+}
+
+/// 一条光线最多穿过几次镂空（或者被剔除的背面），再多就当什么都没打中
+const MAX_CUTOUTS: usize = 64;
+
 pub fn trace<'a>(scene: &'a Scene, ray: &Ray) -> Option<Intersection<'a>> {
-    scene
+    trace_items(scene, ray, true)
+}
+
+/// 和trace一样，但穿过体积，只找表面；体积里面的物体靠它找
+fn trace_surfaces<'a>(scene: &'a Scene, ray: &Ray) -> Option<Intersection<'a>> {
+    trace_items(scene, ray, false)
+}
+
+fn trace_items<'a>(scene: &'a Scene, ray: &Ray, volumes: bool) -> Option<Intersection<'a>> {
+    stats::count(|c| c.rays += 1);
+    let mut segment = ray.spawn(ray.origin, ray.direction);
+    segment.camera = ray.camera;
+    let mut traveled = 0.0;
+    for _ in 0..MAX_CUTOUTS {
+        let mut hit = scene
+            .items
+            .iter()
+            .filter(|i| volumes || i.volume().is_none())
+            .filter_map(|i| i.intersect_hit(&segment))
+            .filter(|i| !i.distance.is_nan())
+            .min_by(|i1, i2| i1.distance.total_cmp(&i2.distance))?;
+        hit.incoming = ray.direction;
+        let hit_point = segment.origin + segment.direction * hit.distance;
+        let backfacing = hit.surface_normal(&hit_point).dot(&ray.direction) > 0.0;
+        if hit.item.backface() == Backface::Flip {
+            hit.flipped = backfacing;
+        }
+        let culled = ray.camera && hit.cull_backface && backfacing;
+        if !culled && !hit.cut_out(&hit_point) {
+            // 穿过镂空的地方或者剔除的背面以后，距离从原来的起点算
+            hit.distance += traveled;
+            return Some(hit);
+        }
+        let origin = offset_origin(
+            scene,
+            hit_point,
+            hit.surface_normal(&hit_point),
+            &ray.direction,
+        );
+        traveled = (origin - ray.origin).length();
+        segment.origin = origin;
+    }
+    None
+}
+
+/// pick打中的东西
+#[derive(Debug, Clone, Copy)]
+pub struct PickResult {
+    /// 打中的物体在scene.items里的下标；加速过的场景里是整个加速结构的下标
+    pub item: usize,
+    pub distance: Distance,
+    pub point: Point,
+    pub normal: Vector3,
+    /// 纹理坐标(u, v)
+    pub uv: (f32, f32),
+}
+
+/// 从像素(x, y)的中心打一条相机光线（快门打开的时刻），返回第一个打中的物体，
+/// 编辑器点选物体用
+pub fn pick(scene: &Scene, x: u32, y: u32) -> Option<PickResult> {
+    let ray = Ray::new_prime(x, y, (0.5, 0.5), scene.camera.shutter.0, scene)?;
+    let (item, intersection) = scene
         .items
         .iter()
-        .filter_map(|i| i.intersect(ray).map(|d| Intersection::new(d, i.as_ref())))
-        .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+        .enumerate()
+        .filter_map(|(index, item)| item.intersect_hit(&ray).map(|i| (index, i)))
+        .filter(|(_, i)| !i.distance.is_nan())
+        .min_by(|(_, i1), (_, i2)| i1.distance.total_cmp(&i2.distance))?;
+    let point = ray.origin + ray.direction * intersection.distance;
+    let uv = intersection.texture_coords(&point);
+    Some(PickResult {
+        item,
+        distance: intersection.distance,
+        point,
+        normal: intersection.surface_normal(&point),
+        uv: (uv.u, uv.v),
+    })
 }
 
-pub fn par_render_pixels(scene: &Scene) -> Vec<Color> {
-    let w = scene.width;
-    let h = scene.height;
-    (0..w * h)
-        .into_par_iter()
-        .map(|i| {
-            let x = i % w;
-            let y = i / w;
-            render_a_pixel(scene, x, y)
+/// 每个像素中心打一条相机光线（快门打开的时刻），对打中的东西调用f，按行排
+fn center_pass<T: Send>(
+    scene: &Scene,
+    f: impl Fn(&Ray, Option<Intersection>) -> T + Sync,
+    miss: impl Fn() -> T + Sync,
+) -> Vec<T> {
+    let time = scene.camera.shutter.0;
+    let width = scene.width;
+    #[cfg(feature = "parallel")]
+    let rows = (0..scene.height).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let rows = 0..scene.height;
+    let rows: Vec<Vec<T>> = rows
+        .map(|y| {
+            (0..width)
+                .map(|x| match Ray::new_prime(x, y, (0.5, 0.5), time, scene) {
+                    Some(ray) => f(&ray, trace(scene, &ray)),
+                    None => miss(),
+                })
+                .collect()
         })
-        .collect()
+        .collect();
+    rows.into_iter().flatten().collect()
+}
+
+/// 物体ID通道：每个像素中心的相机光线第一个打中的物体的编号，按行排；
+/// 什么都没打中或者物体没有编号是0。编号由Scene::assign_object_ids给，从1开始
+pub fn object_id_pass(scene: &Scene) -> Vec<u32> {
+    center_pass(
+        scene,
+        |_, hit| hit.and_then(|i| i.object_id).unwrap_or(0),
+        || 0,
+    )
+}
+
+/// 合成用的辅助通道（AOV）里的一个像素
+#[derive(Debug, Clone, Copy)]
+pub struct Aov {
+    /// 世界空间的法线
+    pub normal: Vector3,
+    /// 沿光线到交点的距离
+    pub depth: Distance,
+    /// 表面的基础颜色，体积是它的albedo
+    pub albedo: Color,
+    /// 和object_id_pass一样，0是没有编号
+    pub object_id: u32,
+}
+
+/// 法线、深度、albedo和物体ID通道，每个像素只取中心一个样本，按行排；什么都没打中是None
+pub fn aov_pass(scene: &Scene) -> Vec<Option<Aov>> {
+    center_pass(
+        scene,
+        |ray, hit| {
+            hit.map(|i| {
+                let hit_point = ray.origin + ray.direction * i.distance;
+                Aov {
+                    normal: i.surface_normal(&hit_point),
+                    depth: i.distance,
+                    albedo: albedo(&i, &hit_point),
+                    object_id: i.object_id.unwrap_or(0),
+                }
+            })
+        },
+        || None,
+    )
+}
+
+/// lighting_pass的一个像素，三个通道加起来就是主图，没有clamp到[0, 1]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lighting {
+    pub direct: Color,
+    pub indirect_diffuse: Color,
+    pub indirect_specular: Color,
+}
+
+impl Lighting {
+    pub fn total(&self) -> Color {
+        self.direct + self.indirect_diffuse + self.indirect_specular
+    }
+}
+
+/// 光照分成的通道
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightingComponent {
+    /// 光源直接照到相机看到的点上的光，包括直接看到的和镜子里看到的光源
+    Direct,
+    /// 第一次弹射是漫反射的间接光
+    IndirectDiffuse,
+    /// 第一次弹射是镜面、玻璃或者高光的间接光
+    IndirectSpecular,
 }
 
-fn render_a_pixel(scene: &Scene, x: u32, y: u32) -> Color {
-    let ray = Ray::new_prime(x, y, scene);
-    if let Some(intersection) = trace(scene, &ray) {
-        get_color(scene, &ray, &intersection, 0).clamp()
+/// 直接光、间接漫反射和间接高光分开的通道，后期可以分别调亮调暗，每个像素
+/// scene.settings.samples个样本，按行排。每个样本用同样的随机数追踪三遍，每遍只留一个通道的光，
+/// 所以三个通道加起来正好是这些样本的主图，花的时间是三倍。总是用路径追踪；
+/// 样本的亮度上限按三个通道的和算，再等比例分下去。不是Shaded模式时颜色都在direct里
+pub fn lighting_pass(scene: &Scene) -> Vec<Lighting> {
+    let lights = LightSampler::for_scene(scene);
+    let filter = FilterSampler::new(scene.settings.filter);
+    let no_splats = Crop {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+    };
+    let splats = Splats::new(scene, &no_splats, 1);
+    let components: &[LightingComponent] = if scene.mode == RenderMode::Shaded {
+        &[
+            LightingComponent::Direct,
+            LightingComponent::IndirectDiffuse,
+            LightingComponent::IndirectSpecular,
+        ]
     } else {
-        Color::black()
+        &[LightingComponent::Direct]
+    };
+    let pixel = |x, y| {
+        let mut sums = [Color::black(); 3];
+        let mut total_weight = 0.0;
+        for sample in 0..scene.settings.samples {
+            let mut colors = [Color::black(); 3];
+            let mut weight = 0.0;
+            for (i, &component) in components.iter().enumerate() {
+                SPLIT.with(|split| {
+                    split.set(Split {
+                        component: (scene.mode == RenderMode::Shaded).then_some(component),
+                        bounces: 0,
+                        diffuse: Color::white(),
+                    })
+                });
+                let s = sample_pixel(scene, &lights, &filter, &splats, x, y, sample);
+                colors[i] = s.color;
+                weight = s.weight;
+            }
+            let scale = scene
+                .settings
+                .sample_clamp
+                .map_or(1.0, |clamp| clamp.scale(colors.iter().copied().sum()));
+            for (sum, color) in sums.iter_mut().zip(colors) {
+                *sum += color * (scale * weight);
+            }
+            total_weight += weight;
+        }
+        SPLIT.with(|split| {
+            split.set(Split {
+                component: None,
+                ..split.get()
+            })
+        });
+        let [direct, indirect_diffuse, indirect_specular] = if total_weight > 0.0 {
+            sums.map(|sum| sum / total_weight)
+        } else {
+            [Color::black(); 3]
+        };
+        Lighting {
+            direct,
+            indirect_diffuse,
+            indirect_specular,
+        }
+    };
+    #[cfg(feature = "parallel")]
+    let rows = (0..scene.height).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let rows = 0..scene.height;
+    let rows: Vec<Vec<Lighting>> = rows
+        .map(|y| (0..scene.width).map(|x| pixel(x, y)).collect())
+        .collect();
+    rows.into_iter().flatten().collect()
+}
+
+/// 按scene.light_groups分开的光，每组一张图，最后多一张是不在任何组里的光源
+/// （还有焦散光子图、辐照度缓存和体积自发光这些分不出来自哪个光源的光），按行排，没有clamp。
+/// 和lighting_pass一样每个样本用同样的随机数每组追踪一遍，所有的图加起来正好是主图，
+/// 花的时间是组数加一倍；总是用路径追踪，样本的亮度上限按所有组的和算
+pub fn light_group_pass(scene: &Scene) -> Vec<Vec<Color>> {
+    let lights = LightSampler::for_scene(scene);
+    let filter = FilterSampler::new(scene.settings.filter);
+    let no_splats = Crop {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+    };
+    let splats = Splats::new(scene, &no_splats, 1);
+    let groups: Vec<Option<usize>> = (0..scene.light_groups.len())
+        .map(Some)
+        .chain([None])
+        .collect();
+    let pixel = |x, y| {
+        let mut sums = vec![Color::black(); groups.len()];
+        let mut total_weight = 0.0;
+        for sample in 0..scene.settings.samples {
+            let mut colors = vec![Color::black(); groups.len()];
+            let mut weight = 0.0;
+            for (color, &group) in colors.iter_mut().zip(&groups) {
+                LIGHT_GROUP.with(|g| g.set(Some(group)));
+                let s = sample_pixel(scene, &lights, &filter, &splats, x, y, sample);
+                *color = s.color;
+                weight = s.weight;
+            }
+            let scale = scene
+                .settings
+                .sample_clamp
+                .map_or(1.0, |clamp| clamp.scale(colors.iter().copied().sum()));
+            for (sum, color) in sums.iter_mut().zip(colors) {
+                *sum += color * (scale * weight);
+            }
+            total_weight += weight;
+        }
+        LIGHT_GROUP.with(|g| g.set(None));
+        if total_weight > 0.0 {
+            sums.iter().map(|&sum| sum / total_weight).collect()
+        } else {
+            sums
+        }
+    };
+    #[cfg(feature = "parallel")]
+    let rows = (0..scene.height).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let rows = 0..scene.height;
+    let rows: Vec<Vec<Vec<Color>>> = rows
+        .map(|y| (0..scene.width).map(|x| pixel(x, y)).collect())
+        .collect();
+    (0..groups.len())
+        .map(|group| rows.iter().flatten().map(|p| p[group]).collect())
+        .collect()
+}
+
+/// Albedo模式和AOV用的颜色
+fn albedo(intersection: &Intersection, hit_point: &Point) -> Color {
+    match intersection.item.volume() {
+        Some(volume) => volume.albedo,
+        None => intersection.base_color(hit_point),
     }
 }
 
-pub fn render(scene: &Scene) -> DynamicImage {
-    let pixels = par_render_pixels(scene);
-    let w = scene.width;
-    let image = ImageBuffer::from_fn(scene.width, scene.height, |x, y| {
-        Rgba::from(pixels[(x + y * w) as usize].to_rgba8())
-        // Rgba::from(render_a_pixel(scene, x, y).to_rgba8())
-    });
-    DynamicImage::ImageRgba8(image)
+/// 找离射线起点最近的会发光的光源
+pub(crate) fn trace_lights(scene: &Scene, ray: &Ray) -> Option<(usize, Distance)> {
+    scene
+        .lights
+        .iter()
+        .enumerate()
+        .filter_map(|(index, l)| l.intersect(ray).map(|d| (index, d)))
+        .filter(|l| !l.1.is_nan())
+        .min_by(|l1, l2| l1.1.total_cmp(&l2.1))
 }
 
-pub fn cast_ray(scene: &Scene, ray: &Ray, depth: usize) -> Color {
-    if depth >= MAX_RECURSION {
-        return Color::black();
+/// 输出什么。除了Shaded，别的都只追踪相机发出的那一条光线、不算光照，调试着色问题时用
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RenderMode {
+    /// 正常的路径追踪
+    #[default]
+    Shaded,
+    /// 世界坐标系里的法线，x、y、z从[-1, 1]映射到r、g、b的[0, 1]
+    Normals,
+    /// 沿光线到交点的距离，0是黑的，far和更远（包括什么都没打中）是白的
+    Depth { far: Distance },
+    /// 纹理坐标u、v的小数部分放在r、g里
+    Uv,
+    /// 交点处材质的基础颜色（贴图、顶点色），体积是它的单次散射反照率
+    Albedo,
+    /// 每个物体一种颜色（见id_color），没有编号的物体是灰的
+    ObjectId,
+    /// 正常渲染，但每个样本按它追踪了多少段光线上色：0是黑的，settings.max_depth是红的，
+    /// 中间依次是蓝、绿、黄。看哪里在玻璃、镜子之间反复弹射
+    Bounces,
+}
+
+/// 每个样本颜色的上限。偶尔有样本打中很亮的小光源，会留下一个怎么也平均不掉的白点（firefly）；
+/// 压住它们会让画面整体暗一点，换来没有噪点
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleClamp {
+    /// 最亮的通道超过上限时整个颜色等比例缩到上限，色相不变
+    Hard(f32),
+    /// 最亮的通道到上限的一半以前不动，之后平滑地压缩，越来越接近上限但不会超过
+    Soft(f32),
+}
+
+impl SampleClamp {
+    pub fn apply(self, color: Color) -> Color {
+        color * self.scale(color)
     }
 
-    let intersection = trace(scene, ray);
-    intersection
-        .map(|i| get_color(scene, &ray, &i, depth))
-        .unwrap_or_else(Color::black)
+    /// color要乘上的系数，没超过上限时是1
+    pub fn scale(self, color: Color) -> f32 {
+        let value = color.r.max(color.g).max(color.b);
+        let limited = match self {
+            SampleClamp::Hard(max) => value.min(max),
+            SampleClamp::Soft(max) => {
+                let knee = max * 0.5;
+                if value <= knee {
+                    value
+                } else {
+                    knee + (max - knee) * (1.0 - (-(value - knee) / (max - knee)).exp())
+                }
+            }
+        };
+        if value > limited {
+            limited / value
+        } else {
+            1.0
+        }
+    }
+
+    pub fn max(self) -> f32 {
+        match self {
+            SampleClamp::Hard(max) | SampleClamp::Soft(max) => max,
+        }
+    }
 }
 
-fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: usize) -> Color {
-    let hit_point = ray.origin + (ray.direction * intersection.distance);
-    let surface_normal = intersection.item.surface_normal(&hit_point);
-    match intersection.item.get_material().surface {
-        SurfaceType::Diffuse => shader_diffuse(scene, intersection.item, hit_point, surface_normal),
-        SurfaceType::Reflective { reflectivity } => {
-            let mut color = shader_diffuse(scene, intersection.item, hit_point, surface_normal);
-            let reflection_ray =
-                Ray::create_reflection(surface_normal, ray.direction, hit_point, SHADOW_BIAS);
-            color = color * (1.0 - reflectivity);
-            color += cast_ray(scene, &reflection_ray, depth + 1) * reflectivity;
-            color
+/// 画面上的一块矩形区域，只渲染这一块时用，单位是像素
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    pub fn full(scene: &Scene) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: scene.width,
+            height: scene.height,
         }
-        SurfaceType::Refractive {
-            index,
-            transparency,
-        } => {
-            let mut refraction_color = Color::black();
-            let uv = intersection.item.texture_coords(&hit_point);
-            let surface_color = intersection.item.get_material().color.color(&uv);
-            let kr = fresnel(ray.direction, surface_normal, index) as f32;
+    }
 
-            if kr < 1.0 {
-                let transmission_ray = Ray::create_transmission(
-                    surface_normal,
-                    ray.direction,
-                    hit_point,
-                    SHADOW_BIAS,
-                    index,
-                )
-                .expect("gettting trans ray");
-                refraction_color = cast_ray(scene, &transmission_ray, depth + 1);
+    /// 把整个画面切成size * size的小块，按行排，右边和下边的块可能小一些
+    pub fn tiles(scene: &Scene, size: u32) -> Vec<Self> {
+        let size = size.max(1);
+        let mut tiles = Vec::new();
+        for y in (0..scene.height).step_by(size as usize) {
+            for x in (0..scene.width).step_by(size as usize) {
+                tiles.push(Self {
+                    x,
+                    y,
+                    width: size.min(scene.width - x),
+                    height: size.min(scene.height - y),
+                });
             }
-            // println!(
-            //     "hit:{:?}, in:{:?}, n:{:?} -> {:?}",
-            //     hit_point, ray.direction, surface_normal, transmission_ray.direction
-            // );
+        }
+        tiles
+    }
 
-            let reflection_ray =
-                Ray::create_reflection(surface_normal, ray.direction, hit_point, SHADOW_BIAS);
-            let reflection_color = cast_ray(scene, &reflection_ray, depth + 1);
-            let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
-            // println!("d: {} refc:{:?}, surfacec:{:?}", depth, color, surface_color);
-            color = color * transparency * surface_color;
-            color
+    /// 裁掉超出画面的部分
+    fn clamped(&self, scene: &Scene) -> Self {
+        let x = self.x.min(scene.width);
+        let y = self.y.min(scene.height);
+        Self {
+            x,
+            y,
+            width: self.width.min(scene.width - x),
+            height: self.height.min(scene.height - y),
         }
     }
+
+    fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
 }
 
-fn shader_diffuse(
-    scene: &Scene,
-    item: &dyn Intersectable,
-    hit_point: Point,
-    surface_normal: Vector3,
-) -> Color {
-    let uv = item.texture_coords(&hit_point);
-    let color = scene
-        .lights
-        .iter()
-        .map(|light| color_from_light(scene, light.as_ref(), hit_point, surface_normal))
-        .sum::<Color>()
-        * item.get_material().albedo
-        / std::f32::consts::PI;
-    item.get_material().color.color(&uv) * color
+/// 取消渲染用的标记，clone一份给别的线程（比如UI），cancel之后还没开始的行都不再算
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
 }
 
-fn color_from_light(
-    scene: &Scene,
-    light: &dyn Light,
-    hit_point: Point,
-    surface_normal: Vector3,
-) -> Color {
-    let dir = light.direction_from(&hit_point);
-    let theta = surface_normal.dot(&dir) as f32;
-    let shadow_ray = Ray {
-        origin: hit_point + surface_normal * SHADOW_BIAS,
-        direction: dir,
-    };
-    let shadow_intersection = trace(scene, &shadow_ray);
-    let is_in_light = shadow_intersection.is_none()
-        || shadow_intersection.unwrap().distance > light.distance(&hit_point);
-    light.color()
-        * if is_in_light {
-            light.intensity(&hit_point) * theta
+/// 渲染进度，按行算
+#[derive(Debug, Clone, Copy)]
+pub struct RenderProgress {
+    pub rows_done: u32,
+    pub total_rows: u32,
+    /// 到目前为止算了多少个样本
+    pub samples: u64,
+    pub elapsed: Duration,
+    /// 按已经花的时间线性估计还要多久，一行都没算完时是None
+    pub eta: Option<Duration>,
+}
+
+impl RenderProgress {
+    fn new(rows_done: u32, total_rows: u32, samples_per_row: u64, elapsed: Duration) -> Self {
+        let eta = if rows_done > 0 {
+            Some(elapsed.mul_f64((total_rows - rows_done) as f64 / rows_done as f64))
         } else {
-            0.0
+            None
+        };
+        Self {
+            rows_done,
+            total_rows,
+            samples: rows_done as u64 * samples_per_row,
+            elapsed,
+            eta,
+        }
+    }
+
+    /// 完成的比例，0到1
+    pub fn fraction(&self) -> f32 {
+        if self.total_rows == 0 {
+            1.0
+        } else {
+            self.rows_done as f32 / self.total_rows as f32
         }
+    }
+}
+
+pub fn par_render_pixels(scene: &Scene) -> Vec<Color> {
+    par_render_crop(scene, &Crop::full(scene))
+}
+
+/// 只渲染crop里的像素，按行排，长度是crop的width * height
+pub fn par_render_crop(scene: &Scene, crop: &Crop) -> Vec<Color> {
+    par_render_crop_with_progress(scene, crop, &|_| {}, &CancelToken::new())
+}
+
+/// 和par_render_crop一样，但颜色是还没clamp的线性平均值，没加后期也没调色，带着alpha。
+/// 一块块分开渲染时用，拼成整张图以后再做post_process和grader。
+/// lights是LightSampler::for_scene(scene)建的，一块块渲染同一个场景时共用一个，
+/// 焦散光子图和辐照度缓存不用每块重建
+pub fn par_render_crop_linear(
+    scene: &Scene,
+    lights: &LightSampler,
+    crop: &Crop,
+) -> Vec<(Color, f32)> {
+    let crop = crop.clamped(scene);
+    linear_rows(scene, lights, &crop, &|_| {}, &CancelToken::new())
+        .0
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect()
+}
+
+/// 和par_render_crop一样，每算完一行调用一次progress；progress会在工作线程里被调用。
+/// cancel之后还没开始的行不再算，直接是黑的
+pub fn par_render_crop_with_progress(
+    scene: &Scene,
+    crop: &Crop,
+    progress: &(dyn Fn(&RenderProgress) + Sync),
+    cancel: &CancelToken,
+) -> Vec<Color> {
+    let crop = crop.clamped(scene);
+    let lights = LightSampler::for_scene(scene);
+    render_rows(scene, &lights, &crop, progress, cancel)
+        .0
+        .into_iter()
+        .flat_map(|row| match row {
+            Some(row) => row.into_iter().map(|(color, _)| color).collect(),
+            None => vec![Color::black(); crop.width as usize],
+        })
+        .collect()
+}
+
+/// wasm32-unknown-unknown上没有时钟，Instant::now会panic，那里计时一直是0
+fn start_timer() -> Option<Instant> {
+    (!cfg!(target_arch = "wasm32")).then(Instant::now)
+}
+
+fn elapsed(start: Option<Instant>) -> Duration {
+    start.map_or(Duration::default(), |start| start.elapsed())
+}
+
+/// 一行像素乘过alpha的颜色和alpha
+type Row = Vec<(Color, f32)>;
+/// 一行像素按滤波器权重累加的颜色、alpha和权重的和
+type Sums = Vec<(Color, f32, f32)>;
+
+/// 按行并行地算crop（已经裁过）里的像素和alpha，加上后期、调色，clamp到[0, 1]，
+/// 被取消没算的行是None。
+/// 同时返回这次渲染的统计，output的时间由调用的人填，setup不含建lights的时间
+fn render_rows(
+    scene: &Scene,
+    lights: &LightSampler,
+    crop: &Crop,
+    progress: &(dyn Fn(&RenderProgress) + Sync),
+    cancel: &CancelToken,
+) -> (Vec<Option<Row>>, RenderStats) {
+    let (mut rows, mut render_stats) = linear_rows(scene, lights, crop, progress, cancel);
+    let start = start_timer();
+    // 分块渲染时只有一块，光晕和色差会在块的边上断开，所以只在整张图上加
+    if has_post_effects(scene) && *crop == Crop::full(scene) {
+        post_process_rows(scene, crop, &mut rows);
+    }
+    // 调色是一个个像素做的，只渲染一块时也可以
+    let grade = grader(scene);
+    for row in rows.iter_mut().flatten() {
+        for (color, _) in row {
+            *color = grade(*color);
+        }
+    }
+    render_stats.tracing += elapsed(start);
+    (rows, render_stats)
+}
+
+/// 和render_rows一样，但颜色是还没clamp的线性平均值，没加后期也没调色
+fn linear_rows(
+    scene: &Scene,
+    lights: &LightSampler,
+    crop: &Crop,
+    progress: &(dyn Fn(&RenderProgress) + Sync),
+    cancel: &CancelToken,
+) -> (Vec<Option<Row>>, RenderStats) {
+    let mut render_stats = RenderStats::default();
+    let setup = start_timer();
+    let filter = FilterSampler::new(scene.settings.filter);
+    let splats = Splats::new(scene, crop, scene.settings.samples);
+    render_stats.setup = elapsed(setup);
+    let start = start_timer();
+    let rows_done = AtomicU32::new(0);
+    let counters = SharedCounters::default();
+    #[cfg(feature = "parallel")]
+    let rows = (0..crop.height).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let rows = 0..crop.height;
+    let rows = rows
+        .map(|row| {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            // 清掉这个线程之前别的渲染留下的计数
+            stats::take();
+            let y = crop.y + row;
+            let sums: Sums = (crop.x..crop.x + crop.width)
+                .map(|x| render_a_pixel(scene, lights, &filter, &splats, x, y))
+                .collect();
+            counters.add(stats::take());
+            let done = rows_done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(&RenderProgress::new(
+                done,
+                crop.height,
+                crop.width as u64 * scene.settings.samples as u64,
+                elapsed(start),
+            ));
+            Some(sums)
+        })
+        .collect::<Vec<_>>();
+    // 光线追踪的贡献要等所有行都算完才齐
+    let rows: Vec<Option<Row>> = rows
+        .into_iter()
+        .zip(crop.y..)
+        .map(|(sums, y)| {
+            let sums = sums?;
+            let row = sums
+                .into_iter()
+                .zip(crop.x..)
+                .map(|((color, alpha, weight), x)| {
+                    average(color + splats.get(x, y) * weight, alpha, weight)
+                })
+                .collect();
+            Some(row)
+        })
+        .collect();
+    render_stats.tracing = elapsed(start);
+    render_stats.set_counters(counters.get());
+    (rows, render_stats)
+}
+
+/// 像素所有样本按权重累加的颜色、alpha和权重的和
+fn render_a_pixel(
+    scene: &Scene,
+    lights: &LightSampler,
+    filter: &FilterSampler,
+    splats: &Splats,
+    x: u32,
+    y: u32,
+) -> (Color, f32, f32) {
+    (0..scene.settings.samples)
+        .map(|sample| sample_pixel(scene, lights, filter, splats, x, y, sample))
+        .fold((Color::black(), 0.0, 0.0), |(color, alpha, weight), s| {
+            (
+                color + s.color * s.weight,
+                alpha + s.alpha * s.weight,
+                weight + s.weight,
+            )
+        })
+}
+
+/// 按权重的和把累加的颜色和alpha变回平均值，颜色不clamp
+pub(crate) fn average(color: Color, alpha: f32, weight: f32) -> (Color, f32) {
+    if weight > 0.0 {
+        (color / weight, (alpha / weight).clamp(0.0, 1.0))
+    } else {
+        (Color::black(), 0.0)
+    }
+}
+
+/// 场景有没有要看整张图的后期：镜头光晕、泛光或色差
+fn has_post_effects(scene: &Scene) -> bool {
+    let settings = &scene.settings;
+    settings.bloom.is_some() || settings.flare.is_some() || scene.camera.chromatic_aberration != 0.0
+}
+
+/// 在整张图还没clamp的线性颜色（按行排）上加镜头光晕、泛光和色差。
+/// render和累积缓冲输出的图都走这里，两边的结果一样
+pub(crate) fn post_process(scene: &Scene, width: u32, height: u32, pixels: &mut [Color]) {
+    if let Some(flare) = &scene.settings.flare {
+        flare::apply(flare, width, height, pixels);
+    }
+    if let Some(bloom) = &scene.settings.bloom {
+        bloom::apply(bloom, width, height, pixels);
+    }
+    aberration::apply(scene.camera.chromatic_aberration, width, height, pixels);
+}
+
+/// 存成HDR的线性颜色要做的：post_process和白平衡。lift、gamma、gain是给显示用的，不做
+pub(crate) fn finish_hdr(scene: &Scene, width: u32, height: u32, pixels: &mut [Color]) {
+    post_process(scene, width, height, pixels);
+    if let Some(grading) = &scene.settings.grading {
+        let balance = grading.white_balance();
+        for color in pixels {
+            *color = *color * balance;
+        }
+    }
+}
+
+/// 后期之后每个像素要做的：有调色就做白平衡和调色，没有就只clamp到[0, 1]
+pub(crate) fn grader(scene: &Scene) -> impl Fn(Color) -> Color {
+    let grading = scene
+        .settings
+        .grading
+        .map(|grading| (grading, grading.white_balance()));
+    move |color| match &grading {
+        Some((grading, balance)) => grading.grade(color * *balance),
+        None => color.clamp(),
+    }
+}
+
+/// 在还没clamp的行上做post_process，被取消没算的行当成黑的
+fn post_process_rows(scene: &Scene, crop: &Crop, rows: &mut [Option<Row>]) {
+    let width = crop.width as usize;
+    let mut pixels: Vec<Color> = rows
+        .iter()
+        .flat_map(|row| match row {
+            Some(row) => row.iter().map(|&(color, _)| color).collect(),
+            None => vec![Color::black(); width],
+        })
+        .collect();
+    post_process(scene, crop.width, crop.height, &mut pixels);
+    for (row, pixels) in rows.iter_mut().zip(pixels.chunks(width)) {
+        if let Some(row) = row {
+            for ((color, _), pixel) in row.iter_mut().zip(pixels) {
+                *color = *pixel;
+            }
+        }
+    }
+}
+
+/// 一个相机样本
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PixelSample {
+    pub color: Color,
+    /// 不是透明背景时总是1，是的话相机光线什么都没打中时是0
+    pub alpha: f32,
+    /// 像素滤波器给的权重，Box滤波器总是1
+    pub weight: f32,
+}
+
+/// 像素(x, y)的第sample个样本，没有clamp；随机数由场景种子、像素和样本号决定。
+/// 采样的位置按filter分布，落到别的像素上的光线追踪贡献加到splats里
+pub(crate) fn sample_pixel(
+    scene: &Scene,
+    lights: &LightSampler,
+    filter: &FilterSampler,
+    splats: &Splats,
+    x: u32,
+    y: u32,
+    sample: u32,
+) -> PixelSample {
+    let state = SampleState::new(
+        scene.seed,
+        x,
+        y,
+        scene.width,
+        sample as u64,
+        scene.settings.samples as u64,
+    );
+    start_sample(scene.settings.sampler, state);
+    let time = scene.camera.sample_time(random());
+    let (offset, weight) = filter.sample(random_2d());
+    let miss = PixelSample {
+        color: Color::black(),
+        alpha: if scene.transparent { 0.0 } else { 1.0 },
+        weight,
+    };
+    let lens_sample = scene.camera.lens.as_ref().map(|_| random_2d());
+    let ray = match Ray::new_lens_prime(x, y, offset, lens_sample, time, scene) {
+        Some(ray) => ray,
+        None => return miss,
+    };
+    stats::count(|c| c.camera_rays += 1);
+    set_camera_alpha(0.0);
+    let mut color = prime_color(scene, lights, splats, &ray);
+    if scene.mode == RenderMode::Shaded {
+        // 暗角按像素在画面上的位置算，调试输出的那些图不加
+        let film = film_position(x, y, offset, scene);
+        color = color * scene.camera.vignette(film, (scene.width, scene.height));
+    }
+    let alpha = CAMERA_ALPHA.with(Cell::get);
+    if !scene.transparent || alpha > 0.0 {
+        PixelSample {
+            color,
+            alpha: if scene.transparent { alpha } else { 1.0 },
+            weight,
+        }
+    } else {
+        miss
+    }
+}
+
+thread_local! {
+    // 当前样本调用了多少次trace_path，Bounces模式用
+    static BOUNCES: Cell<u32> = const { Cell::new(0) };
+    // 当前样本的相机光线看到的东西有多不透明，透明背景用：打中物体或者光源是1，
+    // 什么都没打中是0，影子捕捉面是影子的浓淡
+    static CAMERA_ALPHA: Cell<f32> = const { Cell::new(0.0) };
+    // 用焦散光子图时：shader_diffuse告诉接下来的color_from_bsdf它是收集焦散的漫反射面
+    static CAUSTIC_RECEIVER: Cell<bool> = const { Cell::new(false) };
+    // 当前光线之前最近的一个非镜面的点是收集焦散的漫反射面，中间只有镜面反射、折射
+    static CAUSTIC_PATH: Cell<bool> = const { Cell::new(false) };
+    // light_group_pass分组时在算哪一组，Some(None)是不在任何组里的光
+    static LIGHT_GROUP: Cell<Option<Option<usize>>> = const { Cell::new(None) };
+    // lighting_pass分通道时在算哪个通道，以及当前光线的路径弹射到哪了
+    static SPLIT: Cell<Split> = const {
+        Cell::new(Split {
+            component: None,
+            bounces: 0,
+            diffuse: Color {
+                r: 1.0,
+                g: 1.0,
+                b: 1.0,
+            },
+        })
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Split {
+    /// None是不分通道，所有的光都要
+    component: Option<LightingComponent>,
+    /// 相机看到的点之后弹射了几次
+    bounces: u32,
+    /// 第一次弹射里漫反射占的比例
+    diffuse: Color,
+}
+
+/// 在一次弹射之后接着追踪，diffuse是这次弹射里漫反射占的比例
+fn bounce<T>(diffuse: Color, trace: impl FnOnce() -> T) -> T {
+    let outer = SPLIT.with(Cell::get);
+    if outer.component.is_none() {
+        return trace();
+    }
+    SPLIT.with(|split| {
+        split.set(Split {
+            bounces: outer.bounces + 1,
+            diffuse: if outer.bounces == 0 {
+                diffuse
+            } else {
+                outer.diffuse
+            },
+            ..outer
+        })
+    });
+    let result = trace();
+    SPLIT.with(|split| split.set(outer));
+    result
+}
+
+/// 从这里再弹射extra次到光源的光里，有多少属于正在算的通道；不分通道时是1。
+/// 相机看到的点之后最多弹射一次就到光源的是直接光，其余的按第一次弹射分成间接漫反射和间接高光，
+/// 第一次弹射就在这里时它的漫反射比例是diffuse
+fn split_weight(extra: u32, diffuse: Color) -> Color {
+    let split = SPLIT.with(Cell::get);
+    let Some(component) = split.component else {
+        return Color::white();
+    };
+    let diffuse = if split.bounces == 0 {
+        diffuse
+    } else {
+        split.diffuse
+    };
+    match component {
+        LightingComponent::Direct if split.bounces + extra <= 1 => Color::white(),
+        _ if split.bounces + extra <= 1 => Color::black(),
+        LightingComponent::Direct => Color::black(),
+        LightingComponent::IndirectDiffuse => diffuse,
+        LightingComponent::IndirectSpecular => Color::white() - diffuse,
+    }
+}
+
+/// 第light个光源或者编号object的发光物体的光算不算在light_group_pass正在算的组里，
+/// 两个都是None的（焦散光子图、辐照度缓存、体积的自发光）只算在不在任何组里的那份；不分组时都算
+fn group_weight(scene: &Scene, light: Option<usize>, object: Option<u32>) -> f32 {
+    match LIGHT_GROUP.with(Cell::get) {
+        Some(group) if scene.light_group_of(light, object) != group => 0.0,
+        _ => 1.0,
+    }
+}
+
+/// 相机光线有没有打中物体或者能看到的光源，自己追踪相机光线的积分器要告诉透明背景
+pub(crate) fn set_camera_hit(hit: bool) {
+    set_camera_alpha(if hit { 1.0 } else { 0.0 });
+}
+
+fn set_camera_alpha(alpha: f32) {
+    CAMERA_ALPHA.with(|camera_alpha| camera_alpha.set(alpha));
+}
+
+/// 按scene.mode算相机光线的颜色
+fn prime_color(scene: &Scene, lights: &LightSampler, splats: &Splats, ray: &Ray) -> Color {
+    let hit = || {
+        let hit = trace(scene, ray).map(|i| {
+            let hit_point = ray.origin + ray.direction * i.distance;
+            (i, hit_point)
+        });
+        set_camera_hit(hit.is_some());
+        hit
+    };
+    match scene.mode {
+        // 分光照通道、光源分组时总是路径追踪，亮度上限由lighting_pass、light_group_pass按几份的和来管
+        RenderMode::Shaded
+            if SPLIT.with(Cell::get).component.is_some()
+                || LIGHT_GROUP.with(Cell::get).is_some() =>
+        {
+            cast_ray(scene, lights, ray, 0) * scene.camera.exposure.scale()
+        }
+        // 先曝光再限亮度，上限是按显示出来的亮度定的
+        RenderMode::Shaded => {
+            let integrator = scene.settings.integrator.integrator();
+            let color =
+                integrator.radiance(scene, lights, ray, splats) * scene.camera.exposure.scale();
+            scene
+                .settings
+                .sample_clamp
+                .map_or(color, |clamp| clamp.apply(color))
+        }
+        RenderMode::Bounces => {
+            BOUNCES.with(|bounces| bounces.set(0));
+            cast_ray(scene, lights, ray, 0);
+            heatmap(BOUNCES.with(Cell::get) as f32 / scene.settings.max_depth as f32)
+        }
+        RenderMode::Normals => hit().map_or(Color::black(), |(i, hit_point)| {
+            let n = i.surface_normal(&hit_point);
+            Color::new(
+                (n.x * 0.5 + 0.5) as f32,
+                (n.y * 0.5 + 0.5) as f32,
+                (n.z * 0.5 + 0.5) as f32,
+            )
+        }),
+        RenderMode::Depth { far } => {
+            let distance = hit().map_or(Float::INFINITY, |(i, _)| i.distance);
+            Color::white() * (distance / far).min(1.0) as f32
+        }
+        RenderMode::Uv => hit().map_or(Color::black(), |(i, hit_point)| {
+            let coords = i.texture_coords(&hit_point);
+            Color::new(coords.u.rem_euclid(1.0), coords.v.rem_euclid(1.0), 0.0)
+        }),
+        RenderMode::ObjectId => hit().map_or(Color::black(), |(i, _)| match i.object_id {
+            Some(id) => id_color(id),
+            None => Color::white() * 0.5,
+        }),
+        RenderMode::Albedo => hit().map_or(Color::black(), |(i, hit_point)| albedo(&i, &hit_point)),
+    }
+}
+
+pub fn render(scene: &Scene) -> DynamicImage {
+    render_crop(scene, &Crop::full(scene))
+}
+
+/// 只渲染crop这一块，图还是整个画面那么大，crop外面是透明的黑色
+pub fn render_crop(scene: &Scene, crop: &Crop) -> DynamicImage {
+    render_with_progress(scene, crop, |_| {}, &CancelToken::new())
+}
+
+/// 渲染crop这一块，每算完一行用RenderProgress报告一次进度。
+/// 中途cancel的话返回已经算完的部分，没算的行是透明的黑色
+pub fn render_with_progress(
+    scene: &Scene,
+    crop: &Crop,
+    progress: impl Fn(&RenderProgress) + Sync,
+    cancel: &CancelToken,
+) -> DynamicImage {
+    render_with_stats(scene, crop, progress, cancel).0
+}
+
+/// 和render_with_progress一样，另外返回光线数、节点访问数和各阶段的时间
+pub fn render_with_stats(
+    scene: &Scene,
+    crop: &Crop,
+    progress: impl Fn(&RenderProgress) + Sync,
+    cancel: &CancelToken,
+) -> (DynamicImage, RenderStats) {
+    let crop = crop.clamped(scene);
+    let setup = start_timer();
+    let lights = LightSampler::for_scene(scene);
+    let setup = elapsed(setup);
+    let (rows, mut render_stats) = render_rows(scene, &lights, &crop, &progress, cancel);
+    render_stats.setup += setup;
+    let output = start_timer();
+    let image = ImageBuffer::from_fn(scene.width, scene.height, |x, y| {
+        let row = if crop.contains(x, y) {
+            rows[(y - crop.y) as usize].as_ref()
+        } else {
+            None
+        };
+        match row {
+            Some(row) => {
+                let (color, alpha) = row[(x - crop.x) as usize];
+                Rgba::from(color.to_rgba8_with_alpha(alpha, scene.settings.output_space))
+            }
+            None => Rgba([0, 0, 0, 0]),
+        }
+    });
+    render_stats.output = elapsed(output);
+    (DynamicImage::ImageRgba8(image), render_stats)
+}
+
+/// 和render_with_progress一样渲染整张图，但返回不clamp的HDR图，和Accumulator::hdr_image一样
+/// 加了后期和白平衡。中途cancel的话没算的行是黑的
+pub fn render_hdr_with_progress(
+    scene: &Scene,
+    progress: impl Fn(&RenderProgress) + Sync,
+    cancel: &CancelToken,
+) -> HdrImage {
+    let crop = Crop::full(scene);
+    let lights = LightSampler::for_scene(scene);
+    let (rows, _) = linear_rows(scene, &lights, &crop, &progress, cancel);
+    let mut pixels: Vec<Color> = rows
+        .into_iter()
+        .flat_map(|row| match row {
+            Some(row) => row.into_iter().map(|(color, _)| color).collect(),
+            None => vec![Color::black(); crop.width as usize],
+        })
+        .collect();
+    finish_hdr(scene, scene.width, scene.height, &mut pixels);
+    HdrImage::new(scene.width, scene.height, pixels)
+}
+
+pub fn cast_ray(scene: &Scene, lights: &LightSampler, ray: &Ray, depth: usize) -> Color {
+    trace_path(scene, lights, ray, depth, None)
+}
+
+/// bsdf_sample是上一个着色点和它采样出这条光线的pdf；
+/// 相机和镜面反射这种delta方向的光线是None，打中面光源时直接拿光源的radiance
+fn trace_path(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    depth: usize,
+    bsdf_sample: Option<(Point, Float)>,
+) -> Color {
+    if depth >= scene.settings.max_depth {
+        return Color::black();
+    }
+    BOUNCES.with(|bounces| bounces.set(bounces.get() + 1));
+
+    let intersection = trace(scene, ray);
+    let light_hit = trace_lights(scene, ray)
+        .filter(|(_, distance)| intersection.as_ref().is_none_or(|i| *distance < i.distance));
+    if depth == 0 {
+        // 天光在无穷远处，看到它的像素在透明背景下还是透明的
+        let light_visible = light_hit.is_some_and(|(_, distance)| distance.is_finite());
+        set_camera_hit(intersection.is_some() || light_visible);
+    }
+    let mut throughput = Color::white();
+    if let Some(ref medium) = scene.medium {
+        let nearest = light_hit
+            .map(|(_, distance)| distance)
+            .or_else(|| intersection.as_ref().map(|i| i.distance))
+            .unwrap_or(Float::INFINITY);
+        match medium.sample(ray, nearest, lights.bounds(), random_2d()) {
+            MediumSample::Scatter { distance, weight } => {
+                let point = ray.origin + ray.direction * distance;
+                return shade_bsdf(
+                    scene,
+                    lights,
+                    &IsotropicPhase,
+                    ray,
+                    point,
+                    ray.direction,
+                    depth,
+                ) * weight;
+            }
+            MediumSample::Pass { weight } => throughput = weight,
+        }
+    }
+
+    if let Some((index, _)) = light_hit {
+        let light = scene.lights[index].as_ref();
+        // 漫反射面之后只经过镜面打到发光子的光源，这条路径已经算在焦散光子图里了
+        if bsdf_sample.is_none()
+            && CAUSTIC_PATH.with(Cell::get)
+            && light.emitter().is_some()
+            && lights.caustics().is_some()
+        {
+            return Color::black();
+        }
+        let weight = bsdf_sample.map_or(1.0, |(origin, pdf)| {
+            let light_pdf =
+                light.pdf(&origin, &ray.direction) * light_selection_pdf(scene, lights, index);
+            power_heuristic(pdf, light_pdf)
+        });
+        return light.emitted()
+            * throughput
+            * split_weight(0, Color::white())
+            * (weight as f32 * group_weight(scene, Some(index), None));
+    }
+    if let Some(volume) = intersection.as_ref().and_then(|i| i.item.volume()) {
+        return track_volume(scene, lights, ray, volume, depth, bsdf_sample) * throughput;
+    }
+    if let Some(catcher) = intersection
+        .as_ref()
+        .filter(|i| depth == 0 && scene.transparent && i.material().shadow_catcher)
+    {
+        return shade_catcher(scene, lights, ray, catcher) * throughput;
+    }
+    intersection
+        .map(|i| get_color(scene, lights, ray, &i, depth, bsdf_sample) * throughput)
+        .unwrap_or_else(Color::black)
+}
+
+/// delta tracking：按体积里最大的σt采样碰撞，按σt(p) / σt_max的概率算真碰撞，
+/// 否则是虚碰撞继续往前走。盒子里有不透明的物体时追踪到它为止，走到了就给它着色；
+/// 否则穿出盒子从出口接着追踪。穿过体积不算一次弹射
+fn track_volume(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    volume: &Volume,
+    depth: usize,
+    bsdf_sample: Option<(Point, Float)>,
+) -> Color {
+    let (t0, t1) = match volume.bounds.hit(&ray.origin, &ray.direction) {
+        Some(segment) => segment,
+        None => return Color::black(),
+    };
+    let surface = trace_surfaces(scene, ray).filter(|s| s.distance < t1);
+    let t1 = surface.as_ref().map_or(t1, |s| s.distance);
+    let max_sigma_t = volume.max_sigma_t();
+    if max_sigma_t > 0.0 {
+        let mut t = t0;
+        loop {
+            t -= (1.0 - random()).ln() / max_sigma_t;
+            if t >= t1 {
+                break;
+            }
+            let point = ray.origin + ray.direction * t;
+            if random() < volume.sigma_t(&point) / max_sigma_t {
+                // 真碰撞：被吸收的那部分(1 - albedo)贡献自发光，其余的散射出去
+                let absorbed = Color::white() - volume.albedo;
+                return volume.emitted(&point)
+                    * absorbed
+                    * split_weight(0, Color::white())
+                    * group_weight(scene, None, None)
+                    + shade_bsdf(
+                        scene,
+                        lights,
+                        &IsotropicPhase,
+                        ray,
+                        point,
+                        ray.direction,
+                        depth,
+                    ) * volume.albedo;
+            }
+        }
+    }
+    match surface {
+        Some(surface) => get_color(scene, lights, ray, &surface, depth, bsdf_sample),
+        None => {
+            let exit = ray.spawn(ray.origin + ray.direction * t1, ray.direction);
+            trace_path(scene, lights, &exit, depth, bsdf_sample)
+        }
+    }
+}
+
+/// ratio tracking估计体积里[t0, t1]这一段的透射率
+fn ratio_tracking(volume: &Volume, ray: &Ray, t0: Distance, t1: Distance) -> Float {
+    let max_sigma_t = volume.max_sigma_t();
+    if max_sigma_t <= 0.0 {
+        return 1.0;
+    }
+    let mut transmittance = 1.0;
+    let mut t = t0;
+    loop {
+        t -= (1.0 - random()).ln() / max_sigma_t;
+        if t >= t1 {
+            return transmittance;
+        }
+        let point = ray.origin + ray.direction * t;
+        transmittance *= 1.0 - (volume.sigma_t(&point) / max_sigma_t).min(1.0);
+    }
+}
+
+/// 阴影射线走max_distance的透射率：被不透明的东西挡住就是黑的，穿过体积时用ratio tracking。
+/// 充满场景的雾只算到lights记下的场景包围球为止
+pub(crate) fn transmittance(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    max_distance: Distance,
+) -> Color {
+    stats::count(|c| c.shadow_rays += 1);
+    let mut result = scene.medium.as_ref().map_or(Color::white(), |m| {
+        m.transmittance(ray, max_distance, lights.bounds())
+    });
+    let mut segment = ray.spawn(ray.origin, ray.direction);
+    let mut remaining = max_distance;
+    loop {
+        let intersection = match trace(scene, &segment) {
+            Some(i) if i.distance <= remaining => i,
+            _ => return result,
+        };
+        let volume = match intersection.item.volume() {
+            Some(volume) => volume,
+            None => return Color::black(),
+        };
+        let (t0, t1) = match volume.bounds.hit(&segment.origin, &segment.direction) {
+            Some(hit) => hit,
+            None => return result,
+        };
+        let t1 = t1.min(remaining);
+        // 体积里面的不透明物体挡住了光
+        if trace_surfaces(scene, &segment).is_some_and(|s| s.distance < t1) {
+            return Color::black();
+        }
+        result = result * ratio_tracking(volume, &segment, t0, t1) as f32;
+        remaining -= t1;
+        if remaining <= 0.0 {
+            return result;
+        }
+        segment.origin = segment.origin + segment.direction * t1;
+    }
+}
+
+fn get_color(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    intersection: &Intersection,
+    depth: usize,
+    bsdf_sample: Option<(Point, Float)>,
+) -> Color {
+    let base = shader_surface(scene, lights, ray, intersection, depth, bsdf_sample);
+    match intersection.material().clearcoat {
+        None => base,
+        Some(ref clearcoat) => {
+            // 透明涂层：涂层自己的GGX高光，加上被涂层菲涅尔反射剩下的底层
+            let hit_point = ray.origin + (ray.direction * intersection.distance);
+            let surface_normal = intersection.surface_normal(&hit_point);
+            let f0 = ((clearcoat.ior - 1.0) / (clearcoat.ior + 1.0)).powi(2);
+            let f0 = Color::white() * f0;
+            let cos = surface_normal.dot(&-ray.direction).abs();
+            let coat_fresnel = fresnel_schlick(f0, cos).luminance();
+            let coat = Ggx::new(surface_normal, clearcoat.roughness, f0);
+            base * (1.0 - coat_fresnel)
+                + shade_bsdf(scene, lights, &coat, ray, hit_point, surface_normal, depth)
+        }
+    }
+}
+
+/// bsdf_sample和trace_path的一样，给自发光的MIS权重用
+fn shader_surface(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    intersection: &Intersection,
+    depth: usize,
+    bsdf_sample: Option<(Point, Float)>,
+) -> Color {
+    let hit_point = ray.origin + (ray.direction * intersection.distance);
+    let surface_normal = intersection.surface_normal(&hit_point);
+    match intersection.material().surface {
+        SurfaceType::Diffuse => shader_diffuse(
+            scene,
+            lights,
+            intersection,
+            ray,
+            hit_point,
+            surface_normal,
+            depth,
+        ),
+        SurfaceType::Reflective { reflectivity } => {
+            let mut color = shader_diffuse(
+                scene,
+                lights,
+                intersection,
+                ray,
+                hit_point,
+                surface_normal,
+                depth,
+            );
+            let reflection_ray =
+                Ray::create_reflection(surface_normal, ray, hit_point, scene.settings.shadow_bias);
+            color = color * (1.0 - reflectivity);
+            let reflection = bounce(Color::black(), || {
+                cast_ray(scene, lights, &reflection_ray, depth + 1)
+            });
+            color += reflection * reflectivity;
+            color
+        }
+        SurfaceType::Microfacet {
+            roughness_u,
+            roughness_v,
+            rotation,
+        } => {
+            let f0 = intersection.base_color(&hit_point);
+            let bsdf = Ggx::anisotropic(
+                surface_normal,
+                intersection.tangent(&hit_point),
+                (roughness_u, roughness_v),
+                rotation,
+                f0,
+            );
+            shade_bsdf(scene, lights, &bsdf, ray, hit_point, surface_normal, depth)
+        }
+        SurfaceType::Refractive {
+            index,
+            transparency,
+            dispersion,
+        } => {
+            let surface_color = intersection.base_color(&hit_point);
+            let color = shader_refractive(
+                scene,
+                lights,
+                ray,
+                hit_point,
+                surface_normal,
+                (index, dispersion),
+                depth,
+            );
+            // println!("d: {} refc:{:?}, surfacec:{:?}", depth, color, surface_color);
+            color * transparency * surface_color
+        }
+        SurfaceType::Principled(ref principled) => {
+            let base_color = intersection.base_color(&hit_point);
+            let metallic = intersection.metallic(principled, &hit_point);
+            let transmission = (principled.transmission * (1.0 - metallic)) as Float;
+            // 按透射比例随机选一边，两边的权重正好抵消选择的概率
+            let color = if random() < transmission {
+                shader_refractive(
+                    scene,
+                    lights,
+                    ray,
+                    hit_point,
+                    surface_normal,
+                    (principled.ior, principled.dispersion),
+                    depth,
+                ) * base_color
+            } else {
+                let bsdf = PrincipledBsdf::new(
+                    surface_normal,
+                    base_color,
+                    metallic,
+                    intersection.roughness(principled, &hit_point),
+                    principled.specular,
+                );
+                shade_bsdf(scene, lights, &bsdf, ray, hit_point, surface_normal, depth)
+            };
+            let weight = emission_weight(scene, lights, ray, intersection, bsdf_sample) as f32
+                * group_weight(scene, None, intersection.object_id);
+            color
+                + intersection.emission(principled, &hit_point)
+                    * split_weight(0, Color::white())
+                    * weight
+        }
+        SurfaceType::Hair(ref hair) => {
+            let bsdf = HairBsdf::new(
+                surface_normal,
+                intersection.tangent(&hit_point),
+                intersection.base_color(&hit_point),
+                hair.roughness,
+                hair.azimuthal_roughness,
+                hair.tilt,
+            );
+            shade_bsdf(scene, lights, &bsdf, ray, hit_point, surface_normal, depth)
+        }
+        SurfaceType::Subsurface(ref subsurface) => shader_subsurface(
+            scene,
+            lights,
+            intersection,
+            ray,
+            hit_point,
+            subsurface,
+            depth,
+        ),
+    }
+}
+
+/// BSDF采样的光线打中会发光的物体时自发光的权重：物体也被当成光源采样（见EmissiveLight）时，
+/// 和trace_path打中光源一样按power heuristic分给两边，别的都是1
+fn emission_weight(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    intersection: &Intersection,
+    bsdf_sample: Option<(Point, Float)>,
+) -> Float {
+    let (Some((origin, pdf)), Some(id)) = (bsdf_sample, intersection.object_id) else {
+        return 1.0;
+    };
+    match scene.lights.iter().position(|l| l.object_id() == Some(id)) {
+        Some(index) => {
+            let light_pdf = scene.lights[index].pdf(&origin, &ray.direction)
+                * light_selection_pdf(scene, lights, index);
+            power_heuristic(pdf, light_pdf)
+        }
+        None => 1.0,
+    }
+}
+
+/// 相机直接看到的影子捕捉面：返回的颜色只有Reflective的镜面反射里看到的别的物体，
+/// alpha是影子的浓淡和被反射的物体盖住的部分合起来。影子的浓淡是所有光源照过来的光里
+/// 被挡住的比例，按白色的漫反射面算
+fn shade_catcher(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    intersection: &Intersection,
+) -> Color {
+    let hit_point = ray.origin + ray.direction * intersection.distance;
+    let normal = intersection.surface_normal(&hit_point);
+    let normal = if normal.dot(&ray.direction) > 0.0 {
+        -normal
+    } else {
+        normal
+    };
+    let bsdf = Lambertian {
+        normal,
+        albedo: Color::white(),
+    };
+    let wo = -ray.direction;
+    let (mut lit, mut total) = (0.0, 0.0);
+    for light in &scene.lights {
+        let sample = light.sample(&hit_point);
+        let unshadowed = (sample.intensity * bsdf.eval(&wo, &sample.direction)).luminance();
+        if unshadowed <= 0.0 {
+            continue;
+        }
+        let shadow_ray = ray.spawn(
+            offset_origin(scene, hit_point, normal, &sample.direction),
+            sample.direction,
+        );
+        lit += unshadowed * transmittance(scene, lights, &shadow_ray, sample.distance).luminance();
+        total += unshadowed;
+    }
+    let shadow = if total > 0.0 { 1.0 - lit / total } else { 0.0 };
+    let (reflection, cover) = match intersection.material().surface {
+        SurfaceType::Reflective { reflectivity } => {
+            let reflection_ray =
+                Ray::create_reflection(normal, ray, hit_point, scene.settings.shadow_bias);
+            // 反射里只有别的物体，天空、光源和影子捕捉面都让照片透出来
+            let object = trace(scene, &reflection_ray).filter(|hit| {
+                !hit.material().shadow_catcher
+                    && trace_lights(scene, &reflection_ray)
+                        .is_none_or(|(_, distance)| distance >= hit.distance)
+            });
+            match object {
+                Some(_) => (
+                    cast_ray(scene, lights, &reflection_ray, 1) * reflectivity,
+                    reflectivity,
+                ),
+                None => (Color::black(), 0.0),
+            }
+        }
+        _ => (Color::black(), 0.0),
+    };
+    set_camera_alpha(1.0 - (1.0 - shadow.clamp(0.0, 1.0)) * (1.0 - cover));
+    reflection
+}
+
+/// 由多次散射后的颜色反推单次散射的反照率（Chiang 2016的拟合）
+fn single_scattering_albedo(multiple: f32) -> f32 {
+    let a = multiple.clamp(0.0, 0.999);
+    let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    1.0 - s * s
+}
+
+/// 随机游走：从hit_point折进物体里，按每个通道的自由程在物体内部散射，
+/// 直到从物体表面出来，在出射点按漫反射算光照
+fn shader_subsurface(
+    scene: &Scene,
+    lights: &LightSampler,
+    intersection: &Intersection,
+    ray: &Ray,
+    hit_point: Point,
+    subsurface: &Subsurface,
+    depth: usize,
+) -> Color {
+    let outward = {
+        let n = intersection.surface_normal(&hit_point);
+        if n.dot(&ray.direction) > 0.0 {
+            -n
+        } else {
+            n
+        }
+    };
+    let sigma_t: Vec<Float> = subsurface
+        .radius
+        .iter()
+        .map(|r| 1.0 / (*r as Float).max(1e-6))
+        .collect();
+    let albedo = [
+        single_scattering_albedo(subsurface.scatter_color.r),
+        single_scattering_albedo(subsurface.scatter_color.g),
+        single_scattering_albedo(subsurface.scatter_color.b),
+    ];
+
+    // 按余弦分布折进物体内部
+    let local = cosine_sample_hemisphere(random_2d());
+    let (tangent, bitangent) = orthonormal_basis(&-outward);
+    let mut walk = ray.spawn(
+        hit_point - outward * scene.settings.shadow_bias,
+        (tangent * local.x + bitangent * local.y - outward * local.z).normalize(),
+    );
+    let mut throughput = [1.0; 3];
+
+    for _ in 0..scene.settings.max_subsurface_steps {
+        let exit = match intersection.intersect_again(&walk) {
+            Some(distance) => distance,
+            None => return Color::black(),
+        };
+        // 随机选一个通道来采样距离，pdf取三个通道的平均
+        let channel = ((random() * 3.0) as usize).min(2);
+        let distance = -(1.0 - random()).ln() / sigma_t[channel];
+        if distance >= exit {
+            let transmittance: Vec<Float> = sigma_t.iter().map(|s| (-s * exit).exp()).collect();
+            let pdf = transmittance.iter().sum::<Float>() / 3.0;
+            for c in 0..3 {
+                throughput[c] *= transmittance[c] / pdf;
+            }
+            let exit_point = walk.origin + walk.direction * exit;
+            let exit_normal = {
+                let n = intersection.surface_normal(&exit_point);
+                if n.dot(&walk.direction) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            };
+            let bsdf = Lambertian {
+                normal: exit_normal,
+                albedo: Color {
+                    r: throughput[0] as f32,
+                    g: throughput[1] as f32,
+                    b: throughput[2] as f32,
+                },
+            };
+            let exit_ray = ray.spawn(exit_point, -exit_normal);
+            return shade_bsdf(
+                scene,
+                lights,
+                &bsdf,
+                &exit_ray,
+                exit_point,
+                exit_normal,
+                depth,
+            );
+        }
+        let pdf = sigma_t
+            .iter()
+            .map(|s| s * (-s * distance).exp())
+            .sum::<Float>()
+            / 3.0;
+        for c in 0..3 {
+            throughput[c] *= albedo[c] as Float * sigma_t[c] * (-sigma_t[c] * distance).exp() / pdf;
+        }
+        walk = walk.spawn(
+            walk.origin + walk.direction * distance,
+            uniform_sample_sphere(random_2d()),
+        );
+    }
+    Color::black()
+}
+
+/// ior是(587.6nm处的折射率, 色散系数)
+fn shader_refractive(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    hit_point: Point,
+    surface_normal: Vector3,
+    ior: (f32, f32),
+    depth: usize,
+) -> Color {
+    // 光谱模式下光线第一次碰到有色散的电介质时才选波长，之前的路径都还是RGB
+    if scene.spectral && ior.1 != 0.0 && ray.wavelength.is_none() {
+        let wavelength = MIN_WAVELENGTH + random() as f32 * (MAX_WAVELENGTH - MIN_WAVELENGTH);
+        let mut ray = ray.spawn(ray.origin, ray.direction);
+        ray.wavelength = Some(wavelength);
+        return shader_refractive(scene, lights, &ray, hit_point, surface_normal, ior, depth)
+            * spectral_weight(wavelength);
+    }
+    let index = dispersed_ior(ior.0, ior.1, ray.wavelength);
+    let mut refraction_color = Color::black();
+    let kr = fresnel(ray.direction, surface_normal, index) as f32;
+
+    if kr < 1.0 {
+        let transmission_ray = Ray::create_transmission(
+            surface_normal,
+            ray,
+            hit_point,
+            scene.settings.shadow_bias,
+            index,
+        )
+        .expect("gettting trans ray");
+        refraction_color = bounce(Color::black(), || {
+            cast_ray(scene, lights, &transmission_ray, depth + 1)
+        });
+    }
+    // println!(
+    //     "hit:{:?}, in:{:?}, n:{:?} -> {:?}",
+    //     hit_point, ray.direction, surface_normal, transmission_ray.direction
+    // );
+
+    let reflection_ray =
+        Ray::create_reflection(surface_normal, ray, hit_point, scene.settings.shadow_bias);
+    let reflection_color = bounce(Color::black(), || {
+        cast_ray(scene, lights, &reflection_ray, depth + 1)
+    });
+    reflection_color * kr + refraction_color * (1.0 - kr)
+}
+
+fn shader_diffuse(
+    scene: &Scene,
+    lights: &LightSampler,
+    intersection: &Intersection,
+    ray: &Ray,
+    hit_point: Point,
+    surface_normal: Vector3,
+    depth: usize,
+) -> Color {
+    let material = intersection.material();
+    let bsdf = Lambertian {
+        normal: surface_normal,
+        albedo: intersection.base_color(&hit_point) * material.albedo,
+    };
+    let caustics = lights.caustics().map_or(Color::black(), |map| {
+        let irradiance = map.irradiance(&hit_point, &surface_normal, &ray.direction);
+        irradiance * bsdf.albedo * split_weight(2, Color::white()) * group_weight(scene, None, None)
+            / std::f32::consts::PI
+    });
+    let cached = match lights.irradiance_cache() {
+        Some(cache) if depth == 0 => cache.lookup(&hit_point, &surface_normal),
+        _ => None,
+    };
+    let color = match cached {
+        // 间接光从缓存里插值，不做BSDF采样，直接光就只靠光源采样
+        Some(indirect) => {
+            direct_light(scene, lights, &bsdf, ray, hit_point, surface_normal, false)
+                + indirect
+                    * bsdf.albedo
+                    * split_weight(2, Color::white())
+                    * group_weight(scene, None, None)
+        }
+        None => {
+            CAUSTIC_RECEIVER.with(|r| r.set(lights.caustics().is_some()));
+            shade_bsdf(scene, lights, &bsdf, ray, hit_point, surface_normal, depth)
+        }
+    };
+    color + caustics
+}
+
+/// 辐照度缓存用：point处朝normal那一侧的半球按余弦分布分层发samples条光线，
+/// 返回除过π的间接光辐照度，和打中的东西距离的调和平均（什么都没打中是无穷大）。
+/// 直接打中光源的不算，用缓存着色时光源另外采样
+pub(crate) fn gather_irradiance(
+    scene: &Scene,
+    lights: &LightSampler,
+    ray: &Ray,
+    point: Point,
+    normal: Vector3,
+    samples: usize,
+) -> (Color, Distance) {
+    let columns = (samples as Float).sqrt().ceil().max(1.0) as usize;
+    let rows = samples.div_ceil(columns).max(1);
+    let (tangent, bitangent) = orthonormal_basis(&normal);
+    let origin = offset_origin(scene, point, normal, &normal);
+    let mut sum = Color::black();
+    let mut inverse_distances = 0.0;
+    for i in 0..samples {
+        let (u, v) = random_2d();
+        let u = ((i % columns) as Float + u) / columns as Float;
+        let v = ((i / columns) as Float + v) / rows as Float;
+        let local = cosine_sample_hemisphere((u, v));
+        let direction = (tangent * local.x + bitangent * local.y + normal * local.z).normalize();
+        let next = ray.spawn(origin, direction);
+        let Some(hit) = trace(scene, &next) else {
+            continue;
+        };
+        inverse_distances += 1.0 / hit.distance;
+        if trace_lights(scene, &next).is_some_and(|(_, distance)| distance < hit.distance) {
+            continue;
+        }
+        // 和color_from_bsdf一样，焦散已经在光子图里了。直接光全靠光源采样（mis是false），
+        // pdf给0，打中会发光的物体时不再算它的自发光
+        let outer = CAUSTIC_PATH.with(|p| p.replace(lights.caustics().is_some()));
+        sum += trace_path(scene, lights, &next, 1, Some((point, 0.0)));
+        CAUSTIC_PATH.with(|p| p.set(outer));
+    }
+    let harmonic = if inverse_distances > 0.0 {
+        samples as Float / inverse_distances
+    } else {
+        Float::INFINITY
+    };
+    (sum / samples.max(1) as f32, harmonic)
+}
+
+/// 直接光照用光源采样，再按BSDF采样一次，两边用power heuristic做MIS
+fn shade_bsdf(
+    scene: &Scene,
+    lights: &LightSampler,
+    bsdf: &dyn Bsdf,
+    ray: &Ray,
+    hit_point: Point,
+    surface_normal: Vector3,
+    depth: usize,
+) -> Color {
+    direct_light(scene, lights, bsdf, ray, hit_point, surface_normal, true)
+        + color_from_bsdf(scene, lights, bsdf, ray, hit_point, surface_normal, depth)
+}
+
+/// 光源采样的直接光照；mis是false时不给BSDF采样留份额，所有的直接光都算在这里
+fn direct_light(
+    scene: &Scene,
+    lights: &LightSampler,
+    bsdf: &dyn Bsdf,
+    ray: &Ray,
+    hit_point: Point,
+    surface_normal: Vector3,
+    mis: bool,
+) -> Color {
+    let max_light_samples = scene.settings.max_light_samples;
+    let shade = |index| {
+        color_from_light(
+            scene,
+            lights,
+            index,
+            bsdf,
+            ray,
+            (hit_point, surface_normal),
+            mis,
+        )
+    };
+    if lights.len() <= max_light_samples {
+        (0..lights.len()).map(shade).sum::<Color>()
+    } else {
+        // 按功率抽max_light_samples次，每次的贡献除以被抽中的概率，期望不变
+        (0..max_light_samples)
+            .filter_map(|_| lights.sample(random()))
+            .map(|(index, _)| shade(index))
+            .sum::<Color>()
+    }
+}
+
+/// 每个光源的期望采样次数：光源少时每个都算一次，多了就按功率抽
+fn light_selection_pdf(scene: &Scene, lights: &LightSampler, index: usize) -> Float {
+    let max_light_samples = scene.settings.max_light_samples;
+    if lights.len() <= max_light_samples {
+        1.0
+    } else {
+        lights.pdf(index) as Float * max_light_samples as Float
+    }
+}
+
+/// 射线起点沿法线往direction那一侧挪shadow_bias，免得打中自己
+pub(crate) fn offset_origin(
+    scene: &Scene,
+    hit_point: Point,
+    surface_normal: Vector3,
+    direction: &Vector3,
+) -> Point {
+    let bias = scene.settings.shadow_bias;
+    if surface_normal.dot(direction) >= 0.0 {
+        hit_point + surface_normal * bias
+    } else {
+        hit_point - surface_normal * bias
+    }
+}
+
+fn color_from_light(
+    scene: &Scene,
+    lights: &LightSampler,
+    index: usize,
+    bsdf: &dyn Bsdf,
+    ray: &Ray,
+    (hit_point, surface_normal): (Point, Vector3),
+    mis: bool,
+) -> Color {
+    let wo = &-ray.direction;
+    let light = scene.lights[index].as_ref();
+    let sample = light.sample(&hit_point);
+    let f = bsdf.eval(wo, &sample.direction);
+    if f == Color::black() {
+        return f;
+    }
+    let shadow_ray = ray.spawn(
+        offset_origin(scene, hit_point, surface_normal, &sample.direction),
+        sample.direction,
+    );
+    let transmittance = transmittance(scene, lights, &shadow_ray, sample.distance);
+    if transmittance == Color::black() {
+        return transmittance;
+    }
+    let selection_pdf = light_selection_pdf(scene, lights, index);
+    let weight = match sample.pdf {
+        Some(pdf) if mis => power_heuristic(pdf * selection_pdf, bsdf.pdf(wo, &sample.direction)),
+        _ => 1.0,
+    };
+    sample.intensity
+        * f
+        * transmittance
+        * split_weight(1, Color::white())
+        * ((weight / selection_pdf) as f32 * group_weight(scene, Some(index), None))
+}
+
+fn color_from_bsdf(
+    scene: &Scene,
+    lights: &LightSampler,
+    bsdf: &dyn Bsdf,
+    ray: &Ray,
+    hit_point: Point,
+    surface_normal: Vector3,
+    depth: usize,
+) -> Color {
+    let receiver = CAUSTIC_RECEIVER.with(|r| r.replace(false));
+    let wo = &-ray.direction;
+    if depth + 1 >= scene.settings.max_depth {
+        return Color::black();
+    }
+    let sample = match bsdf.sample(wo, random_2d()) {
+        Some(sample) => sample,
+        None => return Color::black(),
+    };
+    let mut weight = sample.weight;
+    if depth >= scene.settings.russian_roulette_depth {
+        let survival = weight.r.max(weight.g).max(weight.b).min(0.95);
+        if random() as f32 >= survival {
+            return Color::black();
+        }
+        weight = weight / survival;
+    }
+
+    let next = ray.spawn(
+        offset_origin(scene, hit_point, surface_normal, &sample.direction),
+        sample.direction,
+    );
+    let outer = CAUSTIC_PATH.with(|p| p.replace(receiver));
+    let color = bounce(bsdf.diffuse_part(wo, &sample.direction), || {
+        trace_path(
+            scene,
+            lights,
+            &next,
+            depth + 1,
+            Some((hit_point, sample.pdf)),
+        )
+    });
+    CAUSTIC_PATH.with(|p| p.set(outer));
+    color * weight
 }
 
-fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
+pub(crate) fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> Float {
     let i_dot_n = incident.dot(&normal);
     let mut eta_i = 1.0;
-    let mut eta_t = index as f64;
+    let mut eta_t = index as Float;
     if i_dot_n > 0.0 {
         eta_i = eta_t;
         eta_t = 1.0;
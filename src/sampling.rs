@@ -0,0 +1,417 @@
+use crate::math::Float;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+/// 一串样本数：每次random()从当前样本的状态里取下一个[0, 1)的数。
+/// 实现要保证结果只由state决定，这样不管几个线程、像素按什么顺序算，图都一样
+pub trait Sampler: Sync {
+    fn next(&self, state: &mut SampleState) -> Float;
+
+    /// 两个一起用的数，比如像素里的位置、半球上的方向。默认就是连着取两个
+    fn next_2d(&self, state: &mut SampleState) -> (Float, Float) {
+        let u = self.next(state);
+        (u, self.next(state))
+    }
+}
+
+/// 正在算的样本
+#[derive(Debug, Clone, Copy)]
+pub struct SampleState {
+    pub seed: u64,
+    pub x: u32,
+    pub y: u32,
+    pub sample: u64,
+    /// 每个像素一共多少样本，分层的序列按这个分格子
+    pub samples: u64,
+    /// 只由种子和像素决定的哈希
+    pub pixel_key: u64,
+    /// 这个样本已经取了几个数，下一个数是第几维
+    pub dimension: u64,
+    /// xorshift的状态，白噪声用
+    pub rng: u64,
+}
+
+impl SampleState {
+    /// 场景种子是seed、宽width的图上像素(x, y)的第sample个样本，每个像素一共samples个
+    pub fn new(seed: u64, x: u32, y: u32, width: u32, sample: u64, samples: u64) -> Self {
+        let pixel_key = splitmix64(splitmix64(seed) ^ (x as u64 + y as u64 * width as u64));
+        Self {
+            seed,
+            x,
+            y,
+            sample,
+            samples: samples.max(1),
+            pixel_key,
+            dimension: 0,
+            rng: splitmix64(pixel_key ^ sample) | 1,
+        }
+    }
+}
+
+/// 样本里的随机数从哪来
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplerKind {
+    /// 每个像素各自的伪随机数，是白噪声：低样本数时噪点会一团一团的
+    #[default]
+    Random,
+    /// 同一个样本、同一个维度，所有像素共用一个随机偏移，加上蓝噪声掩码在这个像素的值。
+    /// 相邻像素的误差互相错开，噪点均匀细碎，看起来舒服得多
+    BlueNoise,
+    /// 每个像素一个打乱的Sobol序列（准蒙特卡洛），一个像素里的样本在每一维上都分得很匀，
+    /// 收敛比随机数快，样本数是2的幂时最好
+    Sobol,
+    /// 相关多重抖动（correlated multi-jittered）：每一对维度上，一个像素的样本在二维格子里
+    /// 和两个方向的一维投影上都分层，样本数不用是2的幂
+    Cmj,
+}
+
+impl SamplerKind {
+    pub fn sampler(self) -> &'static dyn Sampler {
+        match self {
+            Self::Random => &WhiteNoise,
+            Self::BlueNoise => &BlueNoise,
+            Self::Sobol => &Sobol,
+            Self::Cmj => &Cmj,
+        }
+    }
+}
+
+/// 蓝噪声掩码的边长，画面上按这个大小平铺
+const MASK_SIZE: usize = 64;
+/// void-and-cluster里能量的高斯核的σ（像素）
+const MASK_SIGMA: f32 = 1.5;
+
+static SEED_COUNTER: AtomicU64 = AtomicU64::new(0x853c_49e6_748f_ea9b);
+
+thread_local! {
+    // 每个线程一个样本状态，没调用start_sample之前是白噪声，
+    // 种子从全局计数器里取，保证线程之间不重复
+    static STATE: Cell<SampleState> = Cell::new(SampleState {
+        seed: 0,
+        x: 0,
+        y: 0,
+        sample: 0,
+        samples: 1,
+        pixel_key: 0,
+        dimension: 0,
+        rng: SEED_COUNTER.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed) | 1,
+    });
+    static KIND: Cell<SamplerKind> = const { Cell::new(SamplerKind::Random) };
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 开始算一个样本前调用，之后当前线程的random()按kind的序列从state取数
+pub fn start_sample(kind: SamplerKind, state: SampleState) {
+    STATE.with(|s| s.set(state));
+    KIND.with(|k| k.set(kind));
+}
+
+/// 返回[0, 1)之间均匀分布的随机数
+pub fn random() -> Float {
+    with_sampler(|sampler, state| sampler.next(state))
+}
+
+/// 两个一起用的[0, 1)的数，见Sampler::next_2d
+pub fn random_2d() -> (Float, Float) {
+    with_sampler(|sampler, state| sampler.next_2d(state))
+}
+
+fn with_sampler<T>(f: impl FnOnce(&dyn Sampler, &mut SampleState) -> T) -> T {
+    let sampler = KIND.with(Cell::get).sampler();
+    STATE.with(|s| {
+        let mut state = s.get();
+        let v = f(sampler, &mut state);
+        s.set(state);
+        v
+    })
+}
+
+/// 把[0, 1]的v压到[0, 1)里，舍入可能正好碰到1
+fn below_one(v: Float) -> Float {
+    v.min(1.0 - Float::EPSILON)
+}
+
+pub struct WhiteNoise;
+
+impl Sampler for WhiteNoise {
+    fn next(&self, state: &mut SampleState) -> Float {
+        state.dimension += 1;
+        let mut x = state.rng;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.rng = x;
+        // 只取尾数能放下的那么多位，不然舍入之后可能得到1.0
+        let bits = Float::MANTISSA_DIGITS;
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> (64 - bits)) as Float / (1u64 << bits) as Float
+    }
+}
+
+pub struct BlueNoise;
+
+impl Sampler for BlueNoise {
+    /// 第dimension个数：掩码按这个维度平移一下，再加上所有像素共用的偏移，取小数部分
+    fn next(&self, state: &mut SampleState) -> Float {
+        let dimension = state.dimension;
+        state.dimension += 1;
+        let key = splitmix64(splitmix64(state.seed) ^ state.sample);
+        let h = splitmix64(key ^ dimension.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let shift = splitmix64(h) as usize;
+        let (shift_x, shift_y) = (shift % MASK_SIZE, shift / MASK_SIZE % MASK_SIZE);
+        let bits = Float::MANTISSA_DIGITS;
+        let offset = (h >> (64 - bits)) as Float / (1u64 << bits) as Float;
+        let mx = (state.x as usize + shift_x) % MASK_SIZE;
+        let my = (state.y as usize + shift_y) % MASK_SIZE;
+        let v = blue_noise_mask()[my * MASK_SIZE + mx] as Float + offset;
+        if v >= 1.0 {
+            // 两个都小于1，减完一定在[0, 1)里
+            below_one(v - 1.0)
+        } else {
+            v
+        }
+    }
+}
+
+/// 按二维补齐的Sobol序列（Burley 2020, Practical Hash-based Owen Scrambling）：
+/// 每次取数用一组新的维度对，是Sobol的前两维，一个像素的前2^k个样本在这两维上分层得很匀。
+/// 样本号每组按像素和组号嵌套打乱，组和组之间不相关；结果再Owen打乱，像素之间不相关
+pub struct Sobol;
+
+impl Sobol {
+    fn point(state: &mut SampleState) -> (u32, u32) {
+        let group = state.dimension;
+        state.dimension += 1;
+        let key = splitmix64(state.pixel_key ^ group.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        let index = owen_scramble(state.sample as u32, key as u32);
+        let (u, v) = (index.reverse_bits(), sobol_second(index));
+        (
+            owen_scramble(u, (key >> 32) as u32),
+            owen_scramble(v, splitmix64(key) as u32),
+        )
+    }
+}
+
+impl Sampler for Sobol {
+    fn next(&self, state: &mut SampleState) -> Float {
+        unit(Self::point(state).0)
+    }
+
+    fn next_2d(&self, state: &mut SampleState) -> (Float, Float) {
+        let (u, v) = Self::point(state);
+        (unit(u), unit(v))
+    }
+}
+
+/// Kensler 2013, Correlated Multi-Jittered Sampling：每次取数用一组新的维度，
+/// 每samples个样本一轮，一轮里的样本按轮、像素和组号打乱后排成m × n的格子
+pub struct Cmj;
+
+impl Cmj {
+    /// 这一组的(样本在这一轮里的编号, 一轮几个, 打乱用的种子)
+    fn start(state: &mut SampleState) -> (u32, u32, u32) {
+        let group = state.dimension;
+        state.dimension += 1;
+        let count = state.samples.min(u32::MAX as u64) as u32;
+        let round = state.sample / count as u64;
+        let key = splitmix64(
+            state.pixel_key ^ group.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ round.rotate_left(32),
+        );
+        let index = permute((state.sample % count as u64) as u32, count, key as u32);
+        (index, count, (key >> 32) as u32)
+    }
+}
+
+impl Sampler for Cmj {
+    /// 一维就是分层抖动
+    fn next(&self, state: &mut SampleState) -> Float {
+        let (index, count, p) = Self::start(state);
+        let jitter = rand_unit(index, p.wrapping_mul(0xa399_d265));
+        below_one(((index as f64 + jitter) / count as f64) as Float)
+    }
+
+    fn next_2d(&self, state: &mut SampleState) -> (Float, Float) {
+        let (index, count, p) = Self::start(state);
+        let m = ((count as f64).sqrt() as u32).max(1);
+        let n = count.div_ceil(m);
+        let (column, row) = (index % m, index / m);
+        let sx = permute(column, m, p.wrapping_mul(0xa511_e9b3));
+        let sy = permute(row, n, p.wrapping_mul(0x63d8_3595));
+        let jx = rand_unit(index, p.wrapping_mul(0xa399_d265));
+        let jy = rand_unit(index, p.wrapping_mul(0x711a_d6a5));
+        let x = (column as f64 + (sy as f64 + jx) / n as f64) / m as f64;
+        let y = (row as f64 + (sx as f64 + jy) / m as f64) / n as f64;
+        (below_one(x as Float), below_one(y as Float))
+    }
+}
+
+/// Kensler的哈希置换：把[0, len)里的i按种子p打乱，不在范围里就再哈希一次
+fn permute(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            return i.wrapping_add(p) % len;
+        }
+    }
+}
+
+/// Kensler的哈希随机数，[0, 1)
+fn rand_unit(mut i: u32, p: u32) -> f64 {
+    i ^= p;
+    i ^= i >> 17;
+    i ^= i >> 10;
+    i = i.wrapping_mul(0xb365_34e5);
+    i ^= i >> 12;
+    i ^= i >> 21;
+    i = i.wrapping_mul(0x93fc_4795);
+    i ^= 0xdf6e_307f;
+    i ^= i >> 17;
+    i = i.wrapping_mul(1 | p >> 18);
+    i as f64 / (1u64 << 32) as f64
+}
+
+/// 32位定点数变成[0, 1)
+fn unit(x: u32) -> Float {
+    below_one(x as Float / (1u64 << 32) as Float)
+}
+
+/// Sobol序列的第二维，生成矩阵是模2的帕斯卡矩阵：方向数m_i = m_{i-1} ^ 2m_{i-1}
+fn sobol_second(mut index: u32) -> u32 {
+    let (mut result, mut direction) = (0, 1u32 << 31);
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// 按位嵌套的均匀打乱（Owen scrambling），用的是Laine-Karras哈希：
+/// 每一位翻不翻只取决于比它高的位，所以前2^k个数打乱完还是占满2^k个格子
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// 蓝噪声掩码，第一次用的时候用void-and-cluster算出来。
+/// 每个格子的值是它在排序里的名次，归一化到(0, 1)
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(MASK_SIZE, MASK_SIGMA))
+}
+
+/// Ulichney的void-and-cluster：先把随机撒的点调匀，再按"最挤的点最后去掉、
+/// 最空的地方最先填上"给每个格子排名次。能量是周期边界下到所有点的高斯距离之和
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let n = size * size;
+    // 周期边界下偏移(dx, dy)的高斯核
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    // 再远的格子核已经小到可以忽略
+    let reach = ((sigma * 5.0).ceil() as usize).min(size / 2);
+    let toggle = |energy: &mut [f32], at: usize, sign: f32| {
+        let (ax, ay) = (at % size, at / size);
+        for dy in 0..=2 * reach {
+            for dx in 0..=2 * reach {
+                let (dx, dy) = ((dx + size - reach) % size, (dy + size - reach) % size);
+                let i = (ay + dy) % size * size + (ax + dx) % size;
+                energy[i] += sign * kernel[dy * size + dx];
+            }
+        }
+    };
+    let tightest = |pattern: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..n)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+    };
+
+    // 初始点：大约十分之一的格子，用固定的种子，掩码每次都一样
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let mut state = 0x5eed_u64;
+    let mut ones = 0;
+    while ones < n / 10 {
+        state = splitmix64(state);
+        let i = (state % n as u64) as usize;
+        if !pattern[i] {
+            pattern[i] = true;
+            toggle(&mut energy, i, 1.0);
+            ones += 1;
+        }
+    }
+    // 把最挤的点挪到最空的地方，直到挪回原处
+    loop {
+        let cluster = tightest(&pattern, &energy).unwrap();
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy).unwrap();
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; n];
+    // 初始的点：依次去掉最挤的，名次从ones - 1往下排
+    let (mut p, mut e) = (pattern.clone(), energy.clone());
+    for r in (0..ones).rev() {
+        let cluster = tightest(&p, &e).unwrap();
+        p[cluster] = false;
+        toggle(&mut e, cluster, -1.0);
+        rank[cluster] = r;
+    }
+    // 剩下的：依次填最空的地方
+    for r in ones..n {
+        let void = largest_void(&pattern, &energy).unwrap();
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        rank[void] = r;
+    }
+    rank.iter().map(|&r| (r as f32 + 0.5) / n as f32).collect()
+}
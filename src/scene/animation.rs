@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use crate::color::Color;
+use crate::math::{Float, Point, Transform, Vector3};
+use crate::rendering::{render, Intersectable};
+use crate::scene::{camera::Camera, item::Moving, Scene};
+use crate::Result;
+
+/// 能在两个值之间线性插值的类型，关键帧之间用它补出中间的值
+pub trait Lerp: Clone {
+    fn lerp(&self, other: &Self, t: Float) -> Self;
+}
+
+impl Lerp for Float {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        self * (1.0 - t) + other * t
+    }
+}
+
+/// 材质参数是f32，Float是f64时单独实现一份
+#[cfg(not(feature = "f32"))]
+impl Lerp for f32 {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        let t = t as f32;
+        self * (1.0 - t) + other * t
+    }
+}
+
+impl Lerp for Vector3 {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        *self * (1.0 - t) + *other * t
+    }
+}
+
+impl Lerp for Point {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        *self + (*other - *self) * t
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        *self * (1.0 - t as f32) + *other * t as f32
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(&self, other: &Self, t: Float) -> Self {
+        Transform::lerp(self, other, t)
+    }
+}
+
+pub struct Keyframe<T> {
+    /// 单位是秒
+    pub time: Float,
+    pub value: T,
+}
+
+/// 一条关键帧曲线，关键帧按时间排好序；第一帧之前和最后一帧之后保持不变
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+}
+
+impl<T: Lerp> Track<T> {
+    pub fn new(mut keyframes: Vec<Keyframe<T>>) -> Self {
+        assert!(!keyframes.is_empty());
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keyframes }
+    }
+
+    /// 不动的值，方便和动的值混着用
+    pub fn constant(value: T) -> Self {
+        Self::new(vec![Keyframe { time: 0.0, value }])
+    }
+
+    pub fn key(mut self, time: Float, value: T) -> Self {
+        self.keyframes.push(Keyframe { time, value });
+        self.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        self
+    }
+
+    pub fn sample(&self, time: Float) -> T {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0].value.clone();
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].value.clone();
+        }
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        a.value.lerp(&b.value, (time - a.time) / (b.time - a.time))
+    }
+}
+
+impl Track<Transform> {
+    /// 把item放到time这一帧上，帧内的运动交给Moving做运动模糊
+    pub fn moving(
+        &self,
+        item: Box<dyn Intersectable + Send + Sync>,
+        frame: &Frame,
+    ) -> Box<dyn Intersectable + Send + Sync> {
+        Box::new(Moving {
+            item,
+            start: self.sample(frame.time),
+            end: self.sample(frame.time + frame.duration),
+        })
+    }
+
+    /// 按这条曲线摆放相机，投影和快门沿用传进来的相机
+    pub fn camera(&self, camera: Camera, frame: &Frame) -> Camera {
+        Camera {
+            start: self.sample(frame.time),
+            end: self.sample(frame.time + frame.duration),
+            ..camera
+        }
+    }
+}
+
+/// 正在渲染的那一帧
+pub struct Frame {
+    /// 从1开始的帧号
+    pub number: usize,
+    /// 这一帧开始的时刻（秒）
+    pub time: Float,
+    /// 一帧的长度（秒），也就是1 / fps
+    pub duration: Float,
+}
+
+/// 从第1帧到第frame_count帧依次调用build造出场景，
+/// 渲染后存成output_dir下的frame_0001.png、frame_0002.png……
+pub fn render_frames<F>(frame_count: usize, fps: Float, output_dir: &Path, build: F) -> Result<()>
+where
+    F: Fn(&Frame) -> Scene,
+{
+    for number in 1..=frame_count {
+        let frame = Frame {
+            number,
+            time: (number - 1) as Float / fps,
+            duration: 1.0 / fps,
+        };
+        let scene = build(&frame);
+        let path = output_dir.join(format!("frame_{:04}.png", number));
+        render(&scene).to_rgb().save(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,474 @@
+use std::sync::Arc;
+
+use super::camera::{Camera, Exposure};
+use super::item::{
+    BezierPatch, BezierSurface, Capsule, Curves, Ellipsoid, Mesh, Plane, PointCloud, PolygonMesh,
+    Quad, RoundedBox, Sphere, Strand, Surfel,
+};
+use super::light::{
+    DirectionalLight, EnvironmentLight, Falloff, Portal, QuadLight, SphericalLight,
+};
+use super::material::{Material, MaterialRegistry};
+use super::medium::HomogeneousMedium;
+use super::{Distance, Scene};
+use crate::bloom::BloomSettings;
+use crate::color::{Color, OutputSpace};
+use crate::filter::PixelFilter;
+use crate::flare::FlareSettings;
+use crate::grading::ColorGrading;
+use crate::integrator::IntegratorKind;
+use crate::irradiance::IrradianceSettings;
+use crate::math::{Float, Point, Vector3};
+use crate::photon::CausticSettings;
+use crate::rendering::{Intersectable, Light, RenderMode, RenderSettings, SampleClamp};
+use crate::sampling::SamplerKind;
+use crate::{Error, Result};
+
+/// 物体用哪个材质：注册过的名字，或者直接给一个
+pub enum MaterialRef {
+    Named(String),
+    Shared(Arc<Material>),
+}
+
+impl From<&str> for MaterialRef {
+    fn from(name: &str) -> Self {
+        Self::Named(name.to_string())
+    }
+}
+
+impl From<Arc<Material>> for MaterialRef {
+    fn from(material: Arc<Material>) -> Self {
+        Self::Shared(material)
+    }
+}
+
+impl From<Material> for MaterialRef {
+    fn from(material: Material) -> Self {
+        Self::Shared(Arc::new(material))
+    }
+}
+
+/// 一步一步搭场景，没设置的都有默认值：640x360、fov 90、相机在原点看-z、种子0。
+/// 按名字引用没注册过的材质不会马上报错，build的时候一起报
+pub struct SceneBuilder {
+    scene: Scene,
+    errors: Vec<String>,
+    /// 之后加的光源和物体放进scene.light_groups的第几组
+    light_group: Option<usize>,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self {
+            scene: Scene {
+                width: 640,
+                height: 360,
+                fov: 90.0,
+                camera: Camera::default(),
+                items: Vec::new(),
+                lights: Vec::new(),
+                materials: MaterialRegistry::default(),
+                medium: None,
+                spectral: false,
+                seed: 0,
+                mode: RenderMode::Shaded,
+                transparent: false,
+                settings: RenderSettings::default(),
+                light_groups: Vec::new(),
+            },
+            errors: Vec::new(),
+            light_group: None,
+        }
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.scene.width = width;
+        self.scene.height = height;
+        self
+    }
+
+    pub fn fov(mut self, fov: Distance) -> Self {
+        self.scene.fov = fov;
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> Self {
+        self.scene.camera = camera;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.scene.seed = seed;
+        self
+    }
+
+    pub fn spectral(mut self, spectral: bool) -> Self {
+        self.scene.spectral = spectral;
+        self
+    }
+
+    pub fn mode(mut self, mode: RenderMode) -> Self {
+        self.scene.mode = mode;
+        self
+    }
+
+    pub fn transparent(mut self, transparent: bool) -> Self {
+        self.scene.transparent = transparent;
+        self
+    }
+
+    pub fn settings(mut self, settings: RenderSettings) -> Self {
+        self.scene.settings = settings;
+        self
+    }
+
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.scene.settings.samples = samples;
+        self
+    }
+
+    pub fn filter(mut self, filter: PixelFilter) -> Self {
+        self.scene.settings.filter = filter;
+        self
+    }
+
+    pub fn sampler(mut self, sampler: SamplerKind) -> Self {
+        self.scene.settings.sampler = sampler;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: IntegratorKind) -> Self {
+        self.scene.settings.integrator = integrator;
+        self
+    }
+
+    /// 用焦散光子图，见photon::caustic_map
+    pub fn caustics(mut self, photons: usize, radius: Distance) -> Self {
+        self.scene.settings.caustics = Some(CausticSettings { photons, radius });
+        self
+    }
+
+    /// 用辐照度缓存，见irradiance::irradiance_cache
+    pub fn irradiance_cache(mut self, samples: usize, accuracy: Float) -> Self {
+        self.scene.settings.irradiance_cache = Some(IrradianceSettings { samples, accuracy });
+        self
+    }
+
+    /// 加泛光，见bloom::BloomSettings
+    pub fn bloom(mut self, bloom: BloomSettings) -> Self {
+        self.scene.settings.bloom = Some(bloom);
+        self
+    }
+
+    /// 加镜头光晕，见flare::FlareSettings
+    pub fn flare(mut self, flare: FlareSettings) -> Self {
+        self.scene.settings.flare = Some(flare);
+        self
+    }
+
+    /// 白平衡和调色，见grading::ColorGrading
+    pub fn grading(mut self, grading: ColorGrading) -> Self {
+        self.scene.settings.grading = Some(grading);
+        self
+    }
+
+    /// 输出图片的色彩空间
+    pub fn output_space(mut self, output_space: OutputSpace) -> Self {
+        self.scene.settings.output_space = output_space;
+        self
+    }
+
+    /// 要在camera之后设，camera会连曝光一起换掉
+    pub fn exposure(mut self, exposure: Exposure) -> Self {
+        self.scene.camera.exposure = exposure;
+        self
+    }
+
+    pub fn sample_clamp(mut self, clamp: SampleClamp) -> Self {
+        self.scene.settings.sample_clamp = Some(clamp);
+        self
+    }
+
+    pub fn medium(mut self, medium: HomogeneousMedium) -> Self {
+        self.scene.medium = Some(medium);
+        self
+    }
+
+    /// 注册一个材质，之后的物体可以用名字引用它
+    pub fn material(mut self, name: &str, material: Material) -> Self {
+        self.scene.materials.insert(name, material);
+        self
+    }
+
+    fn resolve(&mut self, material: MaterialRef) -> Arc<Material> {
+        match material {
+            MaterialRef::Shared(material) => material,
+            MaterialRef::Named(name) => self.scene.materials.get(&name).unwrap_or_else(|| {
+                self.errors.push(format!("unknown material {:?}", name));
+                Arc::new(Material::diffuse(Color::white()))
+            }),
+        }
+    }
+
+    pub fn add_item(mut self, item: impl Intersectable + Send + Sync + 'static) -> Self {
+        self.scene.items.push(Box::new(item));
+        // 编号是按加进来的顺序给的，见build
+        if let Some(group) = self.light_group {
+            let id = self.scene.items.len() as u32;
+            self.scene.light_groups[group].objects.push(id);
+        }
+        self
+    }
+
+    pub fn add_sphere(
+        mut self,
+        center: Point,
+        radius: Distance,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Sphere {
+            center,
+            radius,
+            material,
+        })
+    }
+
+    /// radii是三个轴上的半径
+    pub fn add_ellipsoid(
+        mut self,
+        center: Point,
+        radii: Vector3,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Ellipsoid {
+            center,
+            radii,
+            material,
+        })
+    }
+
+    /// 圆角的长方体，half_extent是半边长，radius为0时就是普通的盒子
+    pub fn add_rounded_box(
+        mut self,
+        center: Point,
+        half_extent: Vector3,
+        radius: Distance,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(RoundedBox {
+            center,
+            half_extent,
+            radius,
+            material,
+        })
+    }
+
+    /// 线段a到b加上半径radius，两头是半球
+    pub fn add_capsule(
+        mut self,
+        a: Point,
+        b: Point,
+        radius: Distance,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Capsule {
+            a,
+            b,
+            radius,
+            material,
+        })
+    }
+
+    /// normal会被归一化，从背面看是透明的
+    pub fn add_plane(self, pos: Point, normal: Vector3, material: impl Into<MaterialRef>) -> Self {
+        self.add_plane_sided(pos, normal, material, false)
+    }
+
+    /// 两面都看得到的平面
+    pub fn add_two_sided_plane(
+        self,
+        pos: Point,
+        normal: Vector3,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        self.add_plane_sided(pos, normal, material, true)
+    }
+
+    fn add_plane_sided(
+        mut self,
+        pos: Point,
+        normal: Vector3,
+        material: impl Into<MaterialRef>,
+        two_sided: bool,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Plane {
+            pos,
+            normal: normal.normalize(),
+            material,
+            two_sided,
+        })
+    }
+
+    /// corner和两条边围成的四边形，两面都看得到
+    pub fn add_quad(
+        mut self,
+        corner: Point,
+        edge_u: Vector3,
+        edge_v: Vector3,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Quad {
+            corner,
+            edge_u,
+            edge_v,
+            material,
+        })
+    }
+
+    /// 控制网格Catmull-Clark细分levels次以后的光滑网格
+    pub fn add_subdivision_surface(
+        mut self,
+        cage: &PolygonMesh,
+        levels: usize,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Mesh::new(cage.catmull_clark(levels), material))
+    }
+
+    /// 双三次Bézier曲面片，见BezierSurface
+    pub fn add_bezier_surface(
+        mut self,
+        patches: Vec<BezierPatch>,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(BezierSurface::new(patches, material))
+    }
+
+    /// 一组头发、草叶之类的细曲线，见Curves
+    pub fn add_curves(mut self, strands: Vec<Strand>, material: impl Into<MaterialRef>) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(Curves::new(strands, material))
+    }
+
+    /// 点云，每个点画成一个小圆盘或者小球，见PointCloud
+    pub fn add_point_cloud(
+        mut self,
+        surfels: Vec<Surfel>,
+        material: impl Into<MaterialRef>,
+    ) -> Self {
+        let material = self.resolve(material.into());
+        self.add_item(PointCloud::new(surfels, material))
+    }
+
+    pub fn add_light(mut self, light: impl Light + Send + Sync + 'static) -> Self {
+        self.scene.lights.push(Box::new(light));
+        if let Some(group) = self.light_group {
+            let index = self.scene.lights.len() - 1;
+            self.scene.light_groups[group].lights.push(index);
+        }
+        self
+    }
+
+    /// 之后加的光源和会发光的物体都放进叫name的光源分组，没有就新建一组；
+    /// None是之后加的不放进任何组。见light_group_pass
+    pub fn light_group(mut self, name: Option<&str>) -> Self {
+        self.light_group = name.map(|name| self.scene.light_group_index(name));
+        self
+    }
+
+    /// direction会被归一化
+    pub fn add_directional_light(self, direction: Vector3, color: Color, intensity: f32) -> Self {
+        self.add_light(DirectionalLight {
+            direction: direction.normalize(),
+            color,
+            intensity,
+        })
+    }
+
+    /// 半径为0就是点光源
+    pub fn add_spherical_light(
+        self,
+        position: Point,
+        radius: Distance,
+        color: Color,
+        intensity: f32,
+    ) -> Self {
+        self.add_light(SphericalLight {
+            position,
+            radius,
+            color,
+            intensity,
+            falloff: Falloff::InverseSquare,
+        })
+    }
+
+    /// 按falloff衰减的点光源
+    pub fn add_point_light(
+        self,
+        position: Point,
+        color: Color,
+        intensity: f32,
+        falloff: Falloff,
+    ) -> Self {
+        self.add_light(SphericalLight {
+            position,
+            radius: 0.0,
+            color,
+            intensity,
+            falloff,
+        })
+    }
+
+    /// 只朝edge_u × edge_v那一侧发光的四边形面光源
+    pub fn add_quad_light(
+        self,
+        corner: Point,
+        edge_u: Vector3,
+        edge_v: Vector3,
+        color: Color,
+        intensity: f32,
+    ) -> Self {
+        self.add_light(QuadLight {
+            corner,
+            edge_u,
+            edge_v,
+            color,
+            intensity,
+        })
+    }
+
+    /// 四面八方均匀的天光，portals是它能照进室内的开口，见EnvironmentLight
+    pub fn add_environment_light(self, color: Color, intensity: f32, portals: Vec<Portal>) -> Self {
+        self.add_light(EnvironmentLight {
+            color,
+            intensity,
+            portals,
+        })
+    }
+
+    /// 物体按加进来的顺序编号，见Scene::assign_object_ids；会发光的物体加到光源里，
+    /// 见Scene::add_emissive_lights
+    pub fn build(mut self) -> Result<Scene> {
+        if self.errors.is_empty() {
+            self.scene.assign_object_ids();
+            self.scene.add_emissive_lights();
+            Ok(self.scene)
+        } else {
+            Err(Error::Scene(self.errors.join(", ")))
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::Arc;
+
+use crate::math::consts::PI;
+
+use crate::math::{Float, Point, Transform, Vector3};
+use crate::scene::material::Texture;
+use crate::scene::Distance;
+
+/// 相机的投影方式
+#[derive(Clone, Copy)]
+pub enum Projection {
+    /// 普通的小孔相机，视角用Scene的fov，是短边方向的视角
+    Perspective,
+    /// 等距鱼眼：像素到图像中心的距离和光线偏离光轴的角度成正比，
+    /// fov（度）是内切圆边缘对应的视角，可以超过180
+    Fisheye { fov: Distance },
+    /// 经纬度展开的360°全景，宽是高的两倍，图像中心对着-z
+    Equirectangular,
+    /// 全向立体（ODS）全景，给VR头显看：上半张是左眼，下半张是右眼，
+    /// 每只眼睛都是一张经纬度全景，所以整张图是正方形。
+    /// ipd是两眼的间距，光线从半径ipd / 2的圆上沿切线方向出发
+    StereoEquirectangular { ipd: Distance },
+}
+
+impl Projection {
+    /// 坐标系是z向外，x向右，y向上。是个右手系。
+    /// 相机放在原点，朝负z方向看。
+    /// film是像素在图像上的位置，(0, 0)是左上角，(1, 1)是右下角；
+    /// image的y是朝下的，我们是y朝上，所以y要反一下。
+    /// 返回光线的起点和方向，大多数投影起点都在原点
+    pub fn ray(
+        &self,
+        film: (Float, Float),
+        size: (u32, u32),
+        fov: Distance,
+    ) -> Option<(Point, Vector3)> {
+        let (width, height) = (size.0 as Float, size.1 as Float);
+        match *self {
+            // 胶片在-1.0处摆放，光线就是从原点出发到胶片上的点，z都是-1.0
+            Projection::Perspective => {
+                let (half_width, half_height) = perspective_extent(size, fov);
+                let direction = Vector3::new(
+                    (film.0 * 2.0 - 1.0) * half_width,
+                    -(film.1 * 2.0 - 1.0) * half_height,
+                    -1.0,
+                );
+                Some((Point::zero(), direction))
+            }
+            Projection::Fisheye { fov } => {
+                // 以短边为直径的内切圆，半径归一化成1
+                let radius = width.min(height) / 2.0;
+                let px = (film.0 - 0.5) * width / radius;
+                let py = -(film.1 - 0.5) * height / radius;
+                let r = (px * px + py * py).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * fov.to_radians() / 2.0;
+                let phi = py.atan2(px);
+                let direction = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                Some((Point::zero(), direction))
+            }
+            Projection::Equirectangular => {
+                let (longitude, latitude) = equirectangular_angles(film);
+                Some((Point::zero(), spherical_direction(longitude, latitude)))
+            }
+            Projection::StereoEquirectangular { ipd } => {
+                // 上半张左眼，下半张右眼
+                let (eye, v) = if film.1 < 0.5 {
+                    (-1.0, film.1 * 2.0)
+                } else {
+                    (1.0, film.1 * 2.0 - 1.0)
+                };
+                let (longitude, latitude) = equirectangular_angles((film.0, v));
+                // 朝这个经度看时的右手方向，眼睛在它上面左右各偏ipd / 2
+                let right = Vector3::new(longitude.cos(), 0.0, longitude.sin());
+                let origin = Point::zero() + right * (eye * ipd / 2.0);
+                Some((origin, spherical_direction(longitude, latitude)))
+            }
+        }
+    }
+}
+
+/// 透视相机z = -1处的胶片半宽和半高。fov是短边方向的视角，竖的图也能渲染
+pub(crate) fn perspective_extent(size: (u32, u32), fov: Distance) -> (Float, Float) {
+    let (width, height) = (size.0 as Float, size.1 as Float);
+    let tan = (fov.to_radians() / 2.0).tan();
+    if width >= height {
+        (tan * width / height, tan)
+    } else {
+        (tan, tan * height / width)
+    }
+}
+
+fn spherical_direction(longitude: Float, latitude: Float) -> Vector3 {
+    Vector3::new(
+        longitude.sin() * latitude.cos(),
+        latitude.sin(),
+        -longitude.cos() * latitude.cos(),
+    )
+}
+
+/// 全景图上的位置对应的经度（-π..π，0朝-z，向右为正）和纬度（-π/2..π/2）
+fn equirectangular_angles(film: (Float, Float)) -> (Float, Float) {
+    ((film.0 - 0.5) * 2.0 * PI, (0.5 - film.1) * PI)
+}
+
+/// 曝光：渲染出来的radiance先乘上它，再clamp、编码成图片。
+/// 灯光按物理的亮度写，整体的明暗靠它调，不用一个个改灯
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exposure {
+    /// 曝光补偿，单位是档，+1亮一倍，0是不调
+    Ev(Float),
+    /// 按真实相机的参数算：感光度、快门时间（秒）、光圈的f值。
+    /// 和照相一样，晴天16法则（ISO 100、1/100秒、f/16）对应的是阳光下的亮度
+    Physical {
+        iso: Float,
+        shutter_time: Float,
+        f_number: Float,
+    },
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure::Ev(0.0)
+    }
+}
+
+impl Exposure {
+    /// radiance要乘的系数。Physical按EV100 = log2(N² / t × 100 / ISO)，
+    /// 系数是1 / (1.2 × 2^EV100)，1.2是镜头和传感器的损失
+    pub fn scale(&self) -> f32 {
+        match *self {
+            Exposure::Ev(ev) => ev.exp2() as f32,
+            Exposure::Physical {
+                iso,
+                shutter_time,
+                f_number,
+            } => (shutter_time * iso / (120.0 * f_number * f_number)) as f32,
+        }
+    }
+}
+
+/// 光圈的形状，焦外的亮点（散景）就是这个形状
+#[derive(Clone)]
+pub enum Aperture {
+    Circle,
+    /// 有blades片叶片的正多边形，按rotation（弧度）转一下
+    Polygon {
+        blades: u32,
+        rotation: Float,
+    },
+    /// 按一张灰度图的形状，越亮的地方过的光越多，图的正方形对应光圈的直径
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    /// 把[0, 1)²上均匀的u变成光圈上均匀（按遮罩的亮度）分布的点，光圈半径是1
+    fn sample(&self, u: (Float, Float)) -> (Float, Float) {
+        match self {
+            // 同心圆映射，分层采样的格子到圆上不会拉得太歪
+            Aperture::Circle => {
+                let (a, b) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+                if a == 0.0 && b == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let (r, theta) = if a.abs() > b.abs() {
+                    (a, PI / 4.0 * (b / a))
+                } else {
+                    (b, PI / 2.0 - PI / 4.0 * (a / b))
+                };
+                (r * theta.cos(), r * theta.sin())
+            }
+            // 从中心到每条边的三角形面积一样，先挑一个三角形，再在里面均匀取
+            Aperture::Polygon { blades, rotation } => {
+                let n = (*blades).max(3) as Float;
+                let i = (u.0 * n).floor();
+                let s = (u.0 * n - i).sqrt();
+                let corner = |k: Float| {
+                    let angle = rotation + 2.0 * PI * k / n;
+                    (angle.cos(), angle.sin())
+                };
+                let (a, b) = (corner(i), corner(i + 1.0));
+                (
+                    s * ((1.0 - u.1) * a.0 + u.1 * b.0),
+                    s * ((1.0 - u.1) * a.1 + u.1 * b.1),
+                )
+            }
+            Aperture::Mask(mask) => mask.sample(u),
+        }
+    }
+}
+
+/// 光圈遮罩图按亮度累加的分布，采样时按亮度挑像素
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    /// 到每个像素为止（包括它）的亮度和，除过总和，最后一个是1
+    cdf: Vec<f32>,
+}
+
+impl ApertureMask {
+    /// 图是全黑的话返回None
+    pub fn new(texture: &Texture) -> Option<Self> {
+        let (width, height) = (texture.image.width(), texture.image.height());
+        let mut total = 0.0;
+        let mut cdf: Vec<f32> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                total += texture.texel(x, y).luminance().max(0.0);
+                total
+            })
+            .collect();
+        if !(total > 0.0 && total.is_finite()) {
+            return None;
+        }
+        for value in &mut cdf {
+            *value /= total;
+        }
+        Some(Self { width, height, cdf })
+    }
+
+    /// u.0挑像素，剩下的部分和u.1在像素里面均匀取
+    fn sample(&self, u: (Float, Float)) -> (Float, Float) {
+        let target = u.0 as f32;
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= target)
+            .min(self.cdf.len() - 1);
+        let start = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        let width = self.cdf[index] - start;
+        let fx = if width > 0.0 {
+            ((target - start) / width).clamp(0.0, 1.0) as Float
+        } else {
+            0.5
+        };
+        let (px, py) = (index as u32 % self.width, index as u32 / self.width);
+        // 图的y朝下
+        (
+            (px as Float + fx) / self.width as Float * 2.0 - 1.0,
+            1.0 - (py as Float + u.1) / self.height as Float * 2.0,
+        )
+    }
+}
+
+/// 薄透镜：光线从光圈上的一点出发，穿过对焦平面上小孔相机那条光线经过的点。
+/// 对焦平面上的东西是清楚的，前后的越远越虚
+#[derive(Clone)]
+pub struct Lens {
+    /// 光圈的半径，越大焦外越虚
+    pub radius: Distance,
+    /// 对焦平面到相机的距离，沿着相机看的方向量
+    pub focus_distance: Distance,
+    pub aperture: Aperture,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Self {
+            radius: 0.0,
+            focus_distance: 1.0,
+            aperture: Aperture::Circle,
+        }
+    }
+}
+
+impl Lens {
+    /// 相机坐标系里小孔相机的一条光线，换成从光圈上u对应的点出发的那条
+    pub fn refocus(
+        &self,
+        origin: Point,
+        direction: Vector3,
+        u: (Float, Float),
+    ) -> (Point, Vector3) {
+        // 相机朝-z看，方向不朝前的（全景的后半边）没法对焦，不动
+        if direction.z >= 0.0 {
+            return (origin, direction);
+        }
+        let focus = origin + direction * (self.focus_distance / -direction.z);
+        let (x, y) = self.aperture.sample(u);
+        let start = origin + Vector3::new(x * self.radius, y * self.radius, 0.0);
+        (start, (focus - start).normalize())
+    }
+}
+
+/// 时间以一帧为单位，0是这一帧开始，1是这一帧结束。
+/// shutter是快门打开和关闭的时刻，(0.0, 0.5)就是180°快门；两个相等时没有运动模糊
+#[derive(Clone)]
+pub struct Camera {
+    pub projection: Projection,
+    pub shutter: (Float, Float),
+    /// 相机在t = 0和t = 1时的位置和朝向，中间线性插值
+    pub start: Transform,
+    pub end: Transform,
+    pub exposure: Exposure,
+    /// 径向的镜头畸变，正的是桶形（边上的东西往里挤），负的是枕形，0是没有
+    pub distortion: Float,
+    /// 暗角，画面四角的亮度降低这么多，0是没有，1是角上全黑
+    pub vignette: f32,
+    /// 横向色差，红和蓝的放大率差多少，0.005左右就看得出来；见aberration::apply。
+    /// 和泛光一样只在渲染整张图时做
+    pub chromatic_aberration: f32,
+    /// 景深，None是小孔相机
+    pub lens: Option<Lens>,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            projection: Projection::Perspective,
+            shutter: (0.0, 0.0),
+            start: Transform::identity(),
+            end: Transform::identity(),
+            exposure: Exposure::default(),
+            distortion: 0.0,
+            vignette: 0.0,
+            chromatic_aberration: 0.0,
+            lens: None,
+        }
+    }
+}
+
+impl Camera {
+    /// 在快门打开的区间里均匀取一个时刻
+    pub fn sample_time(&self, u: Float) -> Float {
+        self.shutter.0 + (self.shutter.1 - self.shutter.0) * u
+    }
+
+    pub fn transform_at(&self, time: Float) -> Transform {
+        self.start.lerp(&self.end, time)
+    }
+
+    /// 画面上的位置（和Projection::ray的film一样）经过镜头畸变以后，
+    /// 光线实际按哪个位置发出去。离中心的距离乘上1 + distortion × r²，
+    /// r按半条对角线归一化，四角是1；所有的投影方式都一样
+    pub fn distort(&self, film: (Float, Float), size: (u32, u32)) -> (Float, Float) {
+        if self.distortion == 0.0 {
+            return film;
+        }
+        let factor = 1.0 + self.distortion * radius_squared(film, size);
+        (0.5 + (film.0 - 0.5) * factor, 0.5 + (film.1 - 0.5) * factor)
+    }
+
+    /// 画面上这个位置的亮度要乘的系数，从中心的1往四角按r²降到1 - vignette
+    pub fn vignette(&self, film: (Float, Float), size: (u32, u32)) -> f32 {
+        if self.vignette == 0.0 {
+            return 1.0;
+        }
+        (1.0 - self.vignette * radius_squared(film, size) as f32).max(0.0)
+    }
+}
+
+/// 离画面中心的距离的平方，半条对角线是1
+fn radius_squared(film: (Float, Float), size: (u32, u32)) -> Float {
+    let (width, height) = (size.0 as Float, size.1 as Float);
+    let (x, y) = ((film.0 - 0.5) * width, (film.1 - 0.5) * height);
+    (x * x + y * y) / ((width * width + height * height) / 4.0)
+}
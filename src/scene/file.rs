@@ -0,0 +1,923 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::camera::{Aperture, ApertureMask, Camera, Exposure, Lens};
+use super::item::{
+    load_obj, BezierSurface, Capsule, Curves, Ellipsoid, Mesh, Oriented, Plane, PointCloud,
+    PolygonMesh, Quad, RoundedBox, Sphere, Strand,
+};
+use super::light::{
+    DirectionalLight, EnvironmentLight, Falloff, Portal, QuadLight, SphericalLight,
+};
+use super::material::{
+    ClearCoat, ColorSpace, Coloration, Hair, Material, MaterialRegistry, Opacity, Principled, Ramp,
+    RampInput, SurfaceType, Texture, TextureImage,
+};
+use super::medium::HomogeneousMedium;
+use super::script::expand;
+use super::{Scene, TextureCache};
+use crate::bloom::BloomSettings;
+use crate::color::{Color, OutputSpace};
+use crate::filter::PixelFilter;
+use crate::flare::FlareSettings;
+use crate::grading::ColorGrading;
+use crate::integrator::IntegratorKind;
+use crate::irradiance::IrradianceSettings;
+use crate::math::{consts::PI, Float, Point, Transform, Vector3};
+use crate::photon::CausticSettings;
+use crate::rendering::{RenderMode, RenderSettings, SampleClamp};
+use crate::sampling::SamplerKind;
+use crate::{Error, Result};
+
+// 场景文件是按行的文本，#后面是注释，每行第一个词是关键字：
+//
+//   size 640 360
+//   fov 60
+//   seed 1
+//   spectral
+//   transparent                            # 没打中东西的地方alpha是0
+//   samples 64                             # 每个像素的样本数
+//   maxdepth 8                             # 路径最多追踪几段
+//   bias 1e-6                              # 次级光线起点离表面多远
+//   filter mitchell 2 0.33 0.33            # 像素滤波器：box、tent [半径]、gaussian [半径 sigma]、mitchell [半径 b c]
+//   sampler sobol                          # 样本里的随机数：random、bluenoise（噪点更均匀）、sobol或cmj（收敛更快）
+//   caustics 200000 0.05                   # 焦散光子图：光子数、收集半径
+//   irradiance 256 0.1                     # 辐照度缓存：每个缓存点的光线数、允许的误差
+//   integrator bdpt                        # path（路径追踪）或bdpt（双向路径追踪，室内和焦散收敛快）
+//   clamp 10 soft                          # 每个样本的亮度上限，去掉亮点；soft是平滑压缩
+//   bloom 1 0.3 6                          # 泛光：亮度阈值、强度，可选的金字塔层数
+//   flare ghosts 4 streaks 6 intensity 0.05
+//                                          # 镜头光晕：鬼影、星芒的个数，没写的用默认值
+//   grade temperature 3200 tint 0.1 lift 0.02 0.02 0.04 gamma 1 1 1 gain 1.1 1 0.9
+//                                          # 白平衡（当成白色的色温、色调）和调色，没写的不动
+//   output srgb                            # 输出的色彩空间：gamma22（默认）、srgb、rec709、linear、p3
+//   mode depth 20                          # 调试输出：shaded、normals、depth <最远>、uv、albedo、objectid、bounces
+//   camera 0 1 5 -10 0 0                   # 位置，可选的旋转（角度，先x再y再z）
+//   lens distortion 0.1 vignette 0.4 aberration 0.005
+//                                          # 镜头畸变（正的是桶形，负的是枕形）、暗角、横向色差
+//   lens aperture 0.05 focus 4 blades 6 blade_rotation 15
+//                                          # 景深：光圈半径、对焦距离，可选的六边形光圈（转15°）
+//                                          # 或者bokeh shape.png按图的形状
+//   exposure -2                            # 曝光补偿（档），或者ISO、快门（秒）、f值：exposure 100 0.01 16
+//   material glass refractive color 1 1 1 albedo 0.18 index 1.5 transparency 0.9
+//   material tiles reflective texture tex.png scale 5 reflectivity 0.4
+//   material debug diffuse uvchecker       # 内置的8×8编号格子图，检查纹理坐标用
+//   material screen principled color 0 0 0 emission_map tv.png emission_strength 4
+//                                          # 自发光按贴图，贴图的scale和颜色贴图共用
+//   material scan principled texture albedo.png roughness_map rough.png metallic_map metal.png
+//                                          # 粗糙度、金属度按灰度贴图，不做sRGB解码
+//   material leaf diffuse texture leaf.png opacity leaf.png opacity_alpha cutoff 0.5
+//                                          # 镂空：按贴图的alpha（不写opacity_alpha就是灰度），
+//                                          # 低于cutoff的地方光线穿过去；不写cutoff按不透明度随机穿过
+//   material data diffuse texture mask.png linear
+//                                          # 贴图存的是数据不是颜色，不做sRGB解码
+//   material fur hair color 0.6 0.4 0.2 roughness 0.3 azimuthal 0.3 tilt 3
+//   material ground diffuse catcher        # 影子捕捉面，透明背景下只留影子和反射，合成到照片上用
+//   material toon diffuse ramp facing stop 0.3 0.1 0.1 0.3 stop 0.35 0.8 0.3 0.3
+//                                          # 渐变色：按u、v、height或者facing取值，每个stop是位置和颜色
+//   sphere 0 0.5 -3 1.2 glass              # 中心、半径、材质名
+//   ellipsoid 0 0.5 -3 2 1 1 glass         # 中心、三个轴上的半径、材质名
+//   roundbox 0 0 -3 1 0.5 0.5 0.1 wall     # 中心、半边长、圆角半径、材质名
+//   capsule 0 0 -3 0 2 -3 0.4 glass        # 两头的中心、半径、材质名
+//   plane 0 -7 -5 0 -1 0 tiles             # 平面上一点、法线、材质名，可选的twosided是两面都看得到
+//   quad -1 0 -4 2 0 0 0 2 0 wall          # 一个角、两条边、材质名，两面都看得到
+//   obj models/teapot.obj smooth           # 材质用OBJ自己的mtl，注册成teapot/<材质名>
+//   obj models/room.obj flip cull          # 物体的行后面都可以加flip（法线反过来）
+//                                          # 和cull（相机看不到背面，别的光线照样打中）
+//   subdiv models/cage.obj 3 skin          # OBJ当控制网格，Catmull-Clark细分3次
+//   bezier models/teapot.bpt china         # 双三次Bézier曲面片，Utah茶壶的那种文本格式
+//   points scans/room.ply 0.01 scan        # PLY里的点画成小圆盘，有法线和颜色就用；半径、材质名
+//   strand 0 0 0 0 1 0 1 2 0 1 3 0 .02 0 fur
+//                                          # 四个控制点、根和梢的粗细、材质名
+//   directional -0.5 -1 -1 1 1 1 2         # 方向、颜色、强度
+//   point 3 2 -3 0 1 1 1 255               # 位置、半径、颜色、强度
+//   point 0 2 0 0 1 1 1 20 falloff range 1 4
+//                                          # 点光源的衰减：square（默认）、linear、none、range <近> <远>
+//   arealight -1 3 -4 1 0 0 0 0 1 1 1 1 40 # 一个角、两条边、颜色、强度，朝两条边叉乘的方向发光
+//   environment 0.6 0.7 1 1                # 天光：颜色、强度
+//   portal -1 0 -5 2 0 0 0 2 0             # 天光照进来的开口：一个角、两条边，可以有好几个
+//   lightgroup key                         # 之后的光源和会发光的物体放进key这一组，none是不放
+//   fog 0.01 0.01 0.01 0.05 0.05 0.05      # 吸收系数、散射系数，充满场景的包围球
+//
+// 材质的类型有diffuse、reflective、refractive、microfacet、principled、hair，后面是可选的键值对，
+// 没写的用默认值。文件里的相对路径都相对于场景文件所在的目录。
+// 另外可以用变量、循环和表达式生成重复的东西，见script.rs；
+// 更复杂的场景可以直接写成Rhai脚本（.rhai结尾，要打开scripting功能），见rhai_script.rs
+
+/// filter后面的滤波器名字和可选的参数，没写的参数用默认值
+fn parse_filter(words: &mut Words) -> Result<PixelFilter> {
+    let mut filter = match words.word()? {
+        "box" => return Ok(PixelFilter::Box),
+        "tent" => PixelFilter::tent(),
+        "gaussian" => PixelFilter::gaussian(),
+        "mitchell" => PixelFilter::mitchell(),
+        name => return Err(Error::parse(format!("unknown filter {:?}", name))),
+    };
+    match &mut filter {
+        PixelFilter::Box => {}
+        PixelFilter::Tent { radius } => {
+            if !words.is_empty() {
+                *radius = words.parse()?;
+            }
+        }
+        PixelFilter::Gaussian { radius, sigma } => {
+            if !words.is_empty() {
+                *radius = words.parse()?;
+            }
+            if !words.is_empty() {
+                *sigma = words.parse()?;
+            }
+        }
+        PixelFilter::Mitchell { radius, b, c } => {
+            if !words.is_empty() {
+                *radius = words.parse()?;
+            }
+            if !words.is_empty() {
+                *b = words.parse()?;
+                *c = words.parse()?;
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// 一行里剩下的词
+struct Words<'a> {
+    words: std::slice::Iter<'a, &'a str>,
+}
+
+impl<'a> Words<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        self.words.next().copied()
+    }
+
+    fn word(&mut self) -> Result<&'a str> {
+        self.next().ok_or_else(|| Error::parse("missing value"))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.words.len() == 0
+    }
+
+    /// 下一个词是name的话吃掉它
+    fn flag(&mut self, name: &str) -> bool {
+        let found = self.words.as_slice().first() == Some(&name);
+        if found {
+            self.words.next();
+        }
+        found
+    }
+
+    fn parse<T: std::str::FromStr>(&mut self) -> Result<T> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| Error::parse(format!("bad value {:?}", word)))
+    }
+
+    fn float(&mut self) -> Result<Float> {
+        self.parse()
+    }
+
+    fn floats<const N: usize>(&mut self) -> Result<[Float; N]> {
+        let mut values = [0.0; N];
+        for v in &mut values {
+            *v = self.float()?;
+        }
+        Ok(values)
+    }
+
+    fn color(&mut self) -> Result<Color> {
+        let [r, g, b] = self.floats::<3>()?;
+        Ok(Color {
+            r: r as f32,
+            g: g as f32,
+            b: b as f32,
+        })
+    }
+
+    fn point(&mut self) -> Result<Point> {
+        let [x, y, z] = self.floats::<3>()?;
+        Ok(Point::new(x, y, z))
+    }
+
+    fn vector(&mut self) -> Result<Vector3> {
+        let [x, y, z] = self.floats::<3>()?;
+        Ok(Vector3::new(x, y, z))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.next() {
+            Some(word) => Err(Error::parse(format!("unexpected {:?}", word))),
+            None => Ok(()),
+        }
+    }
+}
+
+struct Parser<'a> {
+    base: &'a Path,
+    scene: Scene,
+    /// 读过（或者试着读过）的贴图和OBJ
+    files: Vec<PathBuf>,
+    /// 天光的颜色、强度和所在的光源分组，和所有的portal一起在最后加到场景里
+    environment: Option<(Color, f32, Option<usize>)>,
+    portals: Vec<Portal>,
+    /// strand按材质攒起来，每种材质在最后合成一个Curves
+    strands: Vec<(Arc<Material>, Vec<Strand>)>,
+    /// lightgroup之后加的光源和物体放进第几组
+    light_group: Option<usize>,
+}
+
+impl Parser<'_> {
+    fn image(&mut self, path: &str) -> Result<TextureImage> {
+        let path = self.base.join(path);
+        if !self.files.contains(&path) {
+            self.files.push(path.clone());
+        }
+        TextureCache::global().image(path)
+    }
+
+    /// 粗糙度、金属度这种存数据的贴图，不做sRGB解码
+    fn data_map(&mut self, words: &mut Words) -> Result<Coloration> {
+        let texture = Texture::new(self.image(words.word()?)?);
+        Ok(Coloration::Texture(
+            texture.with_color_space(ColorSpace::Linear),
+        ))
+    }
+
+    fn material_ref(&self, name: &str) -> Result<Arc<Material>> {
+        self.scene
+            .materials
+            .get(name)
+            .ok_or_else(|| Error::parse(format!("unknown material {:?}", name)))
+    }
+
+    /// lens后面的键值对，aperture、focus、blades、bokeh是景深的，写了其中一个就有景深
+    fn lens(&mut self, words: &mut Words) -> Result<()> {
+        let mut lens = self.scene.camera.lens.clone();
+        let mut blade_rotation = None;
+        while let Some(key) = words.next() {
+            match key {
+                "distortion" => self.scene.camera.distortion = words.float()?,
+                "vignette" => self.scene.camera.vignette = words.float()? as f32,
+                "aberration" => self.scene.camera.chromatic_aberration = words.float()? as f32,
+                "aperture" => lens.get_or_insert_with(Lens::default).radius = words.float()?,
+                "focus" => lens.get_or_insert_with(Lens::default).focus_distance = words.float()?,
+                "blades" => {
+                    lens.get_or_insert_with(Lens::default).aperture = Aperture::Polygon {
+                        blades: words.parse()?,
+                        rotation: 0.0,
+                    }
+                }
+                "blade_rotation" => blade_rotation = Some(words.float()? * (PI / 180.0)),
+                "bokeh" => {
+                    let texture = Texture::new(self.image(words.word()?)?);
+                    let mask = ApertureMask::new(&texture)
+                        .ok_or_else(|| Error::parse("bokeh image is completely black"))?;
+                    lens.get_or_insert_with(Lens::default).aperture =
+                        Aperture::Mask(Arc::new(mask));
+                }
+                _ => return Err(Error::parse(format!("unknown lens option {:?}", key))),
+            }
+        }
+        if let (
+            Some(Lens {
+                aperture: Aperture::Polygon { rotation, .. },
+                ..
+            }),
+            Some(angle),
+        ) = (&mut lens, blade_rotation)
+        {
+            *rotation = angle;
+        }
+        self.scene.camera.lens = lens;
+        Ok(())
+    }
+
+    fn material(&mut self, words: &mut Words) -> Result<()> {
+        let name = words.word()?;
+        let kind = words.word()?;
+        let mut color = Coloration::Color(Color::white());
+        let mut texture_scale = 1.0;
+        let mut color_space = ColorSpace::Srgb;
+        let mut albedo = 0.5;
+        let mut clearcoat = None;
+        let mut shadow_catcher = false;
+        let mut reflectivity = 0.5;
+        let (mut index, mut transparency, mut dispersion) = (1.5, 1.0, 0.0);
+        let (mut roughness_u, mut roughness_v, mut rotation) = (0.5, None, 0.0);
+        let mut principled = Principled::default();
+        let mut hair = Hair::default();
+        let (mut ramp, mut stops) = (None, Vec::new());
+        let mut opacity: Option<Opacity> = None;
+        let (mut opacity_alpha, mut cutoff) = (false, None);
+        while let Some(key) = words.next() {
+            match key {
+                "color" => color = Coloration::Color(words.color()?),
+                "texture" => color = Coloration::Texture(Texture::new(self.image(words.word()?)?)),
+                "ramp" => {
+                    ramp = Some(match words.word()? {
+                        "u" => RampInput::U,
+                        "v" => RampInput::V,
+                        "height" => RampInput::Height,
+                        "facing" => RampInput::Facing,
+                        input => {
+                            return Err(Error::parse(format!("unknown ramp input {:?}", input)))
+                        }
+                    })
+                }
+                "stop" => stops.push((words.float()? as f32, words.color()?)),
+                "uvchecker" => color = Coloration::Texture(Texture::uv_checker(8, 64)),
+                "scale" => texture_scale = words.float()? as f32,
+                "linear" => color_space = ColorSpace::Linear,
+                "albedo" => albedo = words.float()? as f32,
+                "reflectivity" => reflectivity = words.float()? as f32,
+                "index" | "ior" => {
+                    index = words.float()? as f32;
+                    principled.ior = index;
+                }
+                "transparency" => transparency = words.float()? as f32,
+                "dispersion" => {
+                    dispersion = words.float()? as f32;
+                    principled.dispersion = dispersion;
+                }
+                "roughness" => {
+                    roughness_u = words.float()? as f32;
+                    principled.roughness = roughness_u;
+                    hair.roughness = roughness_u;
+                }
+                "azimuthal" => hair.azimuthal_roughness = words.float()? as f32,
+                "tilt" => hair.tilt = (words.float()? * PI / 180.0) as f32,
+                "roughness_v" => roughness_v = Some(words.float()? as f32),
+                "rotation" => rotation = (words.float()? * PI / 180.0) as f32,
+                "metallic" => principled.metallic = words.float()? as f32,
+                "specular" => principled.specular = words.float()? as f32,
+                "transmission" => principled.transmission = words.float()? as f32,
+                "emission" => principled.emission = words.color()?,
+                "emission_map" => {
+                    let image = self.image(words.word()?)?;
+                    principled.emission_map = Some(Coloration::Texture(Texture::new(image)));
+                }
+                "emission_strength" => principled.emission_strength = words.float()? as f32,
+                "opacity" => {
+                    opacity = Some(Opacity {
+                        map: self.data_map(words)?,
+                        alpha: false,
+                        threshold: None,
+                    })
+                }
+                "opacity_alpha" => opacity_alpha = true,
+                "cutoff" => cutoff = Some(words.float()? as f32),
+                "roughness_map" => principled.roughness_map = Some(self.data_map(words)?),
+                "metallic_map" => principled.metallic_map = Some(self.data_map(words)?),
+                "clearcoat" => {
+                    clearcoat = Some(ClearCoat {
+                        roughness: words.float()? as f32,
+                        ior: words.float()? as f32,
+                    })
+                }
+                "catcher" => shadow_catcher = true,
+                _ => return Err(Error::parse(format!("unknown material option {:?}", key))),
+            }
+        }
+        if let Some(input) = ramp {
+            color = Coloration::Ramp(Ramp::new(input, stops));
+        }
+        let maps = [
+            &mut principled.emission_map,
+            &mut principled.roughness_map,
+            &mut principled.metallic_map,
+        ];
+        for map in maps {
+            if let Some(Coloration::Texture(texture)) = map {
+                texture.scale = texture_scale;
+            }
+        }
+        if let Some(opacity) = &mut opacity {
+            opacity.alpha = opacity_alpha;
+            opacity.threshold = cutoff;
+            if let Coloration::Texture(texture) = &mut opacity.map {
+                texture.scale = texture_scale;
+            }
+        }
+        if let Coloration::Texture(texture) = &mut color {
+            texture.scale = texture_scale;
+            texture.color_space = color_space;
+        }
+        let surface = match kind {
+            "diffuse" => SurfaceType::Diffuse,
+            "reflective" => SurfaceType::Reflective { reflectivity },
+            "refractive" => SurfaceType::Refractive {
+                index,
+                transparency,
+                dispersion,
+            },
+            "microfacet" => SurfaceType::Microfacet {
+                roughness_u,
+                roughness_v: roughness_v.unwrap_or(roughness_u),
+                rotation,
+            },
+            "principled" => SurfaceType::Principled(principled),
+            "hair" => SurfaceType::Hair(hair),
+            _ => return Err(Error::parse(format!("unknown material type {:?}", kind))),
+        };
+        self.scene.materials.insert(
+            name,
+            Material {
+                color,
+                albedo,
+                surface,
+                clearcoat,
+                shadow_catcher,
+                opacity,
+            },
+        );
+        Ok(())
+    }
+
+    fn line(&mut self, key: &str, words: &mut Words) -> Result<()> {
+        let first_item = self.scene.items.len();
+        let first_light = self.scene.lights.len();
+        match key {
+            "size" => {
+                self.scene.width = words.parse()?;
+                self.scene.height = words.parse()?;
+            }
+            "fov" => self.scene.fov = words.float()?,
+            "seed" => self.scene.seed = words.parse()?,
+            "spectral" => self.scene.spectral = true,
+            "transparent" => self.scene.transparent = true,
+            "samples" => self.scene.settings.samples = words.parse()?,
+            "maxdepth" => self.scene.settings.max_depth = words.parse()?,
+            "bias" => self.scene.settings.shadow_bias = words.float()?,
+            "filter" => self.scene.settings.filter = parse_filter(words)?,
+            "sampler" => {
+                self.scene.settings.sampler = match words.word()? {
+                    "random" => SamplerKind::Random,
+                    "bluenoise" => SamplerKind::BlueNoise,
+                    "sobol" => SamplerKind::Sobol,
+                    "cmj" => SamplerKind::Cmj,
+                    name => return Err(Error::parse(format!("unknown sampler {:?}", name))),
+                }
+            }
+            "integrator" => {
+                self.scene.settings.integrator = match words.word()? {
+                    "path" => IntegratorKind::Path,
+                    "bdpt" => IntegratorKind::Bidirectional,
+                    name => return Err(Error::parse(format!("unknown integrator {:?}", name))),
+                }
+            }
+            "caustics" => {
+                self.scene.settings.caustics = Some(CausticSettings {
+                    photons: words.parse()?,
+                    radius: words.float()?,
+                })
+            }
+            "irradiance" => {
+                self.scene.settings.irradiance_cache = Some(IrradianceSettings {
+                    samples: words.parse()?,
+                    accuracy: words.float()?,
+                })
+            }
+            "bloom" => {
+                let mut bloom = BloomSettings {
+                    threshold: words.float()? as f32,
+                    intensity: words.float()? as f32,
+                    ..BloomSettings::default()
+                };
+                if !words.is_empty() {
+                    bloom.levels = words.parse()?;
+                }
+                self.scene.settings.bloom = Some(bloom);
+            }
+            "flare" => {
+                let mut flare = FlareSettings::default();
+                while let Some(key) = words.next() {
+                    match key {
+                        "threshold" => flare.threshold = words.float()? as f32,
+                        "ghosts" => flare.ghosts = words.parse()?,
+                        "dispersal" => flare.dispersal = words.float()? as f32,
+                        "streaks" => flare.streaks = words.parse()?,
+                        "intensity" => flare.intensity = words.float()? as f32,
+                        _ => return Err(Error::parse(format!("unknown flare option {:?}", key))),
+                    }
+                }
+                self.scene.settings.flare = Some(flare);
+            }
+            "grade" => {
+                let mut grading = ColorGrading::default();
+                while let Some(key) = words.next() {
+                    match key {
+                        "temperature" => grading.temperature = words.float()? as f32,
+                        "tint" => grading.tint = words.float()? as f32,
+                        "lift" => grading.lift = words.color()?,
+                        "gamma" => grading.gamma = words.color()?,
+                        "gain" => grading.gain = words.color()?,
+                        _ => return Err(Error::parse(format!("unknown grade option {:?}", key))),
+                    }
+                }
+                self.scene.settings.grading = Some(grading);
+            }
+            "output" => {
+                self.scene.settings.output_space = match words.word()? {
+                    "gamma22" => OutputSpace::Gamma22,
+                    "srgb" => OutputSpace::Srgb,
+                    "rec709" => OutputSpace::Rec709,
+                    "linear" => OutputSpace::Linear,
+                    "p3" => OutputSpace::DisplayP3,
+                    name => return Err(Error::parse(format!("unknown color space {:?}", name))),
+                }
+            }
+            "clamp" => {
+                let max = words.parse()?;
+                self.scene.settings.sample_clamp = Some(match words.next() {
+                    None => SampleClamp::Hard(max),
+                    Some("soft") => SampleClamp::Soft(max),
+                    Some(word) => return Err(Error::parse(format!("unknown clamp {:?}", word))),
+                });
+            }
+            "mode" => {
+                self.scene.mode = match words.word()? {
+                    "shaded" => RenderMode::Shaded,
+                    "normals" => RenderMode::Normals,
+                    "depth" => RenderMode::Depth {
+                        far: words.float()?,
+                    },
+                    "uv" => RenderMode::Uv,
+                    "albedo" => RenderMode::Albedo,
+                    "bounces" => RenderMode::Bounces,
+                    "objectid" => RenderMode::ObjectId,
+                    mode => return Err(Error::parse(format!("unknown mode {:?}", mode))),
+                }
+            }
+            "camera" => {
+                let translation = words.vector()?;
+                let rotation = if words.is_empty() {
+                    Vector3::zero()
+                } else {
+                    words.vector()? * (PI / 180.0)
+                };
+                let transform = Transform {
+                    translation,
+                    rotation,
+                    ..Transform::identity()
+                };
+                self.scene.camera.start = transform;
+                self.scene.camera.end = transform;
+            }
+            "exposure" => {
+                let first = words.float()?;
+                self.scene.camera.exposure = if words.is_empty() {
+                    Exposure::Ev(first)
+                } else {
+                    Exposure::Physical {
+                        iso: first,
+                        shutter_time: words.float()?,
+                        f_number: words.float()?,
+                    }
+                };
+            }
+            "lens" => self.lens(words)?,
+            "material" => self.material(words)?,
+            "sphere" => {
+                let center = words.point()?;
+                let radius = words.float()?;
+                let material = self.material_ref(words.word()?)?;
+                self.scene.items.push(Box::new(Sphere {
+                    center,
+                    radius,
+                    material,
+                }));
+            }
+            "ellipsoid" => {
+                let center = words.point()?;
+                let radii = words.vector()?;
+                let material = self.material_ref(words.word()?)?;
+                self.scene.items.push(Box::new(Ellipsoid {
+                    center,
+                    radii,
+                    material,
+                }));
+            }
+            "roundbox" => {
+                let center = words.point()?;
+                let half_extent = words.vector()?;
+                let radius = words.float()?;
+                let material = self.material_ref(words.word()?)?;
+                self.scene.items.push(Box::new(RoundedBox {
+                    center,
+                    half_extent,
+                    radius,
+                    material,
+                }));
+            }
+            "capsule" => {
+                let a = words.point()?;
+                let b = words.point()?;
+                let radius = words.float()?;
+                let material = self.material_ref(words.word()?)?;
+                self.scene.items.push(Box::new(Capsule {
+                    a,
+                    b,
+                    radius,
+                    material,
+                }));
+            }
+            "plane" => {
+                let pos = words.point()?;
+                let normal = words.vector()?.normalize();
+                let material = self.material_ref(words.word()?)?;
+                let two_sided = words.flag("twosided");
+                self.scene.items.push(Box::new(Plane {
+                    pos,
+                    normal,
+                    material,
+                    two_sided,
+                }));
+            }
+            "quad" => {
+                let corner = words.point()?;
+                let edge_u = words.vector()?;
+                let edge_v = words.vector()?;
+                let material = self.material_ref(words.word()?)?;
+                self.scene.items.push(Box::new(Quad {
+                    corner,
+                    edge_u,
+                    edge_v,
+                    material,
+                }));
+            }
+            "obj" => {
+                let path = self.base.join(words.word()?);
+                let smooth = words.flag("smooth");
+                self.files.push(path.clone());
+                for mesh in load_obj(path, &mut self.scene.materials, smooth)? {
+                    self.scene.items.push(Box::new(mesh));
+                }
+            }
+            "subdiv" => {
+                let path = self.base.join(words.word()?);
+                let levels = words.parse()?;
+                let material = self.material_ref(words.word()?)?;
+                self.files.push(path.clone());
+                let cage = PolygonMesh::load_obj(path)?;
+                self.scene
+                    .items
+                    .push(Box::new(Mesh::new(cage.catmull_clark(levels), material)));
+            }
+            "bezier" => {
+                let path = self.base.join(words.word()?);
+                let material = self.material_ref(words.word()?)?;
+                self.files.push(path.clone());
+                self.scene
+                    .items
+                    .push(Box::new(BezierSurface::load_bpt(path, material)?));
+            }
+            "points" => {
+                let path = self.base.join(words.word()?);
+                let radius = words.float()?;
+                let material = self.material_ref(words.word()?)?;
+                self.files.push(path.clone());
+                self.scene
+                    .items
+                    .push(Box::new(PointCloud::load_ply(path, radius, material)?));
+            }
+            "strand" => {
+                let points = [
+                    words.point()?,
+                    words.point()?,
+                    words.point()?,
+                    words.point()?,
+                ];
+                let width = (words.float()?, words.float()?);
+                let material = self.material_ref(words.word()?)?;
+                let strand = Strand { points, width };
+                match self
+                    .strands
+                    .iter_mut()
+                    .find(|(m, _)| Arc::ptr_eq(m, &material))
+                {
+                    Some((_, strands)) => strands.push(strand),
+                    None => self.strands.push((material, vec![strand])),
+                }
+            }
+            "directional" => {
+                let direction = words.vector()?.normalize();
+                let color = words.color()?;
+                let intensity = words.float()? as f32;
+                self.scene.lights.push(Box::new(DirectionalLight {
+                    direction,
+                    color,
+                    intensity,
+                }));
+            }
+            "point" => {
+                let position = words.point()?;
+                let radius = words.float()?;
+                let color = words.color()?;
+                let intensity = words.float()? as f32;
+                let mut falloff = Falloff::InverseSquare;
+                if words.flag("falloff") {
+                    falloff = match words.word()? {
+                        "square" => Falloff::InverseSquare,
+                        "linear" => Falloff::Linear,
+                        "none" => Falloff::None,
+                        "range" => Falloff::Range {
+                            near: words.float()?,
+                            far: words.float()?,
+                        },
+                        name => return Err(Error::parse(format!("unknown falloff {:?}", name))),
+                    };
+                }
+                self.scene.lights.push(Box::new(SphericalLight {
+                    position,
+                    radius,
+                    color,
+                    intensity,
+                    falloff,
+                }));
+            }
+            "arealight" => {
+                let corner = words.point()?;
+                let edge_u = words.vector()?;
+                let edge_v = words.vector()?;
+                let color = words.color()?;
+                let intensity = words.float()? as f32;
+                self.scene.lights.push(Box::new(QuadLight {
+                    corner,
+                    edge_u,
+                    edge_v,
+                    color,
+                    intensity,
+                }));
+            }
+            "environment" => {
+                let color = words.color()?;
+                self.environment = Some((color, words.float()? as f32, self.light_group));
+            }
+            "portal" => self.portals.push(Portal {
+                corner: words.point()?,
+                edge_u: words.vector()?,
+                edge_v: words.vector()?,
+            }),
+            "lightgroup" => {
+                self.light_group = match words.word()? {
+                    "none" => None,
+                    name => Some(self.scene.light_group_index(name)),
+                }
+            }
+            "fog" => {
+                self.scene.medium = Some(HomogeneousMedium {
+                    absorption: words.color()?,
+                    scattering: words.color()?,
+                    bounds: None,
+                })
+            }
+            _ => return Err(Error::parse(format!("unknown keyword {:?}", key))),
+        }
+        self.orient(first_item, words);
+        // 物体的编号是按加进来的顺序给的，见assign_object_ids
+        if let Some(group) = self.light_group {
+            let group = &mut self.scene.light_groups[group];
+            group.lights.extend(first_light..self.scene.lights.len());
+            group
+                .objects
+                .extend(first_item as u32 + 1..=self.scene.items.len() as u32);
+        }
+        words.finish()
+    }
+
+    /// 物体那一行最后可选的flip（法线反过来）和cull（相机看不到背面），
+    /// 套在这一行加进来的所有物体上
+    fn orient(&mut self, first_item: usize, words: &mut Words) {
+        if self.scene.items.len() == first_item {
+            return;
+        }
+        let (mut flip_normals, mut cull_backfaces) = (false, false);
+        loop {
+            if words.flag("flip") {
+                flip_normals = true;
+            } else if words.flag("cull") {
+                cull_backfaces = true;
+            } else {
+                break;
+            }
+        }
+        if !flip_normals && !cull_backfaces {
+            return;
+        }
+        let items: Vec<_> = self.scene.items.drain(first_item..).collect();
+        for item in items {
+            self.scene.items.push(Box::new(Oriented {
+                item,
+                flip_normals,
+                cull_backfaces,
+            }));
+        }
+    }
+}
+
+/// 解析场景文件的内容，相对路径（贴图、OBJ）相对于base。
+/// 出错时信息里带行号
+pub fn parse_scene(text: &str, base: &Path) -> Result<Scene> {
+    parse(text, base).0
+}
+
+/// 除了场景还返回用到的文件（贴图、OBJ），出错时也返回出错之前用到的
+fn parse(text: &str, base: &Path) -> (Result<Scene>, Vec<PathBuf>) {
+    let mut parser = Parser {
+        base,
+        scene: Scene {
+            width: 640,
+            height: 360,
+            fov: 90.0,
+            camera: Camera::default(),
+            items: Vec::new(),
+            lights: Vec::new(),
+            materials: MaterialRegistry::default(),
+            medium: None,
+            spectral: false,
+            seed: 0,
+            mode: RenderMode::Shaded,
+            transparent: false,
+            settings: RenderSettings::default(),
+            light_groups: Vec::new(),
+        },
+        files: Vec::new(),
+        environment: None,
+        portals: Vec::new(),
+        strands: Vec::new(),
+        light_group: None,
+    };
+    let lines = match expand(text) {
+        Ok(lines) => lines,
+        Err(e) => return (Err(e), Vec::new()),
+    };
+    for (number, line) in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (key, rest) = match words.split_first() {
+            Some((key, rest)) => (*key, rest),
+            None => continue,
+        };
+        let mut rest = Words { words: rest.iter() };
+        if let Err(e) = parser.line(key, &mut rest) {
+            let e = Error::parse(format!("line {}: {}", number + 1, e));
+            return (Err(e), parser.files);
+        }
+    }
+    match parser.environment {
+        Some((color, intensity, group)) => {
+            if let Some(group) = group {
+                let index = parser.scene.lights.len();
+                parser.scene.light_groups[group].lights.push(index);
+            }
+            parser.scene.lights.push(Box::new(EnvironmentLight {
+                color,
+                intensity,
+                portals: parser.portals,
+            }))
+        }
+        None if !parser.portals.is_empty() => {
+            let e = Error::parse("portal without an environment light");
+            return (Err(e), parser.files);
+        }
+        None => {}
+    }
+    for (material, strands) in parser.strands {
+        parser
+            .scene
+            .items
+            .push(Box::new(Curves::new(strands, material)));
+    }
+    parser.scene.assign_object_ids();
+    parser.scene.add_emissive_lights();
+    (Ok(parser.scene), parser.files)
+}
+
+/// 读场景文件
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene> {
+    load_scene_with_files(path).0
+}
+
+/// 读场景文件，同时返回它用到的所有文件：场景文件本身、贴图、OBJ（不包括OBJ引用的mtl）。
+/// 出错时文件列表里是出错之前读过的那些，改好了其中哪个就可以再试。
+/// .rhai结尾的是Rhai脚本，见parse_rhai_scene，要打开scripting功能
+pub fn load_scene_with_files<P: AsRef<Path>>(path: P) -> (Result<Scene>, Vec<PathBuf>) {
+    let path = path.as_ref();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return (Err(Error::from(e).in_file(path)), vec![path.to_path_buf()]),
+    };
+    if path.extension().is_some_and(|e| e == "rhai") {
+        #[cfg(feature = "scripting")]
+        let scene = super::parse_rhai_scene(&text);
+        #[cfg(not(feature = "scripting"))]
+        let scene = Err(Error::parse(
+            "Rhai scene scripts need the scripting feature",
+        ));
+        return (scene.map_err(|e| e.in_file(path)), vec![path.to_path_buf()]);
+    }
+    let (scene, mut files) = parse(&text, path.parent().unwrap_or_else(|| Path::new("")));
+    files.insert(0, path.to_path_buf());
+    (scene.map_err(|e| e.in_file(path)), files)
+}
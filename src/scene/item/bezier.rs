@@ -0,0 +1,361 @@
+#[cfg(feature = "fs")]
+use crate::{Error, Result};
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::accel::{Accelerator, Bvh, Item};
+use crate::bsdf::orthonormal_basis;
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Backface, Intersectable, Intersection, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 一片双三次Bézier曲面的4x4个控制点，每行是沿u的四个点，第一行在v = 0
+pub type BezierPatch = [Point; 16];
+
+/// 每片在u、v方向各切成这么多段，每一小块是BVH里的一个叶子
+const SPLITS: usize = 8;
+/// 牛顿迭代最多走几步
+const NEWTON_STEPS: usize = 12;
+/// 交点比收敛误差的这么多倍还近时，当成是从表面出发的光线又打中了出发点。
+/// 迭代出来的交点有误差，shadow_bias那么一点偏移躲不开
+const SELF_HIT: Float = 64.0;
+
+/// 三次Bernstein基函数和它们的导数
+pub(super) fn bernstein(t: Float) -> ([Float; 4], [Float; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * s - 6.0 * t * s,
+            6.0 * t * s - 3.0 * t * t,
+            3.0 * t * t,
+        ],
+    )
+}
+
+/// 曲面上(u, v)处的点和沿u、v的偏导
+fn evaluate(patch: &BezierPatch, u: Float, v: Float) -> (Point, Vector3, Vector3) {
+    let (bu, du) = bernstein(u);
+    let (bv, dv) = bernstein(v);
+    let mut p = Vector3::zero();
+    let mut pu = Vector3::zero();
+    let mut pv = Vector3::zero();
+    for j in 0..4 {
+        for i in 0..4 {
+            let c = patch[j * 4 + i] - Point::zero();
+            p = p + c * (bu[i] * bv[j]);
+            pu = pu + c * (du[i] * bv[j]);
+            pv = pv + c * (bu[i] * dv[j]);
+        }
+    }
+    (Point::zero() + p, pu, pv)
+}
+
+/// de Casteljau在t处把三次曲线分成两段
+fn split(c: [Point; 4], t: Float) -> ([Point; 4], [Point; 4]) {
+    let lerp = |a: Point, b: Point| a + (b - a) * t;
+    let (p01, p12, p23) = (lerp(c[0], c[1]), lerp(c[1], c[2]), lerp(c[2], c[3]));
+    let (p012, p123) = (lerp(p01, p12), lerp(p12, p23));
+    let mid = lerp(p012, p123);
+    ([c[0], p01, p012, mid], [mid, p123, p23, c[3]])
+}
+
+/// 曲线在[t0, t1]上那一段的控制点，t1大于0
+fn segment(c: [Point; 4], t0: Float, t1: Float) -> [Point; 4] {
+    split(split(c, t1).0, t0 / t1).1
+}
+
+/// 曲面在[u0, u1] x [v0, v1]上那一块的控制点，包围盒用它们算
+fn sub_patch(
+    patch: &BezierPatch,
+    (u0, u1): (Float, Float),
+    (v0, v1): (Float, Float),
+) -> BezierPatch {
+    let mut points = *patch;
+    for row in points.chunks_mut(4) {
+        let c = segment([row[0], row[1], row[2], row[3]], u0, u1);
+        row.copy_from_slice(&c);
+    }
+    for i in 0..4 {
+        let column = [points[i], points[4 + i], points[8 + i], points[12 + i]];
+        for (j, p) in segment(column, v0, v1).iter().enumerate() {
+            points[j * 4 + i] = *p;
+        }
+    }
+    points
+}
+
+struct Shared {
+    patches: Vec<BezierPatch>,
+    material: Arc<Material>,
+}
+
+/// 一片曲面上的一小块，u、v是它在曲面上的参数范围
+struct Piece {
+    surface: Arc<Shared>,
+    patch: usize,
+    u: (Float, Float),
+    v: (Float, Float),
+    bounds: Aabb,
+}
+
+impl Piece {
+    fn patch(&self) -> &BezierPatch {
+        &self.surface.patches[self.patch]
+    }
+
+    fn center(&self) -> (Float, Float) {
+        ((self.u.0 + self.u.1) * 0.5, (self.v.0 + self.v.1) * 0.5)
+    }
+
+    /// 离曲面这么近就算收敛了
+    fn tolerance(&self) -> Float {
+        (self.bounds.max - self.bounds.min).length() * Float::EPSILON.sqrt()
+    }
+
+    /// (u, v)落在这一块上，边上留一点余量，免得相邻两块之间漏掉
+    fn contains(&self, u: Float, v: Float) -> bool {
+        let margin = (self.u.1 - self.u.0) * 1e-3;
+        (self.u.0 - margin..=self.u.1 + margin).contains(&u)
+            && (self.v.0 - margin..=self.v.1 + margin).contains(&v)
+    }
+
+    /// 曲面上离p最近的点的(u, v)，从这一块的中心开始做高斯-牛顿迭代
+    fn locate(&self, p: &Point) -> (Float, Float) {
+        let patch = self.patch();
+        let (mut u, mut v) = self.center();
+        let step_tolerance = (self.u.1 - self.u.0) * Float::EPSILON.sqrt();
+        for _ in 0..NEWTON_STEPS {
+            let (s, pu, pv) = evaluate(patch, u, v);
+            let r = *p - s;
+            let (a, b, c) = (pu.dot(&pu), pu.dot(&pv), pv.dot(&pv));
+            let det = a * c - b * b;
+            if det <= 0.0 {
+                break;
+            }
+            let (ru, rv) = (pu.dot(&r), pv.dot(&r));
+            let du = (c * ru - b * rv) / det;
+            let dv = (a * rv - b * ru) / det;
+            u = (u + du).clamp(0.0, 1.0);
+            v = (v + dv).clamp(0.0, 1.0);
+            if du.abs() + dv.abs() < step_tolerance {
+                break;
+            }
+        }
+        (u, v)
+    }
+
+    /// (u, v)处的法线；曲面退化成一个点的地方（茶壶盖顶、壶底中心）偏导是0，
+    /// 往这一片的中间挪一点再算
+    fn normal_at(&self, u: Float, v: Float) -> Vector3 {
+        let patch = self.patch();
+        let (_, pu, pv) = evaluate(patch, u, v);
+        let n = pu.cross(&pv);
+        if n.length() > 1e-6 * pu.length().max(pv.length()) {
+            return n.normalize();
+        }
+        let nudge = |t: Float| t + (0.5 - t) * 1e-3;
+        let (_, pu, pv) = evaluate(patch, nudge(u), nudge(v));
+        pu.cross(&pv).normalize()
+    }
+}
+
+impl Intersectable for Piece {
+    /// 从这一块的中心出发，对S(u, v) = o + t d做牛顿迭代
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let patch = self.patch();
+        let tolerance = self.tolerance();
+        let (mut u, mut v) = self.center();
+        let mut t = (evaluate(patch, u, v).0 - ray.origin).dot(&ray.direction);
+        let d = -ray.direction;
+        for _ in 0..NEWTON_STEPS {
+            let (s, pu, pv) = evaluate(patch, u, v);
+            let f = s - (ray.origin + ray.direction * t);
+            if f.length() < tolerance {
+                return (self.contains(u, v) && t > tolerance * SELF_HIT).then_some(t);
+            }
+            // [pu pv -d] (du, dv, dt) = -f，用克莱姆法则解
+            let det = pu.dot(&pv.cross(&d));
+            if det == 0.0 || !det.is_finite() {
+                return None;
+            }
+            let r = -f;
+            u += r.dot(&pv.cross(&d)) / det;
+            v += pu.dot(&r.cross(&d)) / det;
+            t += pu.dot(&pv.cross(&r)) / det;
+            // 跑到很远的地方就不会是这一块了
+            let (width, height) = (self.u.1 - self.u.0, self.v.1 - self.v.0);
+            if u < self.u.0 - width
+                || u > self.u.1 + width
+                || v < self.v.0 - height
+                || v > self.v.1 + height
+            {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let (u, v) = self.locate(hit_point);
+        self.normal_at(u, v)
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (u, v) = self.locate(hit_point);
+        TextureCoords {
+            u: u as f32,
+            v: v as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        let (u, v) = self.locate(hit_point);
+        let (_, pu, _) = evaluate(self.patch(), u, v);
+        let n = self.normal_at(u, v);
+        let t = pu - n * n.dot(&pu);
+        if t.norm() > 0.0 {
+            t.normalize()
+        } else {
+            orthonormal_basis(&n).0
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.surface.material
+    }
+
+    fn backface(&self) -> Backface {
+        Backface::Flip
+    }
+}
+
+/// 一组双三次Bézier曲面片，直接对曲面求交而不是先拆成三角形。每片先切成
+/// SPLITS x SPLITS小块，用控制点的包围盒（凸包性质保证包住曲面）建BVH，
+/// 打到的小块里再用牛顿迭代求精确的交点。纹理坐标是每片自己的(u, v)。
+/// 现成的曲面数据（比如Utah茶壶）各片的朝向常常不统一，所以当成两面都看得到的薄片
+pub struct BezierSurface {
+    shared: Arc<Shared>,
+    bvh: Bvh,
+}
+
+impl BezierSurface {
+    pub fn new(patches: Vec<BezierPatch>, material: Arc<Material>) -> Self {
+        let shared = Arc::new(Shared { patches, material });
+        let step = 1.0 / SPLITS as Float;
+        let mut pieces: Vec<Item> = Vec::new();
+        for (index, patch) in shared.patches.iter().enumerate() {
+            for j in 0..SPLITS {
+                for i in 0..SPLITS {
+                    let u = (i as Float * step, (i + 1) as Float * step);
+                    let v = (j as Float * step, (j + 1) as Float * step);
+                    pieces.push(Box::new(Piece {
+                        surface: shared.clone(),
+                        patch: index,
+                        u,
+                        v,
+                        bounds: Aabb::from_points(&sub_patch(patch, u, v)),
+                    }));
+                }
+            }
+        }
+        Self {
+            shared,
+            bvh: Bvh::build(pieces),
+        }
+    }
+
+    /// 读Utah茶壶用的那种文本格式：先是曲面片的个数，每片是一行"3 3"（u、v的次数）
+    /// 加16个控制点，每个点三个数
+    #[cfg(feature = "fs")]
+    pub fn load_bpt<P: AsRef<Path>>(path: P, material: Arc<Material>) -> Result<Self> {
+        let path = path.as_ref();
+        let patches = fs::read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|text| parse_bpt(&text))
+            .map_err(|e| e.in_file(path))?;
+        Ok(Self::new(patches, material))
+    }
+
+    pub fn patches(&self) -> &[BezierPatch] {
+        &self.shared.patches
+    }
+}
+
+#[cfg(feature = "fs")]
+fn parse_bpt(text: &str) -> Result<Vec<BezierPatch>> {
+    let mut words = text.split_whitespace();
+    let mut number = |what: &str| -> Result<Float> {
+        words
+            .next()
+            .and_then(|w| w.parse().ok())
+            .ok_or_else(|| Error::parse(format!("bad or missing BPT {}", what)))
+    };
+    let count = number("patch count")? as usize;
+    let mut patches = Vec::with_capacity(count);
+    for _ in 0..count {
+        let degree = (number("degree")?, number("degree")?);
+        if degree != (3.0, 3.0) {
+            return Err(Error::parse(format!(
+                "only bicubic BPT patches are supported, got degree {} {}",
+                degree.0, degree.1
+            )));
+        }
+        let mut patch = [Point::zero(); 16];
+        for p in patch.iter_mut() {
+            *p = Point::new(number("point")?, number("point")?, number("point")?);
+        }
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+impl Intersectable for BezierSurface {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.bvh.intersect(ray)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.bvh.intersect_hit(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        unreachable!("曲面求交返回的是里面的小块，不会拿它本身着色")
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        unreachable!("曲面求交返回的是里面的小块，不会拿它本身着色")
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.shared.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        if self
+            .shared
+            .patches
+            .iter()
+            .flatten()
+            .any(|p| !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite()))
+        {
+            report.error("bezier surface has NaN or infinite control points");
+        }
+        report.material(&self.shared.material);
+    }
+}
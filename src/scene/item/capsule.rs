@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use crate::bsdf::orthonormal_basis;
+use crate::math::consts::PI;
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 线段a到b周围半径为radius的所有点：一段圆柱两头各接半个球。
+/// 纹理坐标u绕着轴转一圈，v从a那头的顶点到b那头的顶点
+#[derive(Clone)]
+pub struct Capsule {
+    pub a: Point,
+    pub b: Point,
+    pub radius: Distance,
+    pub material: Arc<Material>,
+}
+
+impl Capsule {
+    /// 点在轴上的投影，0是a，1是b，夹在两头之间
+    fn along(&self, p: &Point) -> Float {
+        let axis = self.b - self.a;
+        let length2 = axis.dot(&axis);
+        if length2 > 0.0 {
+            ((*p - self.a).dot(&axis) / length2).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// 轴的方向，a和b重合时随便取一个
+    fn axis(&self) -> Vector3 {
+        let axis = self.b - self.a;
+        if axis.norm() > 0.0 {
+            axis.normalize()
+        } else {
+            Vector3::new(0.0, 1.0, 0.0)
+        }
+    }
+}
+
+/// 射线和球的两个交点的距离，不相交时是None
+fn sphere_roots(center: &Point, radius: Distance, ray: &Ray) -> Option<(Float, Float)> {
+    let oc = *center - ray.origin;
+    let projection = oc.dot(&ray.direction);
+    let d2 = oc.dot(&oc) - projection * projection;
+    let r2 = radius * radius;
+    if d2 > r2 {
+        return None;
+    }
+    let half = (r2 - d2).sqrt();
+    Some((projection - half, projection + half))
+}
+
+/// 射线和线段a到b周围半径为radius的胶囊最近的非负交点。
+/// 圆柱面、两头的球面各自求交，只留落在自己那一段上的交点，从里面打出去时也对
+pub(super) fn capsule_intersect(
+    a: &Point,
+    b: &Point,
+    radius: Distance,
+    ray: &Ray,
+) -> Option<Distance> {
+    let ba = *b - *a;
+    let oa = ray.origin - *a;
+    let baba = ba.dot(&ba);
+    let bard = ba.dot(&ray.direction);
+    let baoa = ba.dot(&oa);
+    let mut nearest: Option<Distance> = None;
+    let mut keep = |t: Float| {
+        if t >= 0.0 && nearest.is_none_or(|n| t < n) {
+            nearest = Some(t);
+        }
+    };
+    // 圆柱面：去掉沿轴的分量之后到轴的距离等于radius
+    let k = baba - bard * bard;
+    if k > 0.0 {
+        let h = baba * oa.dot(&ray.direction) - baoa * bard;
+        let c = baba * oa.dot(&oa) - baoa * baoa - radius * radius * baba;
+        let discriminant = h * h - k * c;
+        if discriminant >= 0.0 {
+            let root = discriminant.sqrt();
+            for t in [(-h - root) / k, (-h + root) / k] {
+                let y = baoa + t * bard;
+                if y > 0.0 && y < baba {
+                    keep(t);
+                }
+            }
+        }
+    }
+    // 两头的半球：交点在a的外侧或者b的外侧
+    for (center, outside) in [(*a, -1.0), (*b, 1.0)] {
+        if let Some((t0, t1)) = sphere_roots(&center, radius, ray) {
+            for t in [t0, t1] {
+                let p = ray.origin + ray.direction * t;
+                if (p - center).dot(&ba) * outside >= 0.0 {
+                    keep(t);
+                }
+            }
+        }
+    }
+    nearest
+}
+
+impl Intersectable for Capsule {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        capsule_intersect(&self.a, &self.b, self.radius, ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        let ends = Aabb::from_points(&[self.a, self.b]);
+        Some(Aabb::new(ends.min - r, ends.max + r))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let closest = self.a + (self.b - self.a) * self.along(hit_point);
+        (*hit_point - closest).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let axis = self.axis();
+        let (tangent, bitangent) = orthonormal_basis(&axis);
+        let p = *hit_point - self.a;
+        let phi = p.dot(&bitangent).atan2(p.dot(&tangent));
+        let length = (self.b - self.a).length();
+        let v = (p.dot(&axis) + self.radius) / (length + 2.0 * self.radius);
+        TextureCoords {
+            u: (phi / (2.0 * PI) + 0.5) as f32,
+            v: v as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        // 绕轴转的方向，也就是u增大的方向；在两头的顶点上退化时随便取一个
+        let t = self.axis().cross(&self.surface_normal(hit_point));
+        if t.norm() > 1e-6 {
+            t.normalize()
+        } else {
+            orthonormal_basis(&self.axis()).0
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("capsule end a", &self.a);
+        report.point("capsule end b", &self.b);
+        report.positive("capsule radius", self.radius, false);
+        report.material(&self.material);
+    }
+}
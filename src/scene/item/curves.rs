@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use super::bezier::bernstein;
+use super::capsule::capsule_intersect;
+use crate::accel::{Accelerator, Bvh, Item};
+use crate::bsdf::orthonormal_basis;
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 每根曲线切成这么多段
+const SEGMENTS: usize = 8;
+/// 光线的起点离某一段的轴不到半径的这么多倍时，当成是从这根纤维上出发的
+const THIN: Float = 1.5;
+
+/// 一根三次Bézier曲线，width是根部和梢上的粗细（直径），中间线性过渡，梢上可以是0
+#[derive(Clone)]
+pub struct Strand {
+    pub points: [Point; 4],
+    pub width: (Distance, Distance),
+}
+
+impl Strand {
+    fn position(&self, t: Float) -> Point {
+        let (b, _) = bernstein(t);
+        let mut p = Vector3::zero();
+        for (c, w) in self.points.iter().zip(b) {
+            p = p + (*c - Point::zero()) * w;
+        }
+        Point::zero() + p
+    }
+
+    fn derivative(&self, t: Float) -> Vector3 {
+        let (_, d) = bernstein(t);
+        let mut v = Vector3::zero();
+        for (c, w) in self.points.iter().zip(d) {
+            v = v + (*c - Point::zero()) * w;
+        }
+        v
+    }
+
+    fn radius(&self, t: Float) -> Distance {
+        (self.width.0 + (self.width.1 - self.width.0) * t) * 0.5
+    }
+}
+
+struct Shared {
+    strands: Vec<Strand>,
+    /// 每根曲线切段的端点，SEGMENTS + 1个
+    polylines: Vec<Vec<Point>>,
+    material: Arc<Material>,
+}
+
+impl Shared {
+    /// 第index段的两端和半径，半径取这一段中间的粗细
+    fn segment(&self, strand: usize, index: usize) -> (Point, Point, Distance) {
+        let line = &self.polylines[strand];
+        let t = (index as Float + 0.5) / SEGMENTS as Float;
+        (line[index], line[index + 1], self.strands[strand].radius(t))
+    }
+}
+
+/// 点在线段a到b上的投影，0是a，1是b
+fn along(a: &Point, b: &Point, p: &Point) -> Float {
+    let axis = *b - *a;
+    let length2 = axis.dot(&axis);
+    if length2 > 0.0 {
+        ((*p - *a).dot(&axis) / length2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// 一根曲线上的一段，当成一个胶囊求交
+struct Segment {
+    curves: Arc<Shared>,
+    strand: usize,
+    index: usize,
+}
+
+impl Segment {
+    fn ends(&self) -> (Point, Point, Distance) {
+        self.curves.segment(self.strand, self.index)
+    }
+
+    /// 点在整根曲线上的参数
+    fn parameter(&self, hit_point: &Point) -> Float {
+        let (a, b, _) = self.ends();
+        (self.index as Float + along(&a, &b, hit_point)) / SEGMENTS as Float
+    }
+}
+
+impl Intersectable for Segment {
+    /// 纤维很细，从它上面出发的光线（反射的、穿过去的）不会再打中它自己，
+    /// 所以起点贴着这一段或者前后相邻的段时直接放过去
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let first = self.index.saturating_sub(1);
+        let last = (self.index + 1).min(SEGMENTS - 1);
+        for index in first..=last {
+            let (a, b, radius) = self.curves.segment(self.strand, index);
+            let closest = a + (b - a) * along(&a, &b, &ray.origin);
+            if (ray.origin - closest).length() < radius * THIN {
+                return None;
+            }
+        }
+        let (a, b, radius) = self.ends();
+        capsule_intersect(&a, &b, radius, ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let (a, b, radius) = self.ends();
+        let r = Vector3::new(radius, radius, radius);
+        let ends = Aabb::from_points(&[a, b]);
+        Some(Aabb::new(ends.min - r, ends.max + r))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let (a, b, _) = self.ends();
+        let closest = a + (b - a) * along(&a, &b, hit_point);
+        (*hit_point - closest).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        TextureCoords {
+            u: 0.5,
+            v: self.parameter(hit_point) as f32,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        let t = self.curves.strands[self.strand].derivative(self.parameter(hit_point));
+        if t.norm() > 0.0 {
+            t.normalize()
+        } else {
+            orthonormal_basis(&self.surface_normal(hit_point)).0
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.curves.material
+    }
+}
+
+/// 一组头发、草叶之类的细曲线。每根是一条三次Bézier曲线，切成SEGMENTS段，
+/// 每段当成一个粗细取中间值的胶囊，放进BVH。切线沿着曲线，配合Hair材质用；
+/// 纹理坐标v从根到梢是0到1，u没有意义
+pub struct Curves {
+    shared: Arc<Shared>,
+    bvh: Bvh,
+}
+
+impl Curves {
+    pub fn new(strands: Vec<Strand>, material: Arc<Material>) -> Self {
+        let polylines = strands
+            .iter()
+            .map(|strand| {
+                (0..=SEGMENTS)
+                    .map(|i| strand.position(i as Float / SEGMENTS as Float))
+                    .collect()
+            })
+            .collect();
+        let shared = Arc::new(Shared {
+            strands,
+            polylines,
+            material,
+        });
+        let mut segments: Vec<Item> = Vec::new();
+        for strand in 0..shared.strands.len() {
+            for index in 0..SEGMENTS {
+                segments.push(Box::new(Segment {
+                    curves: shared.clone(),
+                    strand,
+                    index,
+                }));
+            }
+        }
+        Self {
+            shared,
+            bvh: Bvh::build(segments),
+        }
+    }
+
+    pub fn strands(&self) -> &[Strand] {
+        &self.shared.strands
+    }
+}
+
+impl Intersectable for Curves {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.bvh.intersect(ray)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.bvh.intersect_hit(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        unreachable!("曲线求交返回的是里面的一段，不会拿它本身着色")
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        unreachable!("曲线求交返回的是里面的一段，不会拿它本身着色")
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.shared.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        for strand in &self.shared.strands {
+            for p in &strand.points {
+                report.point("strand control point", p);
+            }
+            report.positive("strand root width", strand.width.0, false);
+            report.positive("strand tip width", strand.width.1, true);
+        }
+        report.material(&self.shared.material);
+    }
+}
@@ -0,0 +1,103 @@
+use std::sync::Arc;
+
+use crate::math::{Aabb, Point, Vector3};
+use crate::rendering::{Intersectable, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 三个轴上半径各不相同的椭球，轴和坐标轴对齐，要转的话套一层Instance。
+/// 纹理坐标和同一个位置的单位球一样
+#[derive(Clone)]
+pub struct Ellipsoid {
+    pub center: Point,
+    pub radii: Vector3,
+    pub material: Arc<Material>,
+}
+
+impl Ellipsoid {
+    fn inverse_radii(&self) -> Vector3 {
+        Vector3::new(
+            self.radii.x.recip(),
+            self.radii.y.recip(),
+            self.radii.z.recip(),
+        )
+    }
+
+    /// 点缩放到单位球上的位置
+    fn unit(&self, hit_point: &Point) -> Vector3 {
+        (*hit_point - self.center) * self.inverse_radii()
+    }
+}
+
+impl Intersectable for Ellipsoid {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        // 把光线缩放到单位球的空间里求交，缩放不改变t
+        let inverse = self.inverse_radii();
+        let o = (ray.origin - self.center) * inverse;
+        let d = ray.direction * inverse;
+        let a = d.dot(&d);
+        let b = o.dot(&d);
+        let c = o.dot(&o) - 1.0;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let t0 = (-b - root) / a;
+        let t1 = (-b + root) / a;
+        if t0 >= 0.0 {
+            Some(t0)
+        } else if t1 >= 0.0 {
+            Some(t1)
+        } else {
+            None
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            self.center - self.radii,
+            self.center + self.radii,
+        ))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        // 隐式方程(p/r)² = 1的梯度
+        (self.unit(hit_point) * self.inverse_radii()).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let p = self.unit(hit_point);
+        let phi = (p.z).atan2(p.x);
+        let theta = p.y.clamp(-1.0, 1.0).acos();
+        TextureCoords {
+            u: (1.0 + phi) as f32 / std::f32::consts::PI * 0.5,
+            v: theta as f32 / std::f32::consts::PI,
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        // 单位球上phi增大的方向拉伸回椭球上；两极退化时随便取一个
+        let p = self.unit(hit_point);
+        let t = Vector3::new(-p.z, 0.0, p.x) * self.radii;
+        if t.norm() > 1e-12 {
+            t.normalize()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("ellipsoid center", &self.center);
+        report.positive("ellipsoid x radius", self.radii.x, false);
+        report.positive("ellipsoid y radius", self.radii.y, false);
+        report.positive("ellipsoid z radius", self.radii.z, false);
+        report.material(&self.material);
+    }
+}
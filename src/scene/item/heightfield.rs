@@ -0,0 +1,244 @@
+#[cfg(feature = "fs")]
+use crate::{Error, Result};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::Arc;
+
+use super::mesh::intersect_triangle;
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 高度场地形：xz平面上的规则网格，每个格子拆成两个三角形，y朝上。
+/// 高度是[0, 1]，对应bounds的min.y到max.y
+pub struct Heightfield {
+    pub bounds: Aabb,
+    /// x方向和z方向的采样点数
+    pub resolution: (usize, usize),
+    /// 按x最快排布
+    pub heights: Vec<Float>,
+    /// 每个采样点的法线，插值出来做光滑着色
+    normals: Vec<Vector3>,
+    pub material: Arc<Material>,
+}
+
+impl Heightfield {
+    /// resolution两个方向都至少要有2个采样点，heights的长度要正好是resolution.0 * resolution.1，
+    /// 否则会panic
+    pub fn new(
+        bounds: Aabb,
+        resolution: (usize, usize),
+        heights: Vec<Float>,
+        material: Arc<Material>,
+    ) -> Self {
+        assert!(resolution.0 >= 2 && resolution.1 >= 2);
+        assert_eq!(resolution.0 * resolution.1, heights.len());
+        let mut field = Self {
+            bounds,
+            resolution,
+            heights,
+            normals: Vec::new(),
+            material,
+        };
+        field.normals = (0..resolution.1)
+            .flat_map(|j| (0..resolution.0).map(move |i| (i, j)))
+            .map(|(i, j)| field.vertex_normal(i, j))
+            .collect();
+        field
+    }
+
+    /// 从灰度图读高度，图的列是x，行是z；16位灰度图会保留全部精度。
+    /// 图的宽和高都至少要2个像素，否则返回Error::Parse
+    #[cfg(feature = "fs")]
+    pub fn load_image<P: AsRef<Path>>(
+        path: P,
+        bounds: Aabb,
+        material: Arc<Material>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path).map_err(|e| Error::from(e).in_file(path))?;
+        let (resolution, heights) = match image {
+            image::DynamicImage::ImageLuma16(image) => (
+                (image.width() as usize, image.height() as usize),
+                image.pixels().map(|p| p.0[0] as Float / 65535.0).collect(),
+            ),
+            image => {
+                let image = image.to_luma();
+                (
+                    (image.width() as usize, image.height() as usize),
+                    image.pixels().map(|p| p.0[0] as Float / 255.0).collect(),
+                )
+            }
+        };
+        if resolution.0 < 2 || resolution.1 < 2 {
+            let message = format!(
+                "heightfield needs at least 2x2 samples, got {}x{}",
+                resolution.0, resolution.1
+            );
+            return Err(Error::parse(message).in_file(path));
+        }
+        Ok(Self::new(bounds, resolution, heights, material))
+    }
+
+    fn cell_size(&self) -> (Float, Float) {
+        let extent = self.bounds.max - self.bounds.min;
+        (
+            extent.x / (self.resolution.0 - 1) as Float,
+            extent.z / (self.resolution.1 - 1) as Float,
+        )
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point {
+        let (cx, cz) = self.cell_size();
+        let min = self.bounds.min;
+        let height = self.bounds.max.y - min.y;
+        Point::new(
+            min.x + i as Float * cx,
+            min.y + self.heights[i + j * self.resolution.0] * height,
+            min.z + j as Float * cz,
+        )
+    }
+
+    /// 用相邻采样点的中心差分算法线，边上用单边差分
+    fn vertex_normal(&self, i: usize, j: usize) -> Vector3 {
+        let (nx, nz) = self.resolution;
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+        let dx = self.vertex(i1, j) - self.vertex(i0, j);
+        let dz = self.vertex(i, j1) - self.vertex(i, j0);
+        dz.cross(&dx).normalize()
+    }
+
+    /// 点所在的格子和在格子里的位置(0到1)
+    fn cell_of(&self, p: &Point) -> (usize, usize, Float, Float) {
+        let (cx, cz) = self.cell_size();
+        let fx = ((p.x - self.bounds.min.x) / cx).clamp(0.0, (self.resolution.0 - 1) as Float);
+        let fz = ((p.z - self.bounds.min.z) / cz).clamp(0.0, (self.resolution.1 - 1) as Float);
+        let i = (fx as usize).min(self.resolution.0 - 2);
+        let j = (fz as usize).min(self.resolution.1 - 2);
+        (i, j, fx - i as Float, fz - j as Float)
+    }
+
+    fn intersect_cell(&self, ray: &Ray, i: usize, j: usize) -> Option<Distance> {
+        let (v00, v10) = (self.vertex(i, j), self.vertex(i + 1, j));
+        let (v01, v11) = (self.vertex(i, j + 1), self.vertex(i + 1, j + 1));
+        let a = intersect_triangle(ray, [v00, v01, v11]);
+        let b = intersect_triangle(ray, [v00, v11, v10]);
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn cell_height_range(&self, i: usize, j: usize) -> (Float, Float) {
+        let nx = self.resolution.0;
+        let corners = [
+            self.heights[i + j * nx],
+            self.heights[i + 1 + j * nx],
+            self.heights[i + (j + 1) * nx],
+            self.heights[i + 1 + (j + 1) * nx],
+        ];
+        let lo = corners.iter().cloned().fold(Float::INFINITY, Float::min);
+        let hi = corners
+            .iter()
+            .cloned()
+            .fold(Float::NEG_INFINITY, Float::max);
+        let (min, height) = (self.bounds.min.y, self.bounds.max.y - self.bounds.min.y);
+        (min + lo * height, min + hi * height)
+    }
+}
+
+impl Intersectable for Heightfield {
+    /// 在xz平面上按格子走（2D DDA），只和光线经过的格子求交，
+    /// 格子里的高度范围和这一段光线的高度范围不重叠就直接跳过
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let (t_min, t_max) = self.bounds.hit(&ray.origin, &ray.direction)?;
+        let (cx, cz) = self.cell_size();
+        let start = ray.origin + ray.direction * t_min;
+        let (mut i, mut j, _, _) = self.cell_of(&start);
+
+        // 沿一个轴走到下一条格线要多远
+        let axis = |o: Float, d: Float, min: Float, size: Float, cell: usize| {
+            if d > 0.0 {
+                (1, (min + (cell + 1) as Float * size - o) / d, size / d)
+            } else if d < 0.0 {
+                (-1, (min + cell as Float * size - o) / d, -size / d)
+            } else {
+                (0, Float::INFINITY, Float::INFINITY)
+            }
+        };
+        let (step_x, mut next_x, delta_x) =
+            axis(ray.origin.x, ray.direction.x, self.bounds.min.x, cx, i);
+        let (step_z, mut next_z, delta_z) =
+            axis(ray.origin.z, ray.direction.z, self.bounds.min.z, cz, j);
+
+        let mut t_enter = t_min;
+        loop {
+            let t_exit = next_x.min(next_z).min(t_max);
+            let (y0, y1) = (
+                ray.origin.y + ray.direction.y * t_enter,
+                ray.origin.y + ray.direction.y * t_exit,
+            );
+            let (lo, hi) = self.cell_height_range(i, j);
+            if y0.min(y1) <= hi && y0.max(y1) >= lo {
+                if let Some(t) = self.intersect_cell(ray, i, j) {
+                    return Some(t);
+                }
+            }
+            if t_exit >= t_max {
+                return None;
+            }
+            if next_x < next_z {
+                if (step_x < 0 && i == 0) || (step_x > 0 && i + 2 >= self.resolution.0) {
+                    return None;
+                }
+                i = (i as isize + step_x) as usize;
+                next_x += delta_x;
+            } else {
+                if (step_z < 0 && j == 0) || (step_z > 0 && j + 2 >= self.resolution.1) {
+                    return None;
+                }
+                j = (j as isize + step_z) as usize;
+                next_z += delta_z;
+            }
+            t_enter = t_exit;
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    /// 四个角的法线双线性插值
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let (i, j, fx, fz) = self.cell_of(hit_point);
+        let nx = self.resolution.0;
+        let n = |i: usize, j: usize| self.normals[i + j * nx];
+        let near = n(i, j) * (1.0 - fx) + n(i + 1, j) * fx;
+        let far = n(i, j + 1) * (1.0 - fx) + n(i + 1, j + 1) * fx;
+        (near * (1.0 - fz) + far * fz).normalize()
+    }
+
+    /// uv铺满整个地形
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let extent = self.bounds.max - self.bounds.min;
+        TextureCoords {
+            u: ((hit_point.x - self.bounds.min.x) / extent.x) as f32,
+            v: ((hit_point.z - self.bounds.min.z) / extent.z) as f32,
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        if self.heights.iter().any(|h| !h.is_finite()) {
+            report.error("heightfield has NaN or infinite heights");
+        }
+        report.material(&self.material);
+    }
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use crate::math::{Aabb, Affine, Point, Vector3};
+use crate::overlay::BoundsBox;
+use crate::rendering::{local_ray, Intersectable, Intersection, Ray};
+use crate::scene::{
+    item::Volume,
+    light::EmissiveSurface,
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 共享同一份几何体的实例：很多个Instance指向同一个item（可以是一整个BVH），
+/// 各自只存一个变换，所以一万棵一样的树在内存里只有一份
+pub struct Instance {
+    pub item: Arc<dyn Intersectable + Send + Sync>,
+    to_world: Affine,
+    to_local: Affine,
+}
+
+impl Instance {
+    pub fn new(item: Arc<dyn Intersectable + Send + Sync>, to_world: Affine) -> Self {
+        Self {
+            item,
+            to_world,
+            to_local: to_world.inverse(),
+        }
+    }
+}
+
+impl Intersectable for Instance {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let (local, scale) = local_ray(ray, &self.to_local);
+        self.item.intersect(&local).map(|d| d * scale)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let (local, scale) = local_ray(ray, &self.to_local);
+        self.item
+            .intersect_hit(&local)
+            .map(|hit| hit.transformed(&self.to_world, scale))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.item
+            .bounds()
+            .map(|bounds| bounds.transformed(&self.to_world))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let local = self.to_local.point(hit_point);
+        self.to_world.normal(&self.item.surface_normal(&local))
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.item.texture_coords(&self.to_local.point(hit_point))
+    }
+
+    fn get_material(&self) -> &Material {
+        self.item.get_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        self.item.validate(report);
+    }
+
+    fn volume(&self) -> Option<&Volume> {
+        self.item.volume()
+    }
+
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        let start = out.len();
+        self.item.caustic_bounds(out);
+        for bounds in &mut out[start..] {
+            *bounds = bounds.transformed(&self.to_world);
+        }
+    }
+
+    /// 不等比缩放的球变成了椭球，没法当成光源采样，丢掉
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        let mut local = Vec::new();
+        self.item.emissive_surfaces(&mut local);
+        out.extend(local.iter().filter_map(|s| s.transformed(&self.to_world)));
+    }
+
+    fn collect_bounds(&self, out: &mut Vec<BoundsBox>) {
+        let start = out.len();
+        self.item.collect_bounds(out);
+        for bounds in &mut out[start..] {
+            bounds.bounds = bounds.bounds.transformed(&self.to_world);
+        }
+    }
+}
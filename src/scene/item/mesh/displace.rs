@@ -0,0 +1,175 @@
+use super::MeshData;
+use crate::color::Color;
+use crate::math::{Float, Point, Vector3};
+use crate::scene::material::Texture;
+use crate::{Error, Result};
+use std::collections::HashMap;
+
+/// 细分时新顶点的各个属性，都按重心坐标插值
+struct Attributes<'a> {
+    mesh: &'a MeshData,
+    normals: &'a [Vector3],
+    uvs: &'a [(f32, f32)],
+}
+
+impl Attributes<'_> {
+    fn push(&self, out: &mut MeshData, weights: &[(usize, Float)]) -> usize {
+        let mut p = Vector3::zero();
+        let mut n = Vector3::zero();
+        let (mut u, mut v) = (0.0, 0.0);
+        let mut color = Color::black();
+        for &(i, w) in weights {
+            p = p + (self.mesh.positions[i] - Point::zero()) * w;
+            n = n + self.normals[i] * w;
+            u += self.uvs[i].0 * w as f32;
+            v += self.uvs[i].1 * w as f32;
+            if let Some(colors) = &self.mesh.colors {
+                color += colors[i] * w as f32;
+            }
+        }
+        out.positions.push(Point::zero() + p);
+        if let Some(normals) = &mut out.normals {
+            normals.push(if n.length() > 0.0 { n.normalize() } else { n });
+        }
+        if let Some(uvs) = &mut out.uvs {
+            uvs.push((u, v));
+        }
+        if let Some(colors) = &mut out.colors {
+            colors.push(color);
+        }
+        out.positions.len() - 1
+    }
+}
+
+/// 双线性插值取高度图的值，三个通道的平均，[0, 1]
+fn sample_height(texture: &Texture, u: f32, v: f32) -> Float {
+    let (width, height) = (texture.image.width(), texture.image.height());
+    let x = ((u + texture.offset_x) / texture.scale) * width as f32 - 0.5;
+    let y = ((v + texture.offset_y) / texture.scale) * height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as i64).rem_euclid(height as i64) as u32;
+        let c = texture.texel(x, y);
+        (c.r + c.g + c.b) / 3.0
+    };
+    let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+    let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+    (top * (1.0 - fy) + bottom * fy) as Float
+}
+
+impl MeshData {
+    /// 位移贴图：每个三角形每条边切成subdivisions段，再把顶点沿法线推出去高度图 * scale。
+    /// 高度图按顶点的uv采样，所以网格必须有uv，没有的话返回Error::Scene；
+    /// 高度是数据，贴图一般要设成ColorSpace::Linear，否则会先按sRGB解码；没有顶点法线时先算光滑的法线。
+    /// 相邻三角形共用边上的顶点，推出去以后不会裂开；结果的法线按新的形状重新算
+    pub fn displaced(
+        &self,
+        height: &Texture,
+        scale: Float,
+        subdivisions: usize,
+    ) -> Result<MeshData> {
+        let uvs = self
+            .uvs
+            .as_ref()
+            .ok_or_else(|| Error::Scene("displacement needs a mesh with uv coordinates".into()))?;
+        let smoothed;
+        let normals = match &self.normals {
+            Some(normals) => normals,
+            None => {
+                smoothed = self.clone().with_smooth_normals();
+                smoothed.normals.as_ref().unwrap()
+            }
+        };
+        let attributes = Attributes {
+            mesh: self,
+            normals,
+            uvs,
+        };
+        let n = subdivisions.max(1);
+        let mut out = MeshData {
+            positions: Vec::new(),
+            normals: Some(Vec::new()),
+            colors: self.colors.as_ref().map(|_| Vec::new()),
+            uvs: Some(Vec::new()),
+            triangles: Vec::new(),
+        };
+
+        // 原来的顶点和边上的点在所有三角形之间共用；边上的点按(小下标, 大下标, 离小下标第几段)存
+        let mut corners: HashMap<usize, usize> = HashMap::new();
+        let mut edges: HashMap<(usize, usize, usize), usize> = HashMap::new();
+        let mut corner = |out: &mut MeshData, v: usize| {
+            *corners
+                .entry(v)
+                .or_insert_with(|| attributes.push(out, &[(v, 1.0)]))
+        };
+        for &[a, b, c] in &self.triangles {
+            // 三角形里的格点(i, j)：i沿a到b，j沿a到c
+            let mut grid = vec![vec![0; n + 1]; n + 1];
+            for i in 0..=n {
+                for j in 0..=n - i {
+                    let edge = match (i, j) {
+                        (0, 0) => {
+                            grid[i][j] = corner(&mut out, a);
+                            continue;
+                        }
+                        (i, 0) if i == n => {
+                            grid[i][j] = corner(&mut out, b);
+                            continue;
+                        }
+                        (0, j) if j == n => {
+                            grid[i][j] = corner(&mut out, c);
+                            continue;
+                        }
+                        (i, 0) => Some((a, b, i)),
+                        (0, j) => Some((a, c, j)),
+                        (i, j) if i + j == n => Some((b, c, j)),
+                        _ => None,
+                    };
+                    grid[i][j] = match edge {
+                        Some((from, to, k)) => {
+                            let (lo, hi, k) = if from < to {
+                                (from, to, k)
+                            } else {
+                                (to, from, n - k)
+                            };
+                            *edges.entry((lo, hi, k)).or_insert_with(|| {
+                                let t = k as Float / n as Float;
+                                attributes.push(&mut out, &[(lo, 1.0 - t), (hi, t)])
+                            })
+                        }
+                        None => {
+                            let (w1, w2) = (i as Float / n as Float, j as Float / n as Float);
+                            attributes.push(&mut out, &[(a, 1.0 - w1 - w2), (b, w1), (c, w2)])
+                        }
+                    };
+                }
+            }
+            for i in 0..n {
+                for j in 0..n - i {
+                    out.triangles
+                        .push([grid[i][j], grid[i + 1][j], grid[i][j + 1]]);
+                    if i + j + 1 < n {
+                        out.triangles
+                            .push([grid[i + 1][j], grid[i + 1][j + 1], grid[i][j + 1]]);
+                    }
+                }
+            }
+        }
+
+        let offsets: Vec<Vector3> = {
+            let normals = out.normals.as_ref().unwrap();
+            let uvs = out.uvs.as_ref().unwrap();
+            normals
+                .iter()
+                .zip(uvs)
+                .map(|(n, &(u, v))| *n * (sample_height(height, u, v) * scale))
+                .collect()
+        };
+        for (p, offset) in out.positions.iter_mut().zip(offsets) {
+            *p = *p + offset;
+        }
+        Ok(out.with_smooth_normals())
+    }
+}
@@ -0,0 +1,279 @@
+mod displace;
+#[cfg(feature = "fs")]
+mod mtl;
+#[cfg(feature = "fs")]
+mod obj;
+#[cfg(feature = "fs")]
+mod ply;
+mod subdivision;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::accel::{Accelerator, Bvh};
+use crate::color::Color;
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    light::{EmissiveShape, EmissiveSurface, Portal},
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 三角形比这个多的发光网格不当成光源采样：光线打中它时算pdf要挨个试每个三角形
+const MAX_EMISSIVE_TRIANGLES: usize = 4096;
+
+#[cfg(feature = "fs")]
+pub use obj::load_obj;
+pub use subdivision::PolygonMesh;
+
+/// 三角形网格的数据，normals、colors、uvs有的话都是每个顶点一个
+#[derive(Default, Clone)]
+pub struct MeshData {
+    pub positions: Vec<Point>,
+    pub normals: Option<Vec<Vector3>>,
+    pub colors: Option<Vec<Color>>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    /// 用相邻三角形的法线（按面积加权）算每个顶点的法线，替换掉原来的。
+    /// 位置完全相同的顶点算同一个，这样OBJ里因为uv接缝拆开的顶点不会出现折痕
+    pub fn with_smooth_normals(mut self) -> Self {
+        let key = |p: &Point| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+        let mut sums: HashMap<_, Vector3> = HashMap::new();
+        for &[a, b, c] in &self.triangles {
+            let (p0, p1, p2) = (self.positions[a], self.positions[b], self.positions[c]);
+            // 叉积的长度正好是面积的两倍
+            let n = (p1 - p0).cross(&(p2 - p0));
+            for p in [p0, p1, p2] {
+                let sum = sums.entry(key(&p)).or_insert_with(Vector3::zero);
+                *sum = *sum + n;
+            }
+        }
+        let normals = self
+            .positions
+            .iter()
+            .map(|p| match sums.get(&key(p)) {
+                Some(n) if n.length() > 0.0 => n.normalize(),
+                _ => Vector3::zero(),
+            })
+            .collect();
+        self.normals = Some(normals);
+        self
+    }
+}
+
+/// Möller–Trumbore光线三角形求交
+pub(super) fn intersect_triangle(ray: &Ray, [p0, p1, p2]: [Point; 3]) -> Option<Distance> {
+    let (e1, e2) = (p1 - p0, p2 - p0);
+    let p = ray.direction.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let s = ray.origin - p0;
+    let u = s.dot(&p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&e1);
+    let v = ray.direction.dot(&q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(&q) * inv_det;
+    if t > 0.0 {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+struct Shared {
+    data: MeshData,
+    material: Arc<Material>,
+}
+
+/// 网格里的一个三角形，和其它三角形共用同一份网格数据
+struct Triangle {
+    mesh: Arc<Shared>,
+    index: usize,
+}
+
+impl Triangle {
+    fn vertices(&self) -> [usize; 3] {
+        self.mesh.data.triangles[self.index]
+    }
+
+    fn points(&self) -> [Point; 3] {
+        let [a, b, c] = self.vertices();
+        let positions = &self.mesh.data.positions;
+        [positions[a], positions[b], positions[c]]
+    }
+
+    /// hit_point在三角形上的重心坐标(w0, w1, w2)
+    fn barycentric(&self, hit_point: &Point) -> (Float, Float, Float) {
+        let [p0, p1, p2] = self.points();
+        let (e1, e2, d) = (p1 - p0, p2 - p0, *hit_point - p0);
+        let (d11, d12, d22) = (e1.dot(&e1), e1.dot(&e2), e2.dot(&e2));
+        let (d1p, d2p) = (e1.dot(&d), e2.dot(&d));
+        let denom = d11 * d22 - d12 * d12;
+        if denom.abs() < 1e-20 {
+            return (1.0, 0.0, 0.0);
+        }
+        let w1 = (d22 * d1p - d12 * d2p) / denom;
+        let w2 = (d11 * d2p - d12 * d1p) / denom;
+        (1.0 - w1 - w2, w1, w2)
+    }
+}
+
+impl Intersectable for Triangle {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        intersect_triangle(ray, self.points())
+    }
+
+    /// 有顶点法线时按重心坐标插值，这样低模也是光滑的
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let [p0, p1, p2] = self.points();
+        let geometric = (p1 - p0).cross(&(p2 - p0)).normalize();
+        let normals = match &self.mesh.data.normals {
+            Some(normals) => normals,
+            None => return geometric,
+        };
+        let (w0, w1, w2) = self.barycentric(hit_point);
+        let [a, b, c] = self.vertices();
+        let n = normals[a] * w0 + normals[b] * w1 + normals[c] * w2;
+        // OBJ里有的面没写法线，那几个顶点的法线是0
+        if n.length() < 1e-6 {
+            geometric
+        } else {
+            n.normalize()
+        }
+    }
+
+    /// 有uv就插值uv，没有就直接用重心坐标
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (w0, w1, w2) = self.barycentric(hit_point);
+        match &self.mesh.data.uvs {
+            Some(uvs) => {
+                let [a, b, c] = self.vertices();
+                let (w0, w1, w2) = (w0 as f32, w1 as f32, w2 as f32);
+                TextureCoords {
+                    u: uvs[a].0 * w0 + uvs[b].0 * w1 + uvs[c].0 * w2,
+                    v: uvs[a].1 * w0 + uvs[b].1 * w1 + uvs[c].1 * w2,
+                }
+            }
+            None => TextureCoords {
+                u: w1 as f32,
+                v: w2 as f32,
+            },
+        }
+    }
+
+    fn vertex_color(&self, hit_point: &Point) -> Option<Color> {
+        let colors = self.mesh.data.colors.as_ref()?;
+        let (w0, w1, w2) = self.barycentric(hit_point);
+        let [a, b, c] = self.vertices();
+        Some(colors[a] * w0 as f32 + colors[b] * w1 as f32 + colors[c] * w2 as f32)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(&self.points()))
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.mesh.material
+    }
+}
+
+/// 三角形网格，所有三角形放在一棵BVH里
+pub struct Mesh {
+    shared: Arc<Shared>,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(data: MeshData, material: Arc<Material>) -> Self {
+        let shared = Arc::new(Shared { data, material });
+        let triangles = (0..shared.data.triangles.len())
+            .map(|index| {
+                Box::new(Triangle {
+                    mesh: shared.clone(),
+                    index,
+                }) as Box<dyn Intersectable + Send + Sync>
+            })
+            .collect();
+        Self {
+            shared,
+            bvh: Bvh::build(triangles),
+        }
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.shared.data
+    }
+}
+
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.bvh.intersect(ray)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.bvh.intersect_hit(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        unreachable!("网格求交返回的是里面的三角形，不会拿它本身着色")
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        unreachable!("网格求交返回的是里面的三角形，不会拿它本身着色")
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.shared.material
+    }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        let data = &self.shared.data;
+        let radiance = match self.shared.material.uniform_emission() {
+            Some(radiance) if data.triangles.len() <= MAX_EMISSIVE_TRIANGLES => radiance,
+            _ => return,
+        };
+        for &[a, b, c] in &data.triangles {
+            let (p0, p1, p2) = (data.positions[a], data.positions[b], data.positions[c]);
+            let (edge_u, edge_v) = (p1 - p0, p2 - p0);
+            if edge_u.cross(&edge_v).length() > 0.0 {
+                out.push(EmissiveSurface {
+                    shape: EmissiveShape::Triangle(Portal {
+                        corner: p0,
+                        edge_u,
+                        edge_v,
+                    }),
+                    radiance,
+                    object_id: None,
+                });
+            }
+        }
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        let data = &self.shared.data;
+        if data
+            .positions
+            .iter()
+            .any(|p| !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite()))
+        {
+            report.error("mesh has NaN or infinite vertices");
+        }
+        report.material(&self.shared.material);
+    }
+}
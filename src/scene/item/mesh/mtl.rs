@@ -0,0 +1,161 @@
+use crate::color::Color;
+use crate::scene::material::{
+    ColorSpace, Coloration, Material, Opacity, Principled, SurfaceType, Texture,
+};
+use crate::{Error, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// MTL里一个newmtl的内容，没写的项保持默认
+struct MtlEntry {
+    name: String,
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    /// Ns，Phong高光指数
+    shininess: f32,
+    /// Ni
+    ior: f32,
+    /// d，1是不透明
+    dissolve: f32,
+    diffuse_map: Option<PathBuf>,
+    /// map_d，不透明度贴图，用来镂空
+    dissolve_map: Option<PathBuf>,
+}
+
+impl MtlEntry {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: Color {
+                r: 0.8,
+                g: 0.8,
+                b: 0.8,
+            },
+            specular: Color::black(),
+            emission: Color::black(),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            diffuse_map: None,
+            dissolve_map: None,
+        }
+    }
+
+    /// 只有漫反射的还是Diffuse，有高光、自发光或者透明的用Principled
+    fn to_material(&self) -> Result<Material> {
+        let color = match &self.diffuse_map {
+            Some(path) => Coloration::Texture(Texture::open(path)?),
+            None => Coloration::Color(self.diffuse),
+        };
+        let transmission = 1.0 - self.dissolve.clamp(0.0, 1.0);
+        let has_specular = self.specular.luminance() > 0.0;
+        let has_emission = self.emission.luminance() > 0.0;
+        let surface = if !has_specular && !has_emission && transmission == 0.0 {
+            SurfaceType::Diffuse
+        } else {
+            // Phong指数按alpha = sqrt(2 / (Ns + 2))换算，GGX里alpha = roughness²；
+            // Ks当作垂直入射的反射率，Principled里F0 = 0.08 * specular
+            let alpha = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt();
+            SurfaceType::Principled(Principled {
+                roughness: alpha.sqrt(),
+                specular: self.specular.luminance().min(1.0) / 0.08,
+                transmission,
+                ior: self.ior,
+                emission: self.emission,
+                ..Principled::default()
+            })
+        };
+        Ok(Material {
+            color,
+            albedo: 1.0,
+            surface,
+            clearcoat: None,
+            shadow_catcher: false,
+            opacity: match &self.dissolve_map {
+                Some(path) => Some(Opacity {
+                    map: Coloration::Texture(
+                        Texture::open(path)?.with_color_space(ColorSpace::Linear),
+                    ),
+                    alpha: false,
+                    threshold: None,
+                }),
+                None => None,
+            },
+        })
+    }
+}
+
+fn parse_color(words: &[&str]) -> Result<Color> {
+    let values = words
+        .iter()
+        .map(|w| w.parse::<f32>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::parse("bad MTL color"))?;
+    match values.as_slice() {
+        [r, g, b, ..] => Ok(Color {
+            r: *r,
+            g: *g,
+            b: *b,
+        }),
+        // 只写一个数时三个通道一样
+        [v] => Ok(Color {
+            r: *v,
+            g: *v,
+            b: *v,
+        }),
+        _ => Err(Error::parse("bad MTL color")),
+    }
+}
+
+fn parse_float(words: &[&str]) -> Result<f32> {
+    words
+        .first()
+        .and_then(|w| w.parse().ok())
+        .ok_or_else(|| Error::parse("bad MTL value"))
+}
+
+/// 读MTL文件，返回(材质名, 材质)；贴图路径相对MTL文件所在的目录，同一张图只读一次
+pub fn load_mtl(path: &Path) -> Result<Vec<(String, Material)>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    fs::read_to_string(path)
+        .map_err(Error::from)
+        .and_then(|text| parse_mtl(&text, dir))
+        .map_err(|e| e.in_file(path))
+}
+
+fn parse_mtl(text: &str, dir: &Path) -> Result<Vec<(String, Material)>> {
+    let mut entries: Vec<MtlEntry> = Vec::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (key, args) = match words.split_first() {
+            Some((key, args)) if !key.starts_with('#') => (*key, args),
+            _ => continue,
+        };
+        if key == "newmtl" {
+            entries.push(MtlEntry::new(&args.join(" ")));
+            continue;
+        }
+        let entry = match entries.last_mut() {
+            Some(entry) => entry,
+            None => continue,
+        };
+        match key {
+            "Kd" => entry.diffuse = parse_color(args)?,
+            "Ks" => entry.specular = parse_color(args)?,
+            "Ke" => entry.emission = parse_color(args)?,
+            "Ns" => entry.shininess = parse_float(args)?,
+            "Ni" => entry.ior = parse_float(args)?,
+            "d" => entry.dissolve = parse_float(args)?,
+            "Tr" => entry.dissolve = 1.0 - parse_float(args)?,
+            // 贴图的选项（-s、-o这些）不支持，文件名取最后一个
+            "map_Kd" => entry.diffuse_map = args.last().map(|file| dir.join(file)),
+            "map_d" => entry.dissolve_map = args.last().map(|file| dir.join(file)),
+            _ => {}
+        }
+    }
+    entries
+        .iter()
+        .map(|entry| Ok((entry.name.clone(), entry.to_material()?)))
+        .collect()
+}
@@ -0,0 +1,186 @@
+use super::{mtl, Mesh, MeshData};
+use crate::color::Color;
+use crate::math::{Float, Point, Vector3};
+use crate::scene::material::{Coloration, Material, MaterialRegistry, SurfaceType};
+use crate::{Error, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// 一个材质对应的那部分网格，OBJ里的v/vt/vn是分开编号的，
+/// 这里把用到的每一组(v, vt, vn)展开成一个顶点
+#[derive(Default)]
+struct Part {
+    data: MeshData,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    normals: Vec<Vector3>,
+    uvs: Vec<(f32, f32)>,
+    has_normals: bool,
+    has_uvs: bool,
+}
+
+/// OBJ的下标从1开始，负数是从末尾往前数
+pub(super) fn resolve(index: &str, len: usize) -> Result<usize> {
+    let i: i64 = index
+        .parse()
+        .map_err(|_| Error::parse("bad OBJ face index"))?;
+    let resolved = if i < 0 { len as i64 + i } else { i - 1 };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(Error::parse("OBJ face index out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+pub(super) fn parse_floats<const N: usize>(args: &[&str]) -> Result<[Float; N]> {
+    let mut values = [0.0; N];
+    for (value, arg) in values.iter_mut().zip(args) {
+        *value = arg.parse().map_err(|_| Error::parse("bad OBJ number"))?;
+    }
+    if args.len() < N {
+        return Err(Error::parse("too few numbers in OBJ line"));
+    }
+    Ok(values)
+}
+
+/// 读OBJ文件，mtllib里的材质注册到materials里，名字前面加上OBJ的文件名：
+/// teapot.obj里的metal注册成teapot/metal，不会盖掉场景里同名的材质。
+/// 每个用到的材质出一个Mesh；usemtl先找自己MTL里的，再找场景里的，都没有或者没有指定材质的面
+/// 用灰色的漫反射。mtllib读不出来时打印警告，那些面也用灰色的漫反射。
+/// smooth_normals为true时忽略文件里的vn，重新算光滑的顶点法线
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    materials: &mut MaterialRegistry,
+    smooth_normals: bool,
+) -> Result<Vec<Mesh>> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    fs::read_to_string(path)
+        .map_err(Error::from)
+        .and_then(|text| parse_obj(&text, dir, &stem, materials, smooth_normals))
+        .map_err(|e| e.in_file(path))
+}
+
+fn parse_obj(
+    text: &str,
+    dir: &Path,
+    stem: &str,
+    materials: &mut MaterialRegistry,
+    smooth_normals: bool,
+) -> Result<Vec<Mesh>> {
+    let namespaced = |name: &str| format!("{}/{}", stem, name);
+    let mut positions: Vec<Point> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut uvs: Vec<(f32, f32)> = Vec::new();
+    // 按第一次出现的顺序保存各个材质的部分
+    let mut parts: Vec<(Option<String>, Part)> = Vec::new();
+    let mut current: Option<String> = None;
+
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (key, args) = match words.split_first() {
+            Some((key, args)) => (*key, args),
+            None => continue,
+        };
+        match key {
+            "v" => {
+                let [x, y, z] = parse_floats::<3>(args)?;
+                positions.push(Point::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats::<3>(args)?;
+                normals.push(Vector3::new(x, y, z));
+            }
+            "vt" => {
+                let [u, v] = parse_floats::<2>(args)?;
+                // OBJ的v朝上，图片的行是从上往下数的
+                uvs.push((u as f32, 1.0 - v as f32));
+            }
+            "mtllib" => {
+                for file in args {
+                    match mtl::load_mtl(&dir.join(file)) {
+                        Ok(entries) => {
+                            for (name, material) in entries {
+                                materials.insert(&namespaced(&name), material);
+                            }
+                        }
+                        Err(e) => eprintln!("warning: {}", e),
+                    }
+                }
+            }
+            "usemtl" => current = Some(args.join(" ")),
+            "f" => {
+                let part = match parts.iter().position(|(name, _)| *name == current) {
+                    Some(i) => &mut parts[i].1,
+                    None => {
+                        parts.push((current.clone(), Part::default()));
+                        &mut parts.last_mut().unwrap().1
+                    }
+                };
+                let mut face = Vec::with_capacity(args.len());
+                for arg in args {
+                    let mut fields = arg.split('/');
+                    let v = resolve(fields.next().unwrap_or(""), positions.len())?;
+                    let vt = match fields.next() {
+                        Some(s) if !s.is_empty() => Some(resolve(s, uvs.len())?),
+                        _ => None,
+                    };
+                    let vn = match fields.next() {
+                        Some(s) if !s.is_empty() => Some(resolve(s, normals.len())?),
+                        _ => None,
+                    };
+                    let next = part.data.positions.len();
+                    let index = *part.vertices.entry((v, vt, vn)).or_insert(next);
+                    if index == next {
+                        part.data.positions.push(positions[v]);
+                        part.normals
+                            .push(vn.map_or(Vector3::zero(), |vn| normals[vn]));
+                        part.uvs.push(vt.map_or((0.0, 0.0), |vt| uvs[vt]));
+                        part.has_normals |= vn.is_some();
+                        part.has_uvs |= vt.is_some();
+                    }
+                    face.push(index);
+                }
+                for i in 1..face.len().saturating_sub(1) {
+                    part.data.triangles.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let default_material = Arc::new(Material {
+        color: Coloration::Color(Color {
+            r: 0.8,
+            g: 0.8,
+            b: 0.8,
+        }),
+        albedo: 1.0,
+        surface: SurfaceType::Diffuse,
+        clearcoat: None,
+        shadow_catcher: false,
+        opacity: None,
+    });
+    Ok(parts
+        .into_iter()
+        .map(|(name, mut part)| {
+            if part.has_uvs {
+                part.data.uvs = Some(part.uvs);
+            }
+            if smooth_normals {
+                part.data = part.data.with_smooth_normals();
+            } else if part.has_normals {
+                part.data.normals = Some(part.normals);
+            }
+            let material = name
+                .and_then(|name| {
+                    materials
+                        .get(&namespaced(&name))
+                        .or_else(|| materials.get(&name))
+                })
+                .unwrap_or_else(|| default_material.clone());
+            Mesh::new(part.data, material)
+        })
+        .collect())
+}
@@ -0,0 +1,298 @@
+use super::MeshData;
+use crate::color::Color;
+use crate::math::{Float, Point, Vector3};
+use crate::{Error, Result};
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return Err(Error::parse("unknown PLY property type")),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    /// 列表属性的长度类型
+    count: Option<Scalar>,
+    scalar: Scalar,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// 按格式一个一个往外读值，ascii按空白切分，二进制按类型宽度读
+struct Reader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64> {
+        if self.format == Format::Ascii {
+            let rest = &self.bytes[self.offset..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or_else(|| Error::parse("PLY data ended early"))?;
+            let len = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.offset += start + len;
+            return std::str::from_utf8(&rest[start..start + len])
+                .ok()
+                .and_then(|s| s.parse::<f64>().ok())
+                .ok_or_else(|| Error::parse("bad PLY value"));
+        }
+        let size = scalar.size();
+        let b = self
+            .bytes
+            .get(self.offset..self.offset + size)
+            .ok_or_else(|| Error::parse("PLY data ended early"))?;
+        self.offset += size;
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(b);
+        if self.format == Format::BigEndian {
+            buf[..size].reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => buf[0] as i8 as f64,
+            Scalar::U8 => buf[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(buf),
+        })
+    }
+
+    /// 读一个属性，普通属性得到一个值，列表属性得到整个列表
+    fn read_property(&mut self, property: &Property) -> Result<Vec<f64>> {
+        match property.count {
+            None => Ok(vec![self.read(property.scalar)?]),
+            Some(count) => {
+                let n = self.read(count)? as usize;
+                (0..n).map(|_| self.read(property.scalar)).collect()
+            }
+        }
+    }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize)> {
+    if !bytes.starts_with(b"ply") {
+        return Err(Error::parse("not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+    for line in bytes.split(|b| *b == b'\n') {
+        offset += line.len() + 1;
+        let line = String::from_utf8_lossy(line);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => {
+                let format = format.ok_or_else(|| Error::parse("PLY file has no format"))?;
+                return Ok((format, elements, offset));
+            }
+            ["format", name, ..] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(Error::parse("unknown PLY format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| Error::parse("bad PLY element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, scalar, name] => elements
+                .last_mut()
+                .ok_or_else(|| Error::parse("PLY property outside an element"))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    count: Some(Scalar::parse(count)?),
+                    scalar: Scalar::parse(scalar)?,
+                }),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or_else(|| Error::parse("PLY property outside an element"))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    count: None,
+                    scalar: Scalar::parse(scalar)?,
+                }),
+            _ => {}
+        }
+    }
+    Err(Error::parse("PLY header has no end_header"))
+}
+
+impl MeshData {
+    /// 读PLY文件（ascii和两种二进制），顶点支持位置、法线、颜色和uv，
+    /// 多边形面按扇形拆成三角形，其它element直接跳过
+    pub fn load_ply<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read(path)
+            .map_err(Error::from)
+            .and_then(|bytes| Self::parse_ply(&bytes))
+            .map_err(|e| e.in_file(path))
+    }
+
+    fn parse_ply(bytes: &[u8]) -> Result<Self> {
+        let (format, elements, offset) = parse_header(bytes)?;
+        let mut reader = Reader {
+            format,
+            bytes,
+            offset,
+        };
+        let mut mesh = MeshData::default();
+        let mut normals = Vec::new();
+        let mut colors = Vec::new();
+        let mut uvs = Vec::new();
+        for element in &elements {
+            let find = |names: &[&str]| {
+                element
+                    .properties
+                    .iter()
+                    .position(|p| names.contains(&p.name.as_str()))
+            };
+            let xyz = [find(&["x"]), find(&["y"]), find(&["z"])];
+            let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+            let rgb = [
+                find(&["red", "r"]),
+                find(&["green", "g"]),
+                find(&["blue", "b"]),
+            ];
+            let uv = [
+                find(&["u", "s", "texture_u"]),
+                find(&["v", "t", "texture_v"]),
+            ];
+            let indices = find(&["vertex_indices", "vertex_index"]);
+            for _ in 0..element.count {
+                let values = element
+                    .properties
+                    .iter()
+                    .map(|p| reader.read_property(p))
+                    .collect::<Result<Vec<_>>>()?;
+                // 列表属性取第一个值，空列表是错误
+                let value = |i: usize| {
+                    values[i]
+                        .first()
+                        .copied()
+                        .ok_or_else(|| Error::parse("PLY vertex property is an empty list"))
+                };
+                let get = |i: [Option<usize>; 3]| -> Result<Option<[f64; 3]>> {
+                    match i {
+                        [Some(a), Some(b), Some(c)] => Ok(Some([value(a)?, value(b)?, value(c)?])),
+                        _ => Ok(None),
+                    }
+                };
+                match element.name.as_str() {
+                    "vertex" => {
+                        let p = get(xyz)?.ok_or_else(|| Error::parse("PLY vertex has no x/y/z"))?;
+                        mesh.positions.push(Point::new(
+                            p[0] as Float,
+                            p[1] as Float,
+                            p[2] as Float,
+                        ));
+                        if let Some(n) = get(normal)? {
+                            normals.push(Vector3::new(n[0] as Float, n[1] as Float, n[2] as Float));
+                        }
+                        if let Some(c) = get(rgb)? {
+                            let is_byte =
+                                matches!(element.properties[rgb[0].unwrap()].scalar, Scalar::U8);
+                            colors.push(if is_byte {
+                                Color::from_rgba8([c[0] as u8, c[1] as u8, c[2] as u8, 255])
+                            } else {
+                                Color {
+                                    r: c[0] as f32,
+                                    g: c[1] as f32,
+                                    b: c[2] as f32,
+                                }
+                            });
+                        }
+                        if let [Some(u), Some(v)] = uv {
+                            uvs.push((value(u)? as f32, value(v)? as f32));
+                        }
+                    }
+                    "face" => {
+                        let face = &values[indices
+                            .ok_or_else(|| Error::parse("PLY face has no vertex_indices"))?];
+                        // 负数转成usize会变成0，不能悄悄接受
+                        if face.iter().any(|&i| i < 0.0 || i.fract() != 0.0) {
+                            return Err(Error::parse(
+                                "PLY face index is not a non-negative integer",
+                            ));
+                        }
+                        for i in 1..face.len().saturating_sub(1) {
+                            mesh.triangles.push([
+                                face[0] as usize,
+                                face[i] as usize,
+                                face[i + 1] as usize,
+                            ]);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let vertex_count = mesh.positions.len();
+        if mesh.triangles.iter().flatten().any(|&i| i >= vertex_count) {
+            return Err(Error::parse("PLY face index out of range"));
+        }
+        mesh.normals = Some(normals).filter(|n| n.len() == vertex_count && vertex_count > 0);
+        mesh.colors = Some(colors).filter(|c| c.len() == vertex_count && vertex_count > 0);
+        mesh.uvs = Some(uvs).filter(|uv| uv.len() == vertex_count && vertex_count > 0);
+        Ok(mesh)
+    }
+}
@@ -0,0 +1,219 @@
+#[cfg(feature = "fs")]
+use super::obj::{parse_floats, resolve};
+use super::MeshData;
+use crate::math::{Float, Point, Vector3};
+#[cfg(feature = "fs")]
+use crate::{Error, Result};
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::path::Path;
+
+/// 多边形的控制网格，每个面是按顺序排的顶点下标，一般都是四边形。
+/// 只有位置，细分出来的网格法线重新算、没有uv
+#[derive(Default, Clone)]
+pub struct PolygonMesh {
+    pub positions: Vec<Point>,
+    pub faces: Vec<Vec<usize>>,
+}
+
+/// 点的平均值
+fn average(points: impl Iterator<Item = Point>) -> Point {
+    let mut sum = Vector3::zero();
+    let mut count = 0;
+    for p in points {
+        sum = sum + (p - Point::zero());
+        count += 1;
+    }
+    Point::zero() + sum * (1.0 / count.max(1) as Float)
+}
+
+/// 一条边：两个端点（小的在前）和两边的面，边界上的边只有一个面
+struct Edge {
+    ends: (usize, usize),
+    faces: Vec<usize>,
+}
+
+impl PolygonMesh {
+    /// 读OBJ里的v和f当控制网格，其它行都不管
+    #[cfg(feature = "fs")]
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map_err(Error::from)
+            .and_then(|text| Self::parse_obj(&text))
+            .map_err(|e| e.in_file(path))
+    }
+
+    #[cfg(feature = "fs")]
+    fn parse_obj(text: &str) -> Result<Self> {
+        let mut mesh = Self::default();
+        for line in text.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.split_first() {
+                Some((&"v", args)) => {
+                    let [x, y, z] = parse_floats::<3>(args)?;
+                    mesh.positions.push(Point::new(x, y, z));
+                }
+                Some((&"f", args)) => {
+                    let face = args
+                        .iter()
+                        .map(|arg| {
+                            resolve(arg.split('/').next().unwrap_or(""), mesh.positions.len())
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    if face.len() < 3 {
+                        return Err(Error::parse("OBJ face has fewer than 3 vertices"));
+                    }
+                    mesh.faces.push(face);
+                }
+                _ => {}
+            }
+        }
+        Ok(mesh)
+    }
+
+    /// Catmull-Clark细分一次，结果全是四边形。边界上的边和顶点按折痕的规则处理，
+    /// 开口的网格边缘不会缩进去
+    pub fn subdivide(&self) -> PolygonMesh {
+        let vertex_count = self.positions.len();
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut edge_index: HashMap<(usize, usize), usize> = HashMap::new();
+        // 每个面的第i条边是顶点i到i+1
+        let mut face_edges: Vec<Vec<usize>> = Vec::with_capacity(self.faces.len());
+        for (f, face) in self.faces.iter().enumerate() {
+            let mut indices = Vec::with_capacity(face.len());
+            for i in 0..face.len() {
+                let (a, b) = (face[i], face[(i + 1) % face.len()]);
+                let ends = (a.min(b), a.max(b));
+                let e = *edge_index.entry(ends).or_insert_with(|| {
+                    edges.push(Edge {
+                        ends,
+                        faces: Vec::new(),
+                    });
+                    edges.len() - 1
+                });
+                edges[e].faces.push(f);
+                indices.push(e);
+            }
+            face_edges.push(indices);
+        }
+
+        let face_points: Vec<Point> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|&v| self.positions[v])))
+            .collect();
+        let midpoint = |edge: &Edge| {
+            average(
+                [self.positions[edge.ends.0], self.positions[edge.ends.1]]
+                    .iter()
+                    .copied(),
+            )
+        };
+        let edge_points: Vec<Point> = edges
+            .iter()
+            .map(|edge| match edge.faces.as_slice() {
+                &[f0, f1] => average(
+                    [
+                        self.positions[edge.ends.0],
+                        self.positions[edge.ends.1],
+                        face_points[f0],
+                        face_points[f1],
+                    ]
+                    .iter()
+                    .copied(),
+                ),
+                _ => midpoint(edge),
+            })
+            .collect();
+
+        // 每个顶点连着的边和面
+        let mut vertex_edges: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+        for (e, edge) in edges.iter().enumerate() {
+            vertex_edges[edge.ends.0].push(e);
+            vertex_edges[edge.ends.1].push(e);
+        }
+        let mut vertex_faces: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+        for (f, face) in self.faces.iter().enumerate() {
+            for &v in face {
+                vertex_faces[v].push(f);
+            }
+        }
+        let vertex_points = (0..vertex_count).map(|v| {
+            let p = self.positions[v];
+            let boundary: Vec<&Edge> = vertex_edges[v]
+                .iter()
+                .map(|&e| &edges[e])
+                .filter(|edge| edge.faces.len() != 2)
+                .collect();
+            match boundary.as_slice() {
+                // 没用到的顶点和内部的顶点
+                [] if vertex_faces[v].is_empty() => p,
+                [] => {
+                    let n = vertex_faces[v].len() as Float;
+                    let f = average(vertex_faces[v].iter().map(|&f| face_points[f]));
+                    let r = average(vertex_edges[v].iter().map(|&e| midpoint(&edges[e])));
+                    let sum = (f - Point::zero())
+                        + (r - Point::zero()) * 2.0
+                        + (p - Point::zero()) * (n - 3.0);
+                    Point::zero() + sum * (1.0 / n)
+                }
+                // 边界上的顶点只看两条边界边的另一头：(6P + a + b) / 8
+                [a, b] => {
+                    let other = |edge: &Edge| {
+                        let (x, y) = edge.ends;
+                        self.positions[if x == v { y } else { x }]
+                    };
+                    let sum = (p - Point::zero()) * 6.0
+                        + (other(a) - Point::zero())
+                        + (other(b) - Point::zero());
+                    Point::zero() + sum * 0.125
+                }
+                // 非流形的顶点不动
+                _ => p,
+            }
+        });
+
+        let mut positions: Vec<Point> = vertex_points.collect();
+        positions.extend(edge_points);
+        positions.extend(face_points);
+        let edge_base = vertex_count;
+        let face_base = vertex_count + edges.len();
+        let mut faces = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            let n = face.len();
+            for i in 0..n {
+                let previous = face_edges[f][(i + n - 1) % n];
+                faces.push(vec![
+                    face[i],
+                    edge_base + face_edges[f][i],
+                    face_base + f,
+                    edge_base + previous,
+                ]);
+            }
+        }
+        PolygonMesh { positions, faces }
+    }
+
+    /// 细分levels次再拆成三角形，顶点法线是光滑的
+    pub fn catmull_clark(&self, levels: usize) -> MeshData {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = mesh.subdivide();
+        }
+        let mut triangles = Vec::new();
+        for face in &mesh.faces {
+            for i in 1..face.len().saturating_sub(1) {
+                triangles.push([face[0], face[i], face[i + 1]]);
+            }
+        }
+        MeshData {
+            positions: mesh.positions,
+            triangles,
+            ..MeshData::default()
+        }
+        .with_smooth_normals()
+    }
+}
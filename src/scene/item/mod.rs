@@ -1,5 +1,39 @@
+mod bezier;
+mod capsule;
+mod curves;
+mod ellipsoid;
+mod heightfield;
+mod instance;
+mod mesh;
+mod moving;
+mod oriented;
 mod plane;
+mod point_cloud;
+mod quad;
+mod rounded_box;
+pub mod sdf;
 mod sphere;
+mod sphere_group;
+mod tagged;
+mod volume;
 
+pub use bezier::{BezierPatch, BezierSurface};
+pub use capsule::Capsule;
+pub use curves::{Curves, Strand};
+pub use ellipsoid::Ellipsoid;
+pub use heightfield::Heightfield;
+pub use instance::Instance;
+#[cfg(feature = "fs")]
+pub use mesh::load_obj;
+pub use mesh::{Mesh, MeshData, PolygonMesh};
+pub use moving::Moving;
+pub use oriented::Oriented;
 pub use plane::Plane;
+pub use point_cloud::{PointCloud, Surfel};
+pub use quad::Quad;
+pub use rounded_box::RoundedBox;
+pub use sdf::SdfItem;
 pub use sphere::Sphere;
+pub use sphere_group::SphereGroup;
+pub use tagged::Tagged;
+pub use volume::{DensityGrid, RawFormat, Volume, VolumeEmission};
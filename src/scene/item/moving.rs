@@ -0,0 +1,65 @@
+use crate::math::{Aabb, Float, Point, Transform, Vector3};
+use crate::rendering::{local_ray, Intersectable, Intersection, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 随时间运动的物体：item在自己的局部坐标系里，
+/// t = 0时用start变换到世界坐标，t = 1时用end，中间线性插值。
+/// 体积按世界坐标做delta tracking，不能放进来
+pub struct Moving {
+    pub item: Box<dyn Intersectable + Send + Sync>,
+    pub start: Transform,
+    pub end: Transform,
+}
+
+impl Moving {
+    pub fn transform_at(&self, time: Float) -> Transform {
+        self.start.lerp(&self.end, time)
+    }
+}
+
+impl Intersectable for Moving {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.intersect_hit(ray).map(|hit| hit.distance)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let to_world = self.transform_at(ray.time).affine();
+        let (local, scale) = local_ray(ray, &to_world.inverse());
+        self.item
+            .intersect_hit(&local)
+            .map(|hit| hit.transformed(&to_world, scale))
+    }
+
+    /// 旋转时中间时刻可能比两头更靠外，多取几个时刻包起来
+    fn bounds(&self) -> Option<Aabb> {
+        let local = self.item.bounds()?;
+        (0..=4)
+            .map(|i| local.transformed(&self.transform_at(i as Float / 4.0).affine()))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    // 下面几个只在直接拿Moving着色时用到，按t = 0的位置算
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let transform = self.start.affine();
+        let local = transform.inverse().point(hit_point);
+        transform.normal(&self.item.surface_normal(&local))
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let local = self.start.affine().inverse().point(hit_point);
+        self.item.texture_coords(&local)
+    }
+
+    fn get_material(&self) -> &Material {
+        self.item.get_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.transform("start", &self.start);
+        report.transform("end", &self.end);
+        self.item.validate(report);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::math::{Aabb, Point, Vector3};
+use crate::overlay::BoundsBox;
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    item::Volume,
+    light::EmissiveSurface,
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 改物体正反面的设置：求交转给item，再把设置记到交点上，和Tagged一样放进加速结构以后还在。
+/// flip_normals把法线整个反过来，给导出时朝里的网格用；cull_backfaces时相机看不到背面，
+/// 只有一面的网格从背后看是空的，也可以当成相机看不见、但还挡光的墙
+pub struct Oriented {
+    pub item: Box<dyn Intersectable + Send + Sync>,
+    pub flip_normals: bool,
+    pub cull_backfaces: bool,
+}
+
+impl Intersectable for Oriented {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.item.intersect(ray)
+    }
+
+    /// 嵌套的时候两次翻转抵消，剔除只要有一层要就剔除
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.item.intersect_hit(ray).map(|mut hit| {
+            hit.reversed ^= self.flip_normals;
+            hit.cull_backface |= self.cull_backfaces;
+            hit
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.item.bounds()
+    }
+
+    fn volume(&self) -> Option<&Volume> {
+        self.item.volume()
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let normal = self.item.surface_normal(hit_point);
+        if self.flip_normals {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.item.texture_coords(hit_point)
+    }
+
+    fn get_material(&self) -> &Material {
+        self.item.get_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        self.item.validate(report);
+    }
+
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        self.item.caustic_bounds(out);
+    }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        self.item.emissive_surfaces(out);
+    }
+
+    fn collect_bounds(&self, out: &mut Vec<BoundsBox>) {
+        self.item.collect_bounds(out);
+    }
+}
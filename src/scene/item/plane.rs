@@ -1,24 +1,47 @@
+use std::sync::Arc;
+
 use crate::math::{Point, Vector3};
-use crate::rendering::{Intersectable, Ray};
+use crate::rendering::{Backface, Intersectable, Ray};
 use crate::scene::{
     material::{Material, TextureCoords},
-    Distance,
+    Distance, Validation,
 };
 
+/// 从pos所在的一侧看过去，normal朝着远离观察者的方向
 #[derive(Clone)]
 pub struct Plane {
     pub pos: Point,
     pub normal: Vector3,
-    pub material: Material,
+    pub material: Arc<Material>,
+    /// 背面也看得到，着色时法线翻过来；否则从背面看是透明的
+    pub two_sided: bool,
+}
+
+impl Plane {
+    fn x_axis(&self) -> Vector3 {
+        let mut x_axis = self.normal.cross(&Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        });
+        if x_axis.length() == 0.0 {
+            x_axis = self.normal.cross(&Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            });
+        }
+        x_axis
+    }
 }
 
 impl Intersectable for Plane {
     fn intersect(&self, ray: &Ray) -> Option<Distance> {
         let normal = &self.normal;
         let denom = normal.dot(&ray.direction);
-        if denom > 1e-6 {
+        if denom > 1e-6 || (self.two_sided && denom < -1e-6) {
             let v = self.pos - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
             if distance >= 0.0 {
                 return Some(distance);
             }
@@ -31,18 +54,7 @@ impl Intersectable for Plane {
     }
 
     fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
-        let mut x_axis = self.normal.cross(&Vector3 {
-            x: 0.0,
-            y: 0.0,
-            z: 1.0,
-        });
-        if x_axis.length() == 0.0 {
-            x_axis = self.normal.cross(&Vector3 {
-                x: 0.0,
-                y: 1.0,
-                z: 0.0,
-            });
-        }
+        let x_axis = self.x_axis();
         let y_axis = self.normal.cross(&x_axis);
 
         let p: Vector3 = *hit_point - self.pos;
@@ -53,7 +65,25 @@ impl Intersectable for Plane {
         }
     }
 
+    fn tangent(&self, _hit_point: &Point) -> Vector3 {
+        self.x_axis().normalize()
+    }
+
     fn get_material(&self) -> &Material {
         &self.material
     }
+
+    fn backface(&self) -> Backface {
+        if self.two_sided {
+            Backface::Flip
+        } else {
+            Backface::Cull
+        }
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("plane position", &self.pos);
+        report.unit("plane normal", &self.normal);
+        report.material(&self.material);
+    }
 }
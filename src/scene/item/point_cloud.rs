@@ -0,0 +1,215 @@
+#[cfg(feature = "fs")]
+use super::MeshData;
+#[cfg(feature = "fs")]
+use crate::Result;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::accel::{Accelerator, Bvh, Item};
+use crate::bsdf::orthonormal_basis;
+use crate::color::Color;
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Backface, Intersectable, Intersection, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 光线的起点离面元的平面不到半径的这么多倍、又在它的圆盘范围里时，
+/// 当成是从旁边重叠的面元上出发的，不算打中
+const OVERLAP: Float = 0.5;
+
+/// 点云里的一个点。有法线时是朝着法线的圆盘（面元），没有时是一个小球；
+/// color是扫描出来的颜色，没有的话用材质的颜色
+#[derive(Clone)]
+pub struct Surfel {
+    pub position: Point,
+    pub normal: Option<Vector3>,
+    pub radius: Distance,
+    pub color: Option<Color>,
+}
+
+struct Shared {
+    surfels: Vec<Surfel>,
+    material: Arc<Material>,
+}
+
+struct Splat {
+    cloud: Arc<Shared>,
+    index: usize,
+}
+
+impl Splat {
+    fn surfel(&self) -> &Surfel {
+        &self.cloud.surfels[self.index]
+    }
+}
+
+impl Intersectable for Splat {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let s = self.surfel();
+        let to_origin = ray.origin - s.position;
+        match s.normal {
+            Some(normal) => {
+                let height = to_origin.dot(&normal);
+                let lateral = to_origin - normal * height;
+                // 面元之间是互相重叠的，从一片上出发的光线很容易打中旁边那片
+                if height.abs() < s.radius * OVERLAP && lateral.length() < s.radius {
+                    return None;
+                }
+                let denominator = ray.direction.dot(&normal);
+                if denominator == 0.0 {
+                    return None;
+                }
+                let t = -height / denominator;
+                let p = ray.origin + ray.direction * t;
+                (t >= 0.0 && (p - s.position).length() <= s.radius).then_some(t)
+            }
+            None => {
+                // 小球：从球里面出发的就是从这个点上出发的
+                if to_origin.length() < s.radius {
+                    return None;
+                }
+                let projection = -to_origin.dot(&ray.direction);
+                let d2 = to_origin.dot(&to_origin) - projection * projection;
+                let r2 = s.radius * s.radius;
+                if d2 > r2 {
+                    return None;
+                }
+                let t = projection - (r2 - d2).sqrt();
+                (t >= 0.0).then_some(t)
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let s = self.surfel();
+        let r = Vector3::new(s.radius, s.radius, s.radius);
+        Some(Aabb::new(s.position - r, s.position + r))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let s = self.surfel();
+        s.normal
+            .unwrap_or_else(|| (*hit_point - s.position).normalize())
+    }
+
+    /// 点云没有纹理坐标
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        TextureCoords { u: 0.0, v: 0.0 }
+    }
+
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        orthonormal_basis(&self.surface_normal(hit_point)).0
+    }
+
+    fn vertex_color(&self, _hit_point: &Point) -> Option<Color> {
+        self.surfel().color
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.cloud.material
+    }
+
+    /// 扫描出来的法线朝向常常不统一，圆盘两面都看得到
+    fn backface(&self) -> Backface {
+        match self.surfel().normal {
+            Some(_) => Backface::Flip,
+            None => Backface::Keep,
+        }
+    }
+}
+
+/// 点云，每个点画成一个小圆盘或者小球，半径要大到相邻的点互相盖住才看不出缝。
+/// 用来直接看激光雷达、三维扫描的数据
+pub struct PointCloud {
+    shared: Arc<Shared>,
+    bvh: Bvh,
+}
+
+impl PointCloud {
+    pub fn new(surfels: Vec<Surfel>, material: Arc<Material>) -> Self {
+        let shared = Arc::new(Shared { surfels, material });
+        let splats = (0..shared.surfels.len())
+            .map(|index| {
+                Box::new(Splat {
+                    cloud: shared.clone(),
+                    index,
+                }) as Item
+            })
+            .collect();
+        Self {
+            shared,
+            bvh: Bvh::build(splats),
+        }
+    }
+
+    /// 读PLY里的顶点当成点云，面都不要；法线和颜色有就用，所有点一样大
+    #[cfg(feature = "fs")]
+    pub fn load_ply<P: AsRef<Path>>(
+        path: P,
+        radius: Distance,
+        material: Arc<Material>,
+    ) -> Result<Self> {
+        let data = MeshData::load_ply(path)?;
+        let surfels = data
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| Surfel {
+                position,
+                normal: data
+                    .normals
+                    .as_ref()
+                    .map(|n| n[i])
+                    .filter(|n| n.length() > 0.0)
+                    .map(|n| n.normalize()),
+                radius,
+                color: data.colors.as_ref().map(|c| c[i]),
+            })
+            .collect();
+        Ok(Self::new(surfels, material))
+    }
+
+    pub fn surfels(&self) -> &[Surfel] {
+        &self.shared.surfels
+    }
+}
+
+impl Intersectable for PointCloud {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.bvh.intersect(ray)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.bvh.intersect_hit(ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        unreachable!("点云求交返回的是里面的点，不会拿它本身着色")
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        unreachable!("点云求交返回的是里面的点，不会拿它本身着色")
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.shared.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        for s in &self.shared.surfels {
+            report.point("point cloud position", &s.position);
+            if let Some(normal) = &s.normal {
+                report.unit("point cloud normal", normal);
+            }
+            report.positive("point cloud radius", s.radius, false);
+        }
+        report.material(&self.shared.material);
+    }
+}
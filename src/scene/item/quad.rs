@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Backface, Intersectable, Ray};
+use crate::scene::{
+    light::{EmissiveShape, EmissiveSurface, Portal},
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// corner和两条边edge_u、edge_v围成的平行四边形，两条边垂直时就是矩形。
+/// 法线是edge_u × edge_v的方向，没有里外之分，两面都看得到。
+/// 纹理坐标沿两条边从corner的(0, 0)到对角的(1, 1)
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub material: Arc<Material>,
+}
+
+impl Quad {
+    /// 点在两条边上的坐标，点在四边形的平面上时才有意义
+    fn uv(&self, p: &Vector3) -> (Float, Float) {
+        let cross = self.edge_u.cross(&self.edge_v);
+        let area2 = cross.dot(&cross);
+        let u = p.cross(&self.edge_v).dot(&cross) / area2;
+        let v = self.edge_u.cross(p).dot(&cross) / area2;
+        (u, v)
+    }
+}
+
+impl Intersectable for Quad {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let cross = self.edge_u.cross(&self.edge_v);
+        let denom = cross.dot(&ray.direction);
+        if denom.abs() <= Float::EPSILON * cross.length() {
+            return None;
+        }
+        let distance = cross.dot(&(self.corner - ray.origin)) / denom;
+        if distance < 0.0 {
+            return None;
+        }
+        let p = ray.origin + ray.direction * distance - self.corner;
+        let (u, v) = self.uv(&p);
+        if (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v) {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let c = self.corner;
+        Some(Aabb::from_points(&[
+            c,
+            c + self.edge_u,
+            c + self.edge_v,
+            c + self.edge_u + self.edge_v,
+        ]))
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        self.edge_u.cross(&self.edge_v).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (u, v) = self.uv(&(*hit_point - self.corner));
+        TextureCoords {
+            u: u as f32,
+            v: v as f32,
+        }
+    }
+
+    fn tangent(&self, _hit_point: &Point) -> Vector3 {
+        self.edge_u.normalize()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn backface(&self) -> Backface {
+        Backface::Flip
+    }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        if let Some(radiance) = self.material.uniform_emission() {
+            out.push(EmissiveSurface {
+                shape: EmissiveShape::Quad(Portal {
+                    corner: self.corner,
+                    edge_u: self.edge_u,
+                    edge_v: self.edge_v,
+                }),
+                radiance,
+                object_id: None,
+            });
+        }
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("quad corner", &self.corner);
+        let area = self.edge_u.cross(&self.edge_v).length();
+        if report.finite("quad area", area) && area == 0.0 {
+            report.error("quad edges are parallel or zero, it has no area");
+        }
+        report.material(&self.material);
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use super::sdf::{round_box, sphere_trace};
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 离表面小于它就算打中
+const EPSILON: Float = 1e-4;
+const MAX_STEPS: usize = 256;
+
+/// 棱和角倒了圆角的长方体，和坐标轴对齐，half_extent是半边长，radius是圆角半径，
+/// 0就是直角的盒子。求交在sdf::round_box的距离场里步进，法线是距离场的解析梯度，
+/// 纹理坐标按法线最接近的那个面投影，每个面都是(0, 0)到(1, 1)
+#[derive(Clone)]
+pub struct RoundedBox {
+    pub center: Point,
+    pub half_extent: Vector3,
+    pub radius: Distance,
+    pub material: Arc<Material>,
+}
+
+impl RoundedBox {
+    /// 法线最接近的面的轴，和这个面上u、v的轴
+    fn face_axes(normal: &Vector3) -> (usize, usize, usize) {
+        let (x, y, z) = (normal.x.abs(), normal.y.abs(), normal.z.abs());
+        if x >= y && x >= z {
+            (0, 2, 1)
+        } else if y >= z {
+            (1, 0, 2)
+        } else {
+            (2, 0, 1)
+        }
+    }
+}
+
+impl Intersectable for RoundedBox {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        let bounds = self.bounds()?;
+        let distance = round_box(self.center, self.half_extent, self.radius);
+        sphere_trace(distance, &bounds, EPSILON, MAX_STEPS, ray)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // 往外多留一点，表面正好贴着盒子时步进也能停下来
+        let margin = Vector3::new(EPSILON, EPSILON, EPSILON) * 2.0;
+        Some(Aabb::new(
+            self.center - self.half_extent - margin,
+            self.center + self.half_extent + margin,
+        ))
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let d = *hit_point - self.center;
+        let q = |axis: usize| d.axis(axis).abs() - self.half_extent.axis(axis) + self.radius;
+        let sign = |axis: usize| d.axis(axis).signum();
+        let (qx, qy, qz) = (q(0), q(1), q(2));
+        if qx.max(qy).max(qz) > 0.0 {
+            // 在圆角外面那一层：指向最近的内盒上的点
+            Vector3::new(
+                qx.max(0.0) * sign(0),
+                qy.max(0.0) * sign(1),
+                qz.max(0.0) * sign(2),
+            )
+            .normalize()
+        } else if qx >= qy && qx >= qz {
+            Vector3::new(sign(0), 0.0, 0.0)
+        } else if qy >= qz {
+            Vector3::new(0.0, sign(1), 0.0)
+        } else {
+            Vector3::new(0.0, 0.0, sign(2))
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let d = *hit_point - self.center;
+        let (_, u, v) = Self::face_axes(&self.surface_normal(hit_point));
+        let along = |axis: usize| {
+            let h = self.half_extent.axis(axis);
+            ((d.axis(axis) + h) / (2.0 * h)) as f32
+        };
+        TextureCoords {
+            u: along(u),
+            v: along(v),
+        }
+    }
+
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        let normal = self.surface_normal(hit_point);
+        let (_, u, _) = Self::face_axes(&normal);
+        let mut axis = Vector3::zero();
+        match u {
+            0 => axis.x = 1.0,
+            1 => axis.y = 1.0,
+            _ => axis.z = 1.0,
+        }
+        (axis - normal * normal.dot(&axis)).normalize()
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("rounded box center", &self.center);
+        let h = &self.half_extent;
+        for size in [h.x, h.y, h.z] {
+            report.positive("rounded box half extent", size, false);
+        }
+        report.positive("rounded box radius", self.radius, true);
+        let smallest = h.x.min(h.y).min(h.z);
+        if self.radius > smallest {
+            report.error(format_args!(
+                "rounded box radius {} is larger than its smallest half extent {}",
+                self.radius, smallest
+            ));
+        }
+        report.material(&self.material);
+    }
+}
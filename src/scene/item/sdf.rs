@@ -0,0 +1,230 @@
+use std::sync::Arc;
+
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Ray};
+use crate::scene::{
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 有符号距离函数：外面是正的，里面是负的，绝对值不超过到表面的真实距离
+pub type DistanceFn = Box<dyn Fn(&Point) -> Float + Send + Sync>;
+
+/// 用有符号距离函数描述的形状，求交时在bounds里做sphere tracing，
+/// 法线取距离场的梯度。分形、圆滑的并集这些解析形状做不了的都能表示
+pub struct SdfItem {
+    pub distance: DistanceFn,
+    /// 形状必须整个在这个盒子里，光线只在盒子里步进
+    pub bounds: Aabb,
+    /// 离表面小于它就算打中，也是求梯度的步长
+    pub epsilon: Float,
+    pub max_steps: usize,
+    pub material: Arc<Material>,
+}
+
+impl SdfItem {
+    pub fn new(
+        distance: impl Fn(&Point) -> Float + Send + Sync + 'static,
+        bounds: Aabb,
+        material: Arc<Material>,
+    ) -> Self {
+        Self {
+            distance: Box::new(distance),
+            bounds,
+            epsilon: 1e-4,
+            max_steps: 512,
+            material,
+        }
+    }
+
+    pub fn with_precision(mut self, epsilon: Float, max_steps: usize) -> Self {
+        self.epsilon = epsilon;
+        self.max_steps = max_steps;
+        self
+    }
+}
+
+impl Intersectable for SdfItem {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        sphere_trace(
+            &self.distance,
+            &self.bounds,
+            self.epsilon,
+            self.max_steps,
+            ray,
+        )
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    /// 中心差分求梯度
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let h = self.epsilon;
+        let f = |dx: Float, dy: Float, dz: Float| {
+            (self.distance)(&(*hit_point + Vector3::new(dx, dy, dz)))
+        };
+        Vector3::new(
+            f(h, 0.0, 0.0) - f(-h, 0.0, 0.0),
+            f(0.0, h, 0.0) - f(0.0, -h, 0.0),
+            f(0.0, 0.0, h) - f(0.0, 0.0, -h),
+        )
+        .normalize()
+    }
+
+    /// 按bounds的中心做球面投影
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let p = (*hit_point - self.bounds.centroid()).normalize();
+        let phi = p.z.atan2(p.x);
+        let theta = p.y.clamp(-1.0, 1.0).acos();
+        TextureCoords {
+            u: (1.0 + phi) as f32 / std::f32::consts::PI * 0.5,
+            v: theta as f32 / std::f32::consts::PI,
+        }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.positive("sdf epsilon", self.epsilon, false);
+        if self.max_steps == 0 {
+            report.error("sdf max_steps is 0");
+        }
+        report.material(&self.material);
+    }
+}
+
+/// 在bounds里沿光线按距离场步进，离表面小于epsilon就算打中
+pub(crate) fn sphere_trace(
+    distance: impl Fn(&Point) -> Float,
+    bounds: &Aabb,
+    epsilon: Float,
+    max_steps: usize,
+    ray: &Ray,
+) -> Option<Distance> {
+    let (t_min, t_max) = bounds.hit(&ray.origin, &ray.direction)?;
+    let mut t = t_min;
+    // 从表面上出发的光线（反射、折射、阴影）一开始就在epsilon以内，
+    // 先一小步一小步离开表面，之后再碰到才算打中
+    let mut left_surface = false;
+    for _ in 0..max_steps {
+        if t > t_max {
+            return None;
+        }
+        // 取绝对值，光线在里面时（折射进去）也能走
+        let d = distance(&(ray.origin + ray.direction * t)).abs();
+        if d < epsilon {
+            if left_surface {
+                return Some(t);
+            }
+            t += epsilon;
+        } else {
+            left_surface = true;
+            t += d;
+        }
+    }
+    None
+}
+
+/// 球
+pub fn sphere(center: Point, radius: Float) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| (*p - center).length() - radius
+}
+
+/// 圆角的长方体，half_extent是半边长，radius是圆角半径（0就是直角）
+pub fn round_box(
+    center: Point,
+    half_extent: Vector3,
+    radius: Float,
+) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| {
+        let d = *p - center;
+        let q = Vector3::new(
+            d.x.abs() - half_extent.x + radius,
+            d.y.abs() - half_extent.y + radius,
+            d.z.abs() - half_extent.z + radius,
+        );
+        let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+        outside + q.x.max(q.y).max(q.z).min(0.0) - radius
+    }
+}
+
+/// 躺在xz平面上的圆环，major是环的半径，minor是管子的半径
+pub fn torus(center: Point, major: Float, minor: Float) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| {
+        let d = *p - center;
+        let ring = (d.x * d.x + d.z * d.z).sqrt() - major;
+        (ring * ring + d.y * d.y).sqrt() - minor
+    }
+}
+
+/// 圆滑的并集，k是过渡区域的大小，0就是普通的并集
+pub fn smooth_union(
+    a: impl Fn(&Point) -> Float + Send + Sync,
+    b: impl Fn(&Point) -> Float + Send + Sync,
+    k: Float,
+) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| {
+        let (da, db) = (a(p), b(p));
+        if k <= 0.0 {
+            return da.min(db);
+        }
+        let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+        db + (da - db) * h - k * h * (1.0 - h)
+    }
+}
+
+/// 差集，a里面挖掉b
+pub fn subtract(
+    a: impl Fn(&Point) -> Float + Send + Sync,
+    b: impl Fn(&Point) -> Float + Send + Sync,
+) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| a(p).max(-b(p))
+}
+
+/// 交集
+pub fn intersect(
+    a: impl Fn(&Point) -> Float + Send + Sync,
+    b: impl Fn(&Point) -> Float + Send + Sync,
+) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| a(p).max(b(p))
+}
+
+/// Mandelbulb分形，中心在center，大约占半径scale * 1.2的球；
+/// power一般是8，iterations越多细节越多
+pub fn mandelbulb(
+    center: Point,
+    scale: Float,
+    power: Float,
+    iterations: usize,
+) -> impl Fn(&Point) -> Float + Send + Sync {
+    move |p| {
+        let c = (*p - center) * (1.0 / scale);
+        let mut z = c;
+        let mut dr: Float = 1.0;
+        let mut r: Float = 0.0;
+        for _ in 0..iterations {
+            r = z.length();
+            if r > 2.0 {
+                break;
+            }
+            let theta = (z.z / r).acos() * power;
+            let phi = z.y.atan2(z.x) * power;
+            dr = r.powf(power - 1.0) * power * dr + 1.0;
+            let zr = r.powf(power);
+            z = Vector3::new(
+                theta.sin() * phi.cos(),
+                theta.sin() * phi.sin(),
+                theta.cos(),
+            ) * zr
+                + c;
+        }
+        if r == 0.0 {
+            return -scale;
+        }
+        0.5 * r.ln() * r / dr * scale
+    }
+}
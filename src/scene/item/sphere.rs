@@ -1,15 +1,18 @@
-use crate::math::{Point, Vector3};
+use std::sync::Arc;
+
+use crate::math::{Aabb, Point, Vector3};
 use crate::rendering::{Intersectable, Ray};
 use crate::scene::{
+    light::{EmissiveShape, EmissiveSurface},
     material::{Material, TextureCoords},
-    Distance,
+    Distance, Validation,
 };
 
 #[derive(Clone)]
 pub struct Sphere {
     pub center: Point,
     pub radius: Distance,
-    pub material: Material,
+    pub material: Arc<Material>,
 }
 
 impl Intersectable for Sphere {
@@ -27,7 +30,7 @@ impl Intersectable for Sphere {
             let iq_len = (r2 - d2).sqrt();
             let t0 = -iq_len + os_on_ray;
             let t1 = iq_len + os_on_ray;
-            if t0 < 0f64 && t1 < 0f64 {
+            if t0 < 0.0 && t1 < 0.0 {
                 None
             } else if t0 < 0.0 {
                 Some(t1)
@@ -39,6 +42,11 @@ impl Intersectable for Sphere {
         }
     }
 
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
     fn surface_normal(&self, hit_point: &Point) -> Vector3 {
         (*hit_point - self.center).normalize()
     }
@@ -53,7 +61,37 @@ impl Intersectable for Sphere {
         }
     }
 
+    fn tangent(&self, hit_point: &Point) -> Vector3 {
+        // 经线方向，也就是phi增大的方向；两极退化时随便取一个
+        let p = *hit_point - self.center;
+        let t = Vector3::new(-p.z, 0.0, p.x);
+        if t.norm() > 1e-12 {
+            t.normalize()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        }
+    }
+
     fn get_material(&self) -> &Material {
         &self.material
     }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        if let Some(radiance) = self.material.uniform_emission() {
+            out.push(EmissiveSurface {
+                shape: EmissiveShape::Sphere {
+                    center: self.center,
+                    radius: self.radius,
+                },
+                radiance,
+                object_id: None,
+            });
+        }
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("sphere center", &self.center);
+        report.positive("sphere radius", self.radius, false);
+        report.material(&self.material);
+    }
 }
@@ -0,0 +1,117 @@
+use crate::accel::{fallback_material, fallback_normal, fallback_texture_coords};
+use crate::math::{Aabb, Float4, Point, Vector3, Vector3x4};
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    item::Sphere,
+    light::EmissiveSurface,
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 一大堆球放在一起，四个一组按SoA存，求交时一次算四个球。
+/// 求交会返回具体打中的那个球，所以着色时用的是球自己的法线和材质，
+/// 组本身的surface_normal、get_material这些不会被用到
+pub struct SphereGroup {
+    spheres: Vec<Sphere>,
+    centers: Vec<Vector3x4>,
+    radii2: Vec<Float4>,
+}
+
+impl SphereGroup {
+    pub fn new(spheres: Vec<Sphere>) -> Self {
+        assert!(!spheres.is_empty());
+        let mut centers = Vec::new();
+        let mut radii2 = Vec::new();
+        for chunk in spheres.chunks(4) {
+            // 不满四个的用半径平方为负的球补上
+            let mut points = [Point::zero(); 4];
+            let mut r2 = Float4::splat(-1.0);
+            for (lane, sphere) in chunk.iter().enumerate() {
+                points[lane] = sphere.center;
+                r2.0[lane] = sphere.radius * sphere.radius;
+            }
+            centers.push(Vector3x4::from_points(points));
+            radii2.push(r2);
+        }
+        Self {
+            spheres,
+            centers,
+            radii2,
+        }
+    }
+}
+
+impl Intersectable for SphereGroup {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.intersect_hit(ray).map(|hit| hit.distance)
+    }
+
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let origin = Vector3x4::splat(&Vector3::new(ray.origin.x, ray.origin.y, ray.origin.z));
+        let direction = Vector3x4::splat(&ray.direction);
+        let mut nearest: Option<(Distance, usize)> = None;
+        for (chunk, (centers, r2)) in self.centers.iter().zip(&self.radii2).enumerate() {
+            // 和Sphere::intersect一样的做法，只是四个球一起算
+            let os = *centers - origin;
+            let os_on_ray = os.dot(&direction);
+            let d2 = os.dot(&os) - os_on_ray * os_on_ray;
+            let h2 = *r2 - d2;
+            let iq_len = h2.max(Float4::splat(0.0)).sqrt();
+            let t0 = os_on_ray - iq_len;
+            let t1 = os_on_ray + iq_len;
+            // 最后一组里补上的球不算
+            let lanes = (self.spheres.len() - chunk * 4).min(4);
+            for lane in 0..lanes {
+                if h2.0[lane] < 0.0 || t1.0[lane] < 0.0 {
+                    continue;
+                }
+                let t = if t0.0[lane] < 0.0 {
+                    t1.0[lane]
+                } else {
+                    t0.0[lane]
+                };
+                if nearest.is_none_or(|(best, _)| t < best) {
+                    nearest = Some((t, chunk * 4 + lane));
+                }
+            }
+        }
+        nearest.map(|(t, index)| Intersection::new(t, &self.spheres[index]))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.spheres
+            .iter()
+            .filter_map(|sphere| sphere.bounds())
+            .reduce(|a, b| a.union(&b))
+    }
+
+    fn surface_normal(&self, _hit_point: &Point) -> Vector3 {
+        fallback_normal()
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        fallback_texture_coords()
+    }
+
+    fn get_material(&self) -> &Material {
+        fallback_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        for sphere in &self.spheres {
+            sphere.validate(report);
+        }
+    }
+
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        for sphere in &self.spheres {
+            sphere.caustic_bounds(out);
+        }
+    }
+
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        for sphere in &self.spheres {
+            sphere.emissive_surfaces(out);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::math::{Aabb, Point, Vector3};
+use crate::overlay::BoundsBox;
+use crate::rendering::{Intersectable, Intersection, Ray};
+use crate::scene::{
+    item::Volume,
+    light::EmissiveSurface,
+    material::{Material, TextureCoords},
+    Distance, Validation,
+};
+
+/// 带编号的物体：求交转给item，再把id记到交点上，物体ID通道靠它区分物体。
+/// 放进加速结构以后编号也还在，所以加速前后同一个物体的编号不变
+pub struct Tagged {
+    pub id: u32,
+    pub item: Box<dyn Intersectable + Send + Sync>,
+}
+
+impl Intersectable for Tagged {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        self.item.intersect(ray)
+    }
+
+    /// 里面已经有编号的（嵌套的Tagged）保留里面的
+    fn intersect_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        self.item.intersect_hit(ray).map(|mut hit| {
+            hit.object_id.get_or_insert(self.id);
+            hit
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.item.bounds()
+    }
+
+    fn volume(&self) -> Option<&Volume> {
+        self.item.volume()
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        self.item.surface_normal(hit_point)
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        self.item.texture_coords(hit_point)
+    }
+
+    fn get_material(&self) -> &Material {
+        self.item.get_material()
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        self.item.validate(report);
+    }
+
+    fn caustic_bounds(&self, out: &mut Vec<Aabb>) {
+        self.item.caustic_bounds(out);
+    }
+
+    /// 和intersect_hit一样，里面已经有编号的保留里面的
+    fn emissive_surfaces(&self, out: &mut Vec<EmissiveSurface>) {
+        let start = out.len();
+        self.item.emissive_surfaces(out);
+        for surface in &mut out[start..] {
+            surface.object_id.get_or_insert(self.id);
+        }
+    }
+
+    fn collect_bounds(&self, out: &mut Vec<BoundsBox>) {
+        self.item.collect_bounds(out);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::color::{blackbody, Color};
+use crate::math::{Aabb, Float, Point, Vector3};
+use crate::rendering::{Intersectable, Ray};
+use crate::scene::{
+    material::{Coloration, Material, SurfaceType, TextureCoords},
+    Distance, Validation,
+};
+#[cfg(feature = "fs")]
+use crate::{Error, Result};
+#[cfg(feature = "fs")]
+use std::{fs, path::Path};
+
+/// 体素密度网格，数据按x最快、z最慢排布
+#[derive(Clone)]
+pub struct DensityGrid {
+    pub size: (usize, usize, usize),
+    pub data: Vec<f32>,
+}
+
+/// 原始体素文件里每个值的格式，都按小端读
+#[derive(Debug, Clone, Copy)]
+pub enum RawFormat {
+    U8,
+    U16,
+    F32,
+}
+
+#[cfg(feature = "fs")]
+fn decode_raw(bytes: &[u8], count: usize, format: RawFormat) -> Result<Vec<f32>> {
+    let width = match format {
+        RawFormat::U8 => 1,
+        RawFormat::U16 => 2,
+        RawFormat::F32 => 4,
+    };
+    if bytes.len() < count * width {
+        return Err(Error::parse("volume data is shorter than its size"));
+    }
+    let data = bytes[..count * width]
+        .chunks_exact(width)
+        .map(|b| match format {
+            RawFormat::U8 => b[0] as f32 / 255.0,
+            RawFormat::U16 => u16::from_le_bytes([b[0], b[1]]) as f32 / 65535.0,
+            RawFormat::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        })
+        .collect();
+    Ok(data)
+}
+
+impl DensityGrid {
+    pub fn new(size: (usize, usize, usize), data: Vec<f32>) -> Self {
+        assert_eq!(size.0 * size.1 * size.2, data.len());
+        Self { size, data }
+    }
+
+    /// 读没有文件头的原始体素
+    #[cfg(feature = "fs")]
+    pub fn load_raw<P: AsRef<Path>>(
+        path: P,
+        size: (usize, usize, usize),
+        format: RawFormat,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read(path)
+            .map_err(Error::from)
+            .and_then(|bytes| decode_raw(&bytes, size.0 * size.1 * size.2, format))
+            .map(|data| Self::new(size, data))
+            .map_err(|e| e.in_file(path))
+    }
+
+    /// 读NRRD文件，只支持三维、raw编码、小端的uchar/ushort/float，数据可以在同一个文件里或者用data file分离
+    #[cfg(feature = "fs")]
+    pub fn load_nrrd<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read(path)
+            .map_err(Error::from)
+            .and_then(|bytes| Self::parse_nrrd(&bytes, path))
+            .map_err(|e| e.in_file(path))
+    }
+
+    #[cfg(feature = "fs")]
+    fn parse_nrrd(bytes: &[u8], path: &Path) -> Result<Self> {
+        if !bytes.starts_with(b"NRRD") {
+            return Err(Error::parse("not a NRRD file"));
+        }
+        let mut size = None;
+        let mut format = None;
+        let mut data_file = None;
+        let mut offset = 0;
+        for line in bytes.split(|b| *b == b'\n') {
+            offset += line.len() + 1;
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with('#') || line.starts_with("NRRD") {
+                continue;
+            }
+            let (key, value) = match line.find(':') {
+                Some(i) => (
+                    line[..i].trim(),
+                    line[i + 1..].trim_start_matches('=').trim(),
+                ),
+                None => continue,
+            };
+            match key {
+                "dimension" if value != "3" => {
+                    return Err(Error::parse("only 3D NRRD volumes are supported"))
+                }
+                "type" => {
+                    format = Some(match value {
+                        "uchar" | "unsigned char" | "uint8" | "uint8_t" => RawFormat::U8,
+                        "ushort" | "unsigned short" | "uint16" | "uint16_t" => RawFormat::U16,
+                        "float" => RawFormat::F32,
+                        _ => return Err(Error::parse("unsupported NRRD type")),
+                    })
+                }
+                "sizes" => {
+                    let sizes = value
+                        .split_whitespace()
+                        .map(|s| s.parse::<usize>())
+                        .collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(|_| Error::parse("bad NRRD sizes"))?;
+                    if sizes.len() != 3 {
+                        return Err(Error::parse("bad NRRD sizes"));
+                    }
+                    size = Some((sizes[0], sizes[1], sizes[2]));
+                }
+                "encoding" if value != "raw" => {
+                    return Err(Error::parse("only raw NRRD encoding is supported"))
+                }
+                "endian" if value != "little" => {
+                    return Err(Error::parse("only little endian NRRD is supported"))
+                }
+                "data file" | "datafile" => data_file = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        let size = size.ok_or_else(|| Error::parse("NRRD file has no sizes"))?;
+        let format = format.ok_or_else(|| Error::parse("NRRD file has no type"))?;
+        let count = size.0 * size.1 * size.2;
+        let data = match data_file {
+            Some(name) => {
+                let detached = path.parent().unwrap_or_else(|| Path::new("")).join(name);
+                let bytes = fs::read(&detached).map_err(|e| Error::from(e).in_file(&detached))?;
+                decode_raw(&bytes, count, format)?
+            }
+            None => decode_raw(bytes.get(offset..).unwrap_or(&[]), count, format)?,
+        };
+        Ok(Self::new(size, data))
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> Float {
+        self.data[x + self.size.0 * (y + self.size.1 * z)] as Float
+    }
+
+    pub fn max_density(&self) -> Float {
+        self.data.iter().cloned().fold(0.0f32, f32::max) as Float
+    }
+
+    /// 三线性插值，p是网格里[0, 1]^3的局部坐标
+    pub fn density(&self, p: (Float, Float, Float)) -> Float {
+        let axis = |v: Float, n: usize| {
+            let f = (v * n as Float - 0.5).clamp(0.0, (n - 1) as Float);
+            let i = (f as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), f - i as Float)
+        };
+        let (x0, x1, fx) = axis(p.0, self.size.0);
+        let (y0, y1, fy) = axis(p.1, self.size.1);
+        let (z0, z1, fz) = axis(p.2, self.size.2);
+        let lerp = |a: Float, b: Float, t: Float| a + (b - a) * t;
+        let c00 = lerp(self.voxel(x0, y0, z0), self.voxel(x1, y0, z0), fx);
+        let c10 = lerp(self.voxel(x0, y1, z0), self.voxel(x1, y1, z0), fx);
+        let c01 = lerp(self.voxel(x0, y0, z1), self.voxel(x1, y0, z1), fx);
+        let c11 = lerp(self.voxel(x0, y1, z1), self.voxel(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+}
+
+/// 体积的自发光场，网格和密度网格一样铺满整个盒子
+#[derive(Clone)]
+pub enum VolumeEmission {
+    /// 每个体素直接给r, g, b三个通道的radiance
+    Color([DensityGrid; 3]),
+    /// 每个体素给温度（开尔文），按黑体辐射换算颜色，亮度随温度的四次方增长
+    Blackbody(DensityGrid),
+}
+
+/// 由密度网格描述的非均匀体积（烟、云、火），占据bounds这个盒子
+pub struct Volume {
+    pub bounds: Aabb,
+    pub grid: DensityGrid,
+    /// 密度为1时的消光系数σt
+    pub density_scale: Float,
+    /// 单次散射反照率σs / σt，被吸收的那部分才会发光
+    pub albedo: Color,
+    pub emission: Option<VolumeEmission>,
+    pub emission_scale: f32,
+    max_sigma_t: Float,
+    material: Material,
+}
+
+impl Volume {
+    pub fn new(bounds: Aabb, grid: DensityGrid, density_scale: Float, albedo: Color) -> Self {
+        let max_sigma_t = grid.max_density() * density_scale;
+        Self {
+            bounds,
+            grid,
+            density_scale,
+            albedo,
+            emission: None,
+            emission_scale: 1.0,
+            max_sigma_t,
+            material: Material {
+                color: Coloration::Color(albedo),
+                albedo: 1.0,
+                surface: SurfaceType::Diffuse,
+                clearcoat: None,
+                shadow_catcher: false,
+                opacity: None,
+            },
+        }
+    }
+
+    pub fn with_emission(mut self, emission: VolumeEmission, scale: f32) -> Self {
+        self.emission = Some(emission);
+        self.emission_scale = scale;
+        self
+    }
+
+    /// delta tracking用的上界
+    pub fn max_sigma_t(&self) -> Float {
+        self.max_sigma_t
+    }
+
+    fn local(&self, p: &Point) -> (Float, Float, Float) {
+        let min = self.bounds.min;
+        let extent = self.bounds.max - min;
+        (
+            (p.x - min.x) / extent.x,
+            (p.y - min.y) / extent.y,
+            (p.z - min.z) / extent.z,
+        )
+    }
+
+    pub fn sigma_t(&self, p: &Point) -> Float {
+        self.grid.density(self.local(p)) * self.density_scale
+    }
+
+    /// p点自发光的radiance
+    pub fn emitted(&self, p: &Point) -> Color {
+        let local = self.local(p);
+        match self.emission {
+            None => Color::black(),
+            Some(VolumeEmission::Color(ref grids)) => {
+                Color {
+                    r: grids[0].density(local) as f32,
+                    g: grids[1].density(local) as f32,
+                    b: grids[2].density(local) as f32,
+                } * self.emission_scale
+            }
+            Some(VolumeEmission::Blackbody(ref temperature)) => {
+                let kelvin = temperature.density(local) as f32;
+                if kelvin <= 0.0 {
+                    Color::black()
+                } else {
+                    blackbody(kelvin) * ((kelvin / 1000.0).powi(4) * self.emission_scale)
+                }
+            }
+        }
+    }
+}
+
+impl Intersectable for Volume {
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        // 刚好从盒子边上出去的光线会得到一段极短的区间，不算打中
+        self.bounds
+            .hit(&ray.origin, &ray.direction)
+            .filter(|(t0, t1)| t1 - t0 > 1e-9)
+            .map(|(t0, _)| t0)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        // 盒子上离得最近的那个面的法线
+        let center = self.bounds.min + (self.bounds.max - self.bounds.min) * 0.5;
+        let half = (self.bounds.max - self.bounds.min) * 0.5;
+        let d = *hit_point - center;
+        let (x, y, z) = ((d.x / half.x), (d.y / half.y), (d.z / half.z));
+        if x.abs() >= y.abs() && x.abs() >= z.abs() {
+            Vector3::new(x.signum(), 0.0, 0.0)
+        } else if y.abs() >= z.abs() {
+            Vector3::new(0.0, y.signum(), 0.0)
+        } else {
+            Vector3::new(0.0, 0.0, z.signum())
+        }
+    }
+
+    fn texture_coords(&self, _hit_point: &Point) -> TextureCoords {
+        TextureCoords { u: 0.0, v: 0.0 }
+    }
+
+    fn get_material(&self) -> &Material {
+        &self.material
+    }
+
+    fn volume(&self) -> Option<&Volume> {
+        Some(self)
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.positive("volume density scale", self.density_scale, true);
+        report.color("volume albedo", &self.albedo);
+        if self.grid.data.iter().any(|d| !d.is_finite() || *d < 0.0) {
+            report.error("volume has negative or NaN densities");
+        }
+    }
+}
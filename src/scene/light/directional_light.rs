@@ -1,7 +1,11 @@
+use crate::bsdf::orthonormal_basis;
 use crate::color::Color;
-use crate::math::{Point, Vector3};
-use crate::rendering::Light;
-use crate::scene::Distance;
+use crate::math::consts::PI;
+use crate::math::{Float, Point, Vector3};
+use crate::photon::Emitter;
+use crate::rendering::{Emission, Light, LightSample};
+use crate::sampling::random_2d;
+use crate::scene::{Distance, Validation};
 
 #[derive(Debug)]
 pub struct DirectionalLight {
@@ -11,19 +15,71 @@ pub struct DirectionalLight {
 }
 
 impl Light for DirectionalLight {
-    fn intensity(&self, _hit_point: &Point) -> f32 {
-        self.intensity
+    fn sample(&self, _hit_point: &Point) -> LightSample {
+        LightSample {
+            direction: -self.direction,
+            distance: Float::INFINITY,
+            intensity: self.color * self.intensity,
+            pdf: None,
+        }
     }
 
-    fn direction_from(&self, _hit_point: &Point) -> Vector3 {
-        -self.direction
+    fn power(&self) -> f32 {
+        self.intensity * self.color.luminance()
+    }
+
+    fn emitter(&self) -> Option<Emitter> {
+        Some(Emitter::Parallel {
+            direction: self.direction,
+            irradiance: self.color * self.intensity,
+        })
+    }
+
+    /// 在包围球垂直于光线的截面圆盘上均匀取点，从球外面沿direction射进来
+    fn emit(&self, bounds: &(Point, Distance)) -> Option<Emission> {
+        let origin = disk_origin(bounds, &self.direction, random_2d());
+        self.emission(&origin, &self.direction, bounds)
+    }
+
+    fn emission(
+        &self,
+        point: &Point,
+        _direction: &Vector3,
+        bounds: &(Point, Distance),
+    ) -> Option<Emission> {
+        Some(Emission {
+            origin: *point,
+            direction: self.direction,
+            normal: None,
+            radiance: self.color * self.intensity,
+            pdf_position: 1.0 / (PI * bounds.1 * bounds.1),
+            pdf_direction: 1.0,
+            delta: true,
+            infinite: true,
+        })
     }
 
     fn color(&self) -> Color {
         self.color
     }
 
-    fn distance(&self, _hit_point: &Point) -> Distance {
-        std::f64::INFINITY
+    fn validate(&self, report: &mut Validation) {
+        report.unit("light direction", &self.direction);
+        report.color("light color", &self.color);
+        report.positive("light intensity", self.intensity, true);
     }
 }
+
+/// 无穷远处沿direction来的光线的起点：包围球垂直于direction的截面圆盘上均匀的一点，
+/// 再往后退到球外面
+pub(super) fn disk_origin(
+    bounds: &(Point, Distance),
+    direction: &Vector3,
+    u: (Float, Float),
+) -> Point {
+    let (center, radius) = *bounds;
+    let r = radius * u.0.sqrt();
+    let phi = 2.0 * PI * u.1;
+    let (tangent, bitangent) = orthonormal_basis(direction);
+    center + tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) - *direction * radius
+}
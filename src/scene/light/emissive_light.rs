@@ -0,0 +1,241 @@
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Affine, Float, Point, Vector3};
+use crate::rendering::{Light, LightSample};
+use crate::sampling::{random, random_2d};
+use crate::scene::light::spherical_light::{cone_cos_max, sample_cone};
+use crate::scene::light::Portal;
+use crate::scene::Distance;
+
+/// 会发光的物体上的一块面，光源采样用的形状
+#[derive(Debug, Clone, Copy)]
+pub enum EmissiveShape {
+    Sphere {
+        center: Point,
+        radius: Distance,
+    },
+    /// 两面都发光的平行四边形，和Quad一样
+    Quad(Portal),
+    /// 两面都发光的三角形，三个顶点是corner、corner + edge_u和corner + edge_v
+    Triangle(Portal),
+}
+
+/// 物体收集出来的一块发光面：形状、均匀的radiance，和它属于哪个编号的物体（见Tagged）
+#[derive(Debug, Clone, Copy)]
+pub struct EmissiveSurface {
+    pub shape: EmissiveShape,
+    pub radiance: Color,
+    pub object_id: Option<u32>,
+}
+
+impl EmissiveSurface {
+    /// 经过to_world变换以后的样子。球只有等比缩放时还是球，不等比的返回None，不当成光源采样
+    pub fn transformed(&self, to_world: &Affine) -> Option<Self> {
+        let edges = |portal: Portal| Portal {
+            corner: to_world.point(&portal.corner),
+            edge_u: to_world.vector(&portal.edge_u),
+            edge_v: to_world.vector(&portal.edge_v),
+        };
+        let shape = match self.shape {
+            EmissiveShape::Sphere { center, radius } => {
+                let axes = [
+                    to_world.vector(&Vector3::new(1.0, 0.0, 0.0)),
+                    to_world.vector(&Vector3::new(0.0, 1.0, 0.0)),
+                    to_world.vector(&Vector3::new(0.0, 0.0, 1.0)),
+                ];
+                let scale = axes[0].length();
+                let tolerance = scale * 1e-6;
+                let uniform = axes.iter().all(|a| (a.length() - scale).abs() <= tolerance)
+                    && axes[0].dot(&axes[1]).abs() <= tolerance * scale
+                    && axes[1].dot(&axes[2]).abs() <= tolerance * scale
+                    && axes[2].dot(&axes[0]).abs() <= tolerance * scale;
+                if !uniform {
+                    return None;
+                }
+                EmissiveShape::Sphere {
+                    center: to_world.point(&center),
+                    radius: radius * scale,
+                }
+            }
+            EmissiveShape::Quad(quad) => EmissiveShape::Quad(edges(quad)),
+            EmissiveShape::Triangle(triangle) => EmissiveShape::Triangle(edges(triangle)),
+        };
+        Some(Self { shape, ..*self })
+    }
+
+    fn area(&self) -> Float {
+        match self.shape {
+            EmissiveShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            EmissiveShape::Quad(quad) => quad.edge_u.cross(&quad.edge_v).length(),
+            EmissiveShape::Triangle(triangle) => {
+                triangle.edge_u.cross(&triangle.edge_v).length() / 2.0
+            }
+        }
+    }
+
+    /// 从point沿direction打中这块面的距离，球只算从外面打中
+    fn hit(&self, point: &Point, direction: &Vector3) -> Option<Distance> {
+        match self.shape {
+            EmissiveShape::Sphere { center, radius } => {
+                let os = center - *point;
+                let b = os.dot(direction);
+                let r2 = radius * radius;
+                let d2 = os.norm() - b * b;
+                if os.norm() <= r2 || d2 > r2 {
+                    return None;
+                }
+                let t = b - (r2 - d2).sqrt();
+                (t > 0.0).then_some(t)
+            }
+            EmissiveShape::Quad(quad) => quad.intersect(point, direction),
+            // 先按平行四边形求交，再看在不在对角线的这一半
+            EmissiveShape::Triangle(triangle) => {
+                let t = triangle.intersect(point, direction)?;
+                let cross = triangle.edge_u.cross(&triangle.edge_v);
+                let d = *point + *direction * t - triangle.corner;
+                let u = d.cross(&triangle.edge_v).dot(&cross) / cross.norm();
+                let v = triangle.edge_u.cross(&d).dot(&cross) / cross.norm();
+                (u + v <= 1.0).then_some(t)
+            }
+        }
+    }
+
+    /// 从point朝direction看到这块面时，在它上面采样出这个方向的立体角pdf
+    fn pdf(&self, point: &Point, direction: &Vector3) -> Float {
+        match self.shape {
+            EmissiveShape::Sphere { center, radius } => {
+                match cone_cos_max(&center, radius, point) {
+                    Some(cos_max) if (center - *point).normalize().dot(direction) >= cos_max => {
+                        1.0 / (2.0 * PI * (1.0 - cos_max))
+                    }
+                    _ => 0.0,
+                }
+            }
+            EmissiveShape::Quad(quad) => quad.pdf(point, direction),
+            EmissiveShape::Triangle(triangle) => match self.hit(point, direction) {
+                Some(t) => {
+                    let cross = triangle.edge_u.cross(&triangle.edge_v);
+                    let cos = (cross.dot(direction) / cross.length()).abs();
+                    t * t / (self.area() * cos)
+                }
+                None => 0.0,
+            },
+        }
+    }
+}
+
+/// 场景里一个会发光的物体当成光源：自发光是均匀颜色（没有贴图）的球、四边形和三角形，
+/// 场景建好时按物体的编号收集起来（见Scene::add_emissive_lights）。
+/// 光线打中的还是物体本身，自发光由材质给，这里只负责直接光照时朝它采样；
+/// 打中时两边按power heuristic分，所以光源自己打不中
+#[derive(Debug)]
+pub struct EmissiveLight {
+    pub object_id: u32,
+    surfaces: Vec<EmissiveSurface>,
+    /// 按面积乘亮度挑面的CDF
+    cdf: Vec<Float>,
+}
+
+impl EmissiveLight {
+    pub fn new(object_id: u32, surfaces: Vec<EmissiveSurface>) -> Self {
+        let mut acc = 0.0;
+        let mut cdf: Vec<Float> = surfaces
+            .iter()
+            .map(|s| {
+                acc += s.area() * s.radiance.luminance().max(1e-6) as Float;
+                acc
+            })
+            .collect();
+        for c in &mut cdf {
+            *c /= acc;
+        }
+        Self {
+            object_id,
+            surfaces,
+            cdf,
+        }
+    }
+
+    /// 挑中第index块面的概率
+    fn selection(&self, index: usize) -> Float {
+        let prev = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        self.cdf[index] - prev
+    }
+}
+
+impl Light for EmissiveLight {
+    /// 按面积和亮度挑一块面，球在它所张的圆锥里采样，四边形和三角形在面上均匀取点。
+    /// 挑中的面被同一个物体的别的面挡住时影子射线会打中那块面，贡献是0，
+    /// 所以一个方向的pdf只算最近的那块面
+    fn sample(&self, hit_point: &Point) -> LightSample {
+        let u = random();
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.cdf.len() - 1);
+        let surface = &self.surfaces[index];
+        let (direction, distance) = match surface.shape {
+            EmissiveShape::Sphere { center, radius } => {
+                match cone_cos_max(&center, radius, hit_point) {
+                    Some(cos_max) => sample_cone(&center, radius, hit_point, cos_max),
+                    None => (Vector3::new(0.0, 1.0, 0.0), 0.0),
+                }
+            }
+            EmissiveShape::Quad(quad) => {
+                let (u, v) = random_2d();
+                let to_light = quad.corner + quad.edge_u * u + quad.edge_v * v - *hit_point;
+                let distance = to_light.length();
+                (to_light * (1.0 / distance), distance)
+            }
+            EmissiveShape::Triangle(triangle) => {
+                let (u, v) = random_2d();
+                let su = u.sqrt();
+                let to_light = triangle.corner
+                    + triangle.edge_u * (su * (1.0 - v))
+                    + triangle.edge_v * (su * v)
+                    - *hit_point;
+                let distance = to_light.length();
+                (to_light * (1.0 / distance), distance)
+            }
+        };
+        let pdf = self.selection(index) * surface.pdf(hit_point, &direction);
+        LightSample {
+            direction,
+            // 影子射线停在发光面前面一点，不然会被物体自己挡住
+            distance: distance * (1.0 - 1e-4),
+            intensity: if pdf > 0.0 && distance > 0.0 {
+                surface.radiance / pdf as f32
+            } else {
+                Color::black()
+            },
+            pdf: Some(pdf),
+        }
+    }
+
+    fn pdf(&self, hit_point: &Point, direction: &Vector3) -> Float {
+        self.surfaces
+            .iter()
+            .enumerate()
+            .filter_map(|(index, s)| s.hit(hit_point, direction).map(|t| (index, s, t)))
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .map_or(0.0, |(index, s, _)| {
+                self.selection(index) * s.pdf(hit_point, direction)
+            })
+    }
+
+    fn color(&self) -> Color {
+        self.surfaces[0].radiance
+    }
+
+    /// 按朝外发光的面光源算，radiance × 面积 × π
+    fn power(&self) -> f32 {
+        self.surfaces
+            .iter()
+            .map(|s| s.radiance.luminance() * (s.area() * PI) as f32)
+            .sum()
+    }
+
+    fn object_id(&self) -> Option<u32> {
+        Some(self.object_id)
+    }
+}
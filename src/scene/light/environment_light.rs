@@ -0,0 +1,166 @@
+use crate::bsdf::uniform_sample_sphere;
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Point, Vector3};
+use crate::rendering::{Emission, Light, LightSample, Ray};
+use crate::sampling::{random, random_2d};
+use crate::scene::light::directional_light::disk_origin;
+use crate::scene::{Distance, Validation};
+
+/// 天光能照进来的开口，比如窗户、门洞：corner是一个角，edge_u、edge_v是从它出发的两条边，
+/// 一般是矩形。只影响采样，不挡光也不是物体
+#[derive(Debug, Clone, Copy)]
+pub struct Portal {
+    pub corner: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+}
+
+impl Portal {
+    /// edge_u × edge_v，长度是面积
+    fn cross(&self) -> Vector3 {
+        self.edge_u.cross(&self.edge_v)
+    }
+
+    /// 从origin沿单位向量direction打中开口时的距离
+    pub(super) fn intersect(&self, origin: &Point, direction: &Vector3) -> Option<Distance> {
+        let cross = self.cross();
+        let area2 = cross.norm();
+        let denom = cross.dot(direction);
+        if denom == 0.0 || area2 == 0.0 {
+            return None;
+        }
+        let t = cross.dot(&(self.corner - *origin)) / denom;
+        if t <= 0.0 {
+            return None;
+        }
+        let d = *origin + *direction * t - self.corner;
+        let u = d.cross(&self.edge_v).dot(&cross) / area2;
+        let v = self.edge_u.cross(&d).dot(&cross) / area2;
+        ((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)).then_some(t)
+    }
+
+    /// 在开口上均匀取点时，从origin看direction方向的立体角pdf（没穿过开口是0）
+    pub(super) fn pdf(&self, origin: &Point, direction: &Vector3) -> Float {
+        let cross = self.cross();
+        let area = cross.length();
+        match self.intersect(origin, direction) {
+            Some(t) => {
+                let cos = (cross.dot(direction) / area).abs();
+                t * t / (area * cos)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// 无穷远处四面八方均匀的天光，radiance是color * intensity，没打中任何东西的光线都会看到它。
+/// 没有portal时在整个球面上采样方向；室内只有窗户透进天光时，这样采样的方向几乎都打在墙上，
+/// 把窗户标成portal以后只朝开口采样，从别的方向来的天光还是靠BSDF采样的光线打中
+#[derive(Debug)]
+pub struct EnvironmentLight {
+    pub color: Color,
+    pub intensity: f32,
+    pub portals: Vec<Portal>,
+}
+
+impl EnvironmentLight {
+    fn radiance(&self) -> Color {
+        self.color * self.intensity
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, hit_point: &Point) -> LightSample {
+        let direction = if self.portals.is_empty() {
+            uniform_sample_sphere(random_2d())
+        } else {
+            // 先均匀挑一个开口，再在上面均匀取一点
+            let index =
+                ((random() * self.portals.len() as Float) as usize).min(self.portals.len() - 1);
+            let portal = &self.portals[index];
+            let (u, v) = random_2d();
+            (portal.corner + portal.edge_u * u + portal.edge_v * v - *hit_point).normalize()
+        };
+        let pdf = self.pdf(hit_point, &direction);
+        LightSample {
+            direction,
+            distance: Float::INFINITY,
+            intensity: if pdf > 0.0 && pdf.is_finite() {
+                self.radiance() / pdf as f32
+            } else {
+                Color::black()
+            },
+            pdf: Some(pdf),
+        }
+    }
+
+    /// 几个开口在这个方向上重叠时，每个都可能采样到它，pdf要加起来
+    fn pdf(&self, hit_point: &Point, direction: &Vector3) -> Float {
+        if self.portals.is_empty() {
+            return 1.0 / (4.0 * PI);
+        }
+        let sum: Float = self
+            .portals
+            .iter()
+            .map(|p| p.pdf(hit_point, direction))
+            .sum();
+        sum / self.portals.len() as Float
+    }
+
+    fn intersect(&self, _ray: &Ray) -> Option<Distance> {
+        Some(Float::INFINITY)
+    }
+
+    fn emitted(&self) -> Color {
+        self.radiance()
+    }
+
+    /// 穿过开口的总通量；没有开口时当成照在单位球上
+    fn power(&self) -> f32 {
+        let area: Float = if self.portals.is_empty() {
+            4.0 * PI
+        } else {
+            self.portals.iter().map(|p| p.cross().length()).sum()
+        };
+        self.radiance().luminance() * (area * PI) as f32
+    }
+
+    /// 方向在整个球面上均匀地取，不管开口；起点和平行光一样在包围球的截面上
+    fn emit(&self, bounds: &(Point, Distance)) -> Option<Emission> {
+        let direction = uniform_sample_sphere(random_2d());
+        let origin = disk_origin(bounds, &direction, random_2d());
+        self.emission(&origin, &direction, bounds)
+    }
+
+    fn emission(
+        &self,
+        point: &Point,
+        direction: &Vector3,
+        bounds: &(Point, Distance),
+    ) -> Option<Emission> {
+        Some(Emission {
+            origin: *point,
+            direction: *direction,
+            normal: None,
+            radiance: self.radiance(),
+            pdf_position: 1.0 / (PI * bounds.1 * bounds.1),
+            pdf_direction: 1.0 / (4.0 * PI),
+            delta: false,
+            infinite: true,
+        })
+    }
+
+    fn color(&self) -> Color {
+        self.color
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.color("environment color", &self.color);
+        report.positive("environment intensity", self.intensity, true);
+        for portal in &self.portals {
+            report.point("portal corner", &portal.corner);
+            report.positive("portal area", portal.cross().length(), false);
+        }
+    }
+}
@@ -0,0 +1,9 @@
+/// 一组光源（key、fill、天光……），light_group_pass按组分开输出，
+/// 后期可以分别调亮调暗、换颜色，不用重新渲染。lights是scene.lights里的下标，
+/// objects是会发光的物体的编号（见Scene::assign_object_ids），它们的自发光也算在这一组里
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightGroup {
+    pub name: String,
+    pub lights: Vec<usize>,
+    pub objects: Vec<u32>,
+}
@@ -1,5 +1,15 @@
 mod directional_light;
+mod emissive_light;
+mod environment_light;
+mod group;
+mod quad_light;
+mod sampler;
 mod spherical_light;
 
 pub use directional_light::DirectionalLight;
-pub use spherical_light::SphericalLight;
+pub use emissive_light::{EmissiveLight, EmissiveShape, EmissiveSurface};
+pub use environment_light::{EnvironmentLight, Portal};
+pub use group::LightGroup;
+pub use quad_light::QuadLight;
+pub use sampler::LightSampler;
+pub use spherical_light::{Falloff, SphericalLight};
@@ -0,0 +1,139 @@
+use crate::bsdf::{cosine_sample_hemisphere, orthonormal_basis};
+use crate::color::Color;
+use crate::math::consts::PI;
+use crate::math::{Float, Point, Vector3};
+use crate::rendering::{Emission, Light, LightSample, Ray};
+use crate::sampling::random_2d;
+use crate::scene::light::Portal;
+use crate::scene::{Distance, Validation};
+
+/// 会发光的平行四边形，一般是矩形，像天花板上的灯板：corner是一个角，edge_u、edge_v
+/// 是从它出发的两条边。只朝edge_u × edge_v那一侧发光，从背面看不到。
+/// intensity是总功率，和SphericalLight一样；焦散光子图不从它发光子
+#[derive(Debug)]
+pub struct QuadLight {
+    pub corner: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+}
+
+impl QuadLight {
+    /// 形状和天光的开口一样，求交和pdf借它的
+    fn shape(&self) -> Portal {
+        Portal {
+            corner: self.corner,
+            edge_u: self.edge_u,
+            edge_v: self.edge_v,
+        }
+    }
+
+    fn area(&self) -> Float {
+        self.edge_u.cross(&self.edge_v).length()
+    }
+
+    fn normal(&self) -> Vector3 {
+        self.edge_u.cross(&self.edge_v).normalize()
+    }
+
+    /// 总功率为intensity、朝一侧均匀的radiance
+    fn radiance(&self) -> Color {
+        self.color * (self.intensity / (self.area() * PI) as f32)
+    }
+
+    fn point(&self, (u, v): (Float, Float)) -> Point {
+        self.corner + self.edge_u * u + self.edge_v * v
+    }
+}
+
+impl Light for QuadLight {
+    /// 在面上均匀取一点，面积的pdf换成立体角的
+    fn sample(&self, hit_point: &Point) -> LightSample {
+        let to_light = self.point(random_2d()) - *hit_point;
+        let distance = to_light.length();
+        let direction = to_light * (1.0 / distance);
+        let cos = -self.normal().dot(&direction);
+        if cos <= 0.0 {
+            return LightSample {
+                direction,
+                distance,
+                intensity: Color::black(),
+                pdf: Some(0.0),
+            };
+        }
+        let pdf = distance * distance / (cos * self.area());
+        LightSample {
+            direction,
+            distance,
+            intensity: self.radiance() / pdf as f32,
+            pdf: Some(pdf),
+        }
+    }
+
+    fn pdf(&self, hit_point: &Point, direction: &Vector3) -> Float {
+        if self.normal().dot(direction) >= 0.0 {
+            return 0.0;
+        }
+        self.shape().pdf(hit_point, direction)
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        if self.normal().dot(&ray.direction) >= 0.0 {
+            return None;
+        }
+        self.shape().intersect(&ray.origin, &ray.direction)
+    }
+
+    fn emitted(&self) -> Color {
+        self.radiance()
+    }
+
+    fn power(&self) -> f32 {
+        self.intensity * self.color.luminance()
+    }
+
+    /// 面上均匀取点，按余弦往发光的一侧发
+    fn emit(&self, bounds: &(Point, Distance)) -> Option<Emission> {
+        let normal = self.normal();
+        let local = cosine_sample_hemisphere(random_2d());
+        let (tangent, bitangent) = orthonormal_basis(&normal);
+        let direction = (tangent * local.x + bitangent * local.y + normal * local.z).normalize();
+        self.emission(&self.point(random_2d()), &direction, bounds)
+    }
+
+    fn emission(
+        &self,
+        point: &Point,
+        direction: &Vector3,
+        _bounds: &(Point, Distance),
+    ) -> Option<Emission> {
+        let normal = self.normal();
+        Some(Emission {
+            origin: *point,
+            direction: *direction,
+            normal: Some(normal),
+            radiance: self.radiance(),
+            pdf_position: 1.0 / self.area(),
+            pdf_direction: normal.dot(direction).max(0.0) / PI,
+            delta: false,
+            infinite: false,
+        })
+    }
+
+    fn color(&self) -> Color {
+        self.color
+    }
+
+    fn validate(&self, report: &mut Validation) {
+        report.point("light corner", &self.corner);
+        if !(self.area() > 0.0 && self.area().is_finite()) {
+            report.error(format_args!(
+                "quad light edges {:?} and {:?} span no area",
+                self.edge_u, self.edge_v
+            ));
+        }
+        report.color("light color", &self.color);
+        report.positive("light intensity", self.intensity, true);
+    }
+}
@@ -0,0 +1,106 @@
+use crate::irradiance::{irradiance_cache, IrradianceCache};
+use crate::math::{Float, Point};
+use crate::photon::{caustic_map, PhotonMap};
+use crate::rendering::Light;
+use crate::scene::{Distance, Scene};
+
+/// 按光源功率建立的CDF，光源很多时每个着色点只按功率抽几个光源来算，
+/// 而不是把所有光源都算一遍。每次渲染开始时建一次，从光源发出的焦散光子图
+/// 和渲染前算好的辐照度缓存也放在这里
+pub struct LightSampler {
+    cdf: Vec<f32>,
+    caustics: Option<PhotonMap>,
+    irradiance: Option<IrradianceCache>,
+    bounds: (Point, Distance),
+}
+
+impl LightSampler {
+    /// 渲染scene时用的：按功率抽光源，需要的话建焦散光子图和辐照度缓存，记下场景的包围球。
+    /// 辐照度缓存是用其余这些算出来的
+    pub fn for_scene(scene: &Scene) -> Self {
+        let lights = Self {
+            caustics: caustic_map(scene),
+            bounds: scene.bounding_sphere(),
+            ..Self::new(&scene.lights)
+        };
+        Self {
+            irradiance: irradiance_cache(scene, &lights),
+            ..lights
+        }
+    }
+
+    pub fn new(lights: &[Box<dyn Light + Send + Sync>]) -> Self {
+        let powers: Vec<f32> = lights.iter().map(|l| l.power()).collect();
+        Self::from_powers(&powers)
+    }
+
+    /// 按给定的功率抽，负的当成0
+    pub fn from_powers(powers: &[f32]) -> Self {
+        let powers: Vec<f32> = powers.iter().map(|p| p.max(0.0)).collect();
+        let total: f32 = powers.iter().sum();
+        let mut acc = 0.0;
+        let cdf = powers
+            .iter()
+            .map(|p| {
+                // 全是0功率的时候退化成均匀分布
+                acc += if total > 0.0 {
+                    p / total
+                } else {
+                    1.0 / powers.len() as f32
+                };
+                acc
+            })
+            .collect();
+        Self {
+            cdf,
+            caustics: None,
+            irradiance: None,
+            bounds: (Point::zero(), 1.0),
+        }
+    }
+
+    pub fn with_caustics(mut self, caustics: Option<PhotonMap>) -> Self {
+        self.caustics = caustics;
+        self
+    }
+
+    pub fn caustics(&self) -> Option<&PhotonMap> {
+        self.caustics.as_ref()
+    }
+
+    pub fn irradiance_cache(&self) -> Option<&IrradianceCache> {
+        self.irradiance.as_ref()
+    }
+
+    /// 场景的包围球，见Scene::bounding_sphere；不是for_scene建的是单位球
+    pub fn bounds(&self) -> &(Point, Distance) {
+        &self.bounds
+    }
+
+    pub fn len(&self) -> usize {
+        self.cdf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cdf.is_empty()
+    }
+
+    /// 第index个光源被抽中的概率
+    pub fn pdf(&self, index: usize) -> f32 {
+        let prev = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        self.cdf[index] - prev
+    }
+
+    /// 用[0, 1)的u抽一个光源，返回下标和它的概率
+    pub fn sample(&self, u: Float) -> Option<(usize, f32)> {
+        if self.cdf.is_empty() {
+            return None;
+        }
+        let u = u as f32 * self.cdf[self.cdf.len() - 1];
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .min(self.cdf.len() - 1);
+        Some((index, self.pdf(index)))
+    }
+}
@@ -1,30 +1,263 @@
+use crate::bsdf::{cosine_sample_hemisphere, orthonormal_basis, uniform_sample_sphere};
 use crate::color::Color;
-use crate::math::{Point, Vector3};
-use crate::rendering::Light;
-use crate::scene::Distance;
+use crate::math::consts::PI;
+use crate::math::{Float, Point, Vector3};
+use crate::photon::Emitter;
+use crate::rendering::{Emission, Light, LightSample, Ray};
+use crate::sampling::random_2d;
+use crate::scene::{Distance, Validation};
 
+/// 点光源的亮度随距离怎么衰减
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Falloff {
+    /// 和距离的平方成反比，物理上正确的
+    #[default]
+    InverseSquare,
+    /// 和距离成反比
+    Linear,
+    /// 不衰减，多远都一样亮
+    None,
+    /// near以内不衰减，从near到far平滑地降到0，far以外照不到，
+    /// 室内打光时用来控制一盏灯照多大的范围
+    Range { near: Distance, far: Distance },
+}
+
+impl Falloff {
+    /// 距离distance处的衰减系数，平方反比时就是1 / distance²
+    fn attenuation(self, distance: Distance) -> f32 {
+        match self {
+            Self::InverseSquare => 1.0 / (distance * distance) as f32,
+            Self::Linear => 1.0 / distance as f32,
+            Self::None => 1.0,
+            Self::Range { near, far } => {
+                if distance <= near {
+                    1.0
+                } else if distance >= far {
+                    0.0
+                } else {
+                    let t = ((far - distance) / (far - near)) as f32;
+                    t * t * (3.0 - 2.0 * t)
+                }
+            }
+        }
+    }
+}
+
+/// 从point看球心center、半径radius的球所张圆锥的半角余弦，点在球里面或者半径是0时为None
+pub(super) fn cone_cos_max(center: &Point, radius: Distance, point: &Point) -> Option<Float> {
+    let d2 = (*center - *point).norm();
+    let r2 = radius * radius;
+    if radius <= 0.0 || d2 <= r2 {
+        None
+    } else {
+        Some((1.0 - r2 / d2).max(0.0).sqrt())
+    }
+}
+
+/// 在point看球所张的圆锥里均匀采样一个方向，返回方向和沿它到球面的距离；
+/// 立体角pdf是1 / (2π(1 - cos_max))
+pub(super) fn sample_cone(
+    center: &Point,
+    radius: Distance,
+    point: &Point,
+    cos_max: Float,
+) -> (Vector3, Distance) {
+    let to_center = *center - *point;
+    let distance = to_center.length();
+    let axis = to_center.normalize();
+    let (u, v) = random_2d();
+    let cos_theta = 1.0 - u * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    let (tangent, bitangent) = orthonormal_basis(&axis);
+    let direction = (tangent * (sin_theta * phi.cos())
+        + bitangent * (sin_theta * phi.sin())
+        + axis * cos_theta)
+        .normalize();
+    // 到球面的距离：射线和球求交的近端
+    let b = distance * cos_theta;
+    let surface_distance = b
+        - (radius * radius - distance * distance + b * b)
+            .max(0.0)
+            .sqrt();
+    (direction, surface_distance)
+}
+
+/// radius为0时就是点光源，否则是一个会发光的球，可以被BSDF采样的光线打中。
+/// falloff只对点光源有用，不是平方反比的点光源没法从光源那头往外追，
+/// 不发光子，双向路径追踪也只在相机那头对它采样
 #[derive(Debug)]
 pub struct SphericalLight {
     pub position: Point,
+    pub radius: Distance,
     pub color: Color,
     pub intensity: f32,
+    pub falloff: Falloff,
+}
+
+impl SphericalLight {
+    /// 在hit_point看来光源所张圆锥的半角余弦，点在光源里面或者是点光源时为None
+    fn cos_theta_max(&self, hit_point: &Point) -> Option<Float> {
+        cone_cos_max(&self.position, self.radius, hit_point)
+    }
+
+    /// 有半径的球光源总是平方反比的，falloff不管用
+    fn physical(&self) -> bool {
+        self.radius > 0.0 || self.falloff == Falloff::InverseSquare
+    }
+
+    /// 总功率为intensity的球面上均匀的radiance
+    fn radiance(&self) -> Color {
+        let area = 4.0 * PI * self.radius * self.radius;
+        self.color * (self.intensity / (area * PI) as f32)
+    }
 }
 
 impl Light for SphericalLight {
-    fn intensity(&self, hit_point: &Point) -> f32 {
-        let r2 = (self.position - *hit_point).norm() as f32;
-        self.intensity / (r2 * 4.0 * std::f32::consts::PI)
+    fn sample(&self, hit_point: &Point) -> LightSample {
+        let to_light = self.position - *hit_point;
+        let distance = to_light.length();
+        let axis = to_light.normalize();
+        match self.cos_theta_max(hit_point) {
+            None => LightSample {
+                direction: axis,
+                distance,
+                intensity: self.color
+                    * (self.intensity * self.falloff.attenuation(distance)
+                        / (4.0 * std::f32::consts::PI)),
+                pdf: None,
+            },
+            Some(cos_max) => {
+                let (direction, surface_distance) =
+                    sample_cone(&self.position, self.radius, hit_point, cos_max);
+                let pdf = 1.0 / (2.0 * PI * (1.0 - cos_max));
+                LightSample {
+                    direction,
+                    distance: surface_distance,
+                    intensity: self.radiance() / pdf as f32,
+                    pdf: Some(pdf),
+                }
+            }
+        }
+    }
+
+    fn pdf(&self, hit_point: &Point, _direction: &Vector3) -> Float {
+        self.cos_theta_max(hit_point)
+            .map_or(0.0, |cos_max| 1.0 / (2.0 * PI * (1.0 - cos_max)))
+    }
+
+    fn intersect(&self, ray: &Ray) -> Option<Distance> {
+        if self.radius <= 0.0 {
+            return None;
+        }
+        let os = self.position - ray.origin;
+        let os_on_ray = os.dot(&ray.direction);
+        let d2 = os.dot(&os) - os_on_ray * os_on_ray;
+        let r2 = self.radius * self.radius;
+        if d2 > r2 {
+            return None;
+        }
+        let iq_len = (r2 - d2).sqrt();
+        let t0 = os_on_ray - iq_len;
+        let t1 = os_on_ray + iq_len;
+        if t0 >= 0.0 {
+            Some(t0)
+        } else if t1 >= 0.0 {
+            Some(t1)
+        } else {
+            None
+        }
+    }
+
+    fn emitted(&self) -> Color {
+        if self.radius > 0.0 {
+            self.radiance()
+        } else {
+            Color::black()
+        }
+    }
+
+    fn power(&self) -> f32 {
+        self.intensity * self.color.luminance()
+    }
+
+    /// 有半径的也当成中心处的点光源
+    fn emitter(&self) -> Option<Emitter> {
+        if !self.physical() {
+            return None;
+        }
+        Some(Emitter::Point {
+            position: self.position,
+            intensity: self.color * (self.intensity / (4.0 * std::f32::consts::PI)),
+        })
+    }
+
+    /// 点光源朝四面八方均匀地发；有半径的在球面上均匀取点，再按余弦往外发
+    fn emit(&self, bounds: &(Point, Distance)) -> Option<Emission> {
+        let normal = uniform_sample_sphere(random_2d());
+        if self.radius <= 0.0 {
+            return self.emission(&self.position, &normal, bounds);
+        }
+        let local = cosine_sample_hemisphere(random_2d());
+        let (tangent, bitangent) = orthonormal_basis(&normal);
+        let direction = (tangent * local.x + bitangent * local.y + normal * local.z).normalize();
+        self.emission(&(self.position + normal * self.radius), &direction, bounds)
     }
 
-    fn direction_from(&self, hit_point: &Point) -> Vector3 {
-        (self.position - *hit_point).normalize()
+    fn emission(
+        &self,
+        point: &Point,
+        direction: &Vector3,
+        _bounds: &(Point, Distance),
+    ) -> Option<Emission> {
+        if !self.physical() {
+            return None;
+        }
+        if self.radius <= 0.0 {
+            return Some(Emission {
+                origin: self.position,
+                direction: *direction,
+                normal: None,
+                radiance: self.color * (self.intensity / (4.0 * std::f32::consts::PI)),
+                pdf_position: 1.0,
+                pdf_direction: 1.0 / (4.0 * PI),
+                delta: true,
+                infinite: false,
+            });
+        }
+        let normal = (*point - self.position).normalize();
+        Some(Emission {
+            origin: *point,
+            direction: *direction,
+            normal: Some(normal),
+            radiance: self.radiance(),
+            pdf_position: 1.0 / (4.0 * PI * self.radius * self.radius),
+            pdf_direction: normal.dot(direction).max(0.0) / PI,
+            delta: false,
+            infinite: false,
+        })
     }
 
     fn color(&self) -> Color {
         self.color
     }
 
-    fn distance(&self, hit_point: &Point) -> Distance {
-        (self.position - *hit_point).length()
+    fn validate(&self, report: &mut Validation) {
+        report.point("light position", &self.position);
+        report.positive("light radius", self.radius, true);
+        report.color("light color", &self.color);
+        report.positive("light intensity", self.intensity, true);
+        if let Falloff::Range { near, far } = self.falloff {
+            report.positive("light falloff near", near, true);
+            if report.finite("light falloff far", far) && far <= near {
+                report.error(format_args!(
+                    "light falloff far {} must be greater than near {}",
+                    far, near
+                ));
+            }
+        }
+        if self.radius > 0.0 && self.falloff != Falloff::InverseSquare {
+            report.warning("light falloff only applies to point lights (radius 0)");
+        }
     }
 }
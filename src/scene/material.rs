@@ -1,11 +1,117 @@
 use crate::color::Color;
+use crate::hdr::HdrImage;
+use crate::math::Point;
 use image::ImageBuffer;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub enum SurfaceType {
     Diffuse,
-    Reflective { reflectivity: f32 },
-    Refractive { index: f32, transparency: f32 },
+    Reflective {
+        reflectivity: f32,
+    },
+    /// dispersion是Cauchy公式里的B（μm²），0就是没有色散
+    Refractive {
+        index: f32,
+        transparency: f32,
+        dispersion: f32,
+    },
+    /// GGX微表面反射，颜色作为垂直入射时的反射率。
+    /// roughness_u和roughness_v分别是沿切线和副切线的粗糙度，rotation（弧度）转动切线方向
+    Microfacet {
+        roughness_u: f32,
+        roughness_v: f32,
+        rotation: f32,
+    },
+    Principled(Principled),
+    Subsurface(Subsurface),
+    Hair(Hair),
+}
+
+/// Disney风格的uber材质，基础色用Material的color
+#[derive(Clone)]
+pub struct Principled {
+    pub metallic: f32,
+    pub roughness: f32,
+    /// 非金属的高光强度，0.5对应F0 = 0.04
+    pub specular: f32,
+    /// 透射的比例，透射部分按光滑的电介质处理
+    pub transmission: f32,
+    pub ior: f32,
+    /// 透射部分的色散，Cauchy公式里的B（μm²）
+    pub dispersion: f32,
+    pub emission: Color,
+    /// 自发光的贴图，有的话代替emission，乘上emission_strength；屏幕、霓虹灯、岩浆这种
+    /// 亮的地方有花纹的。浮点贴图可以直接存超过1的亮度
+    pub emission_map: Option<Coloration>,
+    pub emission_strength: f32,
+    /// 灰度的粗糙度和金属度贴图，取三个通道的平均，有的话代替roughness和metallic。
+    /// 存的是数据，贴图要用ColorSpace::Linear
+    pub roughness_map: Option<Coloration>,
+    pub metallic_map: Option<Coloration>,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            transmission: 0.0,
+            ior: 1.5,
+            dispersion: 0.0,
+            emission: Color::black(),
+            emission_map: None,
+            emission_strength: 1.0,
+            roughness_map: None,
+            metallic_map: None,
+        }
+    }
+}
+
+/// 用Cauchy公式n(λ) = A + B / λ²算某个波长下的折射率，
+/// ior是在钠黄线587.6nm处的折射率；没有波长（RGB模式）时就是ior本身
+pub fn dispersed_ior(ior: f32, dispersion: f32, wavelength: Option<f32>) -> f32 {
+    match wavelength {
+        Some(wavelength) if dispersion != 0.0 => {
+            let d_line = 0.5876f32;
+            let micrometers = wavelength / 1000.0;
+            ior + dispersion / (micrometers * micrometers) - dispersion / (d_line * d_line)
+        }
+        _ => ior,
+    }
+}
+
+/// 随机游走的次表面散射，颜色由scatter_color决定
+#[derive(Clone)]
+pub struct Subsurface {
+    /// 多次散射之后整体看上去的颜色
+    pub scatter_color: Color,
+    /// 每个通道(r, g, b)的平均自由程
+    pub radius: [f32; 3],
+}
+
+/// 头发、毛发的纤维散射，颜色是光穿过纤维一次剩下的比例，见bsdf::HairBsdf。
+/// 沿纤维的方向要靠物体的tangent给，Curves的tangent就是纤维的走向
+#[derive(Clone)]
+pub struct Hair {
+    /// 沿纤维方向高光的宽窄
+    pub roughness: f32,
+    /// 透过纤维的光绕着纤维散开的程度
+    pub azimuthal_roughness: f32,
+    /// 表皮鳞片的倾角（弧度），让高光偏离镜面方向，一般2°到4°
+    pub tilt: f32,
+}
+
+impl Default for Hair {
+    fn default() -> Self {
+        Self {
+            roughness: 0.3,
+            azimuthal_roughness: 0.3,
+            tilt: 2f32.to_radians(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -13,20 +119,338 @@ pub struct Material {
     pub color: Coloration,
     pub albedo: f32,
     pub surface: SurfaceType,
+    /// 盖在表面上的一层透明涂层，比如车漆和清漆
+    pub clearcoat: Option<ClearCoat>,
+    /// 影子捕捉面：透明背景下相机直接看到它时是透明的，只留下别的物体投在上面的影子和
+    /// 镜面反射（Reflective时）里的别的物体，用来把渲染的物体合成到照片上。
+    /// 别的光线看到的还是普通的表面
+    pub shadow_catcher: bool,
+    /// 镂空，见Opacity
+    pub opacity: Option<Opacity>,
+}
+
+impl Material {
+    /// albedo默认0.5，没有清漆，不是影子捕捉面
+    pub fn new(color: impl Into<Coloration>, surface: SurfaceType) -> Self {
+        Self {
+            color: color.into(),
+            albedo: 0.5,
+            surface,
+            clearcoat: None,
+            shadow_catcher: false,
+            opacity: None,
+        }
+    }
+
+    pub fn diffuse(color: impl Into<Coloration>) -> Self {
+        Self::new(color, SurfaceType::Diffuse)
+    }
+
+    pub fn reflective(color: impl Into<Coloration>, reflectivity: f32) -> Self {
+        Self::new(color, SurfaceType::Reflective { reflectivity })
+    }
+
+    /// 白色、没有色散的电介质
+    pub fn refractive(index: f32, transparency: f32) -> Self {
+        Self::new(
+            Color::white(),
+            SurfaceType::Refractive {
+                index,
+                transparency,
+                dispersion: 0.0,
+            },
+        )
+    }
+
+    pub fn principled(color: impl Into<Coloration>, principled: Principled) -> Self {
+        Self::new(color, SurfaceType::Principled(principled))
+    }
+
+    pub fn hair(color: impl Into<Coloration>, hair: Hair) -> Self {
+        Self::new(color, SurfaceType::Hair(hair))
+    }
+
+    pub fn with_albedo(mut self, albedo: f32) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    pub fn with_clearcoat(mut self, roughness: f32, ior: f32) -> Self {
+        self.clearcoat = Some(ClearCoat { roughness, ior });
+        self
+    }
+
+    pub fn with_shadow_catcher(mut self, shadow_catcher: bool) -> Self {
+        self.shadow_catcher = shadow_catcher;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: Opacity) -> Self {
+        self.opacity = Some(opacity);
+        self
+    }
+
+    /// 处处一样的自发光，不发光或者用了自发光贴图的是None
+    pub fn uniform_emission(&self) -> Option<Color> {
+        match &self.surface {
+            SurfaceType::Principled(p)
+                if p.emission_map.is_none() && p.emission != Color::black() =>
+            {
+                Some(p.emission)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// 按贴图镂空：光线打到透明的地方就当没打中，接着往前走。
+/// 一块四边形贴上树叶、栅栏、贴花的图就够了，不用建出每个洞
+#[derive(Clone)]
+pub struct Opacity {
+    /// 贴图的值是不透明度，1是实的，0是空的
+    pub map: Coloration,
+    /// 用贴图的alpha通道，不然用RGB的平均
+    pub alpha: bool,
+    /// 不透明度低于它的地方全空，其余的全实，边缘是硬的；
+    /// None时按不透明度随机穿过去，很多个样本平均下来是半透明的
+    pub threshold: Option<f32>,
+}
+
+#[derive(Clone)]
+pub struct ClearCoat {
+    pub roughness: f32,
+    pub ior: f32,
+}
+
+/// 按名字存材质，同一个名字的材质（连同里面的贴图）在内存里只有一份
+#[derive(Default)]
+pub struct MaterialRegistry {
+    materials: HashMap<String, Arc<Material>>,
+}
+
+impl MaterialRegistry {
+    /// 注册一个材质，返回的Arc直接给物体用；同名的会被替换掉
+    pub fn insert(&mut self, name: &str, material: Material) -> Arc<Material> {
+        let material = Arc::new(material);
+        self.materials.insert(name.to_string(), material.clone());
+        material
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Material>> {
+        self.materials.get(name).cloned()
+    }
+
+    /// 所有注册过的材质，顺序不固定
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<Material>)> {
+        self.materials.iter().map(|(name, m)| (name.as_str(), m))
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
 }
 
 #[derive(Clone)]
 pub enum Coloration {
     Color(Color),
     Texture(Texture),
+    /// 库的用户自己写的花纹
+    Procedural(Arc<dyn ProceduralTexture + Send + Sync>),
+    Ramp(Ramp),
+}
+
+/// 渐变色按什么取值
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RampInput {
+    U,
+    V,
+    /// 物体自己坐标系里的y
+    Height,
+    /// 法线和视线夹角的余弦，正对着是1，擦边是0
+    Facing,
+}
+
+/// 把一个数按几个色标插值成颜色，超出两头的用两头的颜色。
+/// 做天空（大球配Height）、卡通渲染（Facing配几个挨得很近的色标）、随手给材质加点变化
+#[derive(Clone, Debug)]
+pub struct Ramp {
+    pub input: RampInput,
+    /// (位置, 颜色)，按位置从小到大排
+    pub stops: Vec<(f32, Color)>,
+}
+
+impl Ramp {
+    pub fn new(input: RampInput, mut stops: Vec<(f32, Color)>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { input, stops }
+    }
+
+    pub fn color(&self, t: f32) -> Color {
+        let i = self.stops.partition_point(|(position, _)| *position <= t);
+        match (i.checked_sub(1).map(|i| self.stops[i]), self.stops.get(i)) {
+            (Some((a, low)), Some(&(b, high))) => {
+                let s = (t - a) / (b - a);
+                low * (1.0 - s) + high * s
+            }
+            (Some((_, c)), None) | (None, Some(&(_, c))) => c,
+            (None, None) => Color::black(),
+        }
+    }
+}
+
+/// 程序生成的纹理：按纹理坐标和打中的点算颜色。hit_point在物体自己的坐标系里，
+/// 物体被实例挪动、转动时花纹跟着它走。闭包也可以直接当纹理用
+pub trait ProceduralTexture {
+    fn color(&self, uv: &TextureCoords, hit_point: &Point) -> Color;
+}
+
+impl<F: Fn(&TextureCoords, &Point) -> Color> ProceduralTexture for F {
+    fn color(&self, uv: &TextureCoords, hit_point: &Point) -> Color {
+        self(uv, hit_point)
+    }
+}
+
+impl From<Color> for Coloration {
+    fn from(color: Color) -> Self {
+        Self::Color(color)
+    }
+}
+
+impl From<Texture> for Coloration {
+    fn from(texture: Texture) -> Self {
+        Self::Texture(texture)
+    }
+}
+
+/// 贴图用的图片，多个材质可以共用一张
+pub type Image = Arc<ImageBuffer<image::Rgba<u8>, std::vec::Vec<u8>>>;
+
+/// 贴图的像素：普通的8位图片，或者.hdr、.exr读进来的浮点图，亮度可以超过1、暗部也不会有色带
+#[derive(Clone)]
+pub enum TextureImage {
+    Rgba8(Image),
+    Rgb32F(Arc<HdrImage>),
+}
+
+impl TextureImage {
+    pub fn width(&self) -> u32 {
+        match self {
+            Self::Rgba8(image) => image.width(),
+            Self::Rgb32F(image) => image.width,
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            Self::Rgba8(image) => image.height(),
+            Self::Rgb32F(image) => image.height,
+        }
+    }
+}
+
+impl From<Image> for TextureImage {
+    fn from(image: Image) -> Self {
+        Self::Rgba8(image)
+    }
+}
+
+impl From<Arc<HdrImage>> for TextureImage {
+    fn from(image: Arc<HdrImage>) -> Self {
+        Self::Rgb32F(image)
+    }
+}
+
+/// 贴图里的数怎么解释
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorSpace {
+    /// 颜色贴图，存的是gamma编码过的sRGB，读的时候解码
+    Srgb,
+    /// 数据贴图（粗糙度、法线、高度），存的就是数值本身，当成sRGB解码会把数改掉。
+    /// 浮点图本来就是线性的，不管设的是哪个
+    Linear,
 }
 
 #[derive(Clone)]
 pub struct Texture {
-    pub image: ImageBuffer<image::Rgba<u8>, std::vec::Vec<u8>>,
+    pub image: TextureImage,
     pub offset_x: f32,
     pub offset_y: f32,
     pub scale: f32,
+    pub color_space: ColorSpace,
+}
+
+impl Texture {
+    pub fn new(image: impl Into<TextureImage>) -> Self {
+        Self {
+            image: image.into(),
+            offset_x: 0.0,
+            offset_y: 0.0,
+            scale: 1.0,
+            color_space: ColorSpace::Srgb,
+        }
+    }
+
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    /// 纹理坐标落在哪个像素上，超出的部分重复
+    fn pixel(&self, texture_coords: &TextureCoords) -> (u32, u32) {
+        (
+            wrap(
+                (texture_coords.u + self.offset_x) / self.scale,
+                self.image.width(),
+            ),
+            wrap(
+                (texture_coords.v + self.offset_y) / self.scale,
+                self.image.height(),
+            ),
+        )
+    }
+
+    /// 第(x, y)个像素的alpha，浮点图没有alpha，都是1
+    pub fn alpha(&self, x: u32, y: u32) -> f32 {
+        match &self.image {
+            TextureImage::Rgba8(image) => image.get_pixel(x, y).0[3] as f32 / 255.0,
+            TextureImage::Rgb32F(_) => 1.0,
+        }
+    }
+
+    /// 第(x, y)个像素，按color_space换成线性的值
+    pub fn texel(&self, x: u32, y: u32) -> Color {
+        match &self.image {
+            TextureImage::Rgba8(image) => {
+                let rgba = image.get_pixel(x, y).0;
+                match self.color_space {
+                    ColorSpace::Srgb => Color::from_rgba8(rgba),
+                    ColorSpace::Linear => Color::from_linear_rgba8(rgba),
+                }
+            }
+            TextureImage::Rgb32F(image) => image.pixels[(y * image.width + x) as usize],
+        }
+    }
+
+    /// 读一张图做贴图，同一个文件只读一次，见TextureCache
+    #[cfg(feature = "fs")]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> crate::Result<Self> {
+        super::TextureCache::global().texture(path)
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_offset(mut self, x: f32, y: f32) -> Self {
+        self.offset_x = x;
+        self.offset_y = y;
+        self
+    }
 }
 
 pub struct TextureCoords {
@@ -46,20 +470,40 @@ fn wrap(val: f32, bound: u32) -> u32 {
 }
 
 impl Coloration {
-    pub fn color(&self, texture_coords: &TextureCoords) -> Color {
+    pub fn procedural(texture: impl ProceduralTexture + Send + Sync + 'static) -> Self {
+        Self::Procedural(Arc::new(texture))
+    }
+
+    /// 渐变色按朝向取值时才要知道facing，别的时候给什么都行
+    pub fn uses_facing(&self) -> bool {
+        matches!(self, Self::Ramp(ramp) if ramp.input == RampInput::Facing)
+    }
+
+    /// alpha通道，只有8位的贴图有，别的都是1
+    pub fn alpha(&self, texture_coords: &TextureCoords) -> f32 {
+        match self {
+            Self::Texture(tex) => {
+                let (x, y) = tex.pixel(texture_coords);
+                tex.alpha(x, y)
+            }
+            _ => 1.0,
+        }
+    }
+
+    pub fn color(&self, texture_coords: &TextureCoords, hit_point: &Point, facing: f32) -> Color {
         match self {
             Self::Color(c) => *c,
             Self::Texture(tex) => {
-                let u = wrap(
-                    (texture_coords.u + tex.offset_x) / tex.scale,
-                    tex.image.width(),
-                );
-                let v = wrap(
-                    (texture_coords.v + tex.offset_y) / tex.scale,
-                    tex.image.height(),
-                );
-                Color::from_rgba8(tex.image.get_pixel(u, v).0)
+                let (x, y) = tex.pixel(texture_coords);
+                tex.texel(x, y)
             }
+            Self::Procedural(texture) => texture.color(texture_coords, hit_point),
+            Self::Ramp(ramp) => ramp.color(match ramp.input {
+                RampInput::U => texture_coords.u,
+                RampInput::V => texture_coords.v,
+                RampInput::Height => hit_point.y as f32,
+                RampInput::Facing => facing,
+            }),
         }
     }
 }
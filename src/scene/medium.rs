@@ -0,0 +1,158 @@
+use crate::color::Color;
+use crate::math::{Aabb, Float, Point};
+use crate::rendering::Ray;
+use crate::scene::Distance;
+
+/// 均匀的参与介质（雾、霾），bounds为None时充满场景的包围球（见Scene::bounding_sphere），
+/// 平行光和天光从球外面照进来，不会被无穷远的雾全挡住
+#[derive(Clone)]
+pub struct HomogeneousMedium {
+    /// 吸收系数σa
+    pub absorption: Color,
+    /// 散射系数σs
+    pub scattering: Color,
+    pub bounds: Option<Aabb>,
+}
+
+pub enum MediumSample {
+    /// 在distance处发生了散射，weight = σs * T / pdf
+    Scatter { distance: Distance, weight: Color },
+    /// 一路穿过去了，weight = T / pdf
+    Pass { weight: Color },
+}
+
+fn channels(color: &Color) -> [Float; 3] {
+    [color.r as Float, color.g as Float, color.b as Float]
+}
+
+fn to_color(c: [Float; 3]) -> Color {
+    Color {
+        r: c[0] as f32,
+        g: c[1] as f32,
+        b: c[2] as f32,
+    }
+}
+
+impl HomogeneousMedium {
+    fn sigma_t(&self) -> [Float; 3] {
+        let a = channels(&self.absorption);
+        let s = channels(&self.scattering);
+        [a[0] + s[0], a[1] + s[1], a[2] + s[2]]
+    }
+
+    /// 射线在[0, max_distance]里和介质重叠的那一段，scene_bounds是场景的包围球
+    fn segment(
+        &self,
+        ray: &Ray,
+        max_distance: Distance,
+        scene_bounds: &(Point, Distance),
+    ) -> Option<(Distance, Distance)> {
+        let (t0, t1) = match self.bounds {
+            None => {
+                let (center, radius) = *scene_bounds;
+                let oc = ray.origin - center;
+                let b = oc.dot(&ray.direction);
+                let discriminant = b * b - (oc.norm() - radius * radius);
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let root = discriminant.sqrt();
+                ((-b - root).max(0.0), -b + root)
+            }
+            Some(ref bounds) => bounds.hit(&ray.origin, &ray.direction)?,
+        };
+        let t1 = t1.min(max_distance);
+        if t0 < t1 {
+            Some((t0, t1))
+        } else {
+            None
+        }
+    }
+
+    /// 沿射线走max_distance的透射率
+    pub fn transmittance(
+        &self,
+        ray: &Ray,
+        max_distance: Distance,
+        scene_bounds: &(Point, Distance),
+    ) -> Color {
+        match self.segment(ray, max_distance, scene_bounds) {
+            None => Color::white(),
+            Some((t0, t1)) => {
+                let length = t1 - t0;
+                let sigma_t = self.sigma_t();
+                to_color([
+                    transmittance(sigma_t[0], length),
+                    transmittance(sigma_t[1], length),
+                    transmittance(sigma_t[2], length),
+                ])
+            }
+        }
+    }
+
+    /// 距离采样：随机挑一个通道按它的σt采样自由程，pdf取三个通道的平均
+    pub fn sample(
+        &self,
+        ray: &Ray,
+        max_distance: Distance,
+        scene_bounds: &(Point, Distance),
+        u: (Float, Float),
+    ) -> MediumSample {
+        let (t0, t1) = match self.segment(ray, max_distance, scene_bounds) {
+            None => {
+                return MediumSample::Pass {
+                    weight: Color::white(),
+                }
+            }
+            Some(segment) => segment,
+        };
+        let sigma_t = self.sigma_t();
+        let channel = ((u.0 * 3.0) as usize).min(2);
+        let step = if sigma_t[channel] > 0.0 {
+            -(1.0 - u.1).ln() / sigma_t[channel]
+        } else {
+            Float::INFINITY
+        };
+        if t0 + step < t1 {
+            let tr = [
+                transmittance(sigma_t[0], step),
+                transmittance(sigma_t[1], step),
+                transmittance(sigma_t[2], step),
+            ];
+            let pdf = (0..3).map(|c| sigma_t[c] * tr[c]).sum::<Float>() / 3.0;
+            let sigma_s = channels(&self.scattering);
+            MediumSample::Scatter {
+                distance: t0 + step,
+                weight: to_color([
+                    sigma_s[0] * tr[0] / pdf,
+                    sigma_s[1] * tr[1] / pdf,
+                    sigma_s[2] * tr[2] / pdf,
+                ]),
+            }
+        } else {
+            let length = t1 - t0;
+            let tr = [
+                transmittance(sigma_t[0], length),
+                transmittance(sigma_t[1], length),
+                transmittance(sigma_t[2], length),
+            ];
+            let pdf = tr.iter().sum::<Float>() / 3.0;
+            if pdf <= 0.0 {
+                return MediumSample::Pass {
+                    weight: Color::black(),
+                };
+            }
+            MediumSample::Pass {
+                weight: to_color([tr[0] / pdf, tr[1] / pdf, tr[2] / pdf]),
+            }
+        }
+    }
+}
+
+fn transmittance(sigma_t: Float, distance: Distance) -> Float {
+    if sigma_t <= 0.0 {
+        1.0
+    } else {
+        (-sigma_t * distance).exp()
+    }
+}
@@ -1,15 +1,154 @@
+pub mod animation;
+mod builder;
+pub mod camera;
+#[cfg(feature = "fs")]
+mod file;
 pub mod item;
 pub mod light;
 pub mod material;
+pub mod medium;
+pub mod presets;
+#[cfg(feature = "scripting")]
+mod rhai_script;
+#[cfg(feature = "fs")]
+mod script;
+pub mod stress;
+#[cfg(feature = "fs")]
+mod texture_cache;
+mod uv_checker;
+mod validate;
 
-use crate::rendering::{Intersectable, Light};
+use crate::accel::AcceleratorKind;
+use crate::math::{Aabb, Float, Point};
+use crate::rendering::{Intersectable, Light, RenderMode, RenderSettings};
+use camera::Camera;
+use item::Tagged;
+use light::{EmissiveLight, EmissiveSurface, LightGroup};
+use material::MaterialRegistry;
+use medium::HomogeneousMedium;
 
-pub type Distance = f64;
+pub use builder::{MaterialRef, SceneBuilder};
+#[cfg(feature = "fs")]
+pub use file::{load_scene, load_scene_with_files, parse_scene};
+#[cfg(feature = "scripting")]
+pub use rhai_script::parse_rhai_scene;
+#[cfg(feature = "fs")]
+pub use texture_cache::TextureCache;
+pub use validate::Validation;
+
+pub type Distance = Float;
 
 pub struct Scene {
     pub width: u32,
     pub height: u32,
     pub fov: Distance,
+    pub camera: Camera,
     pub items: Vec<Box<dyn Intersectable + Send + Sync>>,
     pub lights: Vec<Box<dyn Light + Send + Sync>>,
+    /// 场景里共用的材质
+    pub materials: MaterialRegistry,
+    /// 充满场景（或者一块区域）的雾
+    pub medium: Option<HomogeneousMedium>,
+    /// 光谱模式：光线碰到有色散的电介质时随机选一个波长，色散才能表现出来
+    pub spectral: bool,
+    /// 随机数种子，同一个种子渲染出来的图每次都一样
+    pub seed: u64,
+    /// 正常渲染还是输出法线、深度这些调试用的图
+    pub mode: RenderMode,
+    /// 透明背景：相机光线什么都没打中的地方alpha是0，方便合成到别的背景上
+    pub transparent: bool,
+    /// 样本数、路径深度这些质量设置
+    pub settings: RenderSettings,
+    /// 光源分组，见light_group_pass
+    pub light_groups: Vec<LightGroup>,
+}
+
+impl Scene {
+    /// 按现在的顺序给每个物体编号，第一个是1，物体ID通道用。
+    /// 在加速之前调用；SceneBuilder和场景文件搭出来的场景已经编过号了
+    pub fn assign_object_ids(&mut self) {
+        self.items = self
+            .items
+            .drain(..)
+            .enumerate()
+            .map(|(index, item)| -> Box<dyn Intersectable + Send + Sync> {
+                Box::new(Tagged {
+                    id: index as u32 + 1,
+                    item,
+                })
+            })
+            .collect();
+    }
+
+    /// 把自发光均匀的球、四边形和三角形按物体的编号收集成EmissiveLight加到光源里，
+    /// 直接光照时就会朝它们采样，不用等路径碰巧打中。要在assign_object_ids之后调用，
+    /// 没有编号的物体不收集；SceneBuilder和场景文件搭出来的场景已经收集过了
+    pub fn add_emissive_lights(&mut self) {
+        let mut surfaces = Vec::new();
+        for item in &self.items {
+            item.emissive_surfaces(&mut surfaces);
+        }
+        let mut by_id: Vec<(u32, Vec<EmissiveSurface>)> = Vec::new();
+        for surface in surfaces {
+            let Some(id) = surface.object_id else {
+                continue;
+            };
+            match by_id.iter_mut().find(|(i, _)| *i == id) {
+                Some((_, list)) => list.push(surface),
+                None => by_id.push((id, vec![surface])),
+            }
+        }
+        for (id, surfaces) in by_id {
+            self.lights.push(Box::new(EmissiveLight::new(id, surfaces)));
+        }
+    }
+
+    /// 叫name的光源分组的下标，没有就新建一组
+    pub fn light_group_index(&mut self, name: &str) -> usize {
+        match self.light_groups.iter().position(|g| g.name == name) {
+            Some(index) => index,
+            None => {
+                self.light_groups.push(LightGroup {
+                    name: name.to_string(),
+                    ..LightGroup::default()
+                });
+                self.light_groups.len() - 1
+            }
+        }
+    }
+
+    /// 第light个光源或者编号object的发光物体在第几组，哪组都不在是None。
+    /// 会发光的物体当成的光源按物体的编号找
+    pub fn light_group_of(&self, light: Option<usize>, object: Option<u32>) -> Option<usize> {
+        let object = object.or_else(|| light.and_then(|i| self.lights[i].object_id()));
+        self.light_groups.iter().position(|group| {
+            light.is_some_and(|l| group.lights.contains(&l))
+                || object.is_some_and(|o| group.objects.contains(&o))
+        })
+    }
+
+    /// 包住所有有包围盒的物体和相机的球，平行光和天光从它外面照进来；平面这种无限大的不算
+    pub fn bounding_sphere(&self) -> (Point, Distance) {
+        let camera = self.camera.transform_at(0.0).point(&Point::zero());
+        let bounds = self
+            .items
+            .iter()
+            .filter_map(|item| item.bounds())
+            .fold(Aabb::new(camera, camera), |a, b| a.union(&b));
+        let radius = (bounds.max - bounds.min).length() * 0.5;
+        let center = bounds.min + (bounds.max - bounds.min) * 0.5;
+        (center, if radius > 0.0 { radius } else { 1.0 })
+    }
+
+    /// 把有包围盒的物体收进一个加速结构里；平面这种无限大的和体积还是单独放着
+    pub fn accelerate(&mut self, kind: AcceleratorKind) {
+        let (bounded, mut rest): (Vec<_>, Vec<_>) = self
+            .items
+            .drain(..)
+            .partition(|item| item.bounds().is_some() && item.volume().is_none());
+        if !bounded.is_empty() {
+            rest.push(kind.build(bounded));
+        }
+        self.items = rest;
+    }
 }
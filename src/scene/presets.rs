@@ -0,0 +1,347 @@
+use super::camera::Camera;
+use super::item::{Mesh, MeshData};
+use super::material::{Material, Principled, SurfaceType, Texture};
+use super::stress::Random;
+use super::SceneBuilder;
+use crate::color::Color;
+use crate::math::{consts::PI, Float, Point, Transform, Vector3};
+use crate::rendering::RenderSettings;
+use std::sync::Arc;
+
+fn random_color(random: &mut Random) -> Color {
+    Color::new(
+        random.next() as f32,
+        random.next() as f32,
+        random.next() as f32,
+    )
+}
+
+/// 《Ray Tracing in One Weekend》封面那个场景：地面上一片随机的小球，漫反射、
+/// 金属、玻璃大约按8:1.5:0.5分，中间三个大球。原书的地面是半径1000的大球，
+/// 这里换成平面；原书没有光源只有天空，这里是一个均匀的天光。
+/// 同一个seed生成的场景每次都一样，可以当性能的基准
+pub fn random_spheres(seed: u64) -> SceneBuilder {
+    let mut random = Random(seed);
+    let metal = |color: Color, fuzz: f32| {
+        Material::new(
+            color,
+            SurfaceType::Microfacet {
+                roughness_u: fuzz,
+                roughness_v: fuzz,
+                rotation: 0.0,
+            },
+        )
+    };
+    let glass = || Material::refractive(1.5, 1.0);
+
+    let from = Point::new(13.0, 2.0, 3.0);
+    let direction = (Point::zero() - from).normalize();
+    let view = Transform {
+        translation: from - Point::zero(),
+        rotation: Vector3::new(direction.y.asin(), (-direction.x).atan2(-direction.z), 0.0),
+        scale: 1.0,
+    };
+    let mut builder = SceneBuilder::new()
+        .size(1200, 675)
+        .fov(20.0)
+        .seed(seed)
+        .camera(Camera {
+            start: view,
+            end: view,
+            ..Camera::default()
+        })
+        .add_plane(
+            Point::zero(),
+            Vector3::new(0.0, -1.0, 0.0),
+            Material::diffuse(Color::new(0.5, 0.5, 0.5)).with_albedo(1.0),
+        )
+        .add_environment_light(Color::new(0.7, 0.8, 1.0), 1.0, Vec::new());
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choice = random.next();
+            let center = Point::new(
+                a as Float + 0.9 * random.next(),
+                0.2,
+                b as Float + 0.9 * random.next(),
+            );
+            // 别和右边那个大球挤在一起
+            if (center - Point::new(4.0, 0.2, 0.0)).length() <= 0.9 {
+                continue;
+            }
+            let material = if choice < 0.8 {
+                let albedo = random_color(&mut random) * random_color(&mut random);
+                Material::diffuse(albedo).with_albedo(1.0)
+            } else if choice < 0.95 {
+                let albedo = random_color(&mut random) * 0.5 + Color::new(0.5, 0.5, 0.5);
+                metal(albedo, random.next() as f32 * 0.5)
+            } else {
+                glass()
+            };
+            builder = builder.add_sphere(center, 0.2, material);
+        }
+    }
+
+    builder
+        .add_sphere(Point::new(0.0, 1.0, 0.0), 1.0, glass())
+        .add_sphere(
+            Point::new(-4.0, 1.0, 0.0),
+            1.0,
+            Material::diffuse(Color::new(0.4, 0.2, 0.1)).with_albedo(1.0),
+        )
+        .add_sphere(
+            Point::new(4.0, 1.0, 0.0),
+            1.0,
+            metal(Color::new(0.7, 0.6, 0.5), 0.0),
+        )
+}
+
+/// Cornell大学测量的数据（毫米）换成米
+fn cornell_point(x: Float, y: Float, z: Float) -> Point {
+    Point::new(x * 0.001, y * 0.001, z * 0.001)
+}
+
+/// 几个四边形拼成一个网格，顶点顺序调整成法线朝着center（toward）或者背着它
+fn panels(quads: &[[Point; 4]], center: Point, toward: bool) -> MeshData {
+    let mut mesh = MeshData::default();
+    for quad in quads {
+        let normal = (quad[1] - quad[0]).cross(&(quad[2] - quad[0]));
+        let mut corners = *quad;
+        if (normal.dot(&(center - quad[0])) > 0.0) != toward {
+            corners.reverse();
+        }
+        let base = mesh.positions.len();
+        mesh.positions.extend_from_slice(&corners);
+        mesh.triangles.push([base, base + 1, base + 2]);
+        mesh.triangles.push([base, base + 2, base + 3]);
+    }
+    mesh
+}
+
+/// Cornell盒子：按Cornell大学公布的尺寸，墙、两个方块、天花板上的灯都在原来的位置，
+/// 颜色是常用的那组RGB近似值，灯的radiance是(17, 12, 4)。相机从开口那一面正对着看，
+/// 左边红墙右边绿墙。用来检查全局光照和墙面之间的渗色对不对
+pub fn cornell_box() -> SceneBuilder {
+    let p = cornell_point;
+    let white = Material::diffuse(Color::new(0.725, 0.71, 0.68)).with_albedo(1.0);
+    let red = Material::diffuse(Color::new(0.63, 0.065, 0.05)).with_albedo(1.0);
+    let green = Material::diffuse(Color::new(0.14, 0.45, 0.091)).with_albedo(1.0);
+    let room = p(278.0, 274.4, 279.6);
+
+    let walls = [
+        // 地面、天花板、后墙
+        [
+            p(552.8, 0.0, 0.0),
+            p(0.0, 0.0, 0.0),
+            p(0.0, 0.0, 559.2),
+            p(549.6, 0.0, 559.2),
+        ],
+        [
+            p(556.0, 548.8, 0.0),
+            p(556.0, 548.8, 559.2),
+            p(0.0, 548.8, 559.2),
+            p(0.0, 548.8, 0.0),
+        ],
+        [
+            p(549.6, 0.0, 559.2),
+            p(0.0, 0.0, 559.2),
+            p(0.0, 548.8, 559.2),
+            p(556.0, 548.8, 559.2),
+        ],
+    ];
+    let red_wall = [[
+        p(552.8, 0.0, 0.0),
+        p(549.6, 0.0, 559.2),
+        p(556.0, 548.8, 559.2),
+        p(556.0, 548.8, 0.0),
+    ]];
+    let green_wall = [[
+        p(0.0, 0.0, 559.2),
+        p(0.0, 0.0, 0.0),
+        p(0.0, 548.8, 0.0),
+        p(0.0, 548.8, 559.2),
+    ]];
+    // 方块的侧面是底面四个角往上拉出来的
+    let block = |corners: [Point; 4], height: Float| {
+        let top = corners.map(|c| c + Vector3::new(0.0, height * 0.001, 0.0));
+        let mut quads = vec![top];
+        for i in 0..4 {
+            let j = (i + 1) % 4;
+            quads.push([corners[i], corners[j], top[j], top[i]]);
+        }
+        let center = Point::zero()
+            + corners
+                .iter()
+                .map(|c| *c - Point::zero())
+                .fold(Vector3::zero(), |sum, c| sum + c)
+                * 0.25
+            + Vector3::new(0.0, height * 0.0005, 0.0);
+        panels(&quads, center, false)
+    };
+    let short_block = block(
+        [
+            p(130.0, 0.0, 65.0),
+            p(82.0, 0.0, 225.0),
+            p(240.0, 0.0, 272.0),
+            p(290.0, 0.0, 114.0),
+        ],
+        165.0,
+    );
+    let tall_block = block(
+        [
+            p(423.0, 0.0, 247.0),
+            p(265.0, 0.0, 296.0),
+            p(314.0, 0.0, 456.0),
+            p(472.0, 0.0, 406.0),
+        ],
+        330.0,
+    );
+
+    // 灯贴在天花板下面一点，免得和天花板重合
+    let (edge_u, edge_v) = (Vector3::new(0.0, 0.0, 0.105), Vector3::new(-0.13, 0.0, 0.0));
+    let area = edge_u.cross(&edge_v).length();
+    // 相机在开口外面朝+z看，原来的胶片是35毫米焦距、25毫米见方
+    let view = Transform {
+        translation: p(278.0, 273.0, -800.0) - Point::zero(),
+        rotation: Vector3::new(0.0, PI, 0.0),
+        scale: 1.0,
+    };
+    SceneBuilder::new()
+        .size(512, 512)
+        .fov((2.0 * (12.5 as Float / 35.0).atan()).to_degrees())
+        .camera(Camera {
+            start: view,
+            end: view,
+            ..Camera::default()
+        })
+        .add_item(Mesh::new(panels(&walls, room, true), white.clone().into()))
+        .add_item(Mesh::new(panels(&red_wall, room, true), red.into()))
+        .add_item(Mesh::new(panels(&green_wall, room, true), green.into()))
+        .add_item(Mesh::new(short_block, white.clone().into()))
+        .add_item(Mesh::new(tall_block, white.into()))
+        .add_quad_light(
+            p(343.0, 548.7, 227.0),
+            edge_u,
+            edge_v,
+            Color::new(17.0, 12.0, 4.0),
+            (PI * area) as f32,
+        )
+}
+
+/// 材质展示板每行几个球
+pub const SWEEP_STEPS: usize = 7;
+
+/// 材质展示板的一行改的是哪个参数
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sweep {
+    /// Principled的roughness从0到1
+    Roughness,
+    /// 全透射的玻璃，折射率从1到2
+    Ior,
+    /// 折射率1.5，透射的比例从0到1
+    Transparency,
+    /// 灰色漫反射的球自己发光，从0到2
+    Emission,
+}
+
+impl Sweep {
+    pub const ALL: [Sweep; 4] = [
+        Sweep::Roughness,
+        Sweep::Ior,
+        Sweep::Transparency,
+        Sweep::Emission,
+    ];
+
+    /// t从0到1
+    pub fn material(self, t: f32) -> Material {
+        let glass = |ior: f32, transmission: f32| {
+            Material::principled(
+                Color::white(),
+                Principled {
+                    roughness: 0.0,
+                    transmission,
+                    ior,
+                    ..Principled::default()
+                },
+            )
+        };
+        let base = Color::new(0.8, 0.35, 0.2);
+        match self {
+            Sweep::Roughness => Material::principled(
+                base,
+                Principled {
+                    roughness: t,
+                    ..Principled::default()
+                },
+            ),
+            // 用Principled的透射而不是Refractive：透射比例低的时候剩下的是白色的塑料，
+            // Refractive的transparency只会把整个球调暗
+            Sweep::Ior => glass(1.0 + t, 1.0),
+            Sweep::Transparency => glass(1.5, t),
+            Sweep::Emission => Material::principled(
+                Color::new(0.5, 0.5, 0.5),
+                Principled {
+                    emission: base * (2.0 * t),
+                    ..Principled::default()
+                },
+            ),
+        }
+    }
+}
+
+/// 黑白格子，每格size大
+fn checker(size: f32) -> Texture {
+    let image = image::ImageBuffer::from_fn(2, 2, |x, y| {
+        let c = if (x + y) % 2 == 0 { 200 } else { 40 };
+        image::Rgba([c, c, c, 255])
+    });
+    Texture::new(Arc::new(image)).with_scale(size * 2.0)
+}
+
+/// 材质展示板：每行一排球，从左到右把一个参数从最小扫到最大，行的顺序和Sweep::ALL一样，
+/// 从上往下。背景是灰色的天光和一块黑白格子的墙，方便看折射；另外有一盏斜上方的平行光
+/// 给高光。改了材质的代码以后渲染一张，和以前的比一比
+pub fn material_grid() -> SceneBuilder {
+    let rows = Sweep::ALL.len();
+    let spacing: Float = 1.0;
+    let width = SWEEP_STEPS as Float * spacing;
+    let height = rows as Float * spacing;
+    let distance: Float = 12.0;
+    let view = Transform {
+        translation: Vector3::new(0.0, 0.0, distance),
+        rotation: Vector3::zero(),
+        scale: 1.0,
+    };
+    let mut builder = SceneBuilder::new()
+        .size((SWEEP_STEPS * 120) as u32, (rows * 120) as u32)
+        .fov((2.0 * (height * 0.5 / distance).atan()).to_degrees())
+        // 玻璃每碰一次都同时追反射和折射，一排挨着的玻璃球让深度大了以后分叉得太多
+        .settings(RenderSettings {
+            max_depth: 8,
+            ..RenderSettings::default()
+        })
+        .camera(Camera {
+            start: view,
+            end: view,
+            ..Camera::default()
+        })
+        .add_plane(
+            Point::new(0.0, 0.0, -2.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            Material::diffuse(checker(0.5)).with_albedo(1.0),
+        )
+        .add_directional_light(Vector3::new(-0.3, -0.6, -1.0), Color::white(), 2.0)
+        .add_environment_light(Color::new(0.5, 0.5, 0.5), 1.0, Vec::new());
+    for (row, sweep) in Sweep::ALL.iter().enumerate() {
+        for column in 0..SWEEP_STEPS {
+            let t = column as f32 / (SWEEP_STEPS - 1) as f32;
+            let center = Point::new(
+                (column as Float + 0.5) * spacing - width * 0.5,
+                height * 0.5 - (row as Float + 0.5) * spacing,
+                0.0,
+            );
+            builder = builder.add_sphere(center, spacing * 0.4, sweep.material(t));
+        }
+    }
+    builder
+}
@@ -0,0 +1,280 @@
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use rhai::{Array, Dynamic, Engine, EvalAltResult, INT};
+
+use super::builder::SceneBuilder;
+use super::camera::Camera;
+use super::material::{Material, Principled};
+use super::Scene;
+use crate::color::Color;
+use crate::math::{consts::PI, Float, Point, Transform, Vector3};
+use crate::{Error, Result};
+
+// 用Rhai脚本搭场景，脚本里的函数都转给SceneBuilder；向量和颜色写成三个数的数组，
+// 整数和小数都可以：
+//
+//   size(640, 360);
+//   camera([0, 1, 4]);
+//   for i in 0..5 {
+//       let name = "m" + i;
+//       diffuse(name, [rand(), rand(), rand()]);
+//       sphere([i * 2 - 4, 0, -5], 0.8, name);
+//   }
+//   plane([0, -1, 0], [0, -1, 0], "m0");
+//   sky([1, 1, 1], 1);
+//
+// 场景：size(宽, 高)、fov(度)、samples(个数)、seed(种子)、camera(位置)、camera(位置, 旋转的角度)
+// 材质：diffuse(名字, 颜色)、mirror(名字, 颜色, 反射率)、glass(名字, 折射率)、
+//       metal(名字, 颜色, 粗糙度)、emissive(名字, 颜色, 强度)
+// 物体：sphere(中心, 半径, 材质)、plane(一点, 法线, 材质)、quad(一个角, 边, 边, 材质)
+// 光源：point_light(位置, 颜色, 强度)、sun(方向, 颜色, 强度)、sky(颜色, 强度)
+// rand()是[0, 1)，rand(a, b)是[a, b)，和场景文件里的一样每次运行结果都一样
+
+/// 脚本最多执行多少步，死循环靠它停下
+const MAX_OPERATIONS: u64 = 1 << 28;
+/// 函数调用和表达式最多嵌套几层
+const MAX_DEPTH: usize = 256;
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+fn number(value: &Dynamic) -> ScriptResult<Float> {
+    if let Ok(v) = value.as_float() {
+        return Ok(v as Float);
+    }
+    value
+        .as_int()
+        .map(|v| v as Float)
+        .map_err(|kind| format!("expected a number, got {}", kind).into())
+}
+
+fn triple(values: &Array) -> ScriptResult<[Float; 3]> {
+    match values.as_slice() {
+        [x, y, z] => Ok([number(x)?, number(y)?, number(z)?]),
+        _ => Err(format!("expected 3 numbers, got {}", values.len()).into()),
+    }
+}
+
+fn vector(values: &Array) -> ScriptResult<Vector3> {
+    let [x, y, z] = triple(values)?;
+    Ok(Vector3::new(x, y, z))
+}
+
+fn point(values: &Array) -> ScriptResult<Point> {
+    let [x, y, z] = triple(values)?;
+    Ok(Point::new(x, y, z))
+}
+
+fn color(values: &Array) -> ScriptResult<Color> {
+    let [r, g, b] = triple(values)?;
+    Ok(Color::new(r as f32, g as f32, b as f32))
+}
+
+fn camera(transform: Transform) -> Camera {
+    Camera {
+        start: transform,
+        end: transform,
+        ..Camera::default()
+    }
+}
+
+/// 脚本里的函数共用的状态：搭了一半的场景和随机数
+#[derive(Clone)]
+struct State {
+    builder: Rc<RefCell<Option<SceneBuilder>>>,
+    random: Rc<Cell<u64>>,
+}
+
+impl State {
+    fn update(&self, f: impl FnOnce(SceneBuilder) -> SceneBuilder) -> ScriptResult<()> {
+        let mut builder = self.builder.borrow_mut();
+        *builder = builder.take().map(f);
+        Ok(())
+    }
+
+    /// splitmix64
+    fn rand(&self) -> Float {
+        let random = self.random.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.random.set(random);
+        let mut z = random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as Float / (1u64 << 53) as Float
+    }
+}
+
+fn register(engine: &mut Engine, state: &State) {
+    let s = state.clone();
+    engine.register_fn("size", move |width: INT, height: INT| -> ScriptResult<()> {
+        let (width, height) = (u32::try_from(width), u32::try_from(height));
+        match (width, height) {
+            (Ok(width), Ok(height)) => s.update(|b| b.size(width, height)),
+            _ => Err("image size out of range".into()),
+        }
+    });
+    let s = state.clone();
+    engine.register_fn("fov", move |fov: Dynamic| -> ScriptResult<()> {
+        let fov = number(&fov)?;
+        s.update(|b| b.fov(fov))
+    });
+    let s = state.clone();
+    engine.register_fn("samples", move |samples: INT| -> ScriptResult<()> {
+        let samples = u32::try_from(samples).map_err(|_| "samples out of range")?;
+        s.update(|b| b.samples(samples))
+    });
+    let s = state.clone();
+    engine.register_fn("seed", move |seed: INT| s.update(|b| b.seed(seed as u64)));
+    let s = state.clone();
+    engine.register_fn("camera", move |position: Array| -> ScriptResult<()> {
+        let transform = Transform::translate(vector(&position)?);
+        s.update(|b| b.camera(camera(transform)))
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "camera",
+        move |position: Array, rotation: Array| -> ScriptResult<()> {
+            let transform = Transform {
+                translation: vector(&position)?,
+                rotation: vector(&rotation)? * (PI / 180.0),
+                ..Transform::identity()
+            };
+            s.update(|b| b.camera(camera(transform)))
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("diffuse", move |name: &str, c: Array| -> ScriptResult<()> {
+        let material = Material::diffuse(color(&c)?);
+        s.update(|b| b.material(name, material))
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "mirror",
+        move |name: &str, c: Array, reflectivity: Dynamic| -> ScriptResult<()> {
+            let material = Material::reflective(color(&c)?, number(&reflectivity)? as f32);
+            s.update(|b| b.material(name, material))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "glass",
+        move |name: &str, ior: Dynamic| -> ScriptResult<()> {
+            let material = Material::refractive(number(&ior)? as f32, 1.0);
+            s.update(|b| b.material(name, material))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "metal",
+        move |name: &str, c: Array, roughness: Dynamic| -> ScriptResult<()> {
+            let principled = Principled {
+                metallic: 1.0,
+                roughness: number(&roughness)? as f32,
+                ..Principled::default()
+            };
+            let material = Material::principled(color(&c)?, principled);
+            s.update(|b| b.material(name, material))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "emissive",
+        move |name: &str, c: Array, strength: Dynamic| -> ScriptResult<()> {
+            let principled = Principled {
+                emission: color(&c)?,
+                emission_strength: number(&strength)? as f32,
+                ..Principled::default()
+            };
+            let material = Material::principled(Color::black(), principled);
+            s.update(|b| b.material(name, material))
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "sphere",
+        move |center: Array, radius: Dynamic, material: &str| -> ScriptResult<()> {
+            let (center, radius) = (point(&center)?, number(&radius)?);
+            s.update(|b| b.add_sphere(center, radius, material))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "plane",
+        move |position: Array, normal: Array, material: &str| -> ScriptResult<()> {
+            let (position, normal) = (point(&position)?, vector(&normal)?);
+            s.update(|b| b.add_plane(position, normal, material))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "quad",
+        move |corner: Array, edge_u: Array, edge_v: Array, material: &str| -> ScriptResult<()> {
+            let corner = point(&corner)?;
+            let (edge_u, edge_v) = (vector(&edge_u)?, vector(&edge_v)?);
+            s.update(|b| b.add_quad(corner, edge_u, edge_v, material))
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn(
+        "point_light",
+        move |position: Array, c: Array, intensity: Dynamic| -> ScriptResult<()> {
+            let (position, c) = (point(&position)?, color(&c)?);
+            let intensity = number(&intensity)? as f32;
+            s.update(|b| b.add_spherical_light(position, 0.0, c, intensity))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "sun",
+        move |direction: Array, c: Array, intensity: Dynamic| -> ScriptResult<()> {
+            let (direction, c) = (vector(&direction)?, color(&c)?);
+            let intensity = number(&intensity)? as f32;
+            s.update(|b| b.add_directional_light(direction, c, intensity))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "sky",
+        move |c: Array, intensity: Dynamic| -> ScriptResult<()> {
+            let (c, intensity) = (color(&c)?, number(&intensity)? as f32);
+            s.update(|b| b.add_environment_light(c, intensity, Vec::new()))
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("rand", move || s.rand() as rhai::FLOAT);
+    let s = state.clone();
+    engine.register_fn(
+        "rand",
+        move |low: Dynamic, high: Dynamic| -> ScriptResult<rhai::FLOAT> {
+            let (low, high) = (number(&low)?, number(&high)?);
+            Ok((low + (high - low) * s.rand()) as rhai::FLOAT)
+        },
+    );
+}
+
+/// 运行Rhai脚本搭场景，脚本的语法和能用的函数见文件开头
+pub fn parse_rhai_scene(source: &str) -> Result<Scene> {
+    let state = State {
+        builder: Rc::new(RefCell::new(Some(SceneBuilder::new()))),
+        random: Rc::new(Cell::new(0)),
+    };
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_DEPTH);
+    engine.set_max_expr_depths(MAX_DEPTH, MAX_DEPTH);
+    register(&mut engine, &state);
+    engine
+        .run(source)
+        .map_err(|e| Error::Parse(e.to_string()))?;
+    // 脚本跑完以后engine里的函数还拿着state，先把engine扔掉
+    drop(engine);
+    let builder = state.builder.borrow_mut().take();
+    builder
+        .ok_or_else(|| Error::Parse("script did not build a scene".into()))?
+        .build()
+}
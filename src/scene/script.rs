@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use crate::math::{consts::PI, Float};
+use crate::{Error, Result};
+
+// 场景文件里的一点过程化语法，在解析之前展开成普通的行：
+//
+//   let n = 4                          # 定义变量
+//   for i 0 n                          # i从0到n - 1，可选第四个数是步长，上下界里不能有空格
+//     material m{i} diffuse color {rand()} {rand()} {rand()}
+//     sphere {i * 2 - n} 0 -5 0.8 m{i}
+//   end
+//
+// {}里是表达式：+ - * / %、括号、变量、pi，以及函数sin、cos、sqrt、abs、floor、min、max、
+// rand()（[0, 1)）和rand(a, b)。rand每次展开的结果都一样，同一个文件渲染出来也一样
+
+/// 展开出来的行太多说明循环写错了
+const MAX_LINES: usize = 1 << 20;
+/// 所有循环加起来最多转几圈，循环体不产生行时靠它停下
+const MAX_ITERATIONS: usize = 1 << 20;
+/// 表达式的括号、负号和for最多嵌套几层，再深递归会把栈用完
+const MAX_DEPTH: usize = 256;
+
+fn at_line(line: usize, e: Error) -> Error {
+    Error::parse(format!("line {}: {}", line + 1, e))
+}
+
+struct Expander<'a> {
+    lines: Vec<(usize, &'a str)>,
+    variables: HashMap<String, Float>,
+    random: u64,
+    out: Vec<(usize, String)>,
+    iterations: usize,
+    depth: usize,
+}
+
+impl Expander<'_> {
+    /// splitmix64
+    fn rand(&mut self) -> Float {
+        self.random = self.random.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as Float / (1u64 << 53) as Float
+    }
+
+    fn eval(&mut self, text: &str) -> Result<Float> {
+        let mut parser = Expression {
+            chars: text.chars().collect(),
+            at: 0,
+            depth: 0,
+        };
+        let value = parser.sum(self)?;
+        parser.skip_spaces();
+        if parser.at < parser.chars.len() {
+            return Err(Error::parse(format!("unexpected text in {:?}", text)));
+        }
+        Ok(value)
+    }
+
+    /// 把{}里的表达式换成它的值
+    fn substitute(&mut self, line: &str) -> Result<String> {
+        let mut out = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::parse("missing '}'"))?;
+            out += &rest[..start];
+            out += &self.eval(&rest[start + 1..start + end])?.to_string();
+            rest = &rest[start + end + 1..];
+        }
+        Ok(out + rest)
+    }
+
+    /// 找到和start处的for配对的end
+    fn matching_end(&self, start: usize) -> Result<usize> {
+        let mut depth = 0;
+        for i in start..self.lines.len() {
+            match self.lines[i].1.split_whitespace().next() {
+                Some("for") => depth += 1,
+                Some("end") => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(at_line(
+            self.lines[start].0,
+            Error::parse("for without end"),
+        ))
+    }
+
+    /// 展开lines[from..to]
+    fn expand(&mut self, from: usize, to: usize) -> Result<()> {
+        if self.depth >= MAX_DEPTH {
+            return Err(at_line(
+                self.lines[from.saturating_sub(1)].0,
+                Error::parse("for nested too deeply"),
+            ));
+        }
+        self.depth += 1;
+        let result = self.expand_lines(from, to);
+        self.depth -= 1;
+        result
+    }
+
+    fn expand_lines(&mut self, from: usize, to: usize) -> Result<()> {
+        let mut i = from;
+        while i < to {
+            let (number, line) = self.lines[i];
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["let", name, "=", ..] => {
+                    let expression = line.split_once('=').map_or("", |(_, e)| e);
+                    let value = self.eval(expression).map_err(|e| at_line(number, e))?;
+                    self.variables.insert(name.to_string(), value);
+                }
+                ["for", name, range @ ..] if (2..=3).contains(&range.len()) => {
+                    let end = self.matching_end(i)?;
+                    let mut bounds = [0.0, 0.0, 1.0];
+                    for (bound, text) in bounds.iter_mut().zip(range) {
+                        *bound = self.eval(text).map_err(|e| at_line(number, e))?;
+                    }
+                    let [first, last, step] = bounds;
+                    if !bounds.iter().all(|b| b.is_finite()) {
+                        return Err(at_line(number, Error::parse("for bounds must be finite")));
+                    }
+                    if step == 0.0 {
+                        return Err(at_line(number, Error::parse("for step is 0")));
+                    }
+                    let mut value = first;
+                    while (step > 0.0 && value < last) || (step < 0.0 && value > last) {
+                        self.iterations += 1;
+                        if self.iterations > MAX_ITERATIONS {
+                            return Err(at_line(number, Error::parse("loops run too many times")));
+                        }
+                        self.variables.insert(name.to_string(), value);
+                        self.expand(i + 1, end)?;
+                        if value + step == value {
+                            return Err(at_line(
+                                number,
+                                Error::parse("for step is too small to change the variable"),
+                            ));
+                        }
+                        value += step;
+                    }
+                    i = end;
+                }
+                ["for", ..] => return Err(at_line(number, Error::parse("bad for"))),
+                ["end"] => return Err(at_line(number, Error::parse("end without for"))),
+                _ => {
+                    let line = self.substitute(line).map_err(|e| at_line(number, e))?;
+                    self.out.push((number, line));
+                    if self.out.len() > MAX_LINES {
+                        return Err(at_line(
+                            number,
+                            Error::parse("scene expands to too many lines"),
+                        ));
+                    }
+                }
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+/// 递归下降的表达式解析，边解析边求值
+struct Expression {
+    chars: Vec<char>,
+    at: usize,
+    /// unary现在嵌套了几层
+    depth: usize,
+}
+
+impl Expression {
+    fn skip_spaces(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        if self.chars.get(self.at) == Some(&c) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn sum(&mut self, expander: &mut Expander) -> Result<Float> {
+        let mut value = self.product(expander)?;
+        loop {
+            if self.eat('+') {
+                value += self.product(expander)?;
+            } else if self.eat('-') {
+                value -= self.product(expander)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self, expander: &mut Expander) -> Result<Float> {
+        let mut value = self.unary(expander)?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary(expander)?;
+            } else if self.eat('/') {
+                value /= self.unary(expander)?;
+            } else if self.eat('%') {
+                value %= self.unary(expander)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self, expander: &mut Expander) -> Result<Float> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::parse("expression nested too deeply"));
+        }
+        self.depth += 1;
+        let value = self.operand(expander);
+        self.depth -= 1;
+        value
+    }
+
+    fn operand(&mut self, expander: &mut Expander) -> Result<Float> {
+        if self.eat('-') {
+            return Ok(-self.unary(expander)?);
+        }
+        if self.eat('(') {
+            let value = self.sum(expander)?;
+            if !self.eat(')') {
+                return Err(Error::parse("missing ')'"));
+            }
+            return Ok(value);
+        }
+        self.skip_spaces();
+        let start = self.at;
+        let c = match self.chars.get(self.at) {
+            Some(c) => *c,
+            None => return Err(Error::parse("expression ends too early")),
+        };
+        if c.is_ascii_digit() || c == '.' {
+            while self
+                .chars
+                .get(self.at)
+                .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+            {
+                self.at += 1;
+            }
+            let text: String = self.chars[start..self.at].iter().collect();
+            return text
+                .parse()
+                .map_err(|_| Error::parse(format!("bad number {:?}", text)));
+        }
+        while self
+            .chars
+            .get(self.at)
+            .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+        {
+            self.at += 1;
+        }
+        let name: String = self.chars[start..self.at].iter().collect();
+        if name.is_empty() {
+            return Err(Error::parse(format!("unexpected {:?}", c)));
+        }
+        if !self.eat('(') {
+            return match name.as_str() {
+                "pi" => Ok(PI),
+                _ => expander
+                    .variables
+                    .get(&name)
+                    .copied()
+                    .ok_or_else(|| Error::parse(format!("unknown variable {:?}", name))),
+            };
+        }
+        let mut args = Vec::new();
+        if !self.eat(')') {
+            loop {
+                args.push(self.sum(expander)?);
+                if self.eat(')') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return Err(Error::parse("expected ',' or ')'"));
+                }
+            }
+        }
+        match (name.as_str(), args.as_slice()) {
+            ("sin", [x]) => Ok(x.sin()),
+            ("cos", [x]) => Ok(x.cos()),
+            ("sqrt", [x]) => Ok(x.sqrt()),
+            ("abs", [x]) => Ok(x.abs()),
+            ("floor", [x]) => Ok(x.floor()),
+            ("min", [a, b]) => Ok(a.min(*b)),
+            ("max", [a, b]) => Ok(a.max(*b)),
+            ("rand", []) => Ok(expander.rand()),
+            ("rand", [a, b]) => Ok(a + (b - a) * expander.rand()),
+            _ => Err(Error::parse(format!(
+                "unknown function {}/{}",
+                name,
+                args.len()
+            ))),
+        }
+    }
+}
+
+/// 去掉注释，展开let、for和{}表达式，返回(原来的行号, 展开后的行)
+pub(super) fn expand(text: &str) -> Result<Vec<(usize, String)>> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .collect();
+    let mut expander = Expander {
+        variables: HashMap::new(),
+        random: 0,
+        out: Vec::new(),
+        iterations: 0,
+        depth: 0,
+        lines,
+    };
+    expander.expand(0, expander.lines.len())?;
+    Ok(expander.out)
+}
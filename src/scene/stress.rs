@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use super::camera::Camera;
+use super::item::{Capsule, Instance, Mesh, PolygonMesh, RoundedBox, Sphere};
+use super::material::{Material, Principled};
+use super::{Distance, SceneBuilder};
+use crate::color::{id_color, Color};
+use crate::math::{consts::PI, Float, Point, Transform, Vector3};
+use crate::rendering::Intersectable;
+
+type Prototype = Arc<dyn Intersectable + Send + Sync>;
+
+/// 物体在地面上怎么摆
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layout {
+    /// 整齐的方格，不转也不缩放
+    Grid,
+    /// 每格里随机挪一点，随机转，大小在0.5到1.25倍之间
+    Jittered,
+}
+
+/// 生成测加速结构用的压力场景：几种几何体（球、圆角盒子、胶囊、细分出来的网格）
+/// 各配上每种材质当原型，成千上万个Instance指向这些原型，自己只存一个变换，
+/// 排成一片方形摆在y = 0的地面上。原型都在原点、大约一个单位大，
+/// 所以场景的内存主要是实例，可以用来看几何体共享有没有起作用
+#[derive(Clone, Debug)]
+pub struct StressScene {
+    pub count: usize,
+    pub layout: Layout,
+    /// 相邻两格的间距
+    pub spacing: Distance,
+    /// 材质的种数，漫反射、塑料、金属、镜面轮着来，颜色各不相同
+    pub materials: usize,
+    pub seed: u64,
+}
+
+impl Default for StressScene {
+    fn default() -> Self {
+        Self {
+            count: 10000,
+            layout: Layout::Jittered,
+            spacing: 1.5,
+            materials: 8,
+            seed: 0,
+        }
+    }
+}
+
+/// splitmix64，摆放只要可重复，不需要和渲染的采样器有关系
+pub(super) struct Random(pub(super) u64);
+
+impl Random {
+    pub(super) fn next(&mut self) -> Float {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as Float / (1u64 << 53) as Float
+    }
+}
+
+impl StressScene {
+    /// 每行几个
+    fn side(&self) -> usize {
+        (self.count as Float).sqrt().ceil().max(1.0) as usize
+    }
+
+    fn material(&self, index: usize) -> Material {
+        let color = id_color(((index as u64 + 1) ^ self.seed) as u32);
+        match index % 4 {
+            0 => Material::diffuse(color),
+            1 => Material::principled(
+                color,
+                Principled {
+                    roughness: 0.3,
+                    ..Principled::default()
+                },
+            ),
+            2 => Material::principled(
+                color,
+                Principled {
+                    metallic: 1.0,
+                    roughness: 0.2,
+                    ..Principled::default()
+                },
+            ),
+            _ => Material::reflective(color, 0.6),
+        }
+    }
+
+    /// 每种材质一组原型，每组里是各种几何体，都包在原点处半径0.5的球里
+    fn prototypes(&self) -> Vec<Vec<Prototype>> {
+        let cage = PolygonMesh {
+            positions: vec![
+                Point::new(-0.3, -0.3, -0.3),
+                Point::new(0.3, -0.3, -0.3),
+                Point::new(0.3, 0.3, -0.3),
+                Point::new(-0.3, 0.3, -0.3),
+                Point::new(-0.3, -0.3, 0.3),
+                Point::new(0.3, -0.3, 0.3),
+                Point::new(0.3, 0.3, 0.3),
+                Point::new(-0.3, 0.3, 0.3),
+            ],
+            faces: vec![
+                vec![0, 3, 2, 1],
+                vec![4, 5, 6, 7],
+                vec![0, 1, 5, 4],
+                vec![2, 3, 7, 6],
+                vec![1, 2, 6, 5],
+                vec![0, 4, 7, 3],
+            ],
+        };
+        let blob = cage.catmull_clark(3);
+        (0..self.materials.max(1))
+            .map(|index| {
+                let material = Arc::new(self.material(index));
+                let shapes: Vec<Prototype> = vec![
+                    Arc::new(Sphere {
+                        center: Point::zero(),
+                        radius: 0.5,
+                        material: material.clone(),
+                    }),
+                    Arc::new(RoundedBox {
+                        center: Point::zero(),
+                        half_extent: Vector3::new(0.28, 0.28, 0.28),
+                        radius: 0.08,
+                        material: material.clone(),
+                    }),
+                    Arc::new(Capsule {
+                        a: Point::new(0.0, -0.3, 0.0),
+                        b: Point::new(0.0, 0.3, 0.0),
+                        radius: 0.2,
+                        material: material.clone(),
+                    }),
+                    Arc::new(Mesh::new(blob.clone(), material)),
+                ];
+                shapes
+            })
+            .collect()
+    }
+
+    /// 所有的实例，顺序和位置只由设置决定
+    pub fn instances(&self) -> Vec<Instance> {
+        let prototypes = self.prototypes();
+        let mut random = Random(self.seed);
+        let side = self.side();
+        let half = (side - 1) as Float * 0.5;
+        (0..self.count)
+            .map(|i| {
+                let column = (i % side) as Float - half;
+                let row = (i / side) as Float - half;
+                let mut pick = |n: usize| ((random.next() * n as Float) as usize).min(n - 1);
+                let group = &prototypes[pick(prototypes.len())];
+                let item = group[pick(group.len())].clone();
+                let (offset, rotation, scale) = match self.layout {
+                    Layout::Grid => ((0.0, 0.0), Vector3::zero(), 1.0),
+                    Layout::Jittered => {
+                        let offset = (random.next() - 0.5, random.next() - 0.5);
+                        let rotation = Vector3::new(
+                            random.next() * 2.0 * PI,
+                            random.next() * 2.0 * PI,
+                            random.next() * 2.0 * PI,
+                        );
+                        (offset, rotation, 0.5 + random.next() * 0.75)
+                    }
+                };
+                // 挪动不超过空出来的地方，大的也不会和邻居穿插
+                let room = (self.spacing - scale).max(0.0) * 0.5;
+                let transform = Transform {
+                    translation: Vector3::new(
+                        column * self.spacing + offset.0 * 2.0 * room,
+                        0.5 * scale,
+                        row * self.spacing + offset.1 * 2.0 * room,
+                    ),
+                    rotation,
+                    scale,
+                };
+                Instance::new(item, transform.affine())
+            })
+            .collect()
+    }
+
+    /// 实例加上地面、阳光、天光，相机从斜上方看整片区域。
+    /// 大小、样本数这些再接着在返回的builder上设置
+    pub fn builder(&self) -> SceneBuilder {
+        let extent = self.side() as Float * self.spacing;
+        let position = Vector3::new(0.0, extent * 0.45, extent * 0.85);
+        let view = Transform {
+            translation: position,
+            rotation: Vector3::new(-(position.y / position.z).atan(), 0.0, 0.0),
+            scale: 1.0,
+        };
+        let camera = Camera {
+            start: view,
+            end: view,
+            ..Camera::default()
+        };
+        let mut builder = SceneBuilder::new()
+            .fov(60.0)
+            .camera(camera)
+            .seed(self.seed)
+            .add_plane(
+                Point::zero(),
+                Vector3::new(0.0, -1.0, 0.0),
+                Material::diffuse(Color::new(0.6, 0.6, 0.6)),
+            )
+            .add_directional_light(Vector3::new(-0.4, -1.0, -0.3), Color::white(), 2.5)
+            .add_environment_light(Color::new(0.6, 0.7, 1.0), 0.4, Vec::new());
+        for instance in self.instances() {
+            builder = builder.add_item(instance);
+        }
+        builder
+    }
+}
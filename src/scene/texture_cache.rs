@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::SystemTime;
+
+use super::material::{Texture, TextureImage};
+use crate::hdr::HdrImage;
+use crate::{Error, Result};
+
+enum WeakImage {
+    Rgba8(Weak<image::RgbaImage>),
+    Rgb32F(Weak<HdrImage>),
+}
+
+impl WeakImage {
+    fn new(image: &TextureImage) -> Self {
+        match image {
+            TextureImage::Rgba8(image) => Self::Rgba8(Arc::downgrade(image)),
+            TextureImage::Rgb32F(image) => Self::Rgb32F(Arc::downgrade(image)),
+        }
+    }
+
+    fn upgrade(&self) -> Option<TextureImage> {
+        match self {
+            Self::Rgba8(image) => image.upgrade().map(TextureImage::Rgba8),
+            Self::Rgb32F(image) => image.upgrade().map(TextureImage::Rgb32F),
+        }
+    }
+
+    fn alive(&self) -> bool {
+        match self {
+            Self::Rgba8(image) => image.strong_count() > 0,
+            Self::Rgb32F(image) => image.strong_count() > 0,
+        }
+    }
+}
+
+struct Entry {
+    /// 读的时候文件的修改时间，文件改了就重新读
+    modified: Option<SystemTime>,
+    image: WeakImage,
+}
+
+/// 按路径共享读进来的贴图：同一个文件第一次用到的时候才读，之后拿到的都是同一个Arc，
+/// 场景文件、OBJ的MTL里引用了多少次都只占一份内存。.hdr和.exr读成浮点图。只存弱引用，
+/// 用它的场景都释放了图片也就释放了；文件改过（--watch时）会重新读
+#[derive(Default)]
+pub struct TextureCache {
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl TextureCache {
+    /// 整个程序共用的一个
+    pub fn global() -> &'static TextureCache {
+        static CACHE: OnceLock<TextureCache> = OnceLock::new();
+        CACHE.get_or_init(TextureCache::default)
+    }
+
+    pub fn image<P: AsRef<Path>>(&self, path: P) -> Result<TextureImage> {
+        let path = path.as_ref();
+        // 同一个文件用不同的相对路径写也算一个，文件不存在时交给下面读图报错
+        let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let modified = fs::metadata(&key).and_then(|m| m.modified()).ok();
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.modified == modified {
+                if let Some(image) = entry.image.upgrade() {
+                    return Ok(image);
+                }
+            }
+        }
+        // 解码不占着锁，两个线程同时读同一张图顶多多读一次
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let image = match extension.to_ascii_lowercase().as_str() {
+            "hdr" | "exr" => TextureImage::Rgb32F(Arc::new(HdrImage::load(path)?)),
+            _ => TextureImage::Rgba8(Arc::new(
+                image::open(path)
+                    .map_err(|e| Error::from(e).in_file(path))?
+                    .to_rgba(),
+            )),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.image.alive());
+        entries.insert(
+            key,
+            Entry {
+                modified,
+                image: WeakImage::new(&image),
+            },
+        );
+        Ok(image)
+    }
+
+    pub fn texture<P: AsRef<Path>>(&self, path: P) -> Result<Texture> {
+        self.image(path).map(Texture::new)
+    }
+
+    /// 现在还有人在用的图片有几张
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.image.alive())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
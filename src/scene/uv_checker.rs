@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use super::material::{Material, SurfaceType, Texture};
+
+/// 3×5的数字，每行低三位从左到右
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// 数字的第(x, y)个像素是不是亮的，x、y按字的格子算
+fn lit(digit: u32, x: u32, y: u32) -> bool {
+    x < 3 && y < 5 && DIGITS[digit as usize % 10][y as usize] >> (2 - x) & 1 == 1
+}
+
+impl Texture {
+    /// 调UV用的格子图，不用带图片文件。u、v各分cells格，每格cell_pixels像素见方；
+    /// 格子越往u大越红、越往v大越绿，相邻的一暗一亮，
+    /// 上面写着它在第几列、第几行（两位数字，多于10格时取个位）。
+    /// 看一眼就知道接缝在哪、u和v朝哪边、有没有拉伸
+    pub fn uv_checker(cells: u32, cell_pixels: u32) -> Self {
+        let cells = cells.max(1);
+        let cell_pixels = cell_pixels.max(8);
+        // 两个数字中间空一格，字大约占格子宽度的一半
+        let dot = (cell_pixels / 14).max(1);
+        let (label_width, label_height) = (7 * dot, 5 * dot);
+        let size = cells * cell_pixels;
+        let image = image::ImageBuffer::from_fn(size, size, |x, y| {
+            let (column, row) = (x / cell_pixels, y / cell_pixels);
+            let (cx, cy) = (x % cell_pixels, y % cell_pixels);
+            let shade = if (column + row) % 2 == 0 { 1.0 } else { 0.6 };
+            let left = (cell_pixels - label_width) / 2;
+            let top = (cell_pixels - label_height) / 2;
+            let in_label =
+                cx >= left && cy >= top && cx < left + label_width && cy < top + label_height;
+            if in_label {
+                let (lx, ly) = ((cx - left) / dot, (cy - top) / dot);
+                let on = if lx < 3 {
+                    lit(column, lx, ly)
+                } else {
+                    lit(row, lx.wrapping_sub(4), ly)
+                };
+                if on {
+                    return image::Rgba([255, 255, 255, 255]);
+                }
+            }
+            let channel = |i: u32| ((i as f32 + 0.5) / cells as f32 * shade * 255.0) as u8;
+            image::Rgba([channel(column), channel(row), (128.0 * shade) as u8, 255])
+        });
+        Texture::new(Arc::new(image))
+    }
+}
+
+impl Material {
+    /// 贴着8×8格子图的漫反射材质，给新的几何体检查纹理坐标用
+    pub fn uv_debug() -> Self {
+        Material::new(Texture::uv_checker(8, 64), SurfaceType::Diffuse).with_albedo(1.0)
+    }
+}
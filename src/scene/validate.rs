@@ -0,0 +1,422 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use super::camera::{Aperture, Camera, Exposure, Projection};
+use super::material::{Coloration, Material, SurfaceType};
+use super::Scene;
+use crate::color::Color;
+use crate::filter::PixelFilter;
+use crate::integrator::IntegratorKind;
+use crate::math::{Float, Point, Transform, Vector3};
+use crate::rendering::RenderMode;
+use crate::{Error, Result};
+
+/// 归一化的向量长度和1差得超过它就算没归一化
+const UNIT_TOLERANCE: Float = 1e-3;
+
+/// Scene::validate的结果。errors会让渲染出来的图明显不对，
+/// warnings可能是故意的，比如albedo大于1让物体看起来在发光
+#[derive(Debug, Default)]
+pub struct Validation {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    /// 正在检查的东西，比如"item 3"，加在每条消息前面
+    context: String,
+    /// 查过的材质的地址，共用的材质只报一次
+    materials: HashSet<usize>,
+}
+
+impl Validation {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// 有错误时返回Error::Scene，没有的话返回警告
+    pub fn check(self) -> Result<Vec<String>> {
+        if self.errors.is_empty() {
+            Ok(self.warnings)
+        } else {
+            Err(Error::Scene(self.errors.join("; ")))
+        }
+    }
+
+    fn message(&self, message: impl Display) -> String {
+        if self.context.is_empty() {
+            message.to_string()
+        } else {
+            format!("{}: {}", self.context, message)
+        }
+    }
+
+    pub fn error(&mut self, message: impl Display) {
+        let message = self.message(message);
+        self.errors.push(message);
+    }
+
+    pub fn warning(&mut self, message: impl Display) {
+        let message = self.message(message);
+        self.warnings.push(message);
+    }
+
+    /// value不是NaN、不是无穷大
+    pub fn finite(&mut self, name: &str, value: impl Into<f64>) -> bool {
+        let value = value.into();
+        if !value.is_finite() {
+            self.error(format_args!("{} is {}", name, value));
+        }
+        value.is_finite()
+    }
+
+    /// value在[min, max]里
+    pub fn range(&mut self, name: &str, value: impl Into<f64>, min: f64, max: f64) {
+        let value = value.into();
+        if self.finite(name, value) && !(min..=max).contains(&value) {
+            self.error(format_args!(
+                "{} is {}, should be between {} and {}",
+                name, value, min, max
+            ));
+        }
+    }
+
+    /// value大于0，allow_zero时也可以等于0
+    pub fn positive(&mut self, name: &str, value: impl Into<f64>, allow_zero: bool) {
+        let value = value.into();
+        if self.finite(name, value) && (value < 0.0 || (value == 0.0 && !allow_zero)) {
+            let expected = if allow_zero {
+                "negative"
+            } else {
+                "not positive"
+            };
+            self.error(format_args!("{} is {}, which is {}", name, value, expected));
+        }
+    }
+
+    pub fn point(&mut self, name: &str, p: &Point) {
+        if !(p.x.is_finite() && p.y.is_finite() && p.z.is_finite()) {
+            self.error(format_args!("{} is {:?}", name, p));
+        }
+    }
+
+    /// 方向向量必须已经归一化
+    pub fn unit(&mut self, name: &str, v: &Vector3) {
+        let length = v.length();
+        if !length.is_finite() || length == 0.0 {
+            self.error(format_args!("{} is {:?}", name, v));
+        } else if (length - 1.0).abs() > UNIT_TOLERANCE {
+            self.error(format_args!(
+                "{} has length {}, it should be normalized",
+                name, length
+            ));
+        }
+    }
+
+    pub fn color(&mut self, name: &str, color: &Color) {
+        for value in [color.r, color.g, color.b] {
+            if !value.is_finite() || value < 0.0 {
+                self.error(format_args!("{} is {:?}", name, color));
+                return;
+            }
+        }
+    }
+
+    pub fn transform(&mut self, name: &str, transform: &Transform) {
+        let Transform {
+            translation: t,
+            rotation: r,
+            scale,
+        } = transform;
+        if ![t.x, t.y, t.z, r.x, r.y, r.z].iter().all(|v| v.is_finite()) {
+            self.error(format_args!("{} is {:?}", name, transform));
+        }
+        self.positive(&format!("{} scale", name), *scale, false);
+    }
+
+    /// 材质的颜色和自发光贴图共用的检查，name是报错里说的是哪个
+    fn coloration(&mut self, name: &str, coloration: &Coloration) {
+        match coloration {
+            Coloration::Color(color) => self.color(&format!("{} color", name), color),
+            Coloration::Texture(texture) => {
+                if texture.image.width() == 0 || texture.image.height() == 0 {
+                    self.error(format_args!("{} texture is empty", name));
+                }
+                self.positive("texture scale", texture.scale, false);
+            }
+            // 用户的代码，查不了
+            Coloration::Procedural(_) => {}
+            Coloration::Ramp(ramp) => {
+                if ramp.stops.is_empty() {
+                    self.error("color ramp has no stops");
+                }
+                for (position, color) in &ramp.stops {
+                    self.finite("ramp stop position", *position);
+                    self.color("ramp stop color", color);
+                }
+            }
+        }
+    }
+
+    /// 检查材质参数的范围，同一个材质只查一次
+    pub fn material(&mut self, material: &Material) {
+        if !self.materials.insert(material as *const Material as usize) {
+            return;
+        }
+        self.coloration("material", &material.color);
+        if let Some(opacity) = &material.opacity {
+            self.coloration("opacity map", &opacity.map);
+            if let Some(threshold) = opacity.threshold {
+                self.range("opacity cutoff", threshold, 0.0, 1.0);
+            }
+        }
+        if self.finite("albedo", material.albedo) && material.albedo < 0.0 {
+            self.error(format_args!("albedo is {}", material.albedo));
+        } else if material.albedo > 1.0 {
+            self.warning(format_args!(
+                "albedo is {}, the surface reflects more light than it receives",
+                material.albedo
+            ));
+        }
+        match &material.surface {
+            SurfaceType::Diffuse => {}
+            SurfaceType::Reflective { reflectivity } => {
+                self.range("reflectivity", *reflectivity, 0.0, 1.0)
+            }
+            SurfaceType::Refractive {
+                index,
+                transparency,
+                dispersion,
+            } => {
+                self.positive("refractive index", *index, false);
+                self.range("transparency", *transparency, 0.0, 1.0);
+                self.finite("dispersion", *dispersion);
+            }
+            SurfaceType::Microfacet {
+                roughness_u,
+                roughness_v,
+                rotation,
+            } => {
+                self.range("roughness", *roughness_u, 0.0, 1.0);
+                self.range("roughness", *roughness_v, 0.0, 1.0);
+                self.finite("rotation", *rotation);
+            }
+            SurfaceType::Principled(p) => {
+                self.range("metallic", p.metallic, 0.0, 1.0);
+                self.range("roughness", p.roughness, 0.0, 1.0);
+                self.range("specular", p.specular, 0.0, 1.0);
+                self.range("transmission", p.transmission, 0.0, 1.0);
+                self.positive("ior", p.ior, false);
+                self.finite("dispersion", p.dispersion);
+                self.color("emission", &p.emission);
+                if let Some(map) = &p.emission_map {
+                    self.coloration("emission map", map);
+                }
+                if let Some(map) = &p.roughness_map {
+                    self.coloration("roughness map", map);
+                }
+                if let Some(map) = &p.metallic_map {
+                    self.coloration("metallic map", map);
+                }
+                self.positive("emission strength", p.emission_strength, true);
+            }
+            SurfaceType::Hair(h) => {
+                self.range("hair roughness", h.roughness, 0.0, 1.0);
+                self.range("hair azimuthal roughness", h.azimuthal_roughness, 0.0, 1.0);
+                self.finite("hair tilt", h.tilt);
+            }
+            SurfaceType::Subsurface(s) => {
+                self.color("scatter color", &s.scatter_color);
+                for radius in s.radius {
+                    self.positive("subsurface radius", radius, false);
+                }
+            }
+        }
+        if let Some(clearcoat) = &material.clearcoat {
+            self.range("clearcoat roughness", clearcoat.roughness, 0.0, 1.0);
+            self.positive("clearcoat ior", clearcoat.ior, false);
+        }
+    }
+
+    fn camera(&mut self, camera: &Camera, fov: impl Into<f64>) {
+        let fov = fov.into();
+        match camera.projection {
+            Projection::Perspective => {
+                if self.finite("fov", fov) && !(fov > 0.0 && fov < 180.0) {
+                    self.error(format_args!("fov is {}, should be between 0 and 180", fov));
+                }
+            }
+            Projection::Fisheye { fov } => self.positive("fisheye fov", fov, false),
+            Projection::Equirectangular => {}
+            Projection::StereoEquirectangular { ipd } => self.positive("ipd", ipd, true),
+        }
+        let (open, close) = camera.shutter;
+        if self.finite("shutter open", open) && self.finite("shutter close", close) && open > close
+        {
+            self.error(format_args!(
+                "shutter closes at {} before it opens at {}",
+                close, open
+            ));
+        }
+        self.transform("camera start", &camera.start);
+        self.transform("camera end", &camera.end);
+        if self.finite("lens distortion", camera.distortion) && camera.distortion <= -1.0 {
+            self.error(format_args!(
+                "lens distortion is {}, the image corners would fold over",
+                camera.distortion
+            ));
+        }
+        self.range("vignette", camera.vignette, 0.0, 1.0);
+        if let Some(lens) = &camera.lens {
+            self.positive("aperture radius", lens.radius, true);
+            self.positive("focus distance", lens.focus_distance, false);
+            if let Aperture::Polygon { blades, rotation } = lens.aperture {
+                if blades < 3 {
+                    self.error(format_args!(
+                        "aperture has {} blades, needs at least 3",
+                        blades
+                    ));
+                }
+                self.finite("blade rotation", rotation);
+            }
+        }
+        self.range(
+            "chromatic aberration",
+            camera.chromatic_aberration,
+            -0.5,
+            0.5,
+        );
+        match camera.exposure {
+            Exposure::Ev(ev) => {
+                self.finite("exposure", ev);
+            }
+            Exposure::Physical {
+                iso,
+                shutter_time,
+                f_number,
+            } => {
+                self.positive("iso", iso, false);
+                self.positive("shutter time", shutter_time, false);
+                self.positive("f-number", f_number, false);
+            }
+        }
+    }
+}
+
+impl Scene {
+    /// 渲染之前检查场景：图片大小、相机、光源、物体的几何参数和材质。
+    /// 这些问题渲染时不会报错，只会出一张不对的图
+    pub fn validate(&self) -> Validation {
+        let mut report = Validation::default();
+        if self.width == 0 || self.height == 0 {
+            report.error(format_args!("image size is {}x{}", self.width, self.height));
+        }
+        report.camera(&self.camera, self.fov);
+        if let RenderMode::Depth { far } = self.mode {
+            report.positive("depth far", far, false);
+        }
+        let settings = &self.settings;
+        if settings.samples == 0 {
+            report.error("samples is 0");
+        }
+        if settings.max_depth == 0 {
+            report.error("max depth is 0, nothing would be traced");
+        }
+        if settings.max_light_samples == 0 {
+            report.error("max light samples is 0, lights would never be sampled");
+        }
+        report.positive("shadow bias", settings.shadow_bias, true);
+        if let Some(clamp) = settings.sample_clamp {
+            report.positive("sample clamp", clamp.max(), false);
+        }
+        if let Some(caustics) = settings.caustics {
+            if caustics.photons == 0 {
+                report.error("caustic photon count is 0");
+            }
+            report.positive("caustic radius", caustics.radius, false);
+        }
+        if let Some(irradiance) = settings.irradiance_cache {
+            if irradiance.samples == 0 {
+                report.error("irradiance cache sample count is 0");
+            }
+            report.positive("irradiance cache accuracy", irradiance.accuracy, false);
+        }
+        if let Some(bloom) = settings.bloom {
+            report.positive("bloom threshold", bloom.threshold, true);
+            report.positive("bloom intensity", bloom.intensity, true);
+            if bloom.levels == 0 {
+                report.warning("bloom has no levels and does nothing");
+            }
+        }
+        if let Some(flare) = settings.flare {
+            report.positive("flare threshold", flare.threshold, true);
+            report.positive("flare dispersal", flare.dispersal, true);
+            report.positive("flare intensity", flare.intensity, true);
+        }
+        if let Some(grading) = settings.grading {
+            report.range(
+                "white balance temperature",
+                grading.temperature,
+                1000.0,
+                40000.0,
+            );
+            report.range("tint", grading.tint, -1.0, 1.0);
+            report.color("lift", &grading.lift);
+            report.color("gain", &grading.gain);
+            for gamma in [grading.gamma.r, grading.gamma.g, grading.gamma.b] {
+                report.positive("gamma", gamma, false);
+            }
+        }
+        if settings.integrator == IntegratorKind::Bidirectional {
+            if self.medium.is_some() || self.items.iter().any(|i| i.volume().is_some()) {
+                report.warning(
+                    "bidirectional integrator falls back to path tracing with fog or volumes",
+                );
+            }
+            if settings.caustics.is_some() {
+                report.warning("bidirectional integrator does not use the caustic photon map");
+            }
+            if settings.irradiance_cache.is_some() {
+                report.warning("bidirectional integrator does not use the irradiance cache");
+            }
+        }
+        if self.materials.iter().any(|(_, m)| m.shadow_catcher) {
+            if !self.transparent {
+                report.warning("shadow catchers render as normal surfaces without transparent");
+            } else if settings.integrator == IntegratorKind::Bidirectional {
+                report
+                    .warning("bidirectional integrator renders shadow catchers as normal surfaces");
+            }
+        }
+        match settings.filter {
+            PixelFilter::Box => {}
+            PixelFilter::Tent { radius } => report.positive("filter radius", radius, false),
+            PixelFilter::Gaussian { radius, sigma } => {
+                report.positive("filter radius", radius, false);
+                report.positive("filter sigma", sigma, false);
+            }
+            PixelFilter::Mitchell { radius, b, c } => {
+                report.positive("filter radius", radius, false);
+                report.finite("mitchell b", b);
+                report.finite("mitchell c", c);
+            }
+        }
+        let mut materials: Vec<_> = self.materials.iter().collect();
+        materials.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, material) in materials {
+            report.context = format!("material {:?}", name);
+            report.material(material);
+        }
+        for (i, light) in self.lights.iter().enumerate() {
+            report.context = format!("light {}", i);
+            light.validate(&mut report);
+        }
+        for (i, item) in self.items.iter().enumerate() {
+            report.context = format!("item {}", i);
+            item.validate(&mut report);
+        }
+        report.context.clear();
+        if let Some(medium) = &self.medium {
+            report.color("fog absorption", &medium.absorption);
+            report.color("fog scattering", &medium.scattering);
+        }
+        report
+    }
+}
@@ -0,0 +1,441 @@
+use crate::exr::aov_layers;
+use crate::rendering::{
+    aov_pass, render_hdr_with_progress, render_with_progress, CancelToken, Crop, RenderProgress,
+};
+use crate::scene::{parse_scene, Scene};
+use image::ImageOutputFormat;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// 请求体（场景文件）最大多少字节
+const MAX_BODY: usize = 16 << 20;
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+/// 最多留着多少个渲染好的图，再多就把最早的扔掉，服务开久了内存不会一直涨
+const KEPT_IMAGES: usize = 32;
+/// accept出错（比如文件描述符用完了）以后等一会儿再接着accept
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// 渲染结果的格式，提交时用`?format=exr`选，默认PNG
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Png,
+    /// 不clamp的主图加上法线、深度、albedo和物体ID，见exr::aov_layers
+    Exr,
+}
+
+impl Format {
+    fn from_query(path: &str) -> Option<Self> {
+        let query = path.split_once('?').map_or("", |(_, query)| query);
+        let format = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("format="))
+            .unwrap_or("png");
+        match format {
+            "png" => Some(Format::Png),
+            "exr" => Some(Format::Exr),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Exr => "exr",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Exr => "image/x-exr",
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Status {
+    Queued,
+    Rendering,
+    /// 编码好的图，格式是任务的format
+    Done(Arc<Vec<u8>>),
+    Failed(String),
+    Cancelled,
+    /// 渲染完了，但图已经因为KEPT_IMAGES扔掉了
+    Expired,
+}
+
+struct Job {
+    status: Status,
+    format: Format,
+    progress: f32,
+    width: u32,
+    height: u32,
+    cancel: CancelToken,
+}
+
+impl Job {
+    fn json(&self, id: usize) -> String {
+        let (status, error) = match &self.status {
+            Status::Queued => ("queued", None),
+            Status::Rendering => ("rendering", None),
+            Status::Done(_) => ("done", None),
+            Status::Failed(message) => ("failed", Some(message)),
+            Status::Cancelled => ("cancelled", None),
+            Status::Expired => ("expired", None),
+        };
+        let mut json = format!(
+            "{{\"id\":{},\"status\":\"{}\",\"format\":\"{}\",\"progress\":{:.4},\"width\":{},\"height\":{}",
+            id,
+            status,
+            self.format.extension(),
+            self.progress,
+            self.width,
+            self.height
+        );
+        if let Some(error) = error {
+            json += &format!(",\"error\":{}", json_string(error));
+        }
+        json + "}"
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+/// 所有任务都在这里，id就是下标；一次只渲染一个，别的排队
+struct Service {
+    root: PathBuf,
+    jobs: Mutex<Vec<Job>>,
+    changed: Condvar,
+    queue: Mutex<Sender<(usize, Scene)>>,
+}
+
+impl Service {
+    fn update(&self, id: usize, f: impl FnOnce(&mut Job)) {
+        f(&mut self.jobs.lock().unwrap()[id]);
+        self.changed.notify_all();
+    }
+
+    /// 出错时返回给客户端的响应：场景不对是400，渲染线程没了是503
+    fn submit(&self, body: &[u8], format: Format) -> Result<usize, Response> {
+        let bad = |message: String| Response::text("400 Bad Request", &message);
+        let text = std::str::from_utf8(body).map_err(|_| bad("scene is not UTF-8".into()))?;
+        let scene = parse_scene(text, &self.root).map_err(|e| bad(e.to_string()))?;
+        scene.validate().check().map_err(|e| bad(e.to_string()))?;
+        let mut jobs = self.jobs.lock().unwrap();
+        let id = jobs.len();
+        jobs.push(Job {
+            status: Status::Queued,
+            format,
+            progress: 0.0,
+            width: scene.width,
+            height: scene.height,
+            cancel: CancelToken::new(),
+        });
+        drop(jobs);
+        if self.queue.lock().unwrap().send((id, scene)).is_err() {
+            self.update(id, |job| {
+                job.status = Status::Failed("render thread is not running".into())
+            });
+            return Err(Response::text(
+                "503 Service Unavailable",
+                "render thread is not running",
+            ));
+        }
+        Ok(id)
+    }
+
+    /// 渲染里panic了只让这个任务失败，渲染线程接着处理后面的任务
+    fn run(&self, id: usize, scene: Scene) {
+        let (cancel, format) = {
+            let job = &self.jobs.lock().unwrap()[id];
+            (job.cancel.clone(), job.format)
+        };
+        if cancel.is_cancelled() {
+            return;
+        }
+        self.update(id, |job| job.status = Status::Rendering);
+        let render = || self.render(id, &scene, format, &cancel);
+        let status = panic::catch_unwind(AssertUnwindSafe(render)).unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "render panicked".into());
+            Status::Failed(message)
+        });
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs[id].status = status;
+        // 任务按提交的顺序一个个渲染，id小的就是早渲染完的
+        let mut kept = 0;
+        for job in jobs.iter_mut().rev() {
+            if let Status::Done(_) = job.status {
+                kept += 1;
+                if kept > KEPT_IMAGES {
+                    job.status = Status::Expired;
+                }
+            }
+        }
+        drop(jobs);
+        self.changed.notify_all();
+    }
+
+    fn render(&self, id: usize, scene: &Scene, format: Format, cancel: &CancelToken) -> Status {
+        let progress =
+            |progress: &RenderProgress| self.update(id, |job| job.progress = progress.fraction());
+        let mut bytes = Vec::new();
+        let written = match format {
+            Format::Png => {
+                let image = render_with_progress(scene, &Crop::full(scene), progress, cancel);
+                image
+                    .write_to(&mut bytes, ImageOutputFormat::Png)
+                    .map_err(|e| e.to_string())
+            }
+            Format::Exr => {
+                let beauty = render_hdr_with_progress(scene, progress, cancel);
+                if cancel.is_cancelled() {
+                    return Status::Cancelled;
+                }
+                aov_layers(&beauty, &aov_pass(scene))
+                    .write(&mut bytes)
+                    .map_err(|e| e.to_string())
+            }
+        };
+        if cancel.is_cancelled() {
+            return Status::Cancelled;
+        }
+        match written {
+            Ok(()) => Status::Done(Arc::new(bytes)),
+            Err(message) => Status::Failed(message),
+        }
+    }
+
+    /// 等到任务结束（完成、失败或者取消）
+    fn wait(&self, id: usize) -> Status {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            match &jobs[id].status {
+                Status::Queued | Status::Rendering => jobs = self.changed.wait(jobs).unwrap(),
+                status => return status.clone(),
+            }
+        }
+    }
+
+    fn handle(&self, method: &str, path: &str, body: &[u8]) -> Response {
+        let segments: Vec<&str> = path
+            .split('?')
+            .next()
+            .unwrap_or("")
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+        let job = |id: &str| {
+            id.parse::<usize>()
+                .ok()
+                .filter(|id| *id < self.jobs.lock().unwrap().len())
+        };
+        let format = Format::from_query(path);
+        match (method, segments.as_slice()) {
+            ("OPTIONS", _) => Response::new("204 No Content", "text/plain", Vec::new()),
+            ("POST", ["jobs" | "render"]) if format.is_none() => {
+                Response::text("400 Bad Request", "format must be png or exr")
+            }
+            ("POST", ["jobs"]) => match self.submit(body, format.unwrap_or(Format::Png)) {
+                Ok(id) => Response::json("202 Accepted", self.jobs.lock().unwrap()[id].json(id)),
+                Err(response) => response,
+            },
+            // 同步渲染：一直等到渲染完，直接返回图
+            ("POST", ["render"]) => {
+                let format = format.unwrap_or(Format::Png);
+                match self.submit(body, format) {
+                    Ok(id) => match self.wait(id) {
+                        Status::Done(image) => {
+                            Response::new("200 OK", format.content_type(), image.to_vec())
+                        }
+                        Status::Failed(message) => {
+                            Response::text("500 Internal Server Error", &message)
+                        }
+                        Status::Expired => Response::text("410 Gone", "image was evicted"),
+                        _ => Response::text("409 Conflict", "render was cancelled"),
+                    },
+                    Err(response) => response,
+                }
+            }
+            ("GET", ["jobs"]) => {
+                let jobs = self.jobs.lock().unwrap();
+                let list: Vec<String> = jobs.iter().enumerate().map(|(i, j)| j.json(i)).collect();
+                Response::json("200 OK", format!("[{}]", list.join(",")))
+            }
+            ("GET", ["jobs", id]) => match job(id) {
+                Some(id) => Response::json("200 OK", self.jobs.lock().unwrap()[id].json(id)),
+                None => Response::text("404 Not Found", "no such job"),
+            },
+            ("GET", ["jobs", id, name @ ("image.png" | "image.exr")]) => match job(id) {
+                Some(id) => {
+                    let jobs = self.jobs.lock().unwrap();
+                    let job = &jobs[id];
+                    if *name != format!("image.{}", job.format.extension()) {
+                        return Response::text(
+                            "404 Not Found",
+                            "job is rendered in another format",
+                        );
+                    }
+                    match &job.status {
+                        Status::Done(image) => {
+                            Response::new("200 OK", job.format.content_type(), image.to_vec())
+                        }
+                        Status::Expired => Response::text("410 Gone", "image was evicted"),
+                        _ => Response::text("409 Conflict", "job has no image yet"),
+                    }
+                }
+                None => Response::text("404 Not Found", "no such job"),
+            },
+            ("DELETE", ["jobs", id]) => match job(id) {
+                Some(id) => {
+                    let mut jobs = self.jobs.lock().unwrap();
+                    jobs[id].cancel.cancel();
+                    // 还在排队的直接标成取消，正在渲染的等渲染线程停下来再标
+                    if let Status::Queued = jobs[id].status {
+                        jobs[id].status = Status::Cancelled;
+                    }
+                    self.changed.notify_all();
+                    Response::json("200 OK", jobs[id].json(id))
+                }
+                None => Response::text("404 Not Found", "no such job"),
+            },
+            _ => Response::text("404 Not Found", "unknown endpoint"),
+        }
+    }
+
+    fn connection(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+            _ => return Response::text("400 Bad Request", "bad request line").send(stream),
+        };
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        if content_length > MAX_BODY {
+            return Response::text("413 Payload Too Large", "scene is too large").send(stream);
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        self.handle(&method, &path, &body).send(stream)
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: &'static str, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: &'static str, message: &str) -> Self {
+        Self::new(
+            status,
+            "text/plain; charset=utf-8",
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    fn json(status: &'static str, json: String) -> Self {
+        Self::new(status, "application/json", json.into_bytes())
+    }
+
+    /// 允许任何来源跨域访问，网页前端可以直接调
+    fn send(self, mut stream: TcpStream) -> io::Result<()> {
+        let header = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             Access-Control-Allow-Methods: GET, POST, DELETE, OPTIONS\r\n\
+             Access-Control-Allow-Headers: Content-Type\r\n\
+             Connection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        );
+        stream.write_all(header.as_bytes())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+/// HTTP渲染服务，请求体都是场景文件的文本：
+///
+/// - `POST /jobs`：提交任务，返回任务的状态（JSON，里面有id）
+/// - `POST /render`：提交并等渲染完，直接返回图
+/// - `GET /jobs`、`GET /jobs/<id>`：任务的状态和进度
+/// - `GET /jobs/<id>/image.png`或`image.exr`：渲染好的图
+/// - `DELETE /jobs/<id>`：取消
+///
+/// 两个POST默认渲染PNG，加上`?format=exr`渲染成带AOV通道、不clamp的EXR。
+/// 只留着最近KEPT_IMAGES个渲染好的图，更早的任务状态变成expired。
+/// accept出错时打印出来接着等下一个连接，不会让服务退出。
+/// 场景里的相对路径相对于root。服务会读root下面场景引用到的任何文件，只应该在信任的网络里开
+pub fn serve(listener: &TcpListener, root: PathBuf) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel::<(usize, Scene)>();
+    let service = Arc::new(Service {
+        root,
+        jobs: Mutex::new(Vec::new()),
+        changed: Condvar::new(),
+        queue: Mutex::new(sender),
+    });
+    let renderer = service.clone();
+    thread::spawn(move || {
+        for (id, scene) in receiver {
+            renderer.run(id, scene);
+        }
+    });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("accept failed: {}", e);
+                thread::sleep(ACCEPT_RETRY);
+                continue;
+            }
+        };
+        let service = service.clone();
+        thread::spawn(move || service.connection(stream).ok());
+    }
+    Ok(())
+}
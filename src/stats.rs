@@ -0,0 +1,128 @@
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// 渲染时数的东西。每个线程先记在自己的thread-local里，不用每条光线都碰原子变量，
+/// 每渲染完一行再加到SharedCounters上
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Counters {
+    pub camera_rays: u64,
+    pub rays: u64,
+    pub shadow_rays: u64,
+    pub node_visits: u64,
+}
+
+const ZERO: Counters = Counters {
+    camera_rays: 0,
+    rays: 0,
+    shadow_rays: 0,
+    node_visits: 0,
+};
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = const { Cell::new(ZERO) };
+}
+
+/// 改这个线程的计数
+pub(crate) fn count(f: impl FnOnce(&mut Counters)) {
+    COUNTERS.with(|counters| {
+        let mut c = counters.get();
+        f(&mut c);
+        counters.set(c);
+    });
+}
+
+/// 取出这个线程到现在的计数并清零
+pub(crate) fn take() -> Counters {
+    COUNTERS.with(|counters| counters.replace(ZERO))
+}
+
+/// 所有线程的计数加在一起
+#[derive(Default)]
+pub(crate) struct SharedCounters {
+    camera_rays: AtomicU64,
+    rays: AtomicU64,
+    shadow_rays: AtomicU64,
+    node_visits: AtomicU64,
+}
+
+impl SharedCounters {
+    pub fn add(&self, c: Counters) {
+        self.camera_rays.fetch_add(c.camera_rays, Ordering::Relaxed);
+        self.rays.fetch_add(c.rays, Ordering::Relaxed);
+        self.shadow_rays.fetch_add(c.shadow_rays, Ordering::Relaxed);
+        self.node_visits.fetch_add(c.node_visits, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Counters {
+        Counters {
+            camera_rays: self.camera_rays.load(Ordering::Relaxed),
+            rays: self.rays.load(Ordering::Relaxed),
+            shadow_rays: self.shadow_rays.load(Ordering::Relaxed),
+            node_visits: self.node_visits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 一次渲染的统计。wasm32上没有时钟，时间都是0
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderStats {
+    /// 相机发出的光线，每个样本一条
+    pub camera_rays: u64,
+    /// 和场景求交的次数，包括相机光线、弹射出去的光线和阴影光线
+    pub rays: u64,
+    /// 往光源方向检查遮挡的次数
+    pub shadow_rays: u64,
+    /// BVH、k-d树里访问过的节点数
+    pub node_visits: u64,
+    /// 准备光源采样表
+    pub setup: Duration,
+    /// 追踪光线
+    pub tracing: Duration,
+    /// 把像素拼成图
+    pub output: Duration,
+}
+
+impl RenderStats {
+    pub(crate) fn set_counters(&mut self, c: Counters) {
+        self.camera_rays = c.camera_rays;
+        self.rays = c.rays;
+        self.shadow_rays = c.shadow_rays;
+        self.node_visits = c.node_visits;
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.setup + self.tracing + self.output
+    }
+
+    /// 追踪阶段平均每秒求交多少次，没计时的时候是0
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.tracing.as_secs_f64();
+        if seconds > 0.0 {
+            self.rays as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "camera rays  {}", self.camera_rays)?;
+        writeln!(f, "rays         {}", self.rays)?;
+        writeln!(f, "shadow rays  {}", self.shadow_rays)?;
+        writeln!(
+            f,
+            "node visits  {} ({:.1} per ray)",
+            self.node_visits,
+            self.node_visits as f64 / self.rays.max(1) as f64
+        )?;
+        writeln!(f, "rays/sec     {:.0}", self.rays_per_second())?;
+        write!(
+            f,
+            "time         setup {:?}, tracing {:?}, output {:?}",
+            self.setup, self.tracing, self.output
+        )
+    }
+}
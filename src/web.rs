@@ -0,0 +1,125 @@
+use crate::checkpoint::Accumulator;
+use crate::color::Color;
+use crate::math::{Point, Vector3};
+use crate::rendering::render;
+use crate::scene::{light::LightSampler, material::Material, Scene, SceneBuilder};
+
+// 浏览器里用的接口。像素都是RGBA8、按行排、不预乘alpha，和canvas的ImageData一样，
+// JS那边 new ImageData(new Uint8ClampedArray(memory.buffer, ptr, width * height * 4), width, height)
+// 就能直接画。编译到wasm时关掉默认的feature：
+// cargo build --lib --release --target wasm32-unknown-unknown --no-default-features
+
+/// 一次渲染完，返回ImageData格式的像素
+pub fn render_rgba(scene: &Scene) -> Vec<u8> {
+    render(scene).to_rgba().into_raw()
+}
+
+/// 渐进式渲染：每次step只加几个样本，浏览器里每帧调一次，页面不会卡住
+pub struct WebRenderer {
+    pub scene: Scene,
+    accumulator: Accumulator,
+    lights: LightSampler,
+    pixels: Vec<u8>,
+}
+
+impl WebRenderer {
+    pub fn new(scene: Scene) -> Self {
+        let mut renderer = Self {
+            accumulator: Accumulator::new(0, 0),
+            lights: LightSampler::new(&[]),
+            pixels: Vec::new(),
+            scene,
+        };
+        renderer.reset();
+        renderer
+    }
+
+    /// 改了scene（比如挪了相机或者改了大小）以后清空重新累积
+    pub fn reset(&mut self) {
+        self.accumulator = Accumulator::new(self.scene.width, self.scene.height);
+        self.lights = LightSampler::for_scene(&self.scene);
+        self.pixels = self.accumulator.image(&self.scene).to_rgba().into_raw();
+    }
+
+    /// 每个像素再加samples个样本，返回更新后的像素
+    pub fn step(&mut self, samples: u32) -> &[u8] {
+        self.accumulator
+            .add_samples(&self.scene, &self.lights, samples);
+        self.pixels = self.accumulator.image(&self.scene).to_rgba().into_raw();
+        &self.pixels
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.accumulator.min_samples()
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+}
+
+/// 不用读任何文件的演示场景：地面、玻璃球、镜面球、漫反射球
+pub fn demo_scene(width: u32, height: u32) -> Scene {
+    SceneBuilder::new()
+        .size(width, height)
+        .fov(60.0)
+        .add_plane(
+            Point::new(0.0, -1.0, 0.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            Material::diffuse(Color::new(0.8, 0.8, 0.8)),
+        )
+        .add_sphere(
+            Point::new(0.0, 0.0, -4.0),
+            1.0,
+            Material::refractive(1.5, 0.9).with_albedo(0.18),
+        )
+        .add_sphere(
+            Point::new(2.2, 0.0, -5.0),
+            1.0,
+            Material::reflective(Color::new(0.9, 0.9, 0.9), 0.8),
+        )
+        .add_sphere(
+            Point::new(-2.2, 0.0, -5.0),
+            1.0,
+            Material::diffuse(Color::new(0.9, 0.2, 0.2)),
+        )
+        .add_directional_light(Vector3::new(-0.5, -1.0, -1.0), Color::white(), 2.0)
+        .add_spherical_light(Point::new(0.0, 3.0, -2.0), 0.5, Color::white(), 200.0)
+        .build()
+        // 材质都是直接给的，没有按名字引用，不会出错
+        .unwrap()
+}
+
+// 下面是给JS直接调的C ABI函数，不依赖wasm-bindgen。
+// 用法：let r = nrt_demo_new(w, h); 每帧 let ptr = nrt_step(r, 1); 用ptr建ImageData画出来
+
+/// 用演示场景建一个WebRenderer，用完要nrt_free
+#[no_mangle]
+pub extern "C" fn nrt_demo_new(width: u32, height: u32) -> *mut WebRenderer {
+    Box::into_raw(Box::new(WebRenderer::new(demo_scene(width, height))))
+}
+
+/// 再加samples个样本，返回像素的地址，长度是width * height * 4
+///
+/// # Safety
+/// renderer必须是nrt_demo_new返回的、还没有free的指针
+#[no_mangle]
+pub unsafe extern "C" fn nrt_step(renderer: *mut WebRenderer, samples: u32) -> *const u8 {
+    (*renderer).step(samples).as_ptr()
+}
+
+/// # Safety
+/// 同nrt_step
+#[no_mangle]
+pub unsafe extern "C" fn nrt_samples(renderer: *const WebRenderer) -> u32 {
+    (*renderer).samples()
+}
+
+/// # Safety
+/// 同nrt_step，free之后指针不能再用
+#[no_mangle]
+pub unsafe extern "C" fn nrt_free(renderer: *mut WebRenderer) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}